@@ -1,7 +1,14 @@
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+/// Generated on each machine with `sign-image keygen`, never committed
+const DEVELOPMENT_PUBLIC_KEY: &str = "keys/development.key.pub";
+
+/// Public keys whose private half got out, images signed with them must never be accepted
+const REVOKED_PUBLIC_KEYS: [&str; 1] =
+    ["9f934d170ba96d28df928d3f7ddb5a9d7557e919be2cc5a6bc05c6a7867d4434"];
 
 fn main() {
     let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
@@ -15,4 +22,53 @@ fn main() {
     println!("cargo:rustc-link-arg-bins=--nmagic");
     println!("cargo:rustc-link-arg-bins=-Tlink.x");
     println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
-}
\ No newline at end of file
+
+    write_public_key(out);
+}
+
+/// Embeds the key updates are verified with, set `PYRION_PUBLIC_KEY` to the `.pub` file of the
+/// release key. Only debug builds fall back to the local development key.
+fn write_public_key(out: &Path) {
+    println!("cargo:rerun-if-env-changed=PYRION_PUBLIC_KEY");
+    let path = match env::var("PYRION_PUBLIC_KEY") {
+        Ok(path) => path,
+        Err(_) if env::var("PROFILE").as_deref() == Ok("debug") => {
+            if !Path::new(DEVELOPMENT_PUBLIC_KEY).exists() {
+                panic!(
+                    "PYRION_PUBLIC_KEY is not set and there is no development key, create one with \
+                     `cargo run -p sign-image -- keygen crates/bootloader/keys/development.key`"
+                );
+            }
+            println!(
+                "cargo:warning=PYRION_PUBLIC_KEY is not set, accepting images signed with the development key"
+            );
+            DEVELOPMENT_PUBLIC_KEY.to_string()
+        }
+        Err(_) => panic!("PYRION_PUBLIC_KEY has to point to the public key for release builds"),
+    };
+    println!("cargo:rerun-if-changed={path}");
+
+    let text =
+        fs::read_to_string(&path).unwrap_or_else(|error| panic!("Failed to read {path}: {error}"));
+    let text = text.trim();
+    if REVOKED_PUBLIC_KEYS.contains(&text.to_ascii_lowercase().as_str()) {
+        panic!(
+            "{path} is a revoked key, create a new one with \
+             `cargo run -p sign-image -- keygen <key file>`"
+        );
+    }
+    let key: Vec<u8> = (0..text.len())
+        .step_by(2)
+        .map(|i| {
+            text.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+        })
+        .collect::<Option<_>>()
+        .unwrap_or_else(|| panic!("{path} is not hex"));
+    assert_eq!(key.len(), 32, "{path} does not hold an ed25519 public key");
+
+    File::create(out.join("public_key.rs"))
+        .unwrap()
+        .write_all(format!("const PUBLIC_KEY: [u8; 32] = {key:?};\n").as_bytes())
+        .unwrap();
+}
@@ -1,9 +1,11 @@
 MEMORY
 {
-    FLASH                             : ORIGIN = 0x08000000, LENGTH =  48K
+    FLASH                             : ORIGIN = 0x08000000, LENGTH =  48K - 16
+    BOOTLOADER_INFO                   : ORIGIN = 0x0800BFF0, LENGTH =  16
     BOOTLOADER_STATE                  : ORIGIN = 0x0800C000, LENGTH =   8K
-    ACTIVE                            : ORIGIN = 0x0800E000, LENGTH = 200K
-    DFU                               : ORIGIN = 0x08040000, LENGTH = 256K
+    FAULT_JOURNAL                     : ORIGIN = 0x0800E000, LENGTH =   2K
+    ACTIVE                            : ORIGIN = 0x0800E800, LENGTH = 226K
+    DFU                               : ORIGIN = 0x08047000, LENGTH = 228K
     RAM   (rwx)                       : ORIGIN = 0x20000000, LENGTH = 128K
 }
 
@@ -13,5 +15,10 @@ __bootloader_state_end = ORIGIN(BOOTLOADER_STATE) + LENGTH(BOOTLOADER_STATE) - O
 __bootloader_active_start = ORIGIN(ACTIVE) - ORIGIN(FLASH);
 __bootloader_active_end = ORIGIN(ACTIVE) + LENGTH(ACTIVE) - ORIGIN(FLASH);
 
-__bootloader_dfu_start = ORIGIN(DFU) - ORIGIN(DFU);
-__bootloader_dfu_end = ORIGIN(DFU) + LENGTH(DFU) - ORIGIN(DFU);
\ No newline at end of file
+__bootloader_dfu_start = ORIGIN(DFU) - ORIGIN(FLASH);
+__bootloader_dfu_end = ORIGIN(DFU) + LENGTH(DFU) - ORIGIN(FLASH);
+
+SECTIONS
+{
+    .bootloader_info : { KEEP(*(.bootloader_info)); } > BOOTLOADER_INFO
+} INSERT AFTER .rodata;
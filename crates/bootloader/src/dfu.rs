@@ -2,12 +2,13 @@ use defmt::info;
 use embassy_boot_stm32::BlockingFirmwareUpdater;
 use embassy_usb::class::dfu::consts::DfuAttributes;
 use embassy_usb::class::dfu::dfu_mode::Handler;
-use embassy_usb::control::{InResponse, OutResponse, Request};
+use embassy_usb::control::{InResponse, OutResponse, Request, RequestType};
 use embassy_usb::driver::Driver;
 use embassy_usb::{Builder, FunctionBuilder};
 use embassy_usb_dfu::dfu::{FirmwareHandler, UsbDfuState};
 use embassy_usb_dfu::{Reset, ResetImmediate};
 use embedded_storage::nor_flash::NorFlash;
+use firmware_image::ImageError;
 use hardware::BoardLeds;
 // This code is derived from embassy-usb and embassy-usb-dfu.
 // Modifications were made to ensure that restarts triggered by dfu-util function correctly.
@@ -17,12 +18,22 @@ pub(crate) const APPN_SPEC_SUBCLASS_DFU: u8 = 0x01;
 pub(crate) const DFU_PROTOCOL_DFU: u8 = 0x02;
 pub(crate) const DESC_DFU_FUNCTIONAL: u8 = 0x21;
 
+const REQUEST_GET_STATUS: u8 = 3;
+const REQUEST_CLEAR_STATUS: u8 = 4;
+const REQUEST_GET_STATE: u8 = 5;
+const STATE_DFU_ERROR: u8 = 10;
+const STATUS_ERR_TARGET: u8 = 0x01;
+const STATUS_ERR_FILE: u8 = 0x02;
+const STATUS_ERR_VERIFY: u8 = 0x07;
+const STATUS_ERR_UNKNOWN: u8 = 0x0E;
+
 pub fn new_state<'a, DFU: NorFlash, STATE: NorFlash, const BLOCK_SIZE: usize>(
     updater: BlockingFirmwareUpdater<'a, DFU, STATE>,
     board_leds: BoardLeds<'a>,
+    rejected: Option<ImageError>,
 ) -> DfuState<'a, FirmwareHandler<'a, DFU, STATE, ResetImmediate, BLOCK_SIZE>> {
     let handler = FirmwareHandler::new(updater, ResetImmediate);
-    DfuState::new(handler, board_leds)
+    DfuState::new(handler, board_leds, rejected)
 }
 
 pub struct DfuState<'a, H: Handler> {
@@ -30,10 +41,12 @@ pub struct DfuState<'a, H: Handler> {
     attrs: DfuAttributes,
     board_leds: BoardLeds<'a>,
     finished: bool,
+    /// Why the last update was not swapped in, reported until the host clears the status
+    rejected: Option<ImageError>,
 }
 
 impl<'a, H: Handler> DfuState<'a, H> {
-    pub fn new(handler: H, board_leds: BoardLeds<'a>) -> Self {
+    pub fn new(handler: H, board_leds: BoardLeds<'a>, rejected: Option<ImageError>) -> Self {
         let attrs = DfuAttributes::CAN_DOWNLOAD | DfuAttributes::MANIFESTATION_TOLERANT;
         let inner = UsbDfuState::new(handler, attrs);
         Self {
@@ -41,6 +54,7 @@ impl<'a, H: Handler> DfuState<'a, H> {
             attrs,
             board_leds,
             finished: false,
+            rejected,
         }
     }
 }
@@ -55,6 +69,13 @@ impl<H: Handler> embassy_usb::Handler for DfuState<'_, H> {
     }
 
     fn control_out(&mut self, req: Request, data: &[u8]) -> Option<OutResponse> {
+        if req.request_type == RequestType::Class
+            && req.request == REQUEST_CLEAR_STATUS
+            && self.rejected.take().is_some()
+        {
+            self.board_leds.red.set_low();
+            self.board_leds.green.set_high();
+        }
         if req.request == 1
         //Request::Download && finished
         {
@@ -67,10 +88,36 @@ impl<H: Handler> embassy_usb::Handler for DfuState<'_, H> {
     }
 
     fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> Option<InResponse<'a>> {
+        if let Some(error) = self.rejected
+            && req.request_type == RequestType::Class
+        {
+            match req.request {
+                REQUEST_GET_STATUS => {
+                    buf[..6].copy_from_slice(&[dfu_status(error), 0, 0, 0, STATE_DFU_ERROR, 0]);
+                    return Some(InResponse::Accepted(&buf[..6]));
+                }
+                REQUEST_GET_STATE => {
+                    buf[0] = STATE_DFU_ERROR;
+                    return Some(InResponse::Accepted(&buf[..1]));
+                }
+                _ => {}
+            }
+        }
         self.inner.control_in(req, buf)
     }
 }
 
+fn dfu_status(error: ImageError) -> u8 {
+    match error {
+        ImageError::WrongBoard => STATUS_ERR_TARGET,
+        ImageError::Unsigned | ImageError::InvalidLength | ImageError::MissingHeader => {
+            STATUS_ERR_FILE
+        }
+        ImageError::DigestMismatch | ImageError::BadSignature => STATUS_ERR_VERIFY,
+        ImageError::Flash => STATUS_ERR_UNKNOWN,
+    }
+}
+
 pub fn usb_dfu<'d, D: Driver<'d>, DFU: NorFlash, STATE: NorFlash, const BLOCK_SIZE: usize>(
     builder: &mut Builder<'d, D>,
     state: &'d mut DfuState<FirmwareHandler<DFU, STATE, ResetImmediate, BLOCK_SIZE>>,
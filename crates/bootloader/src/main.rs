@@ -4,9 +4,9 @@
 use cortex_m_rt::{entry, exception};
 use defmt::info;
 use embassy_boot_stm32::*;
-use embassy_stm32::flash::{BANK1_REGION, WRITE_SIZE};
+use embassy_stm32::flash::{FLASH_BASE, WRITE_SIZE};
 use embassy_usb::Builder;
-use hardware::configure_dfu_win_usb;
+use hardware::{BootloaderInfo, configure_dfu_win_usb};
 use hardware::usb::{UsbBuffers, WinUsbExt, get_usb_config};
 
 use crate::dfu::{new_state, usb_dfu};
@@ -14,30 +14,46 @@ use crate::dfu::{new_state, usb_dfu};
 use defmt_rtt as _;
 
 mod dfu;
+mod verify;
+
+#[used]
+#[unsafe(link_section = ".bootloader_info")]
+static BOOTLOADER_INFO: BootloaderInfo = BootloaderInfo::new([
+    parse_version_part(env!("CARGO_PKG_VERSION_MAJOR")),
+    parse_version_part(env!("CARGO_PKG_VERSION_MINOR")),
+    parse_version_part(env!("CARGO_PKG_VERSION_PATCH")),
+]);
+
+const fn parse_version_part(part: &str) -> u8 {
+    match u8::from_str_radix(part, 10) {
+        Ok(value) => value,
+        Err(_) => panic!("Version part does not fit in a byte"),
+    }
+}
 
 #[entry]
 fn main() -> ! {
     let mut board = hardware::Board::init();
 
-    let config = BootLoaderConfig::from_linkerfile_blocking(
-        &board.flash_bank1,
-        &board.flash_bank2,
-        &board.flash_bank1,
-    );
+    let rejected = verify::reject_invalid_update(&board.flash);
+    let config =
+        BootLoaderConfig::from_linkerfile_blocking(&board.flash, &board.flash, &board.flash);
     let active_offset = config.active.offset();
     let bl = BootLoader::prepare::<_, _, _, 8>(config);
 
-    if bl.state == State::DfuDetach {
+    if bl.state == State::DfuDetach || rejected.is_some() {
         info!("Entering detached state");
-        let fw_config =
-            FirmwareUpdaterConfig::from_linkerfile_blocking(&board.flash_bank2, &board.flash_bank1);
+        let fw_config = FirmwareUpdaterConfig::from_linkerfile_blocking(&board.flash, &board.flash);
         let mut aligned_buffer = AlignedBuffer([0; WRITE_SIZE]);
         let updater = BlockingFirmwareUpdater::new(fw_config, &mut aligned_buffer.0[..]);
 
         let usb_config = get_usb_config(&board.serial_number);
         let mut usb_buffers = UsbBuffers::new();
-        board.leds.green.set_high();
-        let mut dfu_state = new_state(updater, board.leds);
+        match rejected {
+            Some(_) => board.leds.red.set_high(),
+            None => board.leds.green.set_high(),
+        }
+        let mut dfu_state = new_state(updater, board.leds, rejected);
 
         let mut builder = Builder::new(
             board.usb,
@@ -58,7 +74,7 @@ fn main() -> ! {
     }
 
     info!("Booting");
-    unsafe { bl.load(BANK1_REGION.base() + active_offset) }
+    unsafe { bl.load(FLASH_BASE as u32 + active_offset) }
 }
 
 #[unsafe(no_mangle)]
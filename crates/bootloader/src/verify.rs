@@ -0,0 +1,56 @@
+use defmt::{info, warn};
+use embassy_boot_stm32::{AlignedBuffer, BlockingFirmwareState, FirmwareUpdaterConfig, State};
+use embassy_stm32::flash::WRITE_SIZE;
+use embedded_storage::nor_flash::ReadNorFlash;
+use firmware_image::{ImageError, ImageHeader};
+use hardware::BoardFlash;
+
+include!(concat!(env!("OUT_DIR"), "/public_key.rs"));
+
+const ERASED: u8 = 0xFF;
+
+/// Checks the signature and the header of an update waiting to be swapped in, the swap is cancelled
+/// if either does not hold
+pub fn reject_invalid_update(flash: &BoardFlash) -> Option<ImageError> {
+    let FirmwareUpdaterConfig { mut dfu, mut state } =
+        FirmwareUpdaterConfig::from_linkerfile_blocking(flash, flash);
+    let mut aligned_buffer = AlignedBuffer([0; WRITE_SIZE]);
+    // A started swap has to finish, and an unconfirmed image has to be reverted
+    if swap_started(&mut state, &mut aligned_buffer.0) {
+        return None;
+    }
+    let mut firmware_state = BlockingFirmwareState::new(state, &mut aligned_buffer.0);
+    if !matches!(firmware_state.get_state(), Ok(State::Swap)) {
+        return None;
+    }
+
+    match check_update(&mut dfu) {
+        Ok(header) => {
+            info!(
+                "Update to {}.{}.{} of {} bytes is signed",
+                header.version[0], header.version[1], header.version[2], header.length
+            );
+            None
+        }
+        Err(error) => {
+            warn!("Rejecting the update: {}", error);
+            if firmware_state.mark_booted().is_err() {
+                warn!("Failed to cancel the swap");
+            }
+            Some(error)
+        }
+    }
+}
+
+fn check_update<F: ReadNorFlash>(dfu: &mut F) -> Result<ImageHeader, ImageError> {
+    firmware_image::verify(dfu, &PUBLIC_KEY)?;
+    firmware_image::check_header(dfu, hardware::BOARD_REVISION)
+}
+
+/// The bootloader keeps its progress in the slots behind the state magic
+fn swap_started<F: ReadNorFlash>(state: &mut F, buffer: &mut [u8]) -> bool {
+    (1..3).any(|slot| {
+        state.read((slot * WRITE_SIZE) as u32, buffer).is_err()
+            || buffer.iter().any(|&byte| byte != ERASED)
+    })
+}
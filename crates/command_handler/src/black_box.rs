@@ -0,0 +1,89 @@
+use crate::blob::Blob;
+use controller_shared::black_box::{self, BlackBox};
+use transport::black_box::{BLACK_BOX_BLOB_ID, BlackBoxFrame, BlackBoxHeader, StrategyKind};
+use transport::blob::BlobId;
+use transport::event::ErrorCode;
+use transport::packet::Field;
+
+/// Serves the control steps the black box kept after the last fault
+pub struct BlackBoxBlob;
+
+impl Blob for BlackBoxBlob {
+    fn id(&self) -> BlobId {
+        BLACK_BOX_BLOB_ID
+    }
+
+    fn size(&self) -> u32 {
+        let frame_count = black_box::black_box().lock(|black_box| black_box.borrow().frame_count());
+        match frame_count {
+            0 => 0,
+            frame_count => (BlackBoxHeader::SIZE + frame_count * BlackBoxFrame::SIZE) as u32,
+        }
+    }
+
+    fn read(&self, offset: u32, buffer: &mut [u8]) -> Result<usize, ErrorCode> {
+        black_box::black_box()
+            .lock(|black_box| read_record(&black_box.borrow(), offset as usize, buffer))
+    }
+}
+
+/// The header and every frame are serialized as a whole and then cut to the requested bytes
+fn read_record(black_box: &BlackBox, offset: usize, buffer: &mut [u8]) -> Result<usize, ErrorCode> {
+    let frame_count = black_box.frame_count();
+    if frame_count == 0 {
+        return Err(ErrorCode::InvalidState);
+    }
+    let header = BlackBoxHeader {
+        frame_count: frame_count as u16,
+        frame_size: BlackBoxFrame::SIZE as u16,
+    };
+
+    let mut item = [0; BlackBoxFrame::SIZE];
+    let mut written = 0;
+    while written < buffer.len() {
+        let position = offset + written;
+        let (start, size) = match position.checked_sub(BlackBoxHeader::SIZE) {
+            None => {
+                header.write(&mut item);
+                (0, BlackBoxHeader::SIZE)
+            }
+            Some(frame_position) => {
+                let index = frame_position / BlackBoxFrame::SIZE;
+                let frame = black_box.frame(index).ok_or(ErrorCode::InvalidArgument)?;
+                map_frame(frame).write(&mut item);
+                (
+                    BlackBoxHeader::SIZE + index * BlackBoxFrame::SIZE,
+                    BlackBoxFrame::SIZE,
+                )
+            }
+        };
+        let from = position - start;
+        let length = (size - from).min(buffer.len() - written);
+        buffer[written..written + length].copy_from_slice(&item[from..from + length]);
+        written += length;
+    }
+    Ok(written)
+}
+
+fn map_frame(frame: &black_box::BlackBoxFrame) -> BlackBoxFrame {
+    BlackBoxFrame {
+        timestamp: frame.timestamp,
+        phase_currents: frame.phase_currents,
+        v_bus: frame.v_bus,
+        electrical_angle: frame.electrical_angle,
+        duty_cycles: frame.duty_cycles,
+        strategy: map_strategy(frame.strategy),
+        setpoint: frame.setpoint,
+        active_faults: frame.active_faults,
+    }
+}
+
+fn map_strategy(strategy: black_box::StrategyKind) -> StrategyKind {
+    match strategy {
+        black_box::StrategyKind::Disabled => StrategyKind::Disabled,
+        black_box::StrategyKind::Current => StrategyKind::Current,
+        black_box::StrategyKind::Velocity => StrategyKind::Velocity,
+        black_box::StrategyKind::Position => StrategyKind::Position,
+        black_box::StrategyKind::DutyCycle => StrategyKind::DutyCycle,
+    }
+}
@@ -0,0 +1,190 @@
+use crate::black_box::BlackBoxBlob;
+use crate::scope::ScopeBlob;
+use logging::warn;
+use transport::blob::{
+    BLOB_CHUNK_MAX_DATA_SIZE, BlobChunk, BlobClose, BlobId, BlobInfo, BlobMode, BlobOpen,
+    BlobRead, Checksum,
+};
+use transport::event::ErrorCode;
+
+/// Data the host can read or write in chunks, e.g. a config dump or a capture buffer
+pub trait Blob: Sync {
+    fn id(&self) -> BlobId;
+
+    /// Size of a read transfer, it must not change while the transfer is open
+    fn size(&self) -> u32;
+
+    /// Fills `buffer` with the data at `offset` and returns how much was written
+    fn read(&self, _offset: u32, _buffer: &mut [u8]) -> Result<usize, ErrorCode> {
+        Err(ErrorCode::NotImplemented)
+    }
+
+    fn begin_write(&self, _total_size: u32) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NotImplemented)
+    }
+
+    /// Chunks arrive in order, `offset` is only passed for convenience
+    fn write(&self, _offset: u32, _data: &[u8]) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NotImplemented)
+    }
+
+    /// Called once all data arrived and the checksum matched
+    fn commit(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NotImplemented)
+    }
+
+    /// Called instead of `commit` if a write transfer is not completed
+    fn abort(&self) {}
+}
+
+/// Every blob served by this firmware
+const BLOBS: &[&dyn Blob] = &[&ScopeBlob, &BlackBoxBlob];
+
+struct Transfer {
+    handle: u8,
+    blob: &'static dyn Blob,
+    mode: BlobMode,
+    total_size: u32,
+    /// Bytes written so far, writes have to arrive in order
+    position: u32,
+    checksum: Checksum,
+}
+
+/// The single blob transfer a link can have open at a time
+pub struct BlobTransfers {
+    open: Option<Transfer>,
+    next_handle: u8,
+}
+
+impl Default for BlobTransfers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BlobTransfers {
+    pub const fn new() -> Self {
+        Self {
+            open: None,
+            next_handle: 0,
+        }
+    }
+
+    /// Opening a transfer aborts the one that is still open, the host has given up on it
+    pub fn open(&mut self, request: BlobOpen) -> Result<BlobInfo, ErrorCode> {
+        self.abort();
+        let blob = *BLOBS
+            .iter()
+            .find(|blob| blob.id() == request.id)
+            .ok_or(ErrorCode::InvalidArgument)?;
+        let total_size = match request.mode {
+            BlobMode::Read => blob.size(),
+            BlobMode::Write => {
+                blob.begin_write(request.total_size)?;
+                request.total_size
+            }
+        };
+
+        let handle = self.next_handle;
+        self.next_handle = self.next_handle.wrapping_add(1);
+        self.open = Some(Transfer {
+            handle,
+            blob,
+            mode: request.mode,
+            total_size,
+            position: 0,
+            checksum: Checksum::new(),
+        });
+        Ok(BlobInfo { handle, total_size })
+    }
+
+    /// Reads may come in any order, so a lost chunk can be asked for again
+    pub fn read(&mut self, request: BlobRead) -> Result<BlobChunk, ErrorCode> {
+        let transfer = self.transfer(request.handle, BlobMode::Read)?;
+        if request.offset > transfer.total_size {
+            return Err(ErrorCode::InvalidArgument);
+        }
+        let length = (request.length as usize)
+            .min(BLOB_CHUNK_MAX_DATA_SIZE)
+            .min((transfer.total_size - request.offset) as usize);
+
+        let mut chunk = BlobChunk::new(request.handle, request.offset, &[]);
+        let length = transfer
+            .blob
+            .read(request.offset, &mut chunk.data[..length])?;
+        chunk.length = length as u16;
+        Ok(chunk)
+    }
+
+    pub fn write(&mut self, chunk: &BlobChunk) -> Result<(), ErrorCode> {
+        let transfer = self.transfer(chunk.handle, BlobMode::Write)?;
+        let data = chunk.slice();
+        if chunk.offset != transfer.position
+            || transfer.position as usize + data.len() > transfer.total_size as usize
+        {
+            return Err(ErrorCode::InvalidArgument);
+        }
+        transfer.blob.write(chunk.offset, data)?;
+        transfer.checksum.update(data);
+        transfer.position += data.len() as u32;
+        Ok(())
+    }
+
+    /// Fails if the data seen by the device does not match the checksum of the host
+    pub fn close(&mut self, request: BlobClose) -> Result<(), ErrorCode> {
+        let transfer = self
+            .open
+            .take_if(|transfer| transfer.handle == request.handle)
+            .ok_or(ErrorCode::InvalidState)?;
+        match transfer.mode {
+            BlobMode::Read => {
+                if read_checksum(&transfer)? != request.checksum {
+                    return Err(ErrorCode::InvalidArgument);
+                }
+                Ok(())
+            }
+            BlobMode::Write => {
+                if transfer.position != transfer.total_size
+                    || transfer.checksum.finish() != request.checksum
+                {
+                    warn!("Blob {} was not written completely", transfer.blob.id().0);
+                    transfer.blob.abort();
+                    return Err(ErrorCode::InvalidArgument);
+                }
+                transfer.blob.commit()
+            }
+        }
+    }
+
+    fn abort(&mut self) {
+        if let Some(transfer) = self.open.take()
+            && transfer.mode == BlobMode::Write
+        {
+            warn!("Aborting an unfinished write of blob {}", transfer.blob.id().0);
+            transfer.blob.abort();
+        }
+    }
+
+    fn transfer(&mut self, handle: u8, mode: BlobMode) -> Result<&mut Transfer, ErrorCode> {
+        self.open
+            .as_mut()
+            .filter(|transfer| transfer.handle == handle && transfer.mode == mode)
+            .ok_or(ErrorCode::InvalidState)
+    }
+}
+
+fn read_checksum(transfer: &Transfer) -> Result<u32, ErrorCode> {
+    let mut checksum = Checksum::new();
+    let mut buffer = [0u8; 64];
+    let mut offset = 0;
+    while offset < transfer.total_size {
+        let length = buffer.len().min((transfer.total_size - offset) as usize);
+        let read = transfer.blob.read(offset, &mut buffer[..length])?;
+        if read == 0 {
+            return Err(ErrorCode::InvalidState);
+        }
+        checksum.update(&buffer[..read]);
+        offset += read as u32;
+    }
+    Ok(checksum.finish())
+}
@@ -0,0 +1,106 @@
+use controller_shared::policy::FaultPolicies;
+use core::sync::atomic::Ordering;
+use embassy_time::{Duration, Instant};
+use logging::fault_register::FaultRegister;
+
+pub use firmware_update::{outcome, set_outcome};
+
+/// How long a new image may take to pass its health checks
+pub const TRIAL_WINDOW: Duration = Duration::from_secs(15);
+
+/// What a new image has to show before it is confirmed
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Health {
+    pub control_loop_running: bool,
+    /// No raised fault locks the motor out
+    pub no_lockout: bool,
+    /// A host got an answer to a request
+    pub host_answered: bool,
+}
+
+impl Health {
+    pub fn sample(policies: &FaultPolicies) -> Self {
+        let state = controller_shared::state::state();
+        Self {
+            control_loop_running: state.foc_loop_frequency.load(Ordering::Relaxed) > 0,
+            no_lockout: !policies.locks_out(FaultRegister::shared()),
+            host_answered: state.requests_handled.load(Ordering::Relaxed) > 0,
+        }
+    }
+
+    fn passed(&self) -> bool {
+        self.control_loop_running && self.no_lockout && self.host_answered
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Keep the watchdog fed and check again
+    Pending,
+    Confirm,
+    /// Stop feeding the watchdog, the bootloader brings the previous image back after the reset
+    Revert,
+}
+
+/// Decides about an image running on trial
+pub struct Trial {
+    deadline: Instant,
+}
+
+impl Trial {
+    pub fn new(started: Instant) -> Self {
+        Self {
+            deadline: started + TRIAL_WINDOW,
+        }
+    }
+
+    pub fn check(&self, health: Health, now: Instant) -> Verdict {
+        if health.passed() {
+            Verdict::Confirm
+        } else if now >= self.deadline {
+            Verdict::Revert
+        } else {
+            Verdict::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEALTHY: Health = Health {
+        control_loop_running: true,
+        no_lockout: true,
+        host_answered: true,
+    };
+
+    #[test]
+    fn image_should_be_confirmed_once_every_check_passed() {
+        let trial = Trial::new(Instant::from_secs(1));
+        let now = Instant::from_secs(2);
+        assert_eq!(trial.check(Health::default(), now), Verdict::Pending);
+        let silent_host = Health {
+            host_answered: false,
+            ..HEALTHY
+        };
+        assert_eq!(trial.check(silent_host, now), Verdict::Pending);
+        assert_eq!(trial.check(HEALTHY, now), Verdict::Confirm);
+    }
+
+    #[test]
+    fn image_should_be_reverted_after_the_window() {
+        let trial = Trial::new(Instant::from_secs(1));
+        let locked_out = Health {
+            no_lockout: false,
+            ..HEALTHY
+        };
+        let deadline = Instant::from_secs(1) + TRIAL_WINDOW;
+        assert_eq!(
+            trial.check(locked_out, deadline - Duration::from_millis(1)),
+            Verdict::Pending
+        );
+        assert_eq!(trial.check(locked_out, deadline), Verdict::Revert);
+    }
+}
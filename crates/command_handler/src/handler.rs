@@ -1,12 +1,42 @@
+use crate::blob::BlobTransfers;
+use crate::firmware_update::FirmwareTarget;
+use crate::log_forwarding;
+use crate::scope;
+use crate::telemetry::TelemetryStream;
+use controller_shared::arming::{ArmingState, arming_state};
 use controller_shared::command::{ControlCommand, ControlCommandChannel};
+use controller_shared::protection::ProtectionLimits;
 use core::sync::atomic::Ordering;
 use logging::info;
-use transport::event::DeviceIntroduction;
+use transport::capabilities::Capabilities;
+use transport::event::{DeviceIntroduction, ErrorCode};
+use transport::fault_history::FaultHistory;
 use transport::{Command, Event};
+use units::{Angle, AngularVelocity, DutyCycle, ElectricCurrent, F32UnitType, Torque};
 
+/// Optional protocol features this firmware implements
+const SUPPORTED_CAPABILITIES: Capabilities = Capabilities::RELIABLE_DELIVERY
+    .union(Capabilities::FAULT_REPORTING)
+    .union(Capabilities::LINK_QUALITY)
+    .union(Capabilities::FIRMWARE_UPDATE)
+    .union(Capabilities::BLOB_TRANSFER)
+    .union(Capabilities::MOTION_CONTROL)
+    .union(Capabilities::TELEMETRY_STREAMS)
+    .union(Capabilities::SCOPE)
+    .union(Capabilities::BLACK_BOX)
+    .union(Capabilities::LOG_FORWARDING)
+    .union(Capabilities::FAULT_EVENTS)
+    .union(Capabilities::FAULT_HISTORY)
+    .union(Capabilities::FIRMWARE_SLOTS);
+
+/// `max_frame_size` is the largest frame the framing of the link accepts
 pub async fn execute_command(
     command: Command,
     control_command_channel: &ControlCommandChannel,
+    blobs: &mut BlobTransfers,
+    telemetry: &mut TelemetryStream,
+    firmware: &mut dyn FirmwareTarget,
+    max_frame_size: u16,
 ) -> Event {
     info!("Command received: {:?}", command);
     match command {
@@ -18,20 +48,128 @@ pub async fn execute_command(
             Event::DeviceIntroduction(DeviceIntroduction {
                 uid: embassy_stm32::uid::uid(),
                 firmware_version: [version_major, version_minor, version_patch],
+                protocol_version: transport::PROTOCOL_VERSION,
+                board_revision: state.board_revision.load(Ordering::Relaxed),
+                bootloader_version: [
+                    state.bootloader_version.major.load(Ordering::Relaxed),
+                    state.bootloader_version.minor.load(Ordering::Relaxed),
+                    state.bootloader_version.patch.load(Ordering::Relaxed),
+                ],
+                max_frame_size,
+                capabilities: SUPPORTED_CAPABILITIES,
             })
         }
-        Command::Stop => match control_command_channel.try_send(ControlCommand::DisableMotor) {
-            Ok(_) => Event::Success,
-            Err(_) => Event::Failure,
-        },
-        Command::WriteFirmwareBlock(_block) => Event::Failure,
-        Command::FinalizeFirmwareUpdate => Event::Failure,
+        Command::Stop => {
+            send_control_command(control_command_channel, ControlCommand::DisableMotor)
+        }
+        Command::Arm => arm(control_command_channel),
+        Command::Disarm => send_control_command(control_command_channel, ControlCommand::Disarm),
+        Command::WriteFirmwareBlock(block) => {
+            to_event(firmware.write_block(&block).map(|_| Event::Success))
+        }
+        Command::FinalizeFirmwareUpdate => to_event(firmware.finalize().map(|_| Event::Success)),
+        Command::ReadFirmwareSlots => to_event(firmware.read_slots().map(Event::FirmwareSlots)),
         Command::ReportFaults => Event::FaultRegister(transport::event::FaultRegister {
             cells: logging::fault_register::FaultRegister::shared().snapshot(),
         }),
         Command::ResetFaults => {
-            logging::fault_register::FaultRegister::shared().reset();
-            Event::Success
+            // Cleared by the controller, which knows how each fault recovers
+            let event = send_control_command(control_command_channel, ControlCommand::ResetFaults);
+            controller_shared::black_box::rearm();
+            event
+        }
+        Command::ReadFaultHistory => Event::FaultHistory(
+            logging::fault_journal::journal()
+                .lock(|journal| FaultHistory::from(&*journal.borrow())),
+        ),
+        Command::OpenBlob(request) => to_event(blobs.open(request).map(Event::BlobOpened)),
+        Command::ReadBlob(request) => to_event(blobs.read(request).map(Event::BlobData)),
+        Command::WriteBlob(chunk) => to_event(blobs.write(&chunk).map(|_| Event::Success)),
+        Command::CloseBlob(request) => to_event(blobs.close(request).map(|_| Event::Success)),
+        Command::SetCurrent(setpoint) => {
+            let d = ElectricCurrent::from_f32(setpoint.d);
+            let q = ElectricCurrent::from_f32(setpoint.q);
+            send_setpoint(
+                control_command_channel,
+                &[setpoint.d, setpoint.q],
+                setpoint_limits().allows_current(d, q),
+                ControlCommand::SetCurrent { d, q },
+            )
+        }
+        Command::SetTorque(setpoint) => {
+            let torque = Torque::from_f32(setpoint.torque);
+            send_setpoint(
+                control_command_channel,
+                &[setpoint.torque],
+                setpoint_limits().allows_torque(torque),
+                ControlCommand::SetTorque(torque),
+            )
+        }
+        Command::SetVelocity(setpoint) => send_setpoint(
+            control_command_channel,
+            &[setpoint.velocity],
+            true,
+            ControlCommand::SetVelocity(AngularVelocity::from_f32(setpoint.velocity)),
+        ),
+        Command::SetPosition(setpoint) => send_setpoint(
+            control_command_channel,
+            &[setpoint.position],
+            true,
+            ControlCommand::SetPosition(Angle::from_f32(setpoint.position)),
+        ),
+        Command::SetDutyCycle(setpoint) => send_setpoint(
+            control_command_channel,
+            &[setpoint.duty_cycle],
+            true,
+            ControlCommand::SetDutyCycle(DutyCycle::from(setpoint.duty_cycle)),
+        ),
+        Command::SubscribeTelemetry(subscription) => {
+            to_event(telemetry.subscribe(subscription).map(|_| Event::Success))
         }
+        Command::ConfigureScope(config) => scope::configure(config),
+        Command::TriggerScope => scope::trigger(),
+        Command::ReportScope => scope::report(),
+        Command::SetLogLevel(level) => log_forwarding::set_min_level(level),
     }
 }
+
+fn send_control_command(channel: &ControlCommandChannel, command: ControlCommand) -> Event {
+    match channel.try_send(command) {
+        Ok(_) => Event::Success,
+        Err(_) => Event::Failure(ErrorCode::Busy),
+    }
+}
+
+/// Arming an armed controller succeeds, arming while a fault stops the motor does not
+fn arm(channel: &ControlCommandChannel) -> Event {
+    match arming_state() {
+        ArmingState::Idle => send_control_command(channel, ControlCommand::Arm),
+        ArmingState::Calibrating | ArmingState::Armed | ArmingState::Running => Event::Success,
+        ArmingState::Boot | ArmingState::Fault => Event::Failure(ErrorCode::InvalidState),
+    }
+}
+
+/// The controller runs with the default limits as well, see `MotorController::new`
+fn setpoint_limits() -> ProtectionLimits {
+    ProtectionLimits::default()
+}
+
+/// NaN, infinite or out of limit setpoints never reach the control loop
+fn send_setpoint(
+    channel: &ControlCommandChannel,
+    values: &[f32],
+    within_limits: bool,
+    command: ControlCommand,
+) -> Event {
+    if !values.iter().all(|value| value.is_finite()) || !within_limits {
+        return Event::Failure(ErrorCode::InvalidArgument);
+    }
+    if !arming_state().is_armed() {
+        return Event::Failure(ErrorCode::NotArmed);
+    }
+    send_control_command(channel, command)
+}
+
+fn to_event(result: Result<Event, ErrorCode>) -> Event {
+    result.unwrap_or_else(Event::Failure)
+}
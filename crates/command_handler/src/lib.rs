@@ -1,4 +1,11 @@
 #![no_std]
 
+pub mod black_box;
+pub mod blob;
+pub mod boot_health;
 pub mod handler;
+pub mod log_forwarding;
+pub mod scope;
 pub mod telemetry;
+
+pub use firmware_update;
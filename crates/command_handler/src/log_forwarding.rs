@@ -0,0 +1,37 @@
+use logging::sink::{self, Level};
+use transport::Event;
+use transport::event::ErrorCode;
+use transport::log::{LogLevel, LogRecord};
+
+pub fn set_min_level(level: LogLevel) -> Event {
+    let level = match level {
+        LogLevel::Unknown => return Event::Failure(ErrorCode::InvalidArgument),
+        LogLevel::Trace => Some(Level::Trace),
+        LogLevel::Debug => Some(Level::Debug),
+        LogLevel::Info => Some(Level::Info),
+        LogLevel::Warn => Some(Level::Warn),
+        LogLevel::Error => Some(Level::Error),
+        LogLevel::Off => None,
+    };
+    sink::set_min_level(level);
+    Event::Success
+}
+
+/// Arguments that do not fit into the record are dropped
+pub fn map_record(record: &sink::LogRecord) -> LogRecord {
+    let mut mapped = LogRecord::new(map_level(record.level), record.timestamp, record.message);
+    for arg in record.args() {
+        mapped.push_arg(arg);
+    }
+    mapped
+}
+
+fn map_level(level: Level) -> LogLevel {
+    match level {
+        Level::Trace => LogLevel::Trace,
+        Level::Debug => LogLevel::Debug,
+        Level::Info => LogLevel::Info,
+        Level::Warn => LogLevel::Warn,
+        Level::Error => LogLevel::Error,
+    }
+}
@@ -0,0 +1,141 @@
+use crate::blob::Blob;
+use controller_shared::scope::{self, Scope};
+use core::sync::atomic::Ordering;
+use transport::Event;
+use transport::blob::BlobId;
+use transport::event::ErrorCode;
+use transport::scope::{
+    SCOPE_BLOB_ID, ScopeChannels, ScopeConfig, ScopeSignal, ScopeState, ScopeStatus, TriggerMode,
+};
+
+/// Serves the last complete capture as little endian `f32` samples
+pub struct ScopeBlob;
+
+pub fn configure(config: ScopeConfig) -> Event {
+    let config = scope::ScopeConfig {
+        channels: config.channels.0.map(|signal| signal.map(map_signal)),
+        trigger: scope::Trigger {
+            mode: map_trigger_mode(config.trigger.mode),
+            channel: config.trigger.channel as usize,
+            level: config.trigger.level,
+        },
+        length: config.length as usize,
+        pre_trigger: config.pre_trigger as usize,
+        decimation: config.decimation as u32,
+    };
+    match scope::scope().lock(|scope| scope.borrow_mut().configure(config)) {
+        Ok(()) => Event::Success,
+        Err(_) => Event::Failure(ErrorCode::InvalidArgument),
+    }
+}
+
+/// Only an armed scope can be triggered, a triggered one is left alone
+pub fn trigger() -> Event {
+    scope::scope().lock(|scope| {
+        let mut scope = scope.borrow_mut();
+        match scope.state() {
+            scope::ScopeState::Armed => {
+                scope.trigger();
+                Event::Success
+            }
+            scope::ScopeState::Triggered => Event::Success,
+            scope::ScopeState::Idle | scope::ScopeState::Done => {
+                Event::Failure(ErrorCode::InvalidState)
+            }
+        }
+    })
+}
+
+pub fn report() -> Event {
+    let foc_loop_frequency = controller_shared::state::state()
+        .foc_loop_frequency
+        .load(Ordering::Relaxed);
+    scope::scope().lock(|scope| {
+        let scope = scope.borrow();
+        let config = scope.config();
+        Event::ScopeStatus(ScopeStatus {
+            state: map_state(scope.state()),
+            channels: ScopeChannels(config.channels.map(|signal| signal.map(map_scope_signal))),
+            length: config.length as u16,
+            pre_trigger: config.pre_trigger as u16,
+            sample_rate: foc_loop_frequency as f32 / config.decimation as f32,
+        })
+    })
+}
+
+impl Blob for ScopeBlob {
+    fn id(&self) -> BlobId {
+        SCOPE_BLOB_ID
+    }
+
+    fn size(&self) -> u32 {
+        scope::scope().lock(|scope| scope.borrow().captured_len() * size_of::<f32>()) as u32
+    }
+
+    fn read(&self, offset: u32, buffer: &mut [u8]) -> Result<usize, ErrorCode> {
+        scope::scope().lock(|scope| read_capture(&scope.borrow(), offset as usize, buffer))
+    }
+}
+
+/// Chunks do not have to start at a sample, so the capture is read byte by byte
+fn read_capture(scope: &Scope, offset: usize, buffer: &mut [u8]) -> Result<usize, ErrorCode> {
+    if scope.state() != scope::ScopeState::Done {
+        return Err(ErrorCode::InvalidState);
+    }
+    for (position, byte) in (offset..).zip(buffer.iter_mut()) {
+        let sample = scope
+            .captured_sample(position / size_of::<f32>())
+            .ok_or(ErrorCode::InvalidArgument)?;
+        *byte = sample.to_le_bytes()[position % size_of::<f32>()];
+    }
+    Ok(buffer.len())
+}
+
+fn map_signal(signal: ScopeSignal) -> scope::ScopeSignal {
+    match signal {
+        ScopeSignal::PhaseCurrentU => scope::ScopeSignal::PhaseCurrentU,
+        ScopeSignal::PhaseCurrentV => scope::ScopeSignal::PhaseCurrentV,
+        ScopeSignal::PhaseCurrentW => scope::ScopeSignal::PhaseCurrentW,
+        ScopeSignal::CurrentD => scope::ScopeSignal::CurrentD,
+        ScopeSignal::CurrentQ => scope::ScopeSignal::CurrentQ,
+        ScopeSignal::VoltageD => scope::ScopeSignal::VoltageD,
+        ScopeSignal::VoltageQ => scope::ScopeSignal::VoltageQ,
+        ScopeSignal::ElectricalAngle => scope::ScopeSignal::ElectricalAngle,
+        ScopeSignal::Velocity => scope::ScopeSignal::Velocity,
+        ScopeSignal::BusVoltage => scope::ScopeSignal::BusVoltage,
+    }
+}
+
+fn map_scope_signal(signal: scope::ScopeSignal) -> ScopeSignal {
+    match signal {
+        scope::ScopeSignal::PhaseCurrentU => ScopeSignal::PhaseCurrentU,
+        scope::ScopeSignal::PhaseCurrentV => ScopeSignal::PhaseCurrentV,
+        scope::ScopeSignal::PhaseCurrentW => ScopeSignal::PhaseCurrentW,
+        scope::ScopeSignal::CurrentD => ScopeSignal::CurrentD,
+        scope::ScopeSignal::CurrentQ => ScopeSignal::CurrentQ,
+        scope::ScopeSignal::VoltageD => ScopeSignal::VoltageD,
+        scope::ScopeSignal::VoltageQ => ScopeSignal::VoltageQ,
+        scope::ScopeSignal::ElectricalAngle => ScopeSignal::ElectricalAngle,
+        scope::ScopeSignal::Velocity => ScopeSignal::Velocity,
+        scope::ScopeSignal::BusVoltage => ScopeSignal::BusVoltage,
+    }
+}
+
+fn map_trigger_mode(mode: TriggerMode) -> scope::TriggerMode {
+    match mode {
+        TriggerMode::Manual => scope::TriggerMode::Manual,
+        TriggerMode::RisingEdge => scope::TriggerMode::RisingEdge,
+        TriggerMode::FallingEdge => scope::TriggerMode::FallingEdge,
+        TriggerMode::AboveLevel => scope::TriggerMode::AboveLevel,
+        TriggerMode::BelowLevel => scope::TriggerMode::BelowLevel,
+    }
+}
+
+fn map_state(state: scope::ScopeState) -> ScopeState {
+    match state {
+        scope::ScopeState::Idle => ScopeState::Idle,
+        scope::ScopeState::Armed => ScopeState::Armed,
+        scope::ScopeState::Triggered => ScopeState::Triggered,
+        scope::ScopeState::Done => ScopeState::Done,
+    }
+}
@@ -1,11 +1,87 @@
+use controller_shared::arming::{self, arming_state};
 use core::sync::atomic::Ordering;
-use embassy_time::Instant;
-use logging::fault_register::FaultRegister;
-use transport::event::Telemetry;
+use embassy_time::{Duration, Instant};
+use logging::fault_register::{FaultRegister, FaultState};
+use transport::Event;
+use transport::decoder::DecoderStats;
+use transport::event::{ArmingState, ErrorCode, Telemetry};
+use transport::telemetry::{
+    MAX_TELEMETRY_RATE_HZ, TelemetryField, TelemetryFields, TelemetrySample, TelemetrySubscription,
+    TelemetryValue,
+};
+use units::si::angle::radian;
+use units::si::angular_velocity::radian_per_second;
+use units::si::electric_current::ampere;
 use units::si::electric_potential::volt;
 use units::si::thermodynamic_temperature::kelvin;
 
-pub fn get_telemetry() -> Telemetry {
+/// Rate of the [`Telemetry`] a link sends until the host subscribes
+const DEFAULT_TELEMETRY_RATE_HZ: u64 = 10;
+
+/// Telemetry of a single link, each link subscribes on its own
+pub struct TelemetryStream {
+    /// `None` until the host subscribes, [`Telemetry`] is sent in the meantime
+    fields: Option<TelemetryFields>,
+    /// `None` while the link streams nothing
+    period: Option<Duration>,
+    next: Instant,
+}
+
+impl Default for TelemetryStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TelemetryStream {
+    pub fn new() -> Self {
+        Self {
+            fields: None,
+            period: Some(Duration::from_hz(DEFAULT_TELEMETRY_RATE_HZ)),
+            next: Instant::now(),
+        }
+    }
+
+    pub fn subscribe(&mut self, subscription: TelemetrySubscription) -> Result<(), ErrorCode> {
+        if subscription.rate_hz > MAX_TELEMETRY_RATE_HZ {
+            return Err(ErrorCode::InvalidArgument);
+        }
+        self.fields = Some(subscription.fields);
+        self.period =
+            (subscription.rate_hz != 0).then(|| Duration::from_hz(subscription.rate_hz as u64));
+        self.next = Instant::now();
+        Ok(())
+    }
+
+    /// When the next telemetry is due, `None` while the link streams nothing
+    pub fn next_due(&self) -> Option<Instant> {
+        self.period.map(|_| self.next)
+    }
+
+    /// Returns the telemetry to send if it is due and schedules the next one
+    pub fn poll(
+        &mut self,
+        now: Instant,
+        usb_link: DecoderStats,
+        serial_link: DecoderStats,
+    ) -> Option<Event> {
+        let period = self.period?;
+        if now < self.next {
+            return None;
+        }
+        // Samples missed by a busy link are skipped instead of being sent in a burst
+        self.next += period;
+        if self.next < now {
+            self.next = now + period;
+        }
+        Some(match self.fields {
+            Some(fields) => Event::TelemetrySample(get_telemetry_sample(fields)),
+            None => Event::Telemetry(get_telemetry(usb_link, serial_link)),
+        })
+    }
+}
+
+pub fn get_telemetry(usb_link: DecoderStats, serial_link: DecoderStats) -> Telemetry {
     let controller_state = controller_shared::state::state();
     Telemetry {
         cpu_temperature: controller_state
@@ -21,5 +97,84 @@ pub fn get_telemetry() -> Telemetry {
         uptime: Instant::now().as_millis(),
         active_faults: FaultRegister::shared().active_count() as u32,
         latched_faults: FaultRegister::shared().latched_count() as u32,
+        usb_link,
+        serial_link,
+        arming_state: map_arming_state(arming_state()),
+    }
+}
+
+pub fn get_telemetry_sample(fields: TelemetryFields) -> TelemetrySample {
+    let mut sample = TelemetrySample::new(Instant::now().as_micros());
+    for field in fields.iter() {
+        sample.insert(read_value(field));
+    }
+    sample
+}
+
+fn read_value(field: TelemetryField) -> TelemetryValue {
+    let state = controller_shared::state::state();
+    match field {
+        TelemetryField::PhaseCurrents => TelemetryValue::PhaseCurrents([
+            state.i_u.load(Ordering::Relaxed).get::<ampere>(),
+            state.i_v.load(Ordering::Relaxed).get::<ampere>(),
+            state.i_w.load(Ordering::Relaxed).get::<ampere>(),
+        ]),
+        TelemetryField::DqCurrents => TelemetryValue::DqCurrents([
+            state.i_d.load(Ordering::Relaxed).get::<ampere>(),
+            state.i_q.load(Ordering::Relaxed).get::<ampere>(),
+        ]),
+        TelemetryField::DqVoltages => TelemetryValue::DqVoltages([
+            state.v_d.load(Ordering::Relaxed).get::<volt>(),
+            state.v_q.load(Ordering::Relaxed).get::<volt>(),
+        ]),
+        TelemetryField::ElectricalAngle => TelemetryValue::ElectricalAngle(
+            state
+                .electrical_angle
+                .load(Ordering::Relaxed)
+                .get::<radian>(),
+        ),
+        TelemetryField::Velocity => TelemetryValue::Velocity(
+            state
+                .velocity
+                .load(Ordering::Relaxed)
+                .get::<radian_per_second>(),
+        ),
+        TelemetryField::BusVoltage => {
+            TelemetryValue::BusVoltage(state.v_bus.load(Ordering::Relaxed).get::<volt>())
+        }
+        TelemetryField::Temperatures => TelemetryValue::Temperatures([
+            state.cpu_temp.load(Ordering::Relaxed).get::<kelvin>(),
+            0.0, // TODO add driver temperature
+            0.0, // TODO add motor temperature
+        ]),
+        TelemetryField::LoopTiming => TelemetryValue::LoopTiming([
+            state.foc_loop_frequency.load(Ordering::Relaxed),
+            state.last_foc_loop_time_us.load(Ordering::Relaxed) as u32,
+        ]),
+        TelemetryField::Faults => TelemetryValue::Faults(fault_bits()),
+    }
+}
+
+/// Bit `n` stands for the fault type `n`, active faults first, then latched ones
+fn fault_bits() -> [u32; 2] {
+    let mut bits = [0; 2];
+    for (i, state) in FaultRegister::shared().snapshot().into_iter().enumerate() {
+        match state {
+            FaultState::Clean => {}
+            FaultState::Active => bits[0] |= 1 << i,
+            FaultState::Latched => bits[1] |= 1 << i,
+        }
+    }
+    bits
+}
+
+fn map_arming_state(state: arming::ArmingState) -> ArmingState {
+    match state {
+        arming::ArmingState::Boot => ArmingState::Boot,
+        arming::ArmingState::Idle => ArmingState::Idle,
+        arming::ArmingState::Calibrating => ArmingState::Calibrating,
+        arming::ArmingState::Armed => ArmingState::Armed,
+        arming::ArmingState::Running => ArmingState::Running,
+        arming::ArmingState::Fault => ArmingState::Fault,
     }
 }
@@ -1,46 +1,136 @@
 use crate::channel_types::{CommandChannel, EventChannel};
+use crate::framing::{Decoder, Encoder, MAX_FRAME_SIZE};
 use crate::packet::{Interface, Packet, split_into_packets};
+use command_handler::blob::BlobTransfers;
+use command_handler::firmware_update::FirmwareTarget;
 use command_handler::handler::execute_command;
-use command_handler::telemetry::get_telemetry;
+use command_handler::log_forwarding;
+use command_handler::telemetry::TelemetryStream;
 use controller_shared::command::ControlCommandChannel;
+use core::sync::atomic::Ordering;
 use crc_engine::CrcEngine;
-use embassy_futures::select::{Either, select};
+use embassy_futures::select::{Either4, select4};
 use embassy_sync::pubsub::PubSubBehavior;
-use embassy_time::Duration;
-use logging::{error, warn};
-use transport::Event;
+use embassy_time::{Duration, Instant, Timer};
+use logging::fault_register::{self, FaultChange, FaultState};
+use logging::{error, sink, warn};
 use transport::command::Error;
-use transport::command::decoder::Decoder;
 use transport::decoder::DecoderError;
-use transport::event::encoder::Encoder;
+use transport::event;
+use transport::frame::{Frame, FrameKind, Header, SequenceCounter};
+use transport::log::LogLevel;
+use transport::reliable::ResponseCache;
+use transport::{Command, Event};
+
+/// A frame that stalls for longer than this is considered lost
+const INTER_BYTE_TIMEOUT: Duration = Duration::from_millis(100);
+
+struct Link {
+    decoder: Decoder,
+    last_received: Instant,
+    sequence: SequenceCounter,
+    responses: ResponseCache<Command>,
+    blobs: BlobTransfers,
+    telemetry: TelemetryStream,
+    /// Set by SetLogLevel, log records go out only on links that asked for them
+    forward_logs: bool,
+}
+
+impl Link {
+    fn new() -> Self {
+        Self {
+            decoder: Decoder::new(),
+            last_received: Instant::now(),
+            sequence: SequenceCounter::new(),
+            responses: ResponseCache::new(),
+            blobs: BlobTransfers::new(),
+            telemetry: TelemetryStream::new(),
+            forward_logs: false,
+        }
+    }
+
+    fn check_timeout(&mut self) {
+        let now = Instant::now();
+        if now - self.last_received > INTER_BYTE_TIMEOUT && self.decoder.timeout() {
+            warn!("Dropped a partially received frame after an inter-byte timeout");
+        }
+        self.last_received = now;
+    }
+}
 
 pub async fn run(
     command_channel: &'static CommandChannel,
     event_channel: &'static EventChannel,
     control_command_channel: &'static ControlCommandChannel,
     crc: &mut impl CrcEngine,
+    firmware: &mut dyn FirmwareTarget,
 ) {
-    let mut telemetry_ticker = embassy_time::Ticker::every(Duration::from_hz(10));
-    let mut usb_decoder = Decoder::new();
-    let mut serial_decoder = Decoder::new();
+    let mut usb_link = Link::new();
+    let mut serial_link = Link::new();
     let encoder = Encoder::new();
-    let mut encoding_buffer = [0u8; transport::MAX_PACKET_SIZE];
+    let mut encoding_buffer = [0u8; MAX_FRAME_SIZE];
     let receiver = command_channel.receiver();
     loop {
-        match select(telemetry_ticker.next(), receiver.receive()).await {
-            Either::First(_) => {
-                broadcast_telemetry(&encoder, &mut encoding_buffer, crc, event_channel).await;
+        // Each link streams at its own rate, the timer wakes up for whichever is due first
+        let next_telemetry = [&usb_link, &serial_link]
+            .into_iter()
+            .filter_map(|link| link.telemetry.next_due())
+            .min()
+            .unwrap_or(Instant::MAX);
+        match select4(
+            Timer::at(next_telemetry),
+            receiver.receive(),
+            sink::receive(),
+            fault_register::next_change(),
+        )
+        .await
+        {
+            Either4::First(_) => {
+                send_telemetry(
+                    &encoder,
+                    &mut encoding_buffer,
+                    crc,
+                    event_channel,
+                    &mut usb_link,
+                    &mut serial_link,
+                )
+                .await;
             }
-            Either::Second(incoming_packet) => {
+            Either4::Second(incoming_packet) => {
                 handle_incoming_packet(
                     event_channel,
                     crc,
-                    &mut usb_decoder,
-                    &mut serial_decoder,
+                    &mut usb_link,
+                    &mut serial_link,
                     &encoder,
                     &mut encoding_buffer,
                     &incoming_packet,
                     control_command_channel,
+                    firmware,
+                )
+                .await;
+            }
+            Either4::Third(record) => {
+                send_unsolicited(
+                    &encoder,
+                    &mut encoding_buffer,
+                    crc,
+                    event_channel,
+                    [&mut usb_link, &mut serial_link],
+                    &Event::Log(log_forwarding::map_record(&record)),
+                    |link| link.forward_logs,
+                )
+                .await;
+            }
+            Either4::Fourth(change) => {
+                send_unsolicited(
+                    &encoder,
+                    &mut encoding_buffer,
+                    crc,
+                    event_channel,
+                    [&mut usb_link, &mut serial_link],
+                    &map_fault_change(change),
+                    |_| true,
                 )
                 .await;
             }
@@ -52,16 +142,17 @@ pub async fn run(
 async fn handle_incoming_packet(
     event_channel: &EventChannel,
     crc: &mut impl CrcEngine,
-    usb_decoder: &mut Decoder,
-    serial_decoder: &mut Decoder,
+    usb_link: &mut Link,
+    serial_link: &mut Link,
     encoder: &Encoder,
     encoding_buffer: &mut [u8],
     incoming_packet: &Packet,
     control_command_channel: &ControlCommandChannel,
+    firmware: &mut dyn FirmwareTarget,
 ) {
-    let decoder = match &incoming_packet.interface {
-        Some(Interface::Serial) => serial_decoder,
-        Some(Interface::Usb) => usb_decoder,
+    let link = match &incoming_packet.interface {
+        Some(Interface::Serial) => serial_link,
+        Some(Interface::Usb) => usb_link,
         None => {
             warn!(
                 "Received a broadcast packet without an interface specified - broadcasts are output-only"
@@ -69,35 +160,145 @@ async fn handle_incoming_packet(
             return;
         }
     };
+    link.check_timeout();
     for &byte in &incoming_packet.buffer[..incoming_packet.length] {
-        match decoder.feed(byte, crc) {
-            Some(Ok(command)) => {
-                let event = execute_command(command, control_command_channel).await;
-                let length = encoder.encode(&event, encoding_buffer, crc);
-                for packet in
-                    split_into_packets(&encoding_buffer[..length], incoming_packet.interface)
-                {
-                    event_channel.publish_immediate(packet);
+        let mut decoded = link.decoder.feed(byte, crc);
+        while let Some(result) = decoded {
+            match result {
+                Ok(frame) => {
+                    let response = handle_request(
+                        frame,
+                        link,
+                        encoder,
+                        encoding_buffer,
+                        crc,
+                        control_command_channel,
+                        firmware,
+                    )
+                    .await;
+                    if let Some(response) = response {
+                        for packet in split_into_packets(response, incoming_packet.interface) {
+                            event_channel.publish_immediate(packet);
+                        }
+                    }
+                }
+                Err(error) => {
+                    handle_error(error).await;
                 }
             }
-            Some(Err(error)) => {
-                handle_error(error).await;
-            }
-            None => {} // Still parsing
+            decoded = link.decoder.poll(crc);
         }
     }
 }
 
-async fn broadcast_telemetry(
+/// Executes a request and returns its encoded response
+async fn handle_request<'a>(
+    frame: Frame<Command>,
+    link: &'a mut Link,
+    encoder: &Encoder,
+    encoding_buffer: &'a mut [u8],
+    crc: &mut impl CrcEngine,
+    control_command_channel: &ControlCommandChannel,
+    firmware: &mut dyn FirmwareTarget,
+) -> Option<&'a [u8]> {
+    if frame.header.kind != FrameKind::Request {
+        warn!("Ignoring a frame that is not a request: {:?}", frame.header);
+        return None;
+    }
+    if frame.header.reliable && link.responses.replay(&frame).is_some() {
+        warn!(
+            "Request {} was retransmitted, replaying its response",
+            frame.header.sequence
+        );
+        return link.responses.replay(&frame);
+    }
+
+    let request = frame.header.reliable.then(|| frame.clone());
+    let log_level = match frame.packet {
+        Command::SetLogLevel(level) => Some(level),
+        _ => None,
+    };
+    let event = execute_command(
+        frame.packet,
+        control_command_channel,
+        &mut link.blobs,
+        &mut link.telemetry,
+        firmware,
+        MAX_FRAME_SIZE as u16,
+    )
+    .await;
+    controller_shared::state::state()
+        .requests_handled
+        .fetch_add(1, Ordering::Relaxed);
+    if let Some(level) = log_level
+        && event == Event::Success
+    {
+        link.forward_logs = level != LogLevel::Off;
+    }
+    let header = Header::response(link.sequence.advance(), frame.header.sequence);
+    let length = encoder.encode(header, &event, encoding_buffer, crc);
+    if let Some(request) = request {
+        link.responses.store(request, &encoding_buffer[..length]);
+    }
+    Some(&encoding_buffer[..length])
+}
+
+/// Sends the telemetry of every link that is due
+async fn send_telemetry(
     encoder: &Encoder,
     encoding_buffer: &mut [u8],
     crc: &mut impl CrcEngine,
     event_channel: &EventChannel,
+    usb_link: &mut Link,
+    serial_link: &mut Link,
 ) {
-    let telemetry = get_telemetry();
-    let length = encoder.encode(&Event::Telemetry(telemetry), encoding_buffer, crc);
-    for packet in split_into_packets(&encoding_buffer[..length], None) {
-        event_channel.publish_immediate(packet);
+    let now = Instant::now();
+    let usb_stats = usb_link.decoder.stats();
+    let serial_stats = serial_link.decoder.stats();
+    // Each link numbers its frames on its own
+    for (link, interface) in [(usb_link, Interface::Usb), (serial_link, Interface::Serial)] {
+        let Some(telemetry) = link.telemetry.poll(now, usb_stats, serial_stats) else {
+            continue;
+        };
+        let header = Header::unsolicited(link.sequence.advance());
+        let length = encoder.encode(header, &telemetry, encoding_buffer, crc);
+        for packet in split_into_packets(&encoding_buffer[..length], Some(interface)) {
+            event_channel.publish_immediate(packet);
+        }
+    }
+}
+
+/// Sends an event to every link `to_link` accepts, the links are USB and serial in this order
+async fn send_unsolicited(
+    encoder: &Encoder,
+    encoding_buffer: &mut [u8],
+    crc: &mut impl CrcEngine,
+    event_channel: &EventChannel,
+    links: [&mut Link; 2],
+    event: &Event,
+    to_link: impl Fn(&Link) -> bool,
+) {
+    for (link, interface) in links.into_iter().zip([Interface::Usb, Interface::Serial]) {
+        if !to_link(link) {
+            continue;
+        }
+        let header = Header::unsolicited(link.sequence.advance());
+        let length = encoder.encode(header, event, encoding_buffer, crc);
+        for packet in split_into_packets(&encoding_buffer[..length], Some(interface)) {
+            event_channel.publish_immediate(packet);
+        }
+    }
+}
+
+fn map_fault_change(change: FaultChange) -> Event {
+    let change = event::FaultChange {
+        fault: change.fault,
+        state: change.state,
+        timestamp: change.timestamp,
+    };
+    match change.state {
+        FaultState::Active => Event::FaultRaised(change),
+        FaultState::Latched | FaultState::Clean => Event::FaultCleared(change),
     }
 }
 
@@ -0,0 +1,16 @@
+#[cfg(not(feature = "cobs"))]
+pub use transport::command::decoder::Decoder;
+#[cfg(not(feature = "cobs"))]
+pub use transport::event::encoder::Encoder;
+
+/// Byte stuffed frames resynchronise on the next delimiter, which suits noisy UART links.
+/// The host has to be configured for the same framing.
+#[cfg(feature = "cobs")]
+pub use transport::command::decoder::CobsDecoder as Decoder;
+#[cfg(feature = "cobs")]
+pub use transport::event::encoder::CobsEncoder as Encoder;
+
+#[cfg(not(feature = "cobs"))]
+pub const MAX_FRAME_SIZE: usize = transport::MAX_PACKET_SIZE;
+#[cfg(feature = "cobs")]
+pub const MAX_FRAME_SIZE: usize = transport::cobs::MAX_FRAME_SIZE;
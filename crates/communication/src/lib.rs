@@ -2,6 +2,7 @@
 
 pub mod channel_types;
 mod communication_handler;
+mod framing;
 pub mod packet;
 
 pub use communication_handler::run;
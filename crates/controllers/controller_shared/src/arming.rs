@@ -0,0 +1,450 @@
+use crate::command::ControlCommand;
+use crate::converters::ConfigValues;
+use crate::core::{control_step, update_strategy};
+use crate::io::{RawInverterValues, RawSnapshot};
+use crate::motion::VelocityEstimator;
+use crate::policy::{FaultPolicies, FaultRecovery, FaultResponse};
+use crate::protection::{self, ProtectionLimits};
+use crate::strategy::ControlStrategy;
+use core::sync::atomic::Ordering;
+use embassy_time::{Duration, Instant};
+use logging::fault_register::{FaultRegister, FaultType};
+
+/// Phase current samples averaged to find the zero offset of the current sensors
+const CALIBRATION_SAMPLES: u32 = 1024;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ArmingState {
+    /// Waiting for the first measurements
+    Boot = 0,
+    Idle = 1,
+    /// Outputs are off while the current sensor offsets are measured
+    Calibrating = 2,
+    /// Setpoints are accepted, the motor is not driven yet
+    Armed = 3,
+    Running = 4,
+    /// Disarmed by a fault that stops the motor, goes back to idle once it no longer does
+    Fault = 5,
+}
+
+impl From<u8> for ArmingState {
+    fn from(v: u8) -> Self {
+        match v {
+            0 => ArmingState::Boot,
+            1 => ArmingState::Idle,
+            2 => ArmingState::Calibrating,
+            3 => ArmingState::Armed,
+            4 => ArmingState::Running,
+            5 => ArmingState::Fault,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl ArmingState {
+    /// Setpoints are only accepted in these states
+    pub fn is_armed(self) -> bool {
+        matches!(self, ArmingState::Armed | ArmingState::Running)
+    }
+}
+
+/// State of the [`MotorController`] driving the motor
+pub fn arming_state() -> ArmingState {
+    crate::state::state()
+        .arming_state
+        .load(Ordering::Relaxed)
+        .into()
+}
+
+/// Owns the control strategy, the motor is only driven after an explicit arm request.
+///
+/// ```text
+/// Boot -> Idle -> Calibrating -> Armed <-> Running
+///          ^                       |          |
+///          +------- Disarm --------+----------+
+/// ```
+///
+/// A fault whose [`FaultPolicies`] entry stops the motor forces `Fault`, which turns into `Idle`
+/// once no fault stops it anymore.
+pub struct MotorController {
+    state: ArmingState,
+    strategy: ControlStrategy,
+    config: ConfigValues,
+    limits: ProtectionLimits,
+    policies: FaultPolicies,
+    recovery: FaultRecovery,
+    /// Taken by [`Self::update`] from the raised faults
+    fault_response: FaultResponse,
+    calibration: Calibration,
+    velocity_estimator: VelocityEstimator,
+    /// Set by [`Self::update`], [`Self::step`] takes the time of the command from it
+    command_received: bool,
+    last_command: Instant,
+}
+
+#[derive(Default)]
+struct Calibration {
+    samples: u32,
+    sum: u32,
+}
+
+impl Default for MotorController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MotorController {
+    pub fn new() -> Self {
+        Self::with_limits(ProtectionLimits::default())
+    }
+
+    pub fn with_limits(limits: ProtectionLimits) -> Self {
+        Self {
+            state: ArmingState::Boot,
+            strategy: ControlStrategy::Disabled,
+            config: ConfigValues::default(),
+            limits,
+            policies: FaultPolicies::default(),
+            recovery: FaultRecovery::default(),
+            fault_response: FaultResponse::Run,
+            calibration: Calibration::default(),
+            velocity_estimator: VelocityEstimator::default(),
+            command_received: false,
+            last_command: Instant::from_ticks(0),
+        }
+    }
+
+    pub fn with_policies(mut self, policies: FaultPolicies) -> Self {
+        self.policies = policies;
+        self
+    }
+
+    pub fn state(&self) -> ArmingState {
+        self.state
+    }
+
+    /// Applies a command and reacts to the raised faults, call it before every [`Self::step`]
+    pub fn update(&mut self, command: Option<ControlCommand>, faults: &FaultRegister) {
+        self.command_received |= command.is_some();
+        if let Some(ControlCommand::ResetFaults) = command {
+            self.policies.reset(faults);
+        }
+        self.fault_response = self.policies.evaluate(faults);
+        if self.fault_response.stops() {
+            if self.state != ArmingState::Boot {
+                self.disarm(ArmingState::Fault);
+            }
+            return;
+        }
+        if self.state == ArmingState::Fault {
+            self.set_state(ArmingState::Idle);
+        }
+
+        let Some(command) = command else {
+            return;
+        };
+        match command {
+            ControlCommand::Arm => {
+                if self.state == ArmingState::Idle {
+                    self.calibration = Calibration::default();
+                    self.set_state(ArmingState::Calibrating);
+                }
+            }
+            ControlCommand::Disarm => {
+                if self.state != ArmingState::Boot {
+                    self.disarm(ArmingState::Idle);
+                }
+            }
+            ControlCommand::ResetFaults => {}
+            ControlCommand::DisableMotor => {
+                self.strategy = ControlStrategy::Disabled;
+                if self.state == ArmingState::Running {
+                    self.set_state(ArmingState::Armed);
+                }
+            }
+            setpoint => {
+                if self.state.is_armed() {
+                    let strategy =
+                        core::mem::replace(&mut self.strategy, ControlStrategy::Disabled);
+                    self.strategy = update_strategy(setpoint, strategy, &self.config);
+                    self.set_state(ArmingState::Running);
+                }
+            }
+        }
+    }
+
+    /// Runs one control step, the outputs stay off unless the state is `Running` or a fault
+    /// brakes the motor
+    pub fn step(
+        &mut self,
+        raw_snapshot: &Option<RawSnapshot>,
+        now: Instant,
+    ) -> Option<RawInverterValues> {
+        if let Some(values) = raw_snapshot {
+            match self.state {
+                ArmingState::Boot => self.set_state(ArmingState::Idle),
+                ArmingState::Calibrating => self.calibrate(values),
+                _ => {}
+            }
+        }
+        self.check_timeouts(raw_snapshot.is_none(), now);
+        self.recovery
+            .update(&self.policies, FaultRegister::shared(), now);
+        control_step(
+            raw_snapshot,
+            &mut self.strategy,
+            &mut self.velocity_estimator,
+            now,
+            &self.config,
+            &self.limits,
+            self.fault_response,
+        )
+    }
+
+    /// Call it with the time the last step took, raises `LoopOverrun` if it was too long
+    pub fn check_loop_time(&self, elapsed: Duration) {
+        self.limits
+            .check_loop_time(elapsed, FaultRegister::shared());
+    }
+
+    fn check_timeouts(&mut self, adc_timed_out: bool, now: Instant) {
+        let faults = FaultRegister::shared();
+        protection::set_if(faults, FaultType::AdcTimeout, adc_timed_out);
+
+        if core::mem::take(&mut self.command_received) {
+            self.last_command = now;
+        }
+        let command_timed_out = self.state == ArmingState::Running
+            && self
+                .limits
+                .command_timeout
+                .is_some_and(|timeout| now - self.last_command > timeout);
+        protection::set_if(faults, FaultType::CommunicationTimeout, command_timed_out);
+    }
+
+    fn calibrate(&mut self, values: &RawSnapshot) {
+        let calibration = &mut self.calibration;
+        calibration.sum += values.i_u as u32 + values.i_v as u32 + values.i_w as u32;
+        calibration.samples += 1;
+        if calibration.samples == CALIBRATION_SAMPLES {
+            self.config.current_zero_offset = (calibration.sum / (3 * CALIBRATION_SAMPLES)) as i32;
+            self.set_state(ArmingState::Armed);
+        }
+    }
+
+    fn disarm(&mut self, state: ArmingState) {
+        self.strategy = ControlStrategy::Disabled;
+        self.set_state(state);
+    }
+
+    fn set_state(&mut self, state: ArmingState) {
+        self.state = state;
+        crate::state::state()
+            .arming_state
+            .store(state as u8, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use logging::fault_register::FaultState;
+    use units::{DutyCycle, ElectricCurrent, F32UnitType};
+
+    fn snapshot(current: u16) -> Option<RawSnapshot> {
+        Some(RawSnapshot {
+            i_u: current,
+            i_v: current,
+            i_w: current,
+            v_u: 0,
+            v_v: 0,
+            v_w: 0,
+            v_ref: 1550,
+            v_bus: 2000,
+            temp_cpu: 0,
+            temp_motor: 0,
+            temp_driver: 0,
+            analog_input: 0,
+            max_duty: 1000,
+            angle: 0,
+        })
+    }
+
+    fn set_current() -> ControlCommand {
+        ControlCommand::SetCurrent {
+            d: ElectricCurrent::from_f32(0.0),
+            q: ElectricCurrent::from_f32(1.0),
+        }
+    }
+
+    fn armed_controller() -> MotorController {
+        arm(MotorController::new())
+    }
+
+    fn arm(mut controller: MotorController) -> MotorController {
+        controller.step(&snapshot(2048), Instant::from_millis(0));
+        controller.update(Some(ControlCommand::Arm), &FaultRegister::default());
+        for _ in 0..CALIBRATION_SAMPLES {
+            controller.step(&snapshot(2050), Instant::from_millis(0));
+        }
+        controller
+    }
+
+    #[test]
+    fn setpoints_should_be_ignored_until_armed() {
+        let mut controller = MotorController::new();
+        controller.step(&snapshot(2048), Instant::from_millis(0));
+        assert_eq!(controller.state(), ArmingState::Idle);
+
+        controller.update(Some(set_current()), &FaultRegister::default());
+        assert_eq!(controller.state(), ArmingState::Idle);
+        assert!(
+            controller
+                .step(&snapshot(2048), Instant::from_millis(1))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn arming_should_calibrate_the_current_offset() {
+        let controller = armed_controller();
+        assert_eq!(controller.state(), ArmingState::Armed);
+        assert_eq!(controller.config.current_zero_offset, 2050);
+    }
+
+    #[test]
+    fn outputs_should_stay_off_while_calibrating() {
+        let mut controller = MotorController::new();
+        controller.step(&snapshot(2048), Instant::from_millis(0));
+        controller.update(Some(ControlCommand::Arm), &FaultRegister::default());
+        controller.update(Some(set_current()), &FaultRegister::default());
+        assert_eq!(controller.state(), ArmingState::Calibrating);
+        assert!(
+            controller
+                .step(&snapshot(2048), Instant::from_millis(1))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn setpoint_should_start_and_stop_should_end_running() {
+        let mut controller = armed_controller();
+        controller.update(
+            Some(ControlCommand::SetDutyCycle(DutyCycle::from(0.5))),
+            &FaultRegister::default(),
+        );
+        assert_eq!(controller.state(), ArmingState::Running);
+        assert!(
+            controller
+                .step(&snapshot(2050), Instant::from_millis(1))
+                .is_some()
+        );
+
+        controller.update(
+            Some(ControlCommand::DisableMotor),
+            &FaultRegister::default(),
+        );
+        assert_eq!(controller.state(), ArmingState::Armed);
+        assert!(
+            controller
+                .step(&snapshot(2050), Instant::from_millis(2))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn active_fault_should_disarm_until_it_clears() {
+        let mut controller = armed_controller();
+        let faults = FaultRegister::default();
+        controller.update(Some(set_current()), &faults);
+        faults.set(FaultType::AdcTimeout);
+        controller.update(None, &faults);
+        assert_eq!(controller.state(), ArmingState::Fault);
+        assert!(
+            controller
+                .step(&snapshot(2050), Instant::from_millis(1))
+                .is_none()
+        );
+
+        controller.update(Some(ControlCommand::Arm), &faults);
+        assert_eq!(controller.state(), ArmingState::Fault);
+
+        faults.resolve_if_set(FaultType::AdcTimeout);
+        controller.update(None, &faults);
+        assert_eq!(controller.state(), ArmingState::Idle);
+    }
+
+    #[test]
+    fn warning_should_keep_the_motor_running() {
+        let mut controller = armed_controller();
+        let faults = FaultRegister::default();
+        faults.set(FaultType::LoopOverrun);
+        controller.update(Some(set_current()), &faults);
+        assert_eq!(controller.state(), ArmingState::Running);
+    }
+
+    #[test]
+    fn lockout_should_need_a_reset_to_leave_fault() {
+        let mut controller = armed_controller();
+        let faults = FaultRegister::default();
+        faults.set(FaultType::OvercurrentU);
+        controller.update(None, &faults);
+        faults.resolve_if_set(FaultType::OvercurrentU);
+        controller.update(None, &faults);
+        assert_eq!(controller.state(), ArmingState::Fault);
+
+        controller.update(Some(ControlCommand::ResetFaults), &faults);
+        assert_eq!(controller.state(), ArmingState::Idle);
+    }
+
+    #[test]
+    fn brake_should_drive_the_outputs_while_stopped() {
+        let mut controller = armed_controller();
+        let faults = FaultRegister::default();
+        faults.set(FaultType::CommunicationTimeout);
+        controller.update(None, &faults);
+        let values = controller
+            .step(&snapshot(2050), Instant::from_millis(1))
+            .unwrap();
+        assert_eq!([values.u, values.v, values.w], [500; 3]);
+    }
+
+    #[test]
+    fn disarm_should_return_to_idle() {
+        let mut controller = armed_controller();
+        controller.update(Some(set_current()), &FaultRegister::default());
+        controller.update(Some(ControlCommand::Disarm), &FaultRegister::default());
+        assert_eq!(controller.state(), ArmingState::Idle);
+        assert!(
+            controller
+                .step(&snapshot(2050), Instant::from_millis(1))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn running_without_commands_should_time_out() {
+        let mut controller = arm(MotorController::with_limits(ProtectionLimits {
+            command_timeout: Some(Duration::from_millis(100)),
+            ..ProtectionLimits::default()
+        }));
+        controller.update(Some(set_current()), &FaultRegister::default());
+        controller.step(&snapshot(2050), Instant::from_millis(10));
+        controller.update(None, &FaultRegister::default());
+        controller.step(&snapshot(2050), Instant::from_millis(100));
+        let faults = FaultRegister::shared();
+        assert_ne!(
+            faults.load(FaultType::CommunicationTimeout),
+            FaultState::Active
+        );
+
+        controller.step(&snapshot(2050), Instant::from_millis(111));
+        assert_eq!(
+            faults.load(FaultType::CommunicationTimeout),
+            FaultState::Active
+        );
+    }
+}
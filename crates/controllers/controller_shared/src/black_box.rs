@@ -0,0 +1,257 @@
+use crate::strategy::ControlStrategy;
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::Instant;
+use foc::snapshot::FocOutput;
+use logging::fault_register::{FaultRegister, FaultState};
+use units::{Angle, ElectricCurrent, ElectricPotential, F32UnitType};
+
+/// Control steps kept before a fault, about 12 KiB of RAM
+pub const BLACK_BOX_FRAMES: usize = 256;
+
+pub type SharedBlackBox = Mutex<CriticalSectionRawMutex, RefCell<BlackBox>>;
+
+/// What [`BlackBoxFrame::setpoint`] holds depends on the strategy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StrategyKind {
+    /// Both setpoints are zero
+    Disabled,
+    /// d and q axis currents in amperes
+    Current,
+    /// Target speed in radians per second and the q axis current requested for it
+    Velocity,
+    /// Target angle in radians and the speed requested for it
+    Position,
+    /// Duty cycle on the q axis, the second setpoint is zero
+    DutyCycle,
+}
+
+/// State of the control loop after one step
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BlackBoxFrame {
+    /// Microseconds since boot
+    pub timestamp: u64,
+    /// u, v and w in amperes
+    pub phase_currents: [f32; 3],
+    pub v_bus: f32,
+    pub electrical_angle: f32,
+    /// u, v and w, zero while the outputs are off
+    pub duty_cycles: [f32; 3],
+    pub strategy: StrategyKind,
+    pub setpoint: [f32; 2],
+    /// Bit `n` is set while the fault type `n` is active
+    pub active_faults: u32,
+}
+
+/// Keeps the last control steps in a ring buffer and stops recording once a fault is raised, so
+/// the steps that led to it can be read afterwards
+pub struct BlackBox {
+    frames: [BlackBoxFrame; BLACK_BOX_FRAMES],
+    next_frame: usize,
+    recorded: usize,
+    /// Raised faults seen so far, see [`FaultRegister::raised_count`]
+    seen_faults: usize,
+    frozen: bool,
+}
+
+impl BlackBoxFrame {
+    const EMPTY: Self = Self {
+        timestamp: 0,
+        phase_currents: [0.0; 3],
+        v_bus: 0.0,
+        electrical_angle: 0.0,
+        duty_cycles: [0.0; 3],
+        strategy: StrategyKind::Disabled,
+        setpoint: [0.0; 2],
+        active_faults: 0,
+    };
+
+    pub(crate) fn new(
+        now: Instant,
+        phase_currents: [ElectricCurrent; 3],
+        v_bus: ElectricPotential,
+        electrical_angle: Angle,
+        output: Option<&FocOutput>,
+        strategy: &ControlStrategy,
+    ) -> Self {
+        let (strategy, setpoint) = strategy_setpoint(strategy);
+        Self {
+            timestamp: now.as_micros(),
+            phase_currents: phase_currents.map(F32UnitType::into_f32),
+            v_bus: v_bus.into_f32(),
+            electrical_angle: electrical_angle.into_f32(),
+            duty_cycles: output.map_or([0.0; 3], |output| {
+                [output.u.value, output.v.value, output.w.value]
+            }),
+            strategy,
+            setpoint,
+            active_faults: 0,
+        }
+    }
+}
+
+impl Default for BlackBox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BlackBox {
+    pub const fn new() -> Self {
+        Self {
+            frames: [BlackBoxFrame::EMPTY; BLACK_BOX_FRAMES],
+            next_frame: 0,
+            recorded: 0,
+            seen_faults: 0,
+            frozen: false,
+        }
+    }
+
+    /// Stores a control step, once `raised_count` changes the recorded steps are kept until
+    /// [`Self::rearm`]
+    pub fn record(&mut self, frame: BlackBoxFrame, raised_count: usize) {
+        if self.frozen {
+            return;
+        }
+        self.frames[self.next_frame] = frame;
+        self.next_frame = (self.next_frame + 1) % BLACK_BOX_FRAMES;
+        self.recorded = (self.recorded + 1).min(BLACK_BOX_FRAMES);
+        if raised_count != self.seen_faults {
+            self.seen_faults = raised_count;
+            self.frozen = true;
+        }
+    }
+
+    /// Drops the kept steps, faults raised up to `raised_count` do not stop the recording again
+    pub fn rearm(&mut self, raised_count: usize) {
+        self.seen_faults = raised_count;
+        self.frozen = false;
+        self.next_frame = 0;
+        self.recorded = 0;
+    }
+
+    /// Steps kept after a fault, 0 while still recording
+    pub fn frame_count(&self) -> usize {
+        if self.frozen { self.recorded } else { 0 }
+    }
+
+    /// Steps are ordered oldest first, the last one is the step the fault was noticed in
+    pub fn frame(&self, index: usize) -> Option<&BlackBoxFrame> {
+        if index >= self.frame_count() {
+            return None;
+        }
+        let start = self.next_frame + BLACK_BOX_FRAMES - self.recorded;
+        Some(&self.frames[(start + index) % BLACK_BOX_FRAMES])
+    }
+}
+
+pub fn black_box() -> &'static SharedBlackBox {
+    static BLACK_BOX: SharedBlackBox = Mutex::new(RefCell::new(BlackBox::new()));
+    &BLACK_BOX
+}
+
+/// Records a control step together with the faults active in it
+pub(crate) fn record(mut frame: BlackBoxFrame) {
+    let faults = FaultRegister::shared();
+    frame.active_faults = active_fault_bits(faults);
+    let raised_count = faults.raised_count();
+    black_box().lock(|black_box| black_box.borrow_mut().record(frame, raised_count));
+}
+
+/// Drops the kept steps so the next fault is recorded
+pub fn rearm() {
+    let raised_count = FaultRegister::shared().raised_count();
+    black_box().lock(|black_box| black_box.borrow_mut().rearm(raised_count));
+}
+
+fn active_fault_bits(faults: &FaultRegister) -> u32 {
+    faults
+        .snapshot()
+        .into_iter()
+        .enumerate()
+        .filter(|(_, state)| *state == FaultState::Active)
+        .fold(0, |bits, (i, _)| bits | 1 << i)
+}
+
+fn strategy_setpoint(strategy: &ControlStrategy) -> (StrategyKind, [f32; 2]) {
+    match strategy {
+        ControlStrategy::Disabled => (StrategyKind::Disabled, [0.0; 2]),
+        ControlStrategy::Foc(state) => (
+            StrategyKind::Current,
+            [state.d_requested.into_f32(), state.q_requested.into_f32()],
+        ),
+        ControlStrategy::Velocity(control) => (
+            StrategyKind::Velocity,
+            [
+                control.target().into_f32(),
+                control.current.q_requested.into_f32(),
+            ],
+        ),
+        ControlStrategy::Position(control) => (
+            StrategyKind::Position,
+            [
+                control.target().into_f32(),
+                control.velocity.target().into_f32(),
+            ],
+        ),
+        ControlStrategy::DutyCycle(duty_cycle) => {
+            (StrategyKind::DutyCycle, [duty_cycle.value, 0.0])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(timestamp: u64) -> BlackBoxFrame {
+        BlackBoxFrame {
+            timestamp,
+            ..BlackBoxFrame::EMPTY
+        }
+    }
+
+    fn timestamps(black_box: &BlackBox) -> impl Iterator<Item = u64> + '_ {
+        (0..black_box.frame_count()).map(|i| black_box.frame(i).unwrap().timestamp)
+    }
+
+    #[test]
+    fn raised_fault_should_keep_the_steps_before_it() {
+        let mut black_box = BlackBox::new();
+        for timestamp in 0..300 {
+            black_box.record(frame(timestamp), 0);
+        }
+        assert_eq!(black_box.frame_count(), 0);
+
+        black_box.record(frame(300), 1);
+        black_box.record(frame(301), 1);
+        assert_eq!(black_box.frame_count(), BLACK_BOX_FRAMES);
+        assert!(timestamps(&black_box).eq(45..=300));
+        assert_eq!(black_box.frame(BLACK_BOX_FRAMES), None);
+    }
+
+    #[test]
+    fn fault_soon_after_boot_should_keep_the_recorded_steps() {
+        let mut black_box = BlackBox::new();
+        black_box.record(frame(0), 0);
+        black_box.record(frame(1), 1);
+        assert!(timestamps(&black_box).eq([0, 1]));
+    }
+
+    #[test]
+    fn rearm_should_wait_for_the_next_fault() {
+        let mut black_box = BlackBox::new();
+        black_box.record(frame(0), 1);
+        black_box.record(frame(1), 2);
+        black_box.rearm(2);
+        assert_eq!(black_box.frame_count(), 0);
+
+        black_box.record(frame(2), 2);
+        assert_eq!(black_box.frame_count(), 0);
+        black_box.record(frame(3), 3);
+        assert!(timestamps(&black_box).eq([2, 3]));
+    }
+}
@@ -1,8 +1,23 @@
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::channel::Channel;
+use units::{Angle, AngularVelocity, DutyCycle, ElectricCurrent, Torque};
 
 pub type ControlCommandChannel = Channel<CriticalSectionRawMutex, ControlCommand, 10>;
 
 pub enum ControlCommand {
+    /// Calibrates and arms an idle controller, setpoints are ignored until then
+    Arm,
+    Disarm,
     DisableMotor,
+    /// Clears the faults their [`crate::policy::Recovery`] lets a command clear
+    ResetFaults,
+    SetCurrent {
+        d: ElectricCurrent,
+        q: ElectricCurrent,
+    },
+    SetTorque(Torque),
+    SetVelocity(AngularVelocity),
+    /// Single turn shaft angle, the shaft takes the shorter way around
+    SetPosition(Angle),
+    SetDutyCycle(DutyCycle),
 }
@@ -1,7 +1,13 @@
-use units::si::electric_potential::millivolt;
+use units::si::angle::radian;
+use units::si::angular_velocity::radian_per_second;
+use units::si::electric_current::ampere;
+use units::si::electric_potential::{millivolt, volt};
 use units::si::electrical_resistance::milliohm;
 use units::si::thermodynamic_temperature::degree_celsius;
-use units::{ElectricCurrent, ElectricPotential, ElectricalResistance, ThermodynamicTemperature};
+use units::{
+    Angle, AngularVelocity, ElectricCurrent, ElectricPotential, ElectricalResistance, Ratio,
+    ThermodynamicTemperature,
+};
 
 pub fn convert_to_current(
     sample: u16,
@@ -27,6 +33,24 @@ pub fn convert_to_temperature(sample: u16, vrefint_sample: u16) -> Thermodynamic
     ThermodynamicTemperature::new::<degree_celsius>(temp_c)
 }
 
+/// Driver and motor sensors, their output rises linearly with the temperature
+pub fn convert_to_sensor_temperature(
+    sample: u16,
+    vrefint_sample: u16,
+    config: &ConfigValues,
+) -> ThermodynamicTemperature {
+    let voltage = convert_to_voltage(sample as i32, vrefint_sample);
+    let temp_c = (voltage - config.temperature_sensor_offset).get::<millivolt>()
+        / config.temperature_sensor_slope;
+    ThermodynamicTemperature::new::<degree_celsius>(temp_c)
+}
+
+pub fn convert_to_shaft_angle(raw_angle: u16) -> Angle {
+    // The AS5600 reports 12 bits per turn
+    const RESOLUTION: f32 = 4096.0;
+    Angle::new::<radian>(raw_angle as f32 * core::f32::consts::TAU / RESOLUTION)
+}
+
 fn convert_to_millivolts(sample: i32, vrefint_sample: u16) -> i32 {
     const VREFINT_MV: i32 = 1210; // mV
     sample * VREFINT_MV / (vrefint_sample as i32)
@@ -41,6 +65,27 @@ pub struct ConfigValues {
 
     // Voltage sensing
     pub v_bus_scale_ratio: f32,
+
+    // Temperature sensing
+    /// Output of the driver and motor sensors at 0 °C
+    pub temperature_sensor_offset: ElectricPotential,
+    /// Millivolts per degree Celsius
+    pub temperature_sensor_slope: f32,
+
+    // Motor
+    /// Newton metres per ampere of q axis current
+    pub torque_constant: f32,
+    pub max_current: ElectricCurrent,
+    pub max_velocity: AngularVelocity,
+
+    // Control loops, the gains are per control step
+    pub current_kp: Ratio,
+    pub current_ki: Ratio,
+    pub max_voltage: ElectricPotential,
+    pub velocity_kp: Ratio,
+    pub velocity_ki: Ratio,
+    pub position_kp: Ratio,
+    pub position_ki: Ratio,
 }
 
 // TODO remove default values, they should be taken from the config file
@@ -49,8 +94,20 @@ impl Default for ConfigValues {
         Self {
             shunt_resistance: ElectricalResistance::new::<milliohm>(5.0),
             v_bus_scale_ratio: (39.0 + 2.0) / 2.0,
+            temperature_sensor_offset: ElectricPotential::new::<millivolt>(500.0),
+            temperature_sensor_slope: 10.0,
             current_gain: 20.0,
             current_zero_offset: 2048,
+            torque_constant: 0.05,
+            max_current: ElectricCurrent::new::<ampere>(5.0),
+            max_velocity: AngularVelocity::new::<radian_per_second>(100.0),
+            current_kp: Ratio::from(0.5),
+            current_ki: Ratio::from(0.05),
+            max_voltage: ElectricPotential::new::<volt>(12.0),
+            velocity_kp: Ratio::from(0.05),
+            velocity_ki: Ratio::from(0.0005),
+            position_kp: Ratio::from(20.0),
+            position_ki: Ratio::from(0.0),
         }
     }
 }
@@ -151,12 +208,21 @@ mod tests {
             )
         }
     }
+
+    #[test]
+    fn test_convert_to_sensor_temperature() {
+        // 1000 mV
+        let result = convert_to_sensor_temperature(1281, VREFINT, &default_config());
+        assert!((result.get::<degree_celsius>() - 50.0).abs() < 1e-3);
+    }
+
     fn default_config() -> ConfigValues {
         ConfigValues {
             shunt_resistance: ElectricalResistance::new::<milliohm>(100.0),
             current_gain: 20.0,
             current_zero_offset: 2048,
             v_bus_scale_ratio: (39.0 + 2.0) / 2.0,
+            ..ConfigValues::default()
         }
     }
 }
@@ -1,64 +1,155 @@
-use crate::command::{ControlCommand, ControlCommandChannel};
+use crate::black_box::{self, BlackBoxFrame};
+use crate::command::ControlCommand;
 use crate::converters::{
-    ConfigValues, convert_to_current, convert_to_temperature, convert_to_voltage,
+    ConfigValues, convert_to_current, convert_to_sensor_temperature, convert_to_shaft_angle,
+    convert_to_temperature, convert_to_voltage,
 };
 use crate::io::{RawInverterValues, RawSnapshot};
+use crate::motion::{
+    PositionControl, VelocityControl, VelocityEstimator, current_control, set_current,
+};
+use crate::policy::FaultResponse;
+use crate::protection::{Measurements, ProtectionLimits};
 use crate::strategy::ControlStrategy;
 use core::sync::atomic::Ordering;
-use foc::snapshot::{AngleSnapshot, FocInput};
+use embassy_time::Instant;
+use foc::snapshot::{AngleSnapshot, FocInput, FocOutput};
+use logging::fault_register::FaultRegister;
 use units::si::angle::radian;
 use units::{
-    Angle, ElectricCurrent, ElectricPotential, IntoRawDutyCycle, ThermodynamicTemperature,
+    Angle, AngularVelocity, ElectricCurrent, ElectricPotential, F32UnitType, IntoRawDutyCycle,
+    ThermodynamicTemperature,
 };
 
-pub fn update_strategy(
-    command_channel: &ControlCommandChannel,
+pub(crate) fn update_strategy(
+    command: ControlCommand,
     current_strategy: ControlStrategy,
+    config: &ConfigValues,
 ) -> ControlStrategy {
-    let command = command_channel.try_receive().ok();
+    // Setpoints for the running strategy keep its loops, so the motor does not jerk
     match command {
-        None => current_strategy,
-        Some(ControlCommand::DisableMotor) => ControlStrategy::Disabled,
+        // Arming and faults are up to the caller, see `MotorController`
+        ControlCommand::Arm | ControlCommand::Disarm | ControlCommand::ResetFaults => {
+            current_strategy
+        }
+        ControlCommand::DisableMotor => ControlStrategy::Disabled,
+        ControlCommand::SetCurrent { d, q } => with_current(current_strategy, d, q, config),
+        ControlCommand::SetTorque(torque) => {
+            let q = ElectricCurrent::from_f32(torque.into_f32() / config.torque_constant);
+            with_current(current_strategy, ElectricCurrent::from_f32(0.0), q, config)
+        }
+        ControlCommand::SetVelocity(velocity) => match current_strategy {
+            ControlStrategy::Velocity(mut control) => {
+                control.set_target(velocity);
+                ControlStrategy::Velocity(control)
+            }
+            _ => ControlStrategy::Velocity(VelocityControl::new(velocity, config)),
+        },
+        ControlCommand::SetPosition(position) => match current_strategy {
+            ControlStrategy::Position(mut control) => {
+                control.set_target(position);
+                ControlStrategy::Position(control)
+            }
+            _ => ControlStrategy::Position(PositionControl::new(position, config)),
+        },
+        ControlCommand::SetDutyCycle(duty_cycle) => ControlStrategy::DutyCycle(duty_cycle),
     }
 }
 
-pub fn control_step(
+fn with_current(
+    strategy: ControlStrategy,
+    d: ElectricCurrent,
+    q: ElectricCurrent,
+    config: &ConfigValues,
+) -> ControlStrategy {
+    match strategy {
+        ControlStrategy::Foc(mut state) => {
+            set_current(&mut state, d, q, config);
+            ControlStrategy::Foc(state)
+        }
+        _ => ControlStrategy::Foc(current_control(d, q, config)),
+    }
+}
+
+pub(crate) fn control_step(
     raw_snapshot: &Option<RawSnapshot>,
     control_strategy: &mut ControlStrategy,
+    velocity_estimator: &mut VelocityEstimator,
+    now: Instant,
+    config: &ConfigValues,
+    limits: &ProtectionLimits,
+    fault_response: FaultResponse,
 ) -> Option<RawInverterValues> {
     match raw_snapshot {
         Some(values) => {
-            let default_config: ConfigValues = ConfigValues::default();
-            let u = convert_to_current(values.i_u, values.v_ref, &default_config);
-            let v = convert_to_current(values.i_v, values.v_ref, &default_config);
-            let w = convert_to_current(values.i_w, values.v_ref, &default_config);
-            let v_bus = convert_to_voltage(values.v_bus as i32, values.v_ref) * default_config.v_bus_scale_ratio;
+            let u = convert_to_current(values.i_u, values.v_ref, config);
+            let v = convert_to_current(values.i_v, values.v_ref, config);
+            let w = convert_to_current(values.i_w, values.v_ref, config);
+            let v_bus =
+                convert_to_voltage(values.v_bus as i32, values.v_ref) * config.v_bus_scale_ratio;
             let cpu_temp = convert_to_temperature(values.temp_cpu, values.v_ref);
+            let driver_temp =
+                convert_to_sensor_temperature(values.temp_driver, values.v_ref, config);
+            let motor_temp = convert_to_sensor_temperature(values.temp_motor, values.v_ref, config);
+            let shaft_angle = convert_to_shaft_angle(values.angle);
+            let velocity = velocity_estimator.update(shaft_angle, now);
             store_in_state(u, v, w, v_bus, cpu_temp);
 
-            match control_strategy {
+            let input = FocInput {
+                // TODO take real angle values
+                angle: AngleSnapshot {
+                    value: Angle::new::<radian>(0.0),
+                    sin: 0.0,
+                    cos: 1.0,
+                },
+                u,
+                v,
+                w,
+                v_bus,
+            };
+            let electrical_angle = input.angle.value;
+            let output = match control_strategy {
                 ControlStrategy::Disabled => None,
-                ControlStrategy::Foc(state) => {
-                    let input = FocInput {
-                        // TODO take real angle values
-                        angle: AngleSnapshot {
-                            value: Angle::new::<radian>(0.0),
-                            sin: 0.0,
-                            cos: 1.0,
-                        },
-                        u,
-                        v,
-                        w,
-                        v_bus,
-                    };
-                    let output = foc::core::foc_step(input, state);
-                    Some(RawInverterValues {
-                        u: output.u.into_raw_duty_cycle(values.max_duty),
-                        v: output.v.into_raw_duty_cycle(values.max_duty),
-                        w: output.w.into_raw_duty_cycle(values.max_duty),
-                    })
+                ControlStrategy::Foc(state) => Some(foc::core::foc_step(input, state)),
+                ControlStrategy::Velocity(control) => {
+                    control.step(velocity);
+                    Some(foc::core::foc_step(input, &mut control.current))
                 }
-            }
+                ControlStrategy::Position(control) => {
+                    control.step(shaft_angle, velocity);
+                    Some(foc::core::foc_step(input, &mut control.velocity.current))
+                }
+                ControlStrategy::DutyCycle(duty_cycle) => {
+                    Some(foc::core::duty_cycle_step(input, *duty_cycle))
+                }
+            };
+            // Checked before recording, so the black box stops at the step that raised a fault
+            let measurements = Measurements {
+                phase_currents: [u, v, w],
+                bus_current: estimate_bus_current(output.as_ref(), v_bus),
+                v_bus,
+                driver_temperature: driver_temp,
+                motor_temperature: motor_temp,
+                mcu_temperature: cpu_temp,
+            };
+            limits.check(&measurements, FaultRegister::shared());
+            store_rotor_state(output.as_ref(), electrical_angle, velocity);
+            black_box::record(BlackBoxFrame::new(
+                now,
+                [u, v, w],
+                v_bus,
+                electrical_angle,
+                output.as_ref(),
+                control_strategy,
+            ));
+            let duty_cycles = output.map(|output| [output.u, output.v, output.w]);
+            fault_response
+                .apply(duty_cycles)
+                .map(|[u, v, w]| RawInverterValues {
+                    u: u.into_raw_duty_cycle(values.max_duty),
+                    v: v.into_raw_duty_cycle(values.max_duty),
+                    w: w.into_raw_duty_cycle(values.max_duty),
+                })
         }
         None => None,
     }
@@ -79,3 +170,44 @@ pub fn store_in_state(
     state.i_w.store(i_w, Ordering::Relaxed);
     state.v_bus.store(v_bus, Ordering::Relaxed);
 }
+
+/// Power drawn by the motor over the bus voltage, zero while the outputs are off
+fn estimate_bus_current(output: Option<&FocOutput>, v_bus: ElectricPotential) -> ElectricCurrent {
+    let power = output.map_or(0.0, |output| {
+        // The Clarke transformation keeps the amplitude, hence the 3/2
+        1.5 * (output.v_d.into_f32() * output.i_d.into_f32()
+            + output.v_q.into_f32() * output.i_q.into_f32())
+    });
+    let v_bus = v_bus.into_f32();
+    if v_bus > 0.0 {
+        ElectricCurrent::from_f32(power / v_bus)
+    } else {
+        ElectricCurrent::from_f32(0.0)
+    }
+}
+
+fn store_rotor_state(
+    output: Option<&FocOutput>,
+    electrical_angle: Angle,
+    velocity: AngularVelocity,
+) {
+    let state = crate::state::state();
+    let (i_d, i_q, v_d, v_q) = match output {
+        Some(output) => (output.i_d, output.i_q, output.v_d, output.v_q),
+        None => (
+            ElectricCurrent::from_f32(0.0),
+            ElectricCurrent::from_f32(0.0),
+            ElectricPotential::from_f32(0.0),
+            ElectricPotential::from_f32(0.0),
+        ),
+    };
+
+    state.i_d.store(i_d, Ordering::Relaxed);
+    state.i_q.store(i_q, Ordering::Relaxed);
+    state.v_d.store(v_d, Ordering::Relaxed);
+    state.v_q.store(v_q, Ordering::Relaxed);
+    state
+        .electrical_angle
+        .store(electrical_angle, Ordering::Relaxed);
+    state.velocity.store(velocity, Ordering::Relaxed);
+}
@@ -1,10 +1,16 @@
 #![no_std]
 
+pub mod arming;
+pub mod black_box;
 mod converters;
 mod core;
 mod io;
+pub mod motion;
+pub mod policy;
+pub mod protection;
+pub mod scope;
 pub mod state;
 pub mod strategy;
-pub use core::{control_step, update_strategy};
+pub use arming::MotorController;
 pub use io::*;
 pub mod command;
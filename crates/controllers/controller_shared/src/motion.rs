@@ -0,0 +1,230 @@
+use crate::converters::ConfigValues;
+use core::f32::consts::{PI, TAU};
+use embassy_time::{Duration, Instant};
+use foc::state::FocState;
+use pid::pi::PiController;
+use units::{Angle, AngularVelocity, ElectricCurrent, F32UnitType};
+
+/// Without a new angle for this long the shaft is taken as standing still
+const STANDSTILL_TIMEOUT: Duration = Duration::from_millis(50);
+
+pub fn current_control(d: ElectricCurrent, q: ElectricCurrent, config: &ConfigValues) -> FocState {
+    let mut state = FocState::new(
+        config.current_kp,
+        config.current_ki,
+        config.max_voltage.value,
+        -config.max_voltage.value,
+        config.max_voltage,
+        -config.max_voltage,
+    );
+    set_current(&mut state, d, q, config);
+    state
+}
+
+pub fn set_current(
+    state: &mut FocState,
+    d: ElectricCurrent,
+    q: ElectricCurrent,
+    config: &ConfigValues,
+) {
+    state.d_requested = limit(d, config.max_current);
+    state.q_requested = limit(q, config.max_current);
+}
+
+/// Velocity loop feeding the q axis current of a current loop
+pub struct VelocityControl {
+    target: AngularVelocity,
+    max_velocity: AngularVelocity,
+    pi: PiController<AngularVelocity, ElectricCurrent>,
+    pub current: FocState,
+}
+
+impl VelocityControl {
+    pub fn new(target: AngularVelocity, config: &ConfigValues) -> Self {
+        let max_current = config.max_current;
+        let mut control = Self {
+            target,
+            max_velocity: config.max_velocity,
+            pi: PiController::new(
+                config.velocity_kp,
+                config.velocity_ki,
+                max_current.value,
+                -max_current.value,
+                max_current,
+                -max_current,
+            ),
+            current: current_control(
+                ElectricCurrent::from_f32(0.0),
+                ElectricCurrent::from_f32(0.0),
+                config,
+            ),
+        };
+        control.set_target(target);
+        control
+    }
+
+    pub fn target(&self) -> AngularVelocity {
+        self.target
+    }
+
+    pub fn set_target(&mut self, target: AngularVelocity) {
+        self.target = limit(target, self.max_velocity);
+    }
+
+    /// Updates the q axis current requested from [`Self::current`]
+    pub fn step(&mut self, velocity: AngularVelocity) {
+        self.current.q_requested = self.pi.step(self.target - velocity);
+    }
+}
+
+/// Position loop feeding the target of a velocity loop
+pub struct PositionControl {
+    target: Angle,
+    pi: PiController<Angle, AngularVelocity>,
+    pub velocity: VelocityControl,
+}
+
+impl PositionControl {
+    pub fn new(target: Angle, config: &ConfigValues) -> Self {
+        let max_velocity = config.max_velocity;
+        Self {
+            target,
+            pi: PiController::new(
+                config.position_kp,
+                config.position_ki,
+                max_velocity.value,
+                -max_velocity.value,
+                max_velocity,
+                -max_velocity,
+            ),
+            velocity: VelocityControl::new(AngularVelocity::from_f32(0.0), config),
+        }
+    }
+
+    pub fn target(&self) -> Angle {
+        self.target
+    }
+
+    pub fn set_target(&mut self, target: Angle) {
+        self.target = target;
+    }
+
+    /// Updates the velocity target and, through it, the requested current
+    pub fn step(&mut self, shaft_angle: Angle, velocity: AngularVelocity) {
+        let error = shortest_turn(self.target - shaft_angle);
+        self.velocity.set_target(self.pi.step(error));
+        self.velocity.step(velocity);
+    }
+}
+
+/// Estimates the shaft speed from the angle of the position sensor.
+///
+/// The sensor is read less often than the control loop runs, so the speed is only updated when
+/// the angle changes and is taken over the whole time since the previous change.
+#[derive(Default)]
+pub(crate) struct VelocityEstimator {
+    last_change: Option<(Angle, Instant)>,
+    velocity: f32,
+}
+
+impl VelocityEstimator {
+    pub(crate) fn update(&mut self, angle: Angle, now: Instant) -> AngularVelocity {
+        match self.last_change {
+            Some((last_angle, since)) if last_angle == angle => {
+                if now.saturating_duration_since(since) > STANDSTILL_TIMEOUT {
+                    self.velocity = 0.0;
+                }
+            }
+            Some((last_angle, since)) => {
+                let elapsed = now.saturating_duration_since(since).as_micros();
+                if elapsed > 0 {
+                    let turned = shortest_turn(angle - last_angle).value;
+                    self.velocity = turned * 1_000_000.0 / elapsed as f32;
+                }
+                self.last_change = Some((angle, now));
+            }
+            None => self.last_change = Some((angle, now)),
+        }
+        AngularVelocity::from_f32(self.velocity)
+    }
+}
+
+fn limit<T: F32UnitType>(value: T, max: T) -> T {
+    let max = max.into_f32();
+    T::from_f32(value.into_f32().clamp(-max, max))
+}
+
+/// Maps an angle difference to (-π, π], the shorter way around the circle
+fn shortest_turn(angle: Angle) -> Angle {
+    let mut value = angle.value % TAU;
+    if value > PI {
+        value -= TAU;
+    } else if value <= -PI {
+        value += TAU;
+    }
+    Angle::from_f32(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn angle(value: f32) -> Angle {
+        Angle::from_f32(value)
+    }
+
+    #[test]
+    fn shortest_turn_should_wrap_around() {
+        assert!((shortest_turn(angle(TAU - 0.5)).value + 0.5).abs() < 1e-5);
+        assert!((shortest_turn(angle(-TAU + 0.5)).value - 0.5).abs() < 1e-5);
+        assert!((shortest_turn(angle(3.0 * TAU + 0.25)).value - 0.25).abs() < 1e-4);
+    }
+
+    #[test]
+    fn velocity_should_be_taken_over_the_time_between_changes() {
+        let mut estimator = VelocityEstimator::default();
+        estimator.update(angle(1.0), Instant::from_millis(0));
+        estimator.update(angle(1.0), Instant::from_millis(5));
+        let velocity = estimator.update(angle(1.1), Instant::from_millis(10));
+        assert!((velocity.value - 10.0).abs() < 1e-3);
+        let velocity = estimator.update(angle(1.1), Instant::from_millis(20));
+        assert!((velocity.value - 10.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn velocity_should_follow_the_angle_across_a_full_turn() {
+        let mut estimator = VelocityEstimator::default();
+        estimator.update(angle(TAU - 0.05), Instant::from_millis(0));
+        let velocity = estimator.update(angle(0.05), Instant::from_millis(10));
+        assert!((velocity.value - 10.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn velocity_should_drop_to_zero_at_standstill() {
+        let mut estimator = VelocityEstimator::default();
+        estimator.update(angle(0.0), Instant::from_millis(0));
+        estimator.update(angle(0.5), Instant::from_millis(10));
+        let velocity = estimator.update(angle(0.5), Instant::from_millis(100));
+        assert_eq!(velocity.value, 0.0);
+    }
+
+    #[test]
+    fn position_loop_should_request_current_towards_the_target() {
+        let config = ConfigValues::default();
+        let mut control = PositionControl::new(angle(0.5), &config);
+        let standstill = AngularVelocity::from_f32(0.0);
+        control.step(angle(0.0), standstill);
+        assert!(control.velocity.current.q_requested.value > 0.0);
+
+        control.set_target(angle(-0.5));
+        control.step(angle(0.0), standstill);
+        assert!(control.velocity.current.q_requested.value < 0.0);
+    }
+
+    #[test]
+    fn velocity_target_should_be_limited() {
+        let config = ConfigValues::default();
+        let control = VelocityControl::new(config.max_velocity * 2.0, &config);
+        assert_eq!(control.target, config.max_velocity);
+    }
+}
@@ -0,0 +1,292 @@
+use embassy_time::{Duration, Instant};
+use enum_iterator::{Sequence, all};
+use logging::fault_register::{FaultRegister, FaultState, FaultType};
+use units::DutyCycle;
+
+/// How hard a fault hits the motor, ordered from the mildest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Severity {
+    /// Only reported
+    Warning,
+    /// The motor keeps running with the voltage limited by [`FaultPolicies::derate_factor`]
+    Derate,
+    /// Disarms while the fault is active
+    Stop,
+    /// Disarms until the fault is cleared, see [`Recovery`]
+    Lockout,
+}
+
+/// What the outputs do while a fault stops the motor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FaultAction {
+    /// All switches off, the motor spins down freely
+    Coast,
+    /// Every phase at half duty, the windings are shorted while the bridge keeps switching
+    Brake,
+    /// All low side switches on
+    ActiveShort,
+}
+
+/// When a latched fault goes back to clean
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Recovery {
+    /// Clears itself once it stayed latched for the given time, ResetFaults clears it earlier
+    AutoClear(Duration),
+    /// On ResetFaults
+    OnCommand,
+    /// Only a reboot clears it, ResetFaults leaves it alone
+    PowerCycle,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FaultPolicy {
+    pub severity: Severity,
+    pub action: FaultAction,
+    pub recovery: Recovery,
+}
+
+/// How the controller reacts to each fault type
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FaultPolicies {
+    policies: [FaultPolicy; FaultType::CARDINALITY],
+    /// Share of the output voltage left while a fault derates the motor
+    pub derate_factor: f32,
+}
+
+/// What the raised faults ask of the outputs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FaultResponse {
+    Run,
+    Derate(f32),
+    Stop(FaultAction),
+}
+
+/// Clears latched faults once their [`Recovery`] allows it
+#[derive(Debug, Default)]
+pub struct FaultRecovery {
+    latched_since: [Option<Instant>; FaultType::CARDINALITY],
+}
+
+impl FaultPolicy {
+    const fn new(severity: Severity, action: FaultAction, recovery: Recovery) -> Self {
+        Self {
+            severity,
+            action,
+            recovery,
+        }
+    }
+}
+
+impl Default for FaultPolicies {
+    fn default() -> Self {
+        use FaultAction::*;
+        use Severity::*;
+        let auto_clear = |millis| Recovery::AutoClear(Duration::from_millis(millis));
+        let policy = |fault| match fault {
+            FaultType::Encoder => FaultPolicy::new(Stop, Coast, Recovery::OnCommand),
+            FaultType::OvercurrentU | FaultType::OvercurrentV | FaultType::OvercurrentW => {
+                FaultPolicy::new(Lockout, Coast, Recovery::OnCommand)
+            }
+            FaultType::OvercurrentBus => FaultPolicy::new(Derate, Coast, auto_clear(1000)),
+            // Shorting the windings keeps the motor from pumping more energy into the bus
+            FaultType::BusOvervoltage => FaultPolicy::new(Stop, ActiveShort, auto_clear(1000)),
+            FaultType::BusUndervoltage => FaultPolicy::new(Stop, Coast, auto_clear(1000)),
+            FaultType::DriverOvertemperature | FaultType::MotorOvertemperature => {
+                FaultPolicy::new(Derate, Coast, auto_clear(10_000))
+            }
+            FaultType::McuOvertemperature => FaultPolicy::new(Stop, Coast, auto_clear(10_000)),
+            FaultType::LoopOverrun => FaultPolicy::new(Warning, Coast, auto_clear(1000)),
+            FaultType::AdcTimeout => FaultPolicy::new(Stop, Coast, Recovery::OnCommand),
+            FaultType::CommunicationTimeout => FaultPolicy::new(Stop, Brake, auto_clear(100)),
+            FaultType::ConfigError => FaultPolicy::new(Lockout, Coast, Recovery::PowerCycle),
+            FaultType::FlashError => FaultPolicy::new(Warning, Coast, Recovery::OnCommand),
+        };
+        let mut faults = all::<FaultType>();
+        Self {
+            policies: core::array::from_fn(|_| policy(faults.next().unwrap())),
+            derate_factor: 0.5,
+        }
+    }
+}
+
+impl FaultPolicies {
+    pub fn get(&self, fault: FaultType) -> &FaultPolicy {
+        &self.policies[fault as usize]
+    }
+
+    pub fn set(&mut self, fault: FaultType, policy: FaultPolicy) {
+        self.policies[fault as usize] = policy;
+    }
+
+    /// Active faults count with their severity, latched ones only if they lock the motor out.
+    /// The most severe fault wins, the first one in the catalogue among equals.
+    pub fn evaluate(&self, faults: &FaultRegister) -> FaultResponse {
+        let mut worst: Option<&FaultPolicy> = None;
+        for (fault, policy) in all::<FaultType>().zip(&self.policies) {
+            let raised = match faults.load(fault) {
+                FaultState::Active => true,
+                FaultState::Latched => policy.severity == Severity::Lockout,
+                FaultState::Clean => false,
+            };
+            if raised && worst.is_none_or(|worst| policy.severity > worst.severity) {
+                worst = Some(policy);
+            }
+        }
+        match worst {
+            None => FaultResponse::Run,
+            Some(policy) => match policy.severity {
+                Severity::Warning => FaultResponse::Run,
+                Severity::Derate => FaultResponse::Derate(self.derate_factor),
+                Severity::Stop | Severity::Lockout => FaultResponse::Stop(policy.action),
+            },
+        }
+    }
+
+    /// Whether a raised fault locks the motor out, latched ones included
+    pub fn locks_out(&self, faults: &FaultRegister) -> bool {
+        all::<FaultType>()
+            .zip(&self.policies)
+            .any(|(fault, policy)| {
+                policy.severity == Severity::Lockout && faults.load(fault) != FaultState::Clean
+            })
+    }
+
+    /// Clears the faults ResetFaults may clear
+    pub fn reset(&self, faults: &FaultRegister) {
+        for (fault, policy) in all::<FaultType>().zip(&self.policies) {
+            if policy.recovery != Recovery::PowerCycle {
+                faults.clear(fault);
+            }
+        }
+    }
+}
+
+impl FaultResponse {
+    pub fn stops(self) -> bool {
+        matches!(self, FaultResponse::Stop(_))
+    }
+
+    /// Duty cycles of u, v and w, `None` turns the outputs off
+    pub(crate) fn apply(self, duty_cycles: Option<[DutyCycle; 3]>) -> Option<[DutyCycle; 3]> {
+        match self {
+            FaultResponse::Run => duty_cycles,
+            FaultResponse::Derate(factor) => duty_cycles.map(|duty_cycles| {
+                // Scaled around the centre, which is zero voltage across the windings
+                duty_cycles.map(|duty| DutyCycle::from(0.5 + (duty.value - 0.5) * factor))
+            }),
+            FaultResponse::Stop(FaultAction::Coast) => None,
+            FaultResponse::Stop(FaultAction::Brake) => Some([DutyCycle::from(0.5); 3]),
+            FaultResponse::Stop(FaultAction::ActiveShort) => Some([DutyCycle::from(0.0); 3]),
+        }
+    }
+}
+
+impl FaultRecovery {
+    pub fn update(&mut self, policies: &FaultPolicies, faults: &FaultRegister, now: Instant) {
+        for (fault, latched_since) in all::<FaultType>().zip(&mut self.latched_since) {
+            if faults.load(fault) != FaultState::Latched {
+                *latched_since = None;
+                continue;
+            }
+            let since = *latched_since.get_or_insert(now);
+            if let Recovery::AutoClear(after) = policies.get(fault).recovery
+                && now - since >= after
+            {
+                faults.clear(fault);
+                *latched_since = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn most_severe_fault_should_decide_the_response() {
+        let policies = FaultPolicies::default();
+        let faults = FaultRegister::default();
+        assert_eq!(policies.evaluate(&faults), FaultResponse::Run);
+
+        faults.set(FaultType::LoopOverrun);
+        assert_eq!(policies.evaluate(&faults), FaultResponse::Run);
+        faults.set(FaultType::MotorOvertemperature);
+        assert_eq!(policies.evaluate(&faults), FaultResponse::Derate(0.5));
+        faults.set(FaultType::BusOvervoltage);
+        assert_eq!(
+            policies.evaluate(&faults),
+            FaultResponse::Stop(FaultAction::ActiveShort)
+        );
+    }
+
+    #[test]
+    fn lockout_should_last_until_the_fault_is_cleared() {
+        let policies = FaultPolicies::default();
+        let faults = FaultRegister::default();
+        faults.set(FaultType::OvercurrentV);
+        faults.resolve_if_set(FaultType::OvercurrentV);
+        assert!(policies.evaluate(&faults).stops());
+        assert!(policies.locks_out(&faults));
+
+        faults.set(FaultType::BusUndervoltage);
+        faults.resolve_if_set(FaultType::BusUndervoltage);
+        policies.reset(&faults);
+        assert_eq!(policies.evaluate(&faults), FaultResponse::Run);
+        assert!(!policies.locks_out(&faults));
+    }
+
+    #[test]
+    fn reset_should_skip_faults_cleared_by_power_cycle() {
+        let policies = FaultPolicies::default();
+        let faults = FaultRegister::default();
+        faults.set(FaultType::ConfigError);
+        faults.set(FaultType::AdcTimeout);
+
+        policies.reset(&faults);
+        assert_eq!(faults.load(FaultType::ConfigError), FaultState::Active);
+        assert_eq!(faults.load(FaultType::AdcTimeout), FaultState::Clean);
+    }
+
+    #[test]
+    fn latched_fault_should_clear_after_its_delay() {
+        let policies = FaultPolicies::default();
+        let faults = FaultRegister::default();
+        let mut recovery = FaultRecovery::default();
+        faults.set(FaultType::CommunicationTimeout);
+        faults.set(FaultType::AdcTimeout);
+        recovery.update(&policies, &faults, Instant::from_millis(0));
+        faults.resolve_if_set(FaultType::CommunicationTimeout);
+        faults.resolve_if_set(FaultType::AdcTimeout);
+
+        recovery.update(&policies, &faults, Instant::from_millis(10));
+        recovery.update(&policies, &faults, Instant::from_millis(100));
+        assert_eq!(
+            faults.load(FaultType::CommunicationTimeout),
+            FaultState::Latched
+        );
+        recovery.update(&policies, &faults, Instant::from_millis(110));
+        assert_eq!(
+            faults.load(FaultType::CommunicationTimeout),
+            FaultState::Clean
+        );
+        assert_eq!(faults.load(FaultType::AdcTimeout), FaultState::Latched);
+    }
+
+    #[test]
+    fn derating_should_scale_the_voltage_around_the_centre() {
+        let duty_cycles = [0.9, 0.5, 0.1].map(DutyCycle::from);
+        let derated = FaultResponse::Derate(0.5).apply(Some(duty_cycles)).unwrap();
+        for (duty, expected) in derated.into_iter().zip([0.7, 0.5, 0.3]) {
+            assert!((duty.value - expected).abs() < 1e-6);
+        }
+        assert_eq!(
+            FaultResponse::Stop(FaultAction::Coast).apply(Some(duty_cycles)),
+            None
+        );
+    }
+}
@@ -0,0 +1,249 @@
+use embassy_time::Duration;
+use logging::fault_register::{FaultRegister, FaultState, FaultType};
+use units::si::electric_current::ampere;
+use units::si::electric_potential::volt;
+use units::si::thermodynamic_temperature::degree_celsius;
+use units::si::torque::newton_meter;
+use units::{ElectricCurrent, ElectricPotential, F32UnitType, ThermodynamicTemperature, Torque};
+
+/// Values of one control step checked against the [`ProtectionLimits`]
+#[derive(Debug, Clone, Copy)]
+pub struct Measurements {
+    /// u, v and w
+    pub phase_currents: [ElectricCurrent; 3],
+    /// Estimated from the power drawn by the motor, there is no bus current sensor
+    pub bus_current: ElectricCurrent,
+    pub v_bus: ElectricPotential,
+    pub driver_temperature: ThermodynamicTemperature,
+    pub motor_temperature: ThermodynamicTemperature,
+    pub mcu_temperature: ThermodynamicTemperature,
+}
+
+/// Thresholds of the faults detected by the controller. A fault is raised once its value crosses
+/// the limit and stays active until the value is back within the limit by the hysteresis.
+#[derive(Debug, Clone, Copy)]
+pub struct ProtectionLimits {
+    /// Applies to each phase
+    pub max_phase_current: ElectricCurrent,
+    /// Largest torque setpoint in either direction
+    pub max_torque: Torque,
+    pub max_bus_current: ElectricCurrent,
+    pub max_bus_voltage: ElectricPotential,
+    pub min_bus_voltage: ElectricPotential,
+    pub max_driver_temperature: ThermodynamicTemperature,
+    pub max_motor_temperature: ThermodynamicTemperature,
+    pub max_mcu_temperature: ThermodynamicTemperature,
+    pub current_hysteresis: ElectricCurrent,
+    pub voltage_hysteresis: ElectricPotential,
+    /// Kelvin
+    pub temperature_hysteresis: f32,
+    /// Longest control step, a longer one raises `LoopOverrun`
+    pub max_loop_time: Duration,
+    /// Longest time without a control command while running, `None` never times out
+    pub command_timeout: Option<Duration>,
+}
+
+// TODO take the limits from the config file
+impl Default for ProtectionLimits {
+    fn default() -> Self {
+        Self {
+            max_phase_current: ElectricCurrent::new::<ampere>(15.0),
+            max_torque: Torque::new::<newton_meter>(0.75),
+            max_bus_current: ElectricCurrent::new::<ampere>(10.0),
+            max_bus_voltage: ElectricPotential::new::<volt>(55.0),
+            min_bus_voltage: ElectricPotential::new::<volt>(8.0),
+            max_driver_temperature: ThermodynamicTemperature::new::<degree_celsius>(100.0),
+            max_motor_temperature: ThermodynamicTemperature::new::<degree_celsius>(110.0),
+            max_mcu_temperature: ThermodynamicTemperature::new::<degree_celsius>(100.0),
+            current_hysteresis: ElectricCurrent::new::<ampere>(1.0),
+            voltage_hysteresis: ElectricPotential::new::<volt>(1.0),
+            temperature_hysteresis: 10.0,
+            max_loop_time: Duration::from_micros(50),
+            command_timeout: None,
+        }
+    }
+}
+
+impl ProtectionLimits {
+    /// Whether the phase current of a d and q setpoint stays within [`Self::max_phase_current`]
+    pub fn allows_current(&self, d: ElectricCurrent, q: ElectricCurrent) -> bool {
+        let (d, q) = (d.into_f32(), q.into_f32());
+        let limit = self.max_phase_current.into_f32();
+        d * d + q * q <= limit * limit
+    }
+
+    pub fn allows_torque(&self, torque: Torque) -> bool {
+        let limit = self.max_torque.into_f32();
+        (-limit..=limit).contains(&torque.into_f32())
+    }
+
+    pub fn check(&self, measurements: &Measurements, faults: &FaultRegister) {
+        let current_hysteresis = self.current_hysteresis.into_f32();
+        let phases = [
+            FaultType::OvercurrentU,
+            FaultType::OvercurrentV,
+            FaultType::OvercurrentW,
+        ];
+        for (fault, current) in phases.into_iter().zip(measurements.phase_currents) {
+            let limit = self.max_phase_current.into_f32();
+            check_above(
+                faults,
+                fault,
+                current.into_f32().abs(),
+                limit,
+                current_hysteresis,
+            );
+        }
+        check_above(
+            faults,
+            FaultType::OvercurrentBus,
+            measurements.bus_current.into_f32().abs(),
+            self.max_bus_current.into_f32(),
+            current_hysteresis,
+        );
+
+        let v_bus = measurements.v_bus.into_f32();
+        let voltage_hysteresis = self.voltage_hysteresis.into_f32();
+        check_above(
+            faults,
+            FaultType::BusOvervoltage,
+            v_bus,
+            self.max_bus_voltage.into_f32(),
+            voltage_hysteresis,
+        );
+        // Negated, so the fault is raised below the limit
+        check_above(
+            faults,
+            FaultType::BusUndervoltage,
+            -v_bus,
+            -self.min_bus_voltage.into_f32(),
+            voltage_hysteresis,
+        );
+
+        let temperatures = [
+            (
+                FaultType::DriverOvertemperature,
+                measurements.driver_temperature,
+                self.max_driver_temperature,
+            ),
+            (
+                FaultType::MotorOvertemperature,
+                measurements.motor_temperature,
+                self.max_motor_temperature,
+            ),
+            (
+                FaultType::McuOvertemperature,
+                measurements.mcu_temperature,
+                self.max_mcu_temperature,
+            ),
+        ];
+        for (fault, temperature, limit) in temperatures {
+            check_above(
+                faults,
+                fault,
+                temperature.into_f32(),
+                limit.into_f32(),
+                self.temperature_hysteresis,
+            );
+        }
+    }
+
+    pub fn check_loop_time(&self, elapsed: Duration, faults: &FaultRegister) {
+        set_if(faults, FaultType::LoopOverrun, elapsed > self.max_loop_time);
+    }
+}
+
+/// Sets the fault while it is present, otherwise resolves it
+pub(crate) fn set_if(faults: &FaultRegister, fault: FaultType, present: bool) {
+    if present {
+        faults.set(fault);
+    } else {
+        faults.resolve_if_set(fault);
+    }
+}
+
+fn check_above(faults: &FaultRegister, fault: FaultType, value: f32, limit: f32, hysteresis: f32) {
+    let threshold = match faults.load(fault) {
+        FaultState::Active => limit - hysteresis,
+        FaultState::Clean | FaultState::Latched => limit,
+    };
+    set_if(faults, fault, value > threshold);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn measurements(phase_current: f32, v_bus: f32) -> Measurements {
+        let temperature = ThermodynamicTemperature::new::<degree_celsius>(25.0);
+        Measurements {
+            phase_currents: [
+                ElectricCurrent::new::<ampere>(0.0),
+                ElectricCurrent::new::<ampere>(-phase_current),
+                ElectricCurrent::new::<ampere>(0.0),
+            ],
+            bus_current: ElectricCurrent::new::<ampere>(0.0),
+            v_bus: ElectricPotential::new::<volt>(v_bus),
+            driver_temperature: temperature,
+            motor_temperature: temperature,
+            mcu_temperature: temperature,
+        }
+    }
+
+    #[test]
+    fn overcurrent_should_clear_only_below_the_hysteresis() {
+        let limits = ProtectionLimits::default();
+        let faults = FaultRegister::default();
+
+        limits.check(&measurements(16.0, 24.0), &faults);
+        assert_eq!(faults.load(FaultType::OvercurrentV), FaultState::Active);
+        assert_eq!(faults.load(FaultType::OvercurrentU), FaultState::Clean);
+
+        limits.check(&measurements(14.5, 24.0), &faults);
+        assert_eq!(faults.load(FaultType::OvercurrentV), FaultState::Active);
+
+        limits.check(&measurements(13.5, 24.0), &faults);
+        assert_eq!(faults.load(FaultType::OvercurrentV), FaultState::Latched);
+        assert!(!faults.any_active());
+    }
+
+    #[test]
+    fn bus_voltage_should_be_kept_between_the_limits() {
+        let limits = ProtectionLimits::default();
+        let faults = FaultRegister::default();
+
+        limits.check(&measurements(0.0, 5.0), &faults);
+        assert_eq!(faults.load(FaultType::BusUndervoltage), FaultState::Active);
+        limits.check(&measurements(0.0, 8.5), &faults);
+        assert_eq!(faults.load(FaultType::BusUndervoltage), FaultState::Active);
+        limits.check(&measurements(0.0, 60.0), &faults);
+        assert_eq!(faults.load(FaultType::BusUndervoltage), FaultState::Latched);
+        assert_eq!(faults.load(FaultType::BusOvervoltage), FaultState::Active);
+    }
+
+    #[test]
+    fn setpoints_beyond_the_limits_should_not_be_allowed() {
+        let limits = ProtectionLimits::default();
+        let current = ElectricCurrent::new::<ampere>;
+        assert!(limits.allows_current(current(-15.0), current(0.0)));
+        assert!(limits.allows_current(current(9.0), current(12.0)));
+        assert!(!limits.allows_current(current(10.0), current(12.0)));
+        assert!(!limits.allows_current(current(0.0), current(1.0e6)));
+
+        assert!(limits.allows_torque(Torque::new::<newton_meter>(-0.75)));
+        assert!(!limits.allows_torque(Torque::new::<newton_meter>(0.8)));
+    }
+
+    #[test]
+    fn loop_overrun_should_follow_the_step_time() {
+        let limits = ProtectionLimits::default();
+        let faults = FaultRegister::default();
+
+        limits.check_loop_time(Duration::from_micros(20), &faults);
+        assert_eq!(faults.load(FaultType::LoopOverrun), FaultState::Clean);
+        limits.check_loop_time(Duration::from_micros(80), &faults);
+        assert_eq!(faults.load(FaultType::LoopOverrun), FaultState::Active);
+        limits.check_loop_time(Duration::from_micros(20), &faults);
+        assert_eq!(faults.load(FaultType::LoopOverrun), FaultState::Latched);
+    }
+}
@@ -0,0 +1,399 @@
+use core::cell::RefCell;
+use core::sync::atomic::Ordering;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use units::F32UnitType;
+
+/// Samples of all channels together, 16 KiB of RAM
+pub const SCOPE_BUFFER_SIZE: usize = 4096;
+pub const MAX_SCOPE_CHANNELS: usize = 4;
+
+pub type SharedScope = Mutex<CriticalSectionRawMutex, RefCell<Scope>>;
+
+/// Values the control loop can record, in SI units
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ScopeSignal {
+    PhaseCurrentU,
+    PhaseCurrentV,
+    PhaseCurrentW,
+    CurrentD,
+    CurrentQ,
+    VoltageD,
+    VoltageQ,
+    ElectricalAngle,
+    Velocity,
+    BusVoltage,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TriggerMode {
+    /// Only [`Scope::trigger`] starts the capture
+    Manual,
+    RisingEdge,
+    FallingEdge,
+    /// Triggers as soon as the channel is at or above the level
+    AboveLevel,
+    BelowLevel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Trigger {
+    pub mode: TriggerMode,
+    /// Index into [`ScopeConfig::channels`]
+    pub channel: usize,
+    pub level: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ScopeConfig {
+    /// Used channels come first
+    pub channels: [Option<ScopeSignal>; MAX_SCOPE_CHANNELS],
+    pub trigger: Trigger,
+    /// Samples per channel
+    pub length: usize,
+    /// Samples per channel recorded before the trigger
+    pub pre_trigger: usize,
+    /// Records every n-th control step
+    pub decimation: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ScopeState {
+    Idle,
+    /// Recording and waiting for the trigger
+    Armed,
+    /// Recording the samples after the trigger
+    Triggered,
+    /// The capture is complete and can be read
+    Done,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct InvalidScopeConfig;
+
+/// Records signals of the control loop into a ring buffer until the trigger fires and the
+/// samples after it are recorded as well. An idle scope is all zeros, so the static one takes no
+/// flash for its initial value.
+pub struct Scope {
+    /// Only the first `channel_count` are used
+    signals: [ScopeSignal; MAX_SCOPE_CHANNELS],
+    channel_count: usize,
+    trigger: Trigger,
+    length: usize,
+    pre_trigger: usize,
+    /// Control steps left out between two frames, one less than the decimation
+    skip: u32,
+    state: ScopeState,
+    /// Frames of one sample per channel
+    buffer: [f32; SCOPE_BUFFER_SIZE],
+    next_frame: usize,
+    recorded: usize,
+    remaining: usize,
+    skipped: u32,
+    previous: Option<f32>,
+    manual_trigger: bool,
+}
+
+impl Default for Scope {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scope {
+    pub const fn new() -> Self {
+        Self {
+            signals: [ScopeSignal::PhaseCurrentU; MAX_SCOPE_CHANNELS],
+            channel_count: 0,
+            trigger: Trigger {
+                mode: TriggerMode::Manual,
+                channel: 0,
+                level: 0.0,
+            },
+            length: 0,
+            pre_trigger: 0,
+            skip: 0,
+            state: ScopeState::Idle,
+            buffer: [0.0; SCOPE_BUFFER_SIZE],
+            next_frame: 0,
+            recorded: 0,
+            remaining: 0,
+            skipped: 0,
+            previous: None,
+            manual_trigger: false,
+        }
+    }
+
+    /// Drops the previous capture and arms the scope
+    pub fn configure(&mut self, config: ScopeConfig) -> Result<(), InvalidScopeConfig> {
+        let channel_count = config.channels.iter().take_while(|c| c.is_some()).count();
+        let valid = channel_count > 0
+            && config.channels[channel_count..].iter().all(Option::is_none)
+            && config.trigger.channel < channel_count
+            && config.trigger.level.is_finite()
+            && config.length > 0
+            && config.length * channel_count <= SCOPE_BUFFER_SIZE
+            && config.pre_trigger < config.length
+            && config.decimation > 0;
+        if !valid {
+            return Err(InvalidScopeConfig);
+        }
+
+        self.signals = config
+            .channels
+            .map(|signal| signal.unwrap_or(ScopeSignal::PhaseCurrentU));
+        self.channel_count = channel_count;
+        self.trigger = config.trigger;
+        self.length = config.length;
+        self.pre_trigger = config.pre_trigger;
+        self.skip = config.decimation - 1;
+        self.state = ScopeState::Armed;
+        self.next_frame = 0;
+        self.recorded = 0;
+        self.skipped = 0;
+        self.previous = None;
+        self.manual_trigger = false;
+        Ok(())
+    }
+
+    /// Forces the trigger in every mode, it fires once the samples before it are recorded
+    pub fn trigger(&mut self) {
+        self.manual_trigger = self.state == ScopeState::Armed;
+    }
+
+    pub fn state(&self) -> ScopeState {
+        self.state
+    }
+
+    pub fn config(&self) -> ScopeConfig {
+        ScopeConfig {
+            channels: core::array::from_fn(|i| (i < self.channel_count).then_some(self.signals[i])),
+            trigger: self.trigger,
+            length: self.length,
+            pre_trigger: self.pre_trigger,
+            decimation: self.skip + 1,
+        }
+    }
+
+    /// Records a frame every `decimation` calls, `read` returns the value of a signal
+    pub fn sample(&mut self, read: impl Fn(ScopeSignal) -> f32) {
+        if !matches!(self.state, ScopeState::Armed | ScopeState::Triggered) {
+            return;
+        }
+        if self.skipped < self.skip {
+            self.skipped += 1;
+            return;
+        }
+        self.skipped = 0;
+
+        let start = self.next_frame * self.channel_count;
+        let frame = &mut self.buffer[start..start + self.channel_count];
+        for (slot, signal) in frame.iter_mut().zip(&self.signals) {
+            *slot = read(*signal);
+        }
+        let value = frame[self.trigger.channel];
+        self.next_frame = (self.next_frame + 1) % self.length;
+        self.recorded = (self.recorded + 1).min(self.length);
+
+        if self.state == ScopeState::Armed
+            && self.recorded > self.pre_trigger
+            && self.is_triggered(value)
+        {
+            self.state = ScopeState::Triggered;
+            self.remaining = self.length - self.pre_trigger;
+        }
+        if self.state == ScopeState::Triggered {
+            self.remaining -= 1;
+            if self.remaining == 0 {
+                self.state = ScopeState::Done;
+            }
+        }
+        self.previous = Some(value);
+    }
+
+    /// Number of samples of a complete capture, counting every channel
+    pub fn captured_len(&self) -> usize {
+        match self.state {
+            ScopeState::Done => self.length * self.channel_count,
+            _ => 0,
+        }
+    }
+
+    /// Samples are ordered oldest first, the channels of a frame next to each other
+    pub fn captured_sample(&self, index: usize) -> Option<f32> {
+        if index >= self.captured_len() {
+            return None;
+        }
+        let frame = (self.next_frame + index / self.channel_count) % self.length;
+        Some(self.buffer[frame * self.channel_count + index % self.channel_count])
+    }
+
+    fn is_triggered(&self, value: f32) -> bool {
+        let level = self.trigger.level;
+        let triggered = match self.trigger.mode {
+            TriggerMode::Manual => false,
+            TriggerMode::RisingEdge => self.previous.is_some_and(|p| p < level) && value >= level,
+            TriggerMode::FallingEdge => self.previous.is_some_and(|p| p > level) && value <= level,
+            TriggerMode::AboveLevel => value >= level,
+            TriggerMode::BelowLevel => value <= level,
+        };
+        triggered || self.manual_trigger
+    }
+}
+
+pub fn scope() -> &'static SharedScope {
+    static SCOPE: SharedScope = Mutex::new(RefCell::new(Scope::new()));
+    &SCOPE
+}
+
+/// Records the signals of the last control step, call it after every step
+pub fn record() {
+    scope().lock(|scope| scope.borrow_mut().sample(read_signal));
+}
+
+fn read_signal(signal: ScopeSignal) -> f32 {
+    let state = crate::state::state();
+    match signal {
+        ScopeSignal::PhaseCurrentU => state.i_u.load(Ordering::Relaxed).into_f32(),
+        ScopeSignal::PhaseCurrentV => state.i_v.load(Ordering::Relaxed).into_f32(),
+        ScopeSignal::PhaseCurrentW => state.i_w.load(Ordering::Relaxed).into_f32(),
+        ScopeSignal::CurrentD => state.i_d.load(Ordering::Relaxed).into_f32(),
+        ScopeSignal::CurrentQ => state.i_q.load(Ordering::Relaxed).into_f32(),
+        ScopeSignal::VoltageD => state.v_d.load(Ordering::Relaxed).into_f32(),
+        ScopeSignal::VoltageQ => state.v_q.load(Ordering::Relaxed).into_f32(),
+        ScopeSignal::ElectricalAngle => state.electrical_angle.load(Ordering::Relaxed).into_f32(),
+        ScopeSignal::Velocity => state.velocity.load(Ordering::Relaxed).into_f32(),
+        ScopeSignal::BusVoltage => state.v_bus.load(Ordering::Relaxed).into_f32(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+
+    fn config(mode: TriggerMode) -> ScopeConfig {
+        ScopeConfig {
+            channels: [
+                Some(ScopeSignal::CurrentQ),
+                Some(ScopeSignal::Velocity),
+                None,
+                None,
+            ],
+            trigger: Trigger {
+                mode,
+                channel: 1,
+                level: 5.0,
+            },
+            length: 8,
+            pre_trigger: 2,
+            decimation: 1,
+        }
+    }
+
+    /// Feeds `values` to the velocity channel and their negation to the current channel
+    fn feed(scope: &mut Scope, values: impl IntoIterator<Item = f32>) {
+        for value in values {
+            scope.sample(|signal| match signal {
+                ScopeSignal::Velocity => value,
+                _ => -value,
+            });
+        }
+    }
+
+    fn captured_velocity(scope: &Scope) -> [f32; 8] {
+        core::array::from_fn(|i| scope.captured_sample(i * 2 + 1).unwrap())
+    }
+
+    #[test]
+    fn invalid_config_should_be_rejected() {
+        let mut scope = Scope::new();
+        let mut gap = config(TriggerMode::Manual);
+        gap.channels = [
+            Some(ScopeSignal::CurrentD),
+            None,
+            Some(ScopeSignal::CurrentQ),
+            None,
+        ];
+        let mut too_long = config(TriggerMode::Manual);
+        too_long.length = SCOPE_BUFFER_SIZE;
+        let mut pre_trigger = config(TriggerMode::Manual);
+        pre_trigger.pre_trigger = 8;
+        let mut trigger_channel = config(TriggerMode::Manual);
+        trigger_channel.trigger.channel = 2;
+
+        for config in [gap, too_long, pre_trigger, trigger_channel] {
+            assert_eq!(scope.configure(config), Err(InvalidScopeConfig));
+        }
+        assert_eq!(scope.state(), ScopeState::Idle);
+        assert_eq!(scope.config().channels, [None; MAX_SCOPE_CHANNELS]);
+        assert_eq!(scope.config().decimation, 1);
+    }
+
+    #[test]
+    fn rising_edge_should_keep_the_samples_before_the_trigger() {
+        let mut scope = Scope::new();
+        scope.configure(config(TriggerMode::RisingEdge)).unwrap();
+        feed(
+            &mut scope,
+            [6.0, 1.0, 2.0, 3.0, 4.0, 6.0, 7.0, 8.0, 9.0, 10.0],
+        );
+        assert_eq!(scope.state(), ScopeState::Triggered);
+        feed(&mut scope, [11.0, 12.0]);
+        assert_eq!(scope.state(), ScopeState::Done);
+        assert_eq!(
+            captured_velocity(&scope),
+            [3.0, 4.0, 6.0, 7.0, 8.0, 9.0, 10.0, 11.0]
+        );
+        assert_eq!(scope.captured_sample(0), Some(-3.0));
+        assert_eq!(scope.captured_sample(16), None);
+
+        feed(&mut scope, [13.0]);
+        assert_eq!(captured_velocity(&scope)[7], 11.0);
+    }
+
+    #[test]
+    fn trigger_should_wait_for_the_samples_before_it() {
+        let mut scope = Scope::new();
+        scope.configure(config(TriggerMode::AboveLevel)).unwrap();
+        feed(&mut scope, [9.0, 9.0]);
+        assert_eq!(scope.state(), ScopeState::Armed);
+        feed(&mut scope, [9.0]);
+        assert_eq!(scope.state(), ScopeState::Triggered);
+    }
+
+    #[test]
+    fn manual_trigger_should_fire_in_every_mode() {
+        let mut scope = Scope::new();
+        scope.configure(config(TriggerMode::FallingEdge)).unwrap();
+        feed(&mut scope, [0.0; 4]);
+        assert_eq!(scope.state(), ScopeState::Armed);
+        scope.trigger();
+        feed(&mut scope, [0.0; 6]);
+        assert_eq!(scope.state(), ScopeState::Done);
+    }
+
+    #[test]
+    fn decimation_should_skip_control_steps() {
+        let mut scope = Scope::new();
+        let mut config = config(TriggerMode::Manual);
+        config.decimation = 3;
+        scope.configure(config).unwrap();
+        scope.trigger();
+        let step = Cell::new(0.0);
+        for _ in 0..24 {
+            step.set(step.get() + 1.0);
+            scope.sample(|_| step.get());
+        }
+        assert_eq!(scope.state(), ScopeState::Done);
+        assert_eq!(
+            captured_velocity(&scope),
+            [3.0, 6.0, 9.0, 12.0, 15.0, 18.0, 21.0, 24.0]
+        );
+    }
+}
@@ -1,17 +1,32 @@
+use crate::arming::ArmingState;
 use portable_atomic::{AtomicU8, AtomicU16, AtomicU32};
 use units::AtomicUnit;
 
 pub struct State {
     pub version: Version,
+    /// All zeros if the bootloader does not report its version
+    pub bootloader_version: Version,
+    pub board_revision: AtomicU8,
     pub raw_angle: AtomicU16,
     pub foc_loop_frequency: AtomicU32,
     pub encoder_loop_frequency: AtomicU32,
     pub last_foc_loop_time_us: AtomicU16,
+    /// Requests answered on any link since boot
+    pub requests_handled: AtomicU32,
     pub cpu_temp: AtomicUnit<units::ThermodynamicTemperature>,
     pub i_u: AtomicUnit<units::ElectricCurrent>,
     pub i_v: AtomicUnit<units::ElectricCurrent>,
     pub i_w: AtomicUnit<units::ElectricCurrent>,
     pub v_bus: AtomicUnit<units::ElectricPotential>,
+    /// Zero while the outputs are off
+    pub i_d: AtomicUnit<units::ElectricCurrent>,
+    pub i_q: AtomicUnit<units::ElectricCurrent>,
+    pub v_d: AtomicUnit<units::ElectricPotential>,
+    pub v_q: AtomicUnit<units::ElectricPotential>,
+    pub electrical_angle: AtomicUnit<units::Angle>,
+    pub velocity: AtomicUnit<units::AngularVelocity>,
+    /// Written by [`crate::arming::MotorController`], read it with [`crate::arming::arming_state`]
+    pub arming_state: AtomicU8,
 }
 
 pub struct Version {
@@ -30,15 +45,25 @@ impl State {
     pub const fn new() -> Self {
         Self {
             version: Version::new(),
+            bootloader_version: Version::new(),
+            board_revision: AtomicU8::new(0),
             raw_angle: AtomicU16::new(0),
             foc_loop_frequency: AtomicU32::new(0),
             encoder_loop_frequency: AtomicU32::new(0),
             last_foc_loop_time_us: AtomicU16::new(0),
+            requests_handled: AtomicU32::new(0),
             cpu_temp: AtomicUnit::zero(),
             i_u: AtomicUnit::zero(),
             i_v: AtomicUnit::zero(),
             i_w: AtomicUnit::zero(),
             v_bus: AtomicUnit::zero(),
+            i_d: AtomicUnit::zero(),
+            i_q: AtomicUnit::zero(),
+            v_d: AtomicUnit::zero(),
+            v_q: AtomicUnit::zero(),
+            electrical_angle: AtomicUnit::zero(),
+            velocity: AtomicUnit::zero(),
+            arming_state: AtomicU8::new(ArmingState::Boot as u8),
         }
     }
 }
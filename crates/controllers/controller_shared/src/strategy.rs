@@ -1,6 +1,13 @@
+use crate::motion::{PositionControl, VelocityControl};
 use foc::state::FocState;
+use units::DutyCycle;
 
 pub enum ControlStrategy {
     Disabled,
+    /// Current control, torque setpoints end up here as well
     Foc(FocState),
+    Velocity(VelocityControl),
+    Position(PositionControl),
+    /// Open loop voltage on the q axis
+    DutyCycle(DutyCycle),
 }
@@ -1,21 +1,103 @@
 use crate::clarke_transformation::balanced_clarke_transformation;
 use crate::park_transformation::{inverse_park_transformation, park_transformation};
 use crate::snapshot::{FocInput, FocOutput};
-use crate::space_vector_modulation::alternate_reverse_space_vector_modulation;
+use crate::space_vector_modulation::{
+    MAX_MODULATION_INDEX, alternate_reverse_space_vector_modulation,
+};
 use crate::state::FocState;
+use units::{DutyCycle, ElectricCurrent, ElectricPotential, F32UnitType};
 
 // TODO make sure the alpha beta to V_Bus / sqrt(3)
 pub fn foc_step(input: FocInput, state: &mut FocState) -> FocOutput {
-    let (alpha, beta) = balanced_clarke_transformation(input.u, input.v, input.w);
-
-    let (d, q) = park_transformation(alpha, beta, input.angle.sin, input.angle.cos);
+    let (d, q) = dq_currents(&input);
     let d_error = state.d_requested - d;
     let q_error = state.q_requested - q;
 
     let d_ref = state.id_pi.step(d_error);
     let q_ref = state.iq_pi.step(q_error);
 
-    let (alpha, beta) = inverse_park_transformation(d_ref, q_ref, input.angle.sin, input.angle.cos);
+    modulate(&input, (d, q), d_ref, q_ref)
+}
+
+/// Open loop voltage control, `duty_cycle` is the share of the largest voltage the modulation can
+/// put on the q axis, from -1 to 1
+pub fn duty_cycle_step(input: FocInput, duty_cycle: DutyCycle) -> FocOutput {
+    let q = input.v_bus * MAX_MODULATION_INDEX * duty_cycle.value.clamp(-1.0, 1.0);
+    modulate(
+        &input,
+        dq_currents(&input),
+        ElectricPotential::from_f32(0.0),
+        q,
+    )
+}
+
+fn dq_currents(input: &FocInput) -> (ElectricCurrent, ElectricCurrent) {
+    let (alpha, beta) = balanced_clarke_transformation(input.u, input.v, input.w);
+    park_transformation(alpha, beta, input.angle.sin, input.angle.cos)
+}
+
+fn modulate(
+    input: &FocInput,
+    (i_d, i_q): (ElectricCurrent, ElectricCurrent),
+    v_d: ElectricPotential,
+    v_q: ElectricPotential,
+) -> FocOutput {
+    let angle = &input.angle;
+    let (alpha, beta) = inverse_park_transformation(v_d, v_q, angle.sin, angle.cos);
     let (u, v, w) = alternate_reverse_space_vector_modulation(alpha, beta, input.v_bus);
-    FocOutput { u, v, w }
+    FocOutput {
+        u,
+        v,
+        w,
+        i_d,
+        i_q,
+        v_d,
+        v_q,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::AngleSnapshot;
+    use units::si::angle::radian;
+    use units::{Angle, Ratio};
+
+    fn input(angle: f32) -> FocInput {
+        FocInput {
+            v_bus: ElectricPotential::from_f32(24.0),
+            angle: AngleSnapshot {
+                value: Angle::new::<radian>(angle),
+                sin: angle.sin(),
+                cos: angle.cos(),
+            },
+            u: ElectricCurrent::from_f32(0.0),
+            v: ElectricCurrent::from_f32(0.0),
+            w: ElectricCurrent::from_f32(0.0),
+        }
+    }
+
+    #[test]
+    fn zero_duty_cycle_should_center_all_phases() {
+        let output = duty_cycle_step(input(1.0), Ratio::from(0.0));
+        for phase in [output.u, output.v, output.w] {
+            assert!((phase.value - 0.5).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn full_duty_cycle_should_stay_within_bounds() {
+        for deg in 0..360 {
+            for duty_cycle in [-1.5, -1.0, 1.0, 1.5] {
+                let output =
+                    duty_cycle_step(input((deg as f32).to_radians()), Ratio::from(duty_cycle));
+                for phase in [output.u, output.v, output.w] {
+                    assert!(
+                        (-1e-4..=1.0 + 1e-4).contains(&phase.value),
+                        "{phase:?} at {deg}°"
+                    );
+                }
+            }
+        }
+    }
 }
@@ -17,4 +17,10 @@ pub struct FocOutput {
     pub u: DutyCycle,
     pub v: DutyCycle,
     pub w: DutyCycle,
+    /// Measured currents in the rotor frame
+    pub i_d: ElectricCurrent,
+    pub i_q: ElectricCurrent,
+    /// Voltages requested from the modulation
+    pub v_d: ElectricPotential,
+    pub v_q: ElectricPotential,
 }
@@ -3,6 +3,8 @@ use units::{DutyCycle, ElectricPotential, Ratio};
 
 pub const ONE_OVER_SQRT3: f32 = 0.577_350_26_f32;
 pub const TWO_OVER_SQRT3: f32 = ONE_OVER_SQRT3 * 2f32;
+/// Largest alpha beta vector, relative to the bus voltage, that stays in the linear range
+pub const MAX_MODULATION_INDEX: f32 = 0.866_025_4_f32;
 
 pub fn alternate_reverse_space_vector_modulation(
     alpha: ElectricPotential,
@@ -1,7 +1,8 @@
 use std::env;
 use std::fs::File;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 
 fn main() {
     let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
@@ -13,4 +14,42 @@ fn main() {
     println!("cargo:rustc-link-arg-bins=--nmagic");
     println!("cargo:rustc-link-arg-bins=-Tlink.x");
     println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
+
+    write_git_hash(out);
+}
+
+/// Embeds the commit the image is built from into its metadata header
+fn write_git_hash(out: &Path) {
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .args(args)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+            .map(|text| text.trim().to_string())
+    };
+    let hash: Vec<u8> = git(&["rev-parse", "HEAD"])
+        .filter(|hash| hash.len() == 40)
+        .map(|hash| {
+            (0..hash.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hash[i..i + 2], 16).unwrap_or(0))
+                .collect()
+        })
+        .unwrap_or_else(|| {
+            println!("cargo:warning=Not building from a git checkout, the git hash stays empty");
+            vec![0; 20]
+        });
+    if let Some(git_dir) = git(&["rev-parse", "--absolute-git-dir"]) {
+        println!("cargo:rerun-if-changed={git_dir}/HEAD");
+        if let Some(reference) = git(&["symbolic-ref", "-q", "HEAD"]) {
+            println!("cargo:rerun-if-changed={git_dir}/{reference}");
+        }
+    }
+
+    File::create(out.join("git_hash.rs"))
+        .unwrap()
+        .write_all(format!("const GIT_HASH: [u8; 20] = {hash:?};\n").as_bytes())
+        .unwrap();
 }
@@ -2,13 +2,33 @@ MEMORY
 {
     BOOTLOADER                        : ORIGIN = 0x08000000, LENGTH =  48K
     BOOTLOADER_STATE                  : ORIGIN = 0x0800C000, LENGTH =   8K
-    FLASH                             : ORIGIN = 0x0800E000, LENGTH = 200K
-    DFU                               : ORIGIN = 0x08040000, LENGTH = 256K
+    FAULT_JOURNAL                     : ORIGIN = 0x0800E000, LENGTH =   2K
+    FLASH                             : ORIGIN = 0x0800E800, LENGTH = 226K
+    DFU                               : ORIGIN = 0x08047000, LENGTH = 228K
     RAM   (rwx)                       : ORIGIN = 0x20000000, LENGTH = 128K
 }
 
 __bootloader_state_start = ORIGIN(BOOTLOADER_STATE) - ORIGIN(BOOTLOADER);
 __bootloader_state_end = ORIGIN(BOOTLOADER_STATE) + LENGTH(BOOTLOADER_STATE) - ORIGIN(BOOTLOADER);
 
-__bootloader_dfu_start = ORIGIN(DFU) - ORIGIN(BOOTLOADER);
-__bootloader_dfu_end = ORIGIN(DFU) + LENGTH(DFU) - ORIGIN(BOOTLOADER);
\ No newline at end of file
+/* The application spans both banks, the DFU partition is reached through the second bank only */
+__flash_bank2 = ORIGIN(BOOTLOADER) + 256K;
+__bootloader_dfu_start = ORIGIN(DFU) - __flash_bank2;
+__bootloader_dfu_end = ORIGIN(DFU) + LENGTH(DFU) - __flash_bank2;
+
+__fault_journal_start = ORIGIN(FAULT_JOURNAL) - ORIGIN(BOOTLOADER);
+__fault_journal_end = ORIGIN(FAULT_JOURNAL) + LENGTH(FAULT_JOURNAL) - ORIGIN(BOOTLOADER);
+
+/* Written by the bootloader into the last bytes of its own region */
+__bootloader_info_start = ORIGIN(BOOTLOADER) + LENGTH(BOOTLOADER) - 16;
+
+/* The image header goes behind the vector table, at an offset the bootloader and host tools know */
+_stext = ORIGIN(FLASH) + 0x200 + 64;
+
+SECTIONS
+{
+    .image_header ORIGIN(FLASH) + 0x200 : { KEEP(*(.image_header)); } > FLASH
+} INSERT AFTER .vector_table;
+
+/* HEADER_OFFSET of firmware-image, the bootloader and the host tools look for the header there */
+ASSERT(ADDR(.image_header) - ORIGIN(FLASH) == 0x200, "The image header moved away from HEADER_OFFSET");
@@ -1,16 +1,18 @@
-use controller_shared::strategy::ControlStrategy;
-use controller_shared::{control_step, update_strategy, RawSnapshot};
+use controller_shared::{scope, MotorController, RawSnapshot};
 use core::sync::atomic::Ordering;
 use embassy_futures::join::join5;
 use embassy_time::{with_timeout, Duration, Instant};
 use hardware::{BoardAdc, BoardInverter};
+use logging::fault_register::FaultRegister;
 use logging::FreqMeter;
+use user_config::UserConfig;
 use crate::app::communication::CONTROL_COMMAND_CHANNEL;
 
 #[embassy_executor::task]
 pub async fn task_adc(
     adc: BoardAdc<'static>,
     mut inverter: BoardInverter<'static>,
+    user_config: &'static UserConfig,
 ) {
     let adc_1 = adc.adc1_running;
     let adc_2 = adc.adc2_running;
@@ -24,7 +26,7 @@ pub async fn task_adc(
     let mut freq_meter = FreqMeter::named("ADC");
     freq_meter.link(&controller_state.foc_loop_frequency);
 
-    let mut strategy = ControlStrategy::Disabled;
+    let mut controller = MotorController::new().with_policies(user_config.fault_policies);
 
     loop {
         let result = with_timeout(
@@ -65,8 +67,12 @@ pub async fn task_adc(
             Err(_) => None,
         };
 
-        strategy = update_strategy(&CONTROL_COMMAND_CHANNEL, strategy);
-        let pwm = control_step(&raw_reading, &mut strategy);
+        controller.update(
+            CONTROL_COMMAND_CHANNEL.try_receive().ok(),
+            FaultRegister::shared(),
+        );
+        let pwm = controller.step(&raw_reading, start_time);
+        scope::record();
 
         match pwm {
             Some(values) => {
@@ -86,7 +92,9 @@ pub async fn task_adc(
         }
 
         freq_meter.tick();
-        let elapsed_us = start_time.elapsed().as_micros() as u16;
+        let elapsed = start_time.elapsed();
+        controller.check_loop_time(elapsed);
+        let elapsed_us = elapsed.as_micros() as u16;
         controller_state
             .last_foc_loop_time_us
             .store(elapsed_us, Ordering::Relaxed);
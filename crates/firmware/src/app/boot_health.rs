@@ -0,0 +1,73 @@
+use command_handler::boot_health::{self, Health, Trial, Verdict};
+use controller_shared::policy::FaultPolicies;
+use embassy_boot_stm32::{AlignedBuffer, BlockingFirmwareState, FirmwareUpdaterConfig, State};
+use embassy_stm32::flash::WRITE_SIZE;
+use embassy_time::{Duration, Instant, Timer};
+use hardware::{BoardFlashBank1, BoardFlashBank2, BoardWatchdog};
+use logging::{error, info, warn};
+use static_cell::StaticCell;
+use transport::firmware::BootOutcome;
+
+/// How often the health checks run, well within the watchdog timeout
+const CHECK_PERIOD: Duration = Duration::from_millis(100);
+
+static ALIGNED_BUFFER: StaticCell<AlignedBuffer<WRITE_SIZE>> = StaticCell::new();
+
+/// Confirms the image for the bootloader. A new image runs on trial until its health checks pass,
+/// if they do not within the trial window the watchdog resets and the bootloader reverts.
+#[embassy_executor::task]
+pub async fn task_boot_health(
+    mut watchdog: BoardWatchdog<'static>,
+    flash_bank1: &'static BoardFlashBank1<'static>,
+    flash_bank2: &'static BoardFlashBank2<'static>,
+    fault_policies: FaultPolicies,
+) {
+    let aligned_buffer = ALIGNED_BUFFER.init(AlignedBuffer([0; WRITE_SIZE]));
+    let firmware_config = FirmwareUpdaterConfig::from_linkerfile_blocking(flash_bank2, flash_bank1);
+    let mut firmware_state =
+        BlockingFirmwareState::from_config(firmware_config, &mut aligned_buffer.0);
+
+    match firmware_state
+        .get_state()
+        .expect("Failed to read the boot state")
+    {
+        State::Swap => {}
+        State::Revert => {
+            warn!("The update failed its trial boot, the previous firmware was restored");
+            boot_health::set_outcome(BootOutcome::RolledBack);
+            firmware_state.mark_booted().expect("Failed to mark booted");
+            return;
+        }
+        _ => {
+            boot_health::set_outcome(BootOutcome::Confirmed);
+            firmware_state.mark_booted().expect("Failed to mark booted");
+            return;
+        }
+    }
+
+    info!("Running a new image on trial");
+    boot_health::set_outcome(BootOutcome::Trial);
+    watchdog.unleash();
+    let trial = Trial::new(Instant::now());
+    loop {
+        let health = Health::sample(&fault_policies);
+        match trial.check(health, Instant::now()) {
+            Verdict::Pending => {}
+            Verdict::Confirm => break,
+            Verdict::Revert => {
+                error!("The new image failed its health checks: {:?}", health);
+                core::future::pending::<()>().await;
+            }
+        }
+        watchdog.pet();
+        Timer::after(CHECK_PERIOD).await;
+    }
+
+    firmware_state.mark_booted().expect("Failed to mark booted");
+    boot_health::set_outcome(BootOutcome::Confirmed);
+    info!("The new image passed its health checks and is confirmed");
+    loop {
+        watchdog.pet();
+        Timer::after(CHECK_PERIOD).await;
+    }
+}
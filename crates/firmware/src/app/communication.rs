@@ -1,21 +1,79 @@
-use hardware::BoardCrc;
+use hardware::{BoardCrc, BoardFlashBank1, BoardFlashBank2};
 
+use command_handler::firmware_update::{FirmwareTarget, FirmwareUpdate};
 use communication::channel_types::{CommandChannel, EventChannel};
 use controller_shared::command::ControlCommandChannel;
+use embassy_boot_stm32::{AlignedBuffer, FirmwareUpdaterConfig};
+use embassy_futures::join::join;
+use embassy_stm32::flash::WRITE_SIZE;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::channel::Channel;
 use embassy_sync::pubsub::PubSubChannel;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Timer};
+use logging::info;
+use static_cell::StaticCell;
+use transport::command::FirmwareBlock;
+use transport::event::ErrorCode;
+use transport::firmware::FirmwareSlots;
 
 pub static COMMAND_CHANNEL: CommandChannel = Channel::new();
 pub static EVENT_CHANNEL: EventChannel = PubSubChannel::new();
 pub static CONTROL_COMMAND_CHANNEL: ControlCommandChannel = ControlCommandChannel::new();
 
+/// Gives the response to FinalizeFirmwareUpdate time to go out before the reset
+const RESET_DELAY: Duration = Duration::from_millis(200);
+
+static ALIGNED_BUFFER: StaticCell<AlignedBuffer<WRITE_SIZE>> = StaticCell::new();
+static UPDATE_FINALIZED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Resets into the bootloader once an update was finalized, so it swaps the images
+struct ResetAfterUpdate<T>(T);
+
+impl<T: FirmwareTarget> FirmwareTarget for ResetAfterUpdate<T> {
+    fn write_block(&mut self, block: &FirmwareBlock) -> Result<(), ErrorCode> {
+        self.0.write_block(block)
+    }
+
+    fn finalize(&mut self) -> Result<(), ErrorCode> {
+        self.0.finalize().inspect(|_| UPDATE_FINALIZED.signal(()))
+    }
+
+    fn read_slots(&mut self) -> Result<FirmwareSlots, ErrorCode> {
+        self.0.read_slots()
+    }
+}
+
 #[embassy_executor::task]
-pub async fn task_communication(mut crc: BoardCrc<'static>) {
-    communication::run(
-        &COMMAND_CHANNEL,
-        &EVENT_CHANNEL,
-        &CONTROL_COMMAND_CHANNEL,
-        &mut crc
+pub async fn task_communication(
+    mut crc: BoardCrc<'static>,
+    flash_bank1: &'static BoardFlashBank1<'static>,
+    flash_bank2: &'static BoardFlashBank2<'static>,
+) {
+    let aligned_buffer = ALIGNED_BUFFER.init(AlignedBuffer([0; WRITE_SIZE]));
+    let firmware_config = FirmwareUpdaterConfig::from_linkerfile_blocking(flash_bank2, flash_bank1);
+    let mut firmware = ResetAfterUpdate(FirmwareUpdate::new(
+        firmware_config,
+        &mut aligned_buffer.0,
+        crate::version::image_header(),
+    ));
+
+    join(
+        communication::run(
+            &COMMAND_CHANNEL,
+            &EVENT_CHANNEL,
+            &CONTROL_COMMAND_CHANNEL,
+            &mut crc,
+            &mut firmware,
+        ),
+        reset_after_update(),
     )
     .await;
 }
+
+async fn reset_after_update() {
+    UPDATE_FINALIZED.wait().await;
+    info!("Resetting to apply the firmware update");
+    Timer::after(RESET_DELAY).await;
+    cortex_m::peripheral::SCB::sys_reset();
+}
@@ -0,0 +1,75 @@
+use controller_shared::arming::{ArmingState, arming_state};
+use embassy_embedded_hal::flash::partition::BlockingPartition;
+use embassy_stm32::flash::{Bank1Region, Blocking};
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_time::{Duration, Timer};
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use hardware::BoardFlashBank1;
+use logging::fault_journal::{self, JOURNAL_SIZE};
+use logging::fault_register::{FaultRegister, FaultType};
+use logging::{error, info};
+
+/// How often a changed journal is written to flash
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+type JournalPartition<'a> = BlockingPartition<'a, NoopRawMutex, Bank1Region<'a, Blocking>>;
+
+fn partition<'a>(flash_bank1: &'a BoardFlashBank1<'a>) -> JournalPartition<'a> {
+    unsafe extern "C" {
+        static __fault_journal_start: u32;
+        static __fault_journal_end: u32;
+    }
+    let start = &raw const __fault_journal_start as u32;
+    let end = &raw const __fault_journal_end as u32;
+    BlockingPartition::new(flash_bank1, start, end - start)
+}
+
+/// Continues the journal stored by the previous boot
+pub fn restore_fault_journal<'a>(flash_bank1: &'a BoardFlashBank1<'a>) {
+    let mut buffer = [0; JOURNAL_SIZE];
+    if let Err(error) = partition(flash_bank1).read(0, &mut buffer) {
+        error!("Failed to read the fault journal: {:?}", error);
+        return;
+    }
+    if !fault_journal::restore(&buffer) {
+        info!("No fault journal stored, starting a new one");
+    }
+}
+
+#[embassy_executor::task]
+pub async fn task_fault_journal(flash_bank1: &'static BoardFlashBank1<'static>) {
+    let mut partition = partition(flash_bank1);
+    let mut buffer = [0; JOURNAL_SIZE];
+    loop {
+        Timer::after(SAVE_INTERVAL).await;
+        // Erasing stalls the CPU, which would stall the control loop as well
+        let state = arming_state();
+        if state.is_armed() || state == ArmingState::Calibrating {
+            continue;
+        }
+        let dirty = fault_journal::journal().lock(|journal| {
+            let mut journal = journal.borrow_mut();
+            let dirty = journal.is_dirty();
+            if dirty {
+                journal.to_bytes(&mut buffer);
+            }
+            dirty
+        });
+        if !dirty {
+            continue;
+        }
+
+        let capacity = partition.capacity() as u32;
+        let result = partition
+            .erase(0, capacity)
+            .and_then(|_| partition.write(0, &buffer));
+        let faults = FaultRegister::shared();
+        match result {
+            Ok(()) => faults.resolve_if_set(FaultType::FlashError),
+            Err(error) => {
+                error!("Failed to store the fault journal: {:?}", error);
+                faults.set(FaultType::FlashError);
+            }
+        }
+    }
+}
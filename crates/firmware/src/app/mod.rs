@@ -1,13 +1,17 @@
 mod adc;
+mod boot_health;
 mod communication;
+mod fault_journal;
 mod leds;
 mod shaft_position;
 mod uart;
 mod usb;
 
 pub use adc::task_adc;
+pub use boot_health::task_boot_health;
 pub use communication::task_communication;
 pub use communication::{COMMAND_CHANNEL, EVENT_CHANNEL};
+pub use fault_journal::{restore_fault_journal, task_fault_journal};
 pub use leds::task_leds;
 pub use shaft_position::task_shaft_position;
 pub use uart::task_uart;
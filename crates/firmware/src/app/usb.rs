@@ -50,9 +50,7 @@ pub async fn task_usb(
     let aligned_buffer = ALIGNED_BUFFER.init(AlignedBuffer([0; WRITE_SIZE]));
 
     let firmware_config = FirmwareUpdaterConfig::from_linkerfile_blocking(flash_bank2, flash_bank1);
-    let mut firmware_state =
-        BlockingFirmwareState::from_config(firmware_config, &mut aligned_buffer.0);
-    firmware_state.mark_booted().expect("Failed to mark booted");
+    let firmware_state = BlockingFirmwareState::from_config(firmware_config, &mut aligned_buffer.0);
 
     let dfu_handler = DfuHandler { firmware_state };
     let dfu_state = DFU_STATE.init(DfuState::new(
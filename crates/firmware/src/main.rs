@@ -44,12 +44,15 @@ fn main() -> ! {
     let serial_number = SERIAL_NUMBER.init(board.serial_number);
     let flash_bank1 = FLASH_BANK1.init(board.flash_bank1);
     let flash_bank2 = FLASH_BANK2.init(board.flash_bank2);
+    if user_config.persist_fault_journal {
+        app::restore_fault_journal(flash_bank1);
+    }
 
     let usb_config = get_usb_config(serial_number);
 
     interrupt::UART4.set_priority(Priority::P6);
     let high_priority_spawner = EXECUTOR_HIGH.start(interrupt::UART4);
-    high_priority_spawner.spawn(app::task_adc(board.adc, board.inverter).unwrap());
+    high_priority_spawner.spawn(app::task_adc(board.adc, board.inverter, user_config).unwrap());
 
     interrupt::UART5.set_priority(Priority::P7);
     let medium_priority_spawner = EXECUTOR_MED.start(interrupt::UART5);
@@ -57,10 +60,23 @@ fn main() -> ! {
 
     let low_priority_executor = EXECUTOR_LOW.init(Executor::new());
     low_priority_executor.run(|low_priority_spawner| {
-        low_priority_spawner.spawn(app::task_communication(board.crc).unwrap());
+        low_priority_spawner
+            .spawn(app::task_communication(board.crc, flash_bank1, flash_bank2).unwrap());
         low_priority_spawner.spawn(app::task_uart(board.uart).unwrap());
         low_priority_spawner.spawn(app::task_leds(board.leds).unwrap());
         low_priority_spawner
             .spawn(app::task_usb(board.usb, usb_config, flash_bank1, flash_bank2).unwrap());
+        low_priority_spawner.spawn(
+            app::task_boot_health(
+                board.watchdog,
+                flash_bank1,
+                flash_bank2,
+                user_config.fault_policies,
+            )
+            .unwrap(),
+        );
+        if user_config.persist_fault_journal {
+            low_priority_spawner.spawn(app::task_fault_journal(flash_bank1).unwrap());
+        }
     });
 }
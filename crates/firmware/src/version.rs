@@ -1,21 +1,49 @@
-use logging::info;
+use controller_shared::state::Version;
+use core::sync::atomic::Ordering;
+use firmware_image::ImageHeader;
+use logging::{info, warn};
+
+include!(concat!(env!("OUT_DIR"), "/git_hash.rs"));
+
+const VERSION: [u8; 3] = parse_version(env!("CARGO_PKG_VERSION"));
+
+#[used]
+#[unsafe(link_section = ".image_header")]
+static IMAGE_HEADER: ImageHeader = ImageHeader::new(VERSION, hardware::BOARD_REVISION, GIT_HASH);
+
+/// The header as the signing tool left it, the build only knows the fields it filled in
+pub fn image_header() -> ImageHeader {
+    unsafe { core::ptr::read_volatile(&raw const IMAGE_HEADER) }
+}
 
 pub fn populate_version() {
-    let version = parse_version(env!("CARGO_PKG_VERSION"));
+    let version = VERSION;
     info!("Version: {}.{}.{}", version[0], version[1], version[2]);
+    if image_header().length == 0 {
+        warn!("The image was not stamped, its length and digest are unknown");
+    }
     let controller_state = controller_shared::state::state();
+    store_version(&controller_state.version, version);
+
+    match hardware::BootloaderInfo::read() {
+        Some(bootloader_version) => {
+            info!(
+                "Bootloader version: {}.{}.{}",
+                bootloader_version[0], bootloader_version[1], bootloader_version[2]
+            );
+            store_version(&controller_state.bootloader_version, bootloader_version);
+        }
+        None => warn!("Bootloader does not report its version"),
+    }
     controller_state
-        .version
-        .major
-        .store(version[0], core::sync::atomic::Ordering::Relaxed);
-    controller_state
-        .version
-        .minor
-        .store(version[1], core::sync::atomic::Ordering::Relaxed);
-    controller_state
-        .version
-        .patch
-        .store(version[2], core::sync::atomic::Ordering::Relaxed);
+        .board_revision
+        .store(hardware::BOARD_REVISION, Ordering::Relaxed);
+}
+
+fn store_version(target: &Version, version: [u8; 3]) {
+    target.major.store(version[0], Ordering::Relaxed);
+    target.minor.store(version[1], Ordering::Relaxed);
+    target.patch.store(version[2], Ordering::Relaxed);
 }
 
 const fn parse_version(version: &str) -> [u8; 3] {
@@ -5,7 +5,10 @@ use adc::{Adc, Continuous, Taken};
 use crc_engine::hardware::HardwareCrcEngine;
 #[cfg(feature = "full")]
 use embassy_stm32::can::Can;
+#[cfg(feature = "full")]
 use embassy_stm32::flash::{Bank1Region, Bank2Region, Blocking};
+#[cfg(not(feature = "full"))]
+use embassy_stm32::flash::{Blocking, Flash};
 use embassy_stm32::gpio::Output;
 #[cfg(feature = "full")]
 use embassy_stm32::i2c::I2c;
@@ -14,7 +17,7 @@ use embassy_stm32::mode::Async;
 #[cfg(not(feature = "full"))]
 use embassy_stm32::peripherals::USB;
 #[cfg(feature = "full")]
-use embassy_stm32::peripherals::{ADC1, ADC2, ADC3, ADC4, ADC5, TIM1, USB};
+use embassy_stm32::peripherals::{ADC1, ADC2, ADC3, ADC4, ADC5, IWDG, TIM1, USB};
 #[cfg(feature = "full")]
 use embassy_stm32::spi::Spi;
 #[cfg(feature = "full")]
@@ -23,6 +26,8 @@ use embassy_stm32::usart::Uart;
 use embassy_stm32::usb;
 #[cfg(feature = "full")]
 use embassy_stm32::{i2c, spi, usb};
+#[cfg(feature = "full")]
+use embassy_stm32::wdg::IndependentWatchdog;
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
 use embassy_sync::blocking_mutex::Mutex;
 #[cfg(feature = "full")]
@@ -35,7 +40,11 @@ pub struct Board<'a> {
     pub can: BoardCan<'a>,
     #[cfg(feature = "full")]
     pub crc: BoardCrc<'a>,
+    #[cfg(not(feature = "full"))]
+    pub flash: BoardFlash<'a>,
+    #[cfg(feature = "full")]
     pub flash_bank1: BoardFlashBank1<'a>,
+    #[cfg(feature = "full")]
     pub flash_bank2: BoardFlashBank2<'a>,
     #[cfg(feature = "full")]
     pub ext_i2c: BoardI2c<'a>,
@@ -52,6 +61,8 @@ pub struct Board<'a> {
     pub uart: BoardUart<'a>,
     pub usb: BoardUsb<'a>,
     pub serial_number: BoardSerialNumber,
+    #[cfg(feature = "full")]
+    pub watchdog: BoardWatchdog<'a>,
 }
 
 #[cfg(feature = "full")]
@@ -74,7 +85,12 @@ pub type BoardCan<'a> = Can<'a>;
 
 #[cfg(feature = "full")]
 pub type BoardCrc<'a> = HardwareCrcEngine<'a>;
+/// The whole flash, the active partition of the bootloader spans both banks
+#[cfg(not(feature = "full"))]
+pub type BoardFlash<'a> = Mutex<NoopRawMutex, RefCell<Flash<'a, Blocking>>>;
+#[cfg(feature = "full")]
 pub type BoardFlashBank1<'a> = Mutex<NoopRawMutex, RefCell<Bank1Region<'a, Blocking>>>;
+#[cfg(feature = "full")]
 pub type BoardFlashBank2<'a> = Mutex<NoopRawMutex, RefCell<Bank2Region<'a, Blocking>>>;
 
 #[cfg(feature = "full")]
@@ -90,4 +106,7 @@ pub type BoardSpi<'a> = Spi<'a, Async, spi::mode::Master>;
 #[cfg(feature = "full")]
 pub type BoardUart<'a> = Uart<'a, Async>;
 pub type BoardUsb<'a> = usb::Driver<'a, USB>;
+/// Not running until it is unleashed, it cannot be stopped afterwards
+#[cfg(feature = "full")]
+pub type BoardWatchdog<'a> = IndependentWatchdog<'a, IWDG>;
 pub type BoardSerialNumber = [u8; 24];
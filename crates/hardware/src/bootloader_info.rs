@@ -0,0 +1,29 @@
+/// Record the bootloader keeps in the last bytes of its flash region, so the application can
+/// report which bootloader it was started by
+#[repr(C)]
+pub struct BootloaderInfo {
+    magic: u32,
+    version: [u8; 3],
+    _reserved: u8,
+}
+
+const MAGIC: u32 = 0x424C_4931;
+
+impl BootloaderInfo {
+    pub const fn new(version: [u8; 3]) -> Self {
+        Self {
+            magic: MAGIC,
+            version,
+            _reserved: 0,
+        }
+    }
+
+    /// Returns `None` if the bootloader is too old to leave a record behind
+    pub fn read() -> Option<[u8; 3]> {
+        unsafe extern "C" {
+            static __bootloader_info_start: BootloaderInfo;
+        }
+        let info = unsafe { core::ptr::read_volatile(&raw const __bootloader_info_start) };
+        (info.magic == MAGIC).then_some(info.version)
+    }
+}
@@ -29,6 +29,8 @@ use embassy_stm32::time::Hertz;
 #[cfg(feature = "full")]
 use embassy_stm32::usart::Uart;
 #[cfg(feature = "full")]
+use embassy_stm32::wdg::IndependentWatchdog;
+#[cfg(feature = "full")]
 use embassy_stm32::{Peripherals, can, i2c, spi, usart, usb};
 #[cfg(not(feature = "full"))]
 use embassy_stm32::{Peripherals, usb};
@@ -38,6 +40,10 @@ use inverter::Inverter;
 #[cfg(feature = "full")]
 use user_config::UserConfig;
 
+/// Resets the MCU if the watchdog is not fed for this long
+#[cfg(feature = "full")]
+const WATCHDOG_TIMEOUT_US: u32 = 2_000_000;
+
 impl Board<'static> {
     pub fn init(#[cfg(feature = "full")] user_config: &UserConfig) -> Self {
         let peripherals = Self::configure_mcu();
@@ -254,12 +260,22 @@ impl Board<'static> {
             BoardLeds { green, red }
         };
 
-        let flash = Flash::new_blocking(peripherals.FLASH).into_blocking_regions();
-        let flash_bank1 = Mutex::new(RefCell::new(flash.bank1_region));
-        let flash_bank2 = Mutex::new(RefCell::new(flash.bank2_region));
+        #[cfg(feature = "full")]
+        let (flash_bank1, flash_bank2) = {
+            let flash = Flash::new_blocking(peripherals.FLASH).into_blocking_regions();
+            (
+                Mutex::new(RefCell::new(flash.bank1_region)),
+                Mutex::new(RefCell::new(flash.bank2_region)),
+            )
+        };
+        #[cfg(not(feature = "full"))]
+        let flash = Mutex::new(RefCell::new(Flash::new_blocking(peripherals.FLASH)));
 
         let serial_number = get_serial_number_as_hex();
 
+        #[cfg(feature = "full")]
+        let watchdog = IndependentWatchdog::new(peripherals.IWDG, WATCHDOG_TIMEOUT_US);
+
         #[cfg(feature = "full")]
         return Self {
             adc,
@@ -276,12 +292,12 @@ impl Board<'static> {
             uart,
             usb,
             serial_number,
+            watchdog,
         };
 
         #[cfg(not(feature = "full"))]
         Self {
-            flash_bank1,
-            flash_bank2,
+            flash,
             leds,
             usb,
             serial_number,
@@ -1,6 +1,7 @@
 #![no_std]
 
 mod board;
+mod bootloader_info;
 mod config;
 mod irqs;
 mod serial_number;
@@ -8,3 +9,7 @@ mod serial_number;
 pub mod usb;
 
 pub use board::*;
+pub use bootloader_info::BootloaderInfo;
+
+/// Revision of the PCB this crate is written for
+pub const BOARD_REVISION: u8 = 1;
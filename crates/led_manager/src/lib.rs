@@ -1,5 +1,7 @@
 #![no_std]
 
+#[cfg(feature = "hardware-support")]
+use controller_shared::arming::{ArmingState, arming_state};
 #[cfg(feature = "hardware-support")]
 use embassy_time::Timer;
 use embassy_time::{Duration, Instant};
@@ -99,9 +101,10 @@ impl LedAnimator {
 
 #[cfg(feature = "hardware-support")]
 fn calculate_visual_state() -> VisualState {
+    let arming_state = arming_state();
     VisualState {
-        armed: false,
-        calibrating: false,
+        armed: arming_state.is_armed(),
+        calibrating: arming_state == ArmingState::Calibrating,
         warning: FaultRegister::shared().any_latched(),
         fault: FaultRegister::shared().any_active(),
     }
@@ -7,6 +7,10 @@ fn main() {
             &[
                 "proto/pyrion/v1/discovery.proto",
                 "proto/pyrion/v1/session.proto",
+                "proto/pyrion/v1/scope.proto",
+                "proto/pyrion/v1/black_box.proto",
+                "proto/pyrion/v1/fault_history.proto",
+                "proto/pyrion/v1/firmware_update.proto",
             ],
             &["proto"],
         )
@@ -1,3 +1,4 @@
+use crate::features::session::Framing;
 use serde::Deserialize;
 
 pub fn get_configuration() -> Result<Configuration, config::ConfigError> {
@@ -15,6 +16,7 @@ pub fn get_configuration() -> Result<Configuration, config::ConfigError> {
 pub struct Configuration {
     pub application: ApplicationConfiguration,
     pub interfaces: InterfacesConfiguration,
+    pub session: SessionConfiguration,
 }
 
 #[derive(Deserialize)]
@@ -33,4 +35,15 @@ pub struct SerialConfiguration {
     pub enabled: bool,
     pub show_only_usb_devices: bool,
     pub hide_call_up_devices: bool,
+    /// Has to match the framing the firmware was built with
+    #[serde(default)]
+    pub framing: Framing,
+}
+
+#[derive(Deserialize)]
+pub struct SessionConfiguration {
+    /// Retransmit requests until the device answers them
+    pub reliable: bool,
+    pub retransmit_timeout_ms: u64,
+    pub max_attempts: u8,
 }
@@ -0,0 +1,95 @@
+use transport::black_box::{BlackBoxFrame, BlackBoxHeader};
+use transport::packet::Field;
+
+/// The downloaded record does not match its header
+#[derive(Debug, PartialEq, Eq)]
+pub enum InvalidRecord {
+    MissingHeader,
+    /// The device sends frames shorter than the ones this build knows
+    FrameTooShort(usize),
+    WrongSize {
+        expected: usize,
+        received: usize,
+    },
+}
+
+/// Parses the black box blob, fields a newer firmware appended to the frames are skipped
+pub fn parse_record(data: &[u8]) -> Result<Vec<BlackBoxFrame>, InvalidRecord> {
+    let header = BlackBoxHeader::read(data).map_err(|_| InvalidRecord::MissingHeader)?;
+    let frame_size = header.frame_size as usize;
+    if frame_size < BlackBoxFrame::SIZE {
+        return Err(InvalidRecord::FrameTooShort(frame_size));
+    }
+    let frames = &data[BlackBoxHeader::SIZE..];
+    let expected = header.frame_count as usize * frame_size;
+    if frames.len() != expected {
+        return Err(InvalidRecord::WrongSize {
+            expected,
+            received: frames.len(),
+        });
+    }
+
+    Ok(frames
+        .chunks_exact(frame_size)
+        .map(|frame| BlackBoxFrame::read(frame).unwrap())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use transport::black_box::StrategyKind;
+
+    fn frame(timestamp: u64) -> BlackBoxFrame {
+        BlackBoxFrame {
+            timestamp,
+            phase_currents: [1.0, -0.5, -0.5],
+            v_bus: 24.0,
+            electrical_angle: 0.5,
+            duty_cycles: [0.5, 0.5, 0.5],
+            strategy: StrategyKind::Current,
+            setpoint: [0.0, 2.0],
+            active_faults: 0,
+        }
+    }
+
+    fn record(frame_size: usize, frames: &[BlackBoxFrame]) -> Vec<u8> {
+        let header = BlackBoxHeader {
+            frame_count: frames.len() as u16,
+            frame_size: frame_size as u16,
+        };
+        let mut data = vec![0; BlackBoxHeader::SIZE + frames.len() * frame_size];
+        header.write(&mut data);
+        for (frame, buffer) in frames
+            .iter()
+            .zip(data[BlackBoxHeader::SIZE..].chunks_exact_mut(frame_size))
+        {
+            frame.write(buffer);
+        }
+        data
+    }
+
+    #[test]
+    fn frames_of_a_newer_firmware_should_be_parsed() {
+        let frames = [frame(1), frame(2)];
+        let data = record(BlackBoxFrame::SIZE + 8, &frames);
+        assert_eq!(parse_record(&data), Ok(frames.to_vec()));
+    }
+
+    #[test]
+    fn record_of_wrong_size_should_be_rejected() {
+        let mut data = record(BlackBoxFrame::SIZE, &[frame(1), frame(2)]);
+        data.pop();
+        assert_eq!(
+            parse_record(&data),
+            Err(InvalidRecord::WrongSize {
+                expected: 2 * BlackBoxFrame::SIZE,
+                received: 2 * BlackBoxFrame::SIZE - 1,
+            })
+        );
+        assert_eq!(
+            parse_record(&record(4, &[])),
+            Err(InvalidRecord::FrameTooShort(4))
+        );
+    }
+}
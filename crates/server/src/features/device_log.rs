@@ -0,0 +1,71 @@
+use transport::log::{LogLevel, LogRecord};
+
+/// Fills the placeholders of the message with the arguments, the way the firmware would have
+/// printed it
+pub fn format_record(record: &LogRecord) -> String {
+    let message = record.message();
+    let mut args = record.args();
+    let mut formatted = String::with_capacity(message.len());
+    let mut rest = message;
+    while let Some(start) = rest.find(['{', '}']) {
+        formatted.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if rest.starts_with("{{") || rest.starts_with("}}") {
+            formatted.push_str(&rest[..1]);
+            rest = &rest[2..];
+            continue;
+        }
+        match rest.find('}').filter(|_| rest.starts_with('{')) {
+            Some(end) => {
+                formatted.push_str(args.next().unwrap_or("?"));
+                rest = &rest[end + 1..];
+            }
+            None => {
+                formatted.push_str(&rest[..1]);
+                rest = &rest[1..];
+            }
+        }
+    }
+    formatted.push_str(rest);
+    formatted
+}
+
+/// Logs a record of the device with the `device` target
+pub fn trace_record(record: &LogRecord) {
+    let message = format_record(record);
+    let timestamp_us = record.timestamp;
+    match record.level {
+        LogLevel::Trace => tracing::trace!(target: "device", timestamp_us, "{message}"),
+        LogLevel::Debug => tracing::debug!(target: "device", timestamp_us, "{message}"),
+        LogLevel::Info => tracing::info!(target: "device", timestamp_us, "{message}"),
+        LogLevel::Warn => tracing::warn!(target: "device", timestamp_us, "{message}"),
+        LogLevel::Error | LogLevel::Unknown | LogLevel::Off => {
+            tracing::error!(target: "device", timestamp_us, "{message}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(message: &str, args: &[&str]) -> LogRecord {
+        let mut record = LogRecord::new(LogLevel::Info, 0, message);
+        for arg in args {
+            record.push_arg(arg);
+        }
+        record
+    }
+
+    #[test]
+    fn placeholders_should_be_replaced_by_arguments() {
+        let record = record("Request {} of {:?} took {=u32} ms", &["7", "Usb", "12"]);
+        assert_eq!(format_record(&record), "Request 7 of Usb took 12 ms");
+    }
+
+    #[test]
+    fn missing_arguments_and_escaped_braces_should_be_kept_readable() {
+        let record = record("{{}} {} {} }", &["1"]);
+        assert_eq!(format_record(&record), "{} 1 ? }");
+    }
+}
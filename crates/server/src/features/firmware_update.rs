@@ -0,0 +1,355 @@
+use crate::features::session::ClientError;
+use firmware_image::{HEADER_OFFSET, ImageHeader};
+use transport::command::FirmwareBlock;
+use transport::event::ErrorCode;
+use transport::{Command, Event};
+
+const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const ELF_HEADER_SIZE: usize = 52;
+const PROGRAM_HEADER_SIZE: usize = 32;
+const PT_LOAD: u32 = 1;
+/// Gaps between the loaded segments are filled with it, like objcopy does
+const FILL: u8 = 0xFF;
+/// Larger images do not fit any flash this firmware runs on
+const MAX_IMAGE_SIZE: usize = 16 * 1024 * 1024;
+/// Blocks are a multiple of it, so the device programs them right away
+const BLOCK_ALIGNMENT: usize = 8;
+/// How often a single block is sent before the upload gives up
+const MAX_ATTEMPTS: u32 = 5;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum InvalidImage {
+    Empty,
+    /// Only 32-bit little endian ELF files are converted
+    UnsupportedElf,
+    Truncated,
+    NoLoadableSegments,
+    TooLarge(usize),
+    /// The image has no metadata header, it was not built by this firmware
+    MissingHeader,
+    /// The length and digest of the header are filled in by sign-image
+    Unstamped,
+    WrongBoard {
+        image: u8,
+        device: u8,
+    },
+}
+
+/// Returns the raw image of a `.bin` or an ELF file, an ELF file is converted like
+/// `objcopy -O binary` does
+pub fn load_image(file: &[u8]) -> Result<Vec<u8>, InvalidImage> {
+    let image = if file.starts_with(&ELF_MAGIC) {
+        convert_elf(file)?
+    } else {
+        file.to_vec()
+    };
+    match image.len() {
+        0 => Err(InvalidImage::Empty),
+        size if size > MAX_IMAGE_SIZE => Err(InvalidImage::TooLarge(size)),
+        _ => Ok(image),
+    }
+}
+
+/// Refuses images the device would refuse after the whole upload
+pub fn check_image(image: &[u8], board_revision: u8) -> Result<ImageHeader, InvalidImage> {
+    let header = image
+        .get(HEADER_OFFSET..HEADER_OFFSET + ImageHeader::SIZE)
+        .and_then(|header| ImageHeader::read(header.try_into().unwrap()))
+        .ok_or(InvalidImage::MissingHeader)?;
+    if header.board_revision != board_revision {
+        return Err(InvalidImage::WrongBoard {
+            image: header.board_revision,
+            device: board_revision,
+        });
+    }
+    if header.length == 0 || header.length as usize > image.len() {
+        return Err(InvalidImage::Unstamped);
+    }
+    Ok(header)
+}
+
+/// Lays the loadable segments out by their physical address, starting at the lowest one
+fn convert_elf(file: &[u8]) -> Result<Vec<u8>, InvalidImage> {
+    if file.len() < ELF_HEADER_SIZE {
+        return Err(InvalidImage::Truncated);
+    }
+    // 32-bit, little endian
+    if file[4] != 1 || file[5] != 1 {
+        return Err(InvalidImage::UnsupportedElf);
+    }
+    let u16_at = |offset: usize| u16::from_le_bytes([file[offset], file[offset + 1]]) as usize;
+    let program_headers = read_u32(file, 28)? as usize;
+    let entry_size = u16_at(42);
+    let entry_count = u16_at(44);
+    if entry_size < PROGRAM_HEADER_SIZE {
+        return Err(InvalidImage::UnsupportedElf);
+    }
+
+    let mut segments = Vec::new();
+    for index in 0..entry_count {
+        let header = program_headers + index * entry_size;
+        let field = |offset: usize| read_u32(file, header + offset).map(|value| value as usize);
+        let size = field(16)?;
+        if field(0)? != PT_LOAD as usize || size == 0 {
+            continue;
+        }
+        let offset = field(4)?;
+        let data = file
+            .get(offset..offset + size)
+            .ok_or(InvalidImage::Truncated)?;
+        segments.push((field(12)?, data));
+    }
+
+    let start = segments
+        .iter()
+        .map(|(address, _)| *address)
+        .min()
+        .ok_or(InvalidImage::NoLoadableSegments)?;
+    let end = segments
+        .iter()
+        .map(|(address, data)| address + data.len())
+        .max()
+        .unwrap_or(start);
+    if end - start > MAX_IMAGE_SIZE {
+        return Err(InvalidImage::TooLarge(end - start));
+    }
+    let mut image = vec![FILL; end - start];
+    for (address, data) in segments {
+        image[address - start..address - start + data.len()].copy_from_slice(data);
+    }
+    Ok(image)
+}
+
+fn read_u32(file: &[u8], offset: usize) -> Result<u32, InvalidImage> {
+    let bytes = file
+        .get(offset..offset + 4)
+        .ok_or(InvalidImage::Truncated)?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+#[derive(Debug)]
+pub enum UploadError {
+    /// A block was not confirmed after [`MAX_ATTEMPTS`]
+    RetriesExhausted,
+    /// The device did not receive the image up to the offset the upload continues at
+    OutOfSync,
+    Rejected(ErrorCode),
+    UnexpectedResponse,
+    Client(ClientError),
+}
+
+/// What to do after a response to a block
+#[derive(Debug, PartialEq, Eq)]
+pub enum BlockResult {
+    Written,
+    Retry,
+}
+
+/// Sends an image block by block, the next block goes out once the device confirmed the previous
+/// one
+#[derive(Debug)]
+pub struct FirmwareUpload {
+    image: Vec<u8>,
+    block_size: usize,
+    offset: usize,
+    /// Attempts of the current block that failed
+    attempts: u32,
+    retries: u32,
+}
+
+impl FirmwareUpload {
+    /// `block_size` is the largest block the device takes, an upload resumed at `offset` expects
+    /// the device to have the image up to there
+    pub fn new(image: Vec<u8>, block_size: usize, offset: usize) -> Self {
+        let block_size = (block_size - block_size % BLOCK_ALIGNMENT).max(BLOCK_ALIGNMENT);
+        Self {
+            offset: offset.min(image.len()),
+            image,
+            block_size,
+            attempts: 0,
+            retries: 0,
+        }
+    }
+
+    pub fn next_request(&self) -> Option<Command> {
+        let end = (self.offset + self.block_size).min(self.image.len());
+        (self.offset < end).then(|| {
+            Command::WriteFirmwareBlock(FirmwareBlock::new(
+                self.offset as u32,
+                &self.image[self.offset..end],
+            ))
+        })
+    }
+
+    /// Takes the response to the block of [`FirmwareUpload::next_request`]
+    pub fn receive(
+        &mut self,
+        response: Result<Event, ClientError>,
+    ) -> Result<BlockResult, UploadError> {
+        match response {
+            Ok(Event::Success) => {}
+            // The block got through but its response did not, the device expects the next one
+            Ok(Event::Failure(ErrorCode::InvalidArgument)) if self.attempts > 0 => {}
+            Ok(Event::Failure(ErrorCode::InvalidArgument)) => return Err(UploadError::OutOfSync),
+            Ok(Event::Failure(ErrorCode::Busy))
+            | Err(ClientError::Timeout | ClientError::Unanswered) => {
+                self.attempts += 1;
+                self.retries += 1;
+                if self.attempts >= MAX_ATTEMPTS {
+                    return Err(UploadError::RetriesExhausted);
+                }
+                return Ok(BlockResult::Retry);
+            }
+            Ok(Event::Failure(code)) => return Err(UploadError::Rejected(code)),
+            Ok(_) => return Err(UploadError::UnexpectedResponse),
+            Err(error) => return Err(UploadError::Client(error)),
+        }
+        self.offset = (self.offset + self.block_size).min(self.image.len());
+        self.attempts = 0;
+        Ok(BlockResult::Written)
+    }
+
+    /// Bytes the device confirmed, an interrupted upload can be resumed from here
+    pub fn written(&self) -> usize {
+        self.offset
+    }
+
+    pub fn total(&self) -> usize {
+        self.image.len()
+    }
+
+    /// Blocks sent again over the whole upload
+    pub fn retries(&self) -> u32 {
+        self.retries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn elf(segments: &[(u32, u32, &[u8])]) -> Vec<u8> {
+        let mut file = vec![0; ELF_HEADER_SIZE];
+        file[..4].copy_from_slice(&ELF_MAGIC);
+        file[4] = 1;
+        file[5] = 1;
+        file[28..32].copy_from_slice(&(ELF_HEADER_SIZE as u32).to_le_bytes());
+        file[42..44].copy_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+        file[44..46].copy_from_slice(&(segments.len() as u16).to_le_bytes());
+        let data_start = ELF_HEADER_SIZE + segments.len() * PROGRAM_HEADER_SIZE;
+        let mut data = Vec::new();
+        for &(kind, address, content) in segments {
+            let mut header = [0; PROGRAM_HEADER_SIZE];
+            header[0..4].copy_from_slice(&kind.to_le_bytes());
+            header[4..8].copy_from_slice(&((data_start + data.len()) as u32).to_le_bytes());
+            // The virtual address of data differs from where it is loaded
+            header[8..12].copy_from_slice(&0x2000_0000u32.to_le_bytes());
+            header[12..16].copy_from_slice(&address.to_le_bytes());
+            header[16..20].copy_from_slice(&(content.len() as u32).to_le_bytes());
+            file.extend_from_slice(&header);
+            data.extend_from_slice(content);
+        }
+        file.extend_from_slice(&data);
+        file
+    }
+
+    fn written_block(upload: &FirmwareUpload) -> FirmwareBlock {
+        match upload.next_request() {
+            Some(Command::WriteFirmwareBlock(block)) => block,
+            other => panic!("Expected a block, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn elf_should_be_converted_by_physical_address() {
+        let file = elf(&[
+            (PT_LOAD, 0x0800_0010, &[3, 4]),
+            (PT_LOAD, 0x0800_0000, &[1, 2]),
+            (0x6474_e551, 0x0800_0100, &[9; 4]),
+        ]);
+        let mut expected = vec![1, 2];
+        expected.extend_from_slice(&[FILL; 14]);
+        expected.extend_from_slice(&[3, 4]);
+        assert_eq!(load_image(&file), Ok(expected));
+    }
+
+    #[test]
+    fn invalid_image_should_be_rejected() {
+        assert_eq!(load_image(&[]), Err(InvalidImage::Empty));
+        assert_eq!(load_image(&elf(&[])), Err(InvalidImage::NoLoadableSegments));
+        let mut file = elf(&[(PT_LOAD, 0x0800_0000, &[1, 2])]);
+        file.pop();
+        assert_eq!(load_image(&file), Err(InvalidImage::Truncated));
+        assert_eq!(load_image(&[1, 2, 3]), Ok(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn image_for_another_board_should_be_refused() {
+        let mut image = vec![0; 1024];
+        let header = ImageHeader::new([1, 2, 3], 2, [0; 20]);
+        let header_bytes = &mut image[HEADER_OFFSET..HEADER_OFFSET + ImageHeader::SIZE];
+        header.write(header_bytes.try_into().unwrap());
+        assert_eq!(check_image(&image, 2), Err(InvalidImage::Unstamped));
+
+        firmware_image::stamp(&mut image).unwrap();
+        assert_eq!(
+            check_image(&image, 2).map(|header| header.version),
+            Ok([1, 2, 3])
+        );
+        assert_eq!(
+            check_image(&image, 1),
+            Err(InvalidImage::WrongBoard {
+                image: 2,
+                device: 1
+            })
+        );
+        assert_eq!(check_image(&[0; 1024], 2), Err(InvalidImage::MissingHeader));
+    }
+
+    #[test]
+    fn image_should_be_split_into_aligned_blocks() {
+        let mut upload = FirmwareUpload::new((0..100).collect(), 30, 0);
+        let mut blocks = Vec::new();
+        while upload.next_request().is_some() {
+            blocks.push(written_block(&upload));
+            assert_eq!(
+                upload.receive(Ok(Event::Success)).unwrap(),
+                BlockResult::Written
+            );
+        }
+        let sizes: Vec<_> = blocks.iter().map(|block| block.slice().len()).collect();
+        assert_eq!(sizes, [24, 24, 24, 24, 4]);
+        assert_eq!(blocks[4].offset, 96);
+        assert_eq!(blocks[4].slice(), &[96, 97, 98, 99]);
+        assert_eq!(upload.written(), 100);
+    }
+
+    #[test]
+    fn lost_block_should_be_sent_again() {
+        let mut upload = FirmwareUpload::new(vec![0; 64], 16, 16);
+        assert_eq!(written_block(&upload).offset, 16);
+        let timeout = upload.receive(Err(ClientError::Timeout));
+        assert_eq!(timeout.unwrap(), BlockResult::Retry);
+        assert_eq!(written_block(&upload).offset, 16);
+
+        // The device got the first attempt, only its response was lost
+        let resent = upload.receive(Ok(Event::Failure(ErrorCode::InvalidArgument)));
+        assert_eq!(resent.unwrap(), BlockResult::Written);
+        assert_eq!(upload.written(), 32);
+        assert_eq!(upload.retries(), 1);
+
+        let out_of_sync = upload.receive(Ok(Event::Failure(ErrorCode::InvalidArgument)));
+        assert!(matches!(out_of_sync, Err(UploadError::OutOfSync)));
+    }
+
+    #[test]
+    fn upload_should_give_up_after_the_last_attempt() {
+        let mut upload = FirmwareUpload::new(vec![0; 64], 16, 0);
+        for _ in 1..MAX_ATTEMPTS {
+            let busy = upload.receive(Ok(Event::Failure(ErrorCode::Busy)));
+            assert_eq!(busy.unwrap(), BlockResult::Retry);
+        }
+        let busy = upload.receive(Ok(Event::Failure(ErrorCode::Busy)));
+        assert!(matches!(busy, Err(UploadError::RetriesExhausted)));
+    }
+}
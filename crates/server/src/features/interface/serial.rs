@@ -1,7 +1,7 @@
 use crate::features::interface::device_info::DeviceInfo;
 use crate::features::interface::error::{ConnectionError, DiscoveryError};
 use crate::features::interface_kind::InterfaceKind;
-use crate::features::session::DeviceHandle;
+use crate::features::session::{DeviceHandle, Framing};
 use tokio_serial::{
     SerialPortBuilderExt, SerialPortInfo, SerialPortType, SerialStream, available_ports,
 };
@@ -10,6 +10,7 @@ use tokio_serial::{
 pub struct SerialInterface {
     show_only_usb_devices: bool,
     hide_call_up_devices: bool,
+    framing: Framing,
 }
 
 impl SerialInterface {
@@ -17,6 +18,7 @@ impl SerialInterface {
         Self {
             show_only_usb_devices: config.show_only_usb_devices,
             hide_call_up_devices: config.hide_call_up_devices,
+            framing: config.framing,
         }
     }
 
@@ -43,7 +45,7 @@ impl SerialInterface {
         address: &str,
     ) -> Result<DeviceHandle<SerialStream>, ConnectionError> {
         let device = tokio_serial::new(address, 115200).open_native_async()?;
-        Ok(DeviceHandle::new(device))
+        Ok(DeviceHandle::new(device, self.framing))
     }
 }
 
@@ -1,5 +1,9 @@
+pub mod black_box;
 pub mod connection_string;
+pub mod device_log;
+pub mod firmware_update;
 
 pub mod interface;
 pub mod interface_kind;
+pub mod scope;
 pub mod session;
@@ -0,0 +1,95 @@
+use transport::scope::{ScopeSignal, ScopeStatus};
+
+/// Samples of one channel of a capture, oldest first
+#[derive(Debug, PartialEq)]
+pub struct ScopeTrace {
+    pub signal: ScopeSignal,
+    pub samples: Vec<f32>,
+}
+
+/// The downloaded capture does not match the status the device reported
+#[derive(Debug, PartialEq, Eq)]
+pub struct InvalidCapture {
+    pub expected: usize,
+    pub received: usize,
+}
+
+/// Splits the scope blob into a trace per channel, the blob holds the channels of a control step
+/// next to each other
+pub fn split_capture(status: &ScopeStatus, data: &[u8]) -> Result<Vec<ScopeTrace>, InvalidCapture> {
+    let channels = status.channels.iter().count();
+    let expected = status.length as usize * channels * size_of::<f32>();
+    if data.len() != expected {
+        return Err(InvalidCapture {
+            expected,
+            received: data.len(),
+        });
+    }
+
+    let mut traces: Vec<ScopeTrace> = status
+        .channels
+        .iter()
+        .map(|signal| ScopeTrace {
+            signal,
+            samples: Vec::with_capacity(status.length as usize),
+        })
+        .collect();
+    for (i, sample) in data.chunks_exact(size_of::<f32>()).enumerate() {
+        let sample = f32::from_le_bytes(sample.try_into().unwrap());
+        traces[i % channels].samples.push(sample);
+    }
+    Ok(traces)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use transport::scope::{ScopeChannels, ScopeState};
+
+    fn status(length: u16) -> ScopeStatus {
+        ScopeStatus {
+            state: ScopeState::Done,
+            channels: ScopeChannels([
+                Some(ScopeSignal::CurrentQ),
+                Some(ScopeSignal::Velocity),
+                None,
+                None,
+            ]),
+            length,
+            pre_trigger: 1,
+            sample_rate: 20_000.0,
+        }
+    }
+
+    #[test]
+    fn capture_should_be_split_into_channels() {
+        let data: Vec<u8> = [1.0f32, -1.0, 2.0, -2.0, 3.0, -3.0]
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        assert_eq!(
+            split_capture(&status(3), &data),
+            Ok(vec![
+                ScopeTrace {
+                    signal: ScopeSignal::CurrentQ,
+                    samples: vec![1.0, 2.0, 3.0],
+                },
+                ScopeTrace {
+                    signal: ScopeSignal::Velocity,
+                    samples: vec![-1.0, -2.0, -3.0],
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn capture_of_wrong_size_should_be_rejected() {
+        assert_eq!(
+            split_capture(&status(3), &[0; 20]),
+            Err(InvalidCapture {
+                expected: 24,
+                received: 20,
+            })
+        );
+    }
+}
@@ -0,0 +1,205 @@
+use transport::Command;
+use transport::blob::{BlobChunk, BlobClose, BlobInfo, BlobRead, Checksum};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum BlobTransferError {
+    /// The chunk belongs to another transfer
+    WrongHandle,
+    /// The chunk is not the one that was asked for next
+    UnexpectedOffset { expected: u32, received: u32 },
+    /// The device sent more data than the blob holds
+    TooLong,
+}
+
+/// Reads a blob from the device, one chunk in flight at a time
+#[derive(Debug)]
+pub struct BlobDownload {
+    info: BlobInfo,
+    chunk_size: u16,
+    data: Vec<u8>,
+    checksum: Checksum,
+}
+
+impl BlobDownload {
+    /// `opened` is the answer to an `OpenBlob` request in read mode
+    pub fn new(opened: BlobInfo, chunk_size: usize) -> Self {
+        Self {
+            info: opened,
+            chunk_size: chunk_size.min(u16::MAX as usize) as u16,
+            data: Vec::with_capacity(opened.total_size as usize),
+            checksum: Checksum::new(),
+        }
+    }
+
+    /// The request for the next chunk, `None` once the whole blob has arrived
+    pub fn next_request(&self) -> Option<Command> {
+        if self.is_complete() {
+            return None;
+        }
+        Some(Command::ReadBlob(BlobRead {
+            handle: self.info.handle,
+            offset: self.data.len() as u32,
+            length: self.chunk_size,
+        }))
+    }
+
+    pub fn receive(&mut self, chunk: &BlobChunk) -> Result<(), BlobTransferError> {
+        if chunk.handle != self.info.handle {
+            return Err(BlobTransferError::WrongHandle);
+        }
+        let expected = self.data.len() as u32;
+        if chunk.offset != expected {
+            return Err(BlobTransferError::UnexpectedOffset {
+                expected,
+                received: chunk.offset,
+            });
+        }
+        let data = chunk.slice();
+        if self.data.len() + data.len() > self.info.total_size as usize {
+            return Err(BlobTransferError::TooLong);
+        }
+        self.data.extend_from_slice(data);
+        self.checksum.update(data);
+        Ok(())
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.data.len() >= self.info.total_size as usize
+    }
+
+    /// Answered with a failure by the device if its data differs from what was received
+    pub fn close_request(&self) -> Command {
+        Command::CloseBlob(BlobClose {
+            handle: self.info.handle,
+            checksum: self.checksum.finish(),
+        })
+    }
+
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Writes a blob to the device, chunks have to be sent in order
+#[derive(Debug)]
+pub struct BlobUpload {
+    handle: u8,
+    data: Vec<u8>,
+    chunk_size: usize,
+    position: usize,
+}
+
+impl BlobUpload {
+    /// `opened` is the answer to an `OpenBlob` request in write mode for `data.len()` bytes
+    pub fn new(opened: BlobInfo, data: Vec<u8>, chunk_size: usize) -> Self {
+        Self {
+            handle: opened.handle,
+            data,
+            chunk_size: chunk_size.max(1),
+            position: 0,
+        }
+    }
+
+    /// The next chunk to send, `None` once everything has been sent
+    pub fn next_request(&mut self) -> Option<Command> {
+        if self.position >= self.data.len() {
+            return None;
+        }
+        let end = (self.position + self.chunk_size).min(self.data.len());
+        let chunk = BlobChunk::new(
+            self.handle,
+            self.position as u32,
+            &self.data[self.position..end],
+        );
+        self.position = end;
+        Some(Command::WriteBlob(chunk))
+    }
+
+    /// The device commits the data only if the checksum matches
+    pub fn close_request(&self) -> Command {
+        let mut checksum = Checksum::new();
+        checksum.update(&self.data);
+        Command::CloseBlob(BlobClose {
+            handle: self.handle,
+            checksum: checksum.finish(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPENED: BlobInfo = BlobInfo {
+        handle: 4,
+        total_size: 10,
+    };
+
+    #[test]
+    fn download_should_request_chunks_until_complete() {
+        let mut download = BlobDownload::new(OPENED, 6);
+        assert_eq!(
+            download.next_request(),
+            Some(Command::ReadBlob(BlobRead {
+                handle: 4,
+                offset: 0,
+                length: 6,
+            }))
+        );
+        download
+            .receive(&BlobChunk::new(4, 0, &[0, 1, 2, 3, 4, 5]))
+            .unwrap();
+        assert_eq!(
+            download.next_request(),
+            Some(Command::ReadBlob(BlobRead {
+                handle: 4,
+                offset: 6,
+                length: 6,
+            }))
+        );
+        download
+            .receive(&BlobChunk::new(4, 6, &[6, 7, 8, 9]))
+            .unwrap();
+        assert_eq!(download.next_request(), None);
+
+        let Command::CloseBlob(close) = download.close_request() else {
+            panic!("Expected a close request");
+        };
+        let mut checksum = Checksum::new();
+        checksum.update(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        assert_eq!(close.checksum, checksum.finish());
+        assert_eq!(download.into_data(), vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn download_should_reject_unexpected_chunks() {
+        let mut download = BlobDownload::new(OPENED, 6);
+        assert_eq!(
+            download.receive(&BlobChunk::new(5, 0, &[0])),
+            Err(BlobTransferError::WrongHandle)
+        );
+        assert_eq!(
+            download.receive(&BlobChunk::new(4, 1, &[0])),
+            Err(BlobTransferError::UnexpectedOffset {
+                expected: 0,
+                received: 1,
+            })
+        );
+        assert_eq!(
+            download.receive(&BlobChunk::new(4, 0, &[0; 11])),
+            Err(BlobTransferError::TooLong)
+        );
+    }
+
+    #[test]
+    fn upload_should_split_data_into_chunks() {
+        let mut upload = BlobUpload::new(OPENED, (0..10).collect(), 4);
+        let mut sent = Vec::new();
+        while let Some(Command::WriteBlob(chunk)) = upload.next_request() {
+            assert_eq!(chunk.handle, 4);
+            assert_eq!(chunk.offset as usize, sent.len());
+            sent.extend_from_slice(chunk.slice());
+        }
+        assert_eq!(sent, (0..10).collect::<Vec<u8>>());
+    }
+}
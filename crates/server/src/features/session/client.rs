@@ -0,0 +1,89 @@
+use crate::features::session::error::EncoderError;
+use crate::features::session::handle::{
+    DeviceHandleWrapper, DeviceReaderWrapper, DeviceWriterWrapper,
+};
+use crate::features::session::profile::DeviceProfile;
+use std::time::Duration;
+use transport::reliable::RetransmitConfig;
+use transport::{Command, Event};
+
+/// How often overdue reliable requests are looked for
+pub const RETRANSMIT_CHECK_PERIOD: Duration = Duration::from_millis(20);
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// The device did not answer in time
+    Timeout,
+    /// The device did not answer any transmission of a reliable request
+    Unanswered,
+    /// The device stream was closed
+    Closed,
+    Encoder(EncoderError),
+}
+
+impl From<EncoderError> for ClientError {
+    fn from(e: EncoderError) -> Self {
+        Self::Encoder(e)
+    }
+}
+
+/// Talks to the device one request at a time, for services that run a whole procedure on the
+/// device instead of forwarding the messages of a host
+#[derive(Debug)]
+pub struct DeviceClient {
+    reader: DeviceReaderWrapper,
+    writer: DeviceWriterWrapper,
+    next_request_id: u32,
+    timeout: Duration,
+}
+
+impl DeviceClient {
+    /// `timeout` is how long every single request may take, retransmissions included
+    pub fn new(
+        handle: DeviceHandleWrapper,
+        reliability: Option<RetransmitConfig>,
+        timeout: Duration,
+    ) -> Self {
+        let (reader, writer) = handle.split(reliability);
+        Self {
+            reader,
+            writer,
+            next_request_id: 0,
+            timeout,
+        }
+    }
+
+    /// What the device reported in its last introduction
+    pub fn profile(&self) -> Option<DeviceProfile> {
+        self.reader.profile()
+    }
+
+    /// Events that do not answer this request, like telemetry, are dropped
+    pub async fn request(&mut self, command: Command) -> Result<Event, ClientError> {
+        let request_id = self.next_request_id;
+        self.next_request_id = self.next_request_id.wrapping_add(1);
+        self.writer.write(command, request_id).await?;
+
+        let deadline = tokio::time::sleep(self.timeout);
+        tokio::pin!(deadline);
+        let mut retransmit_ticker = tokio::time::interval(RETRANSMIT_CHECK_PERIOD);
+        loop {
+            tokio::select! {
+                _ = &mut deadline => return Err(ClientError::Timeout),
+                _ = retransmit_ticker.tick() => {
+                    if self.writer.retransmit().await?.contains(&request_id) {
+                        return Err(ClientError::Unanswered);
+                    }
+                }
+                next = self.reader.read_next() => match next {
+                    Some(Ok(incoming)) if incoming.request_id == Some(request_id) => {
+                        return Ok(incoming.event);
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(error)) => tracing::error!("Error reading event: {:?}", error),
+                    None => return Err(ClientError::Closed),
+                },
+            }
+        }
+    }
+}
@@ -1,66 +1,241 @@
 use crate::features::session::error::{DecoderError, EncoderError};
 use crc_engine::software::SoftwareCrcEngine;
 use prost::bytes::BytesMut;
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio_util::bytes::Buf;
 use tokio_util::codec::{Decoder, Encoder};
+use transport::decoder::DecoderStats;
+use transport::frame::{Frame, Header};
+use transport::{command, event};
+
+/// A frame that stalls for longer than this is considered lost
+const INTER_BYTE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Decoder statistics that stay readable after the framed stream has been split
+pub type SharedDecoderStats = Arc<Mutex<DecoderStats>>;
+
+/// How frames are delimited on the wire, it has to match the framing the firmware was built with
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Framing {
+    /// Start byte and length, understood by every firmware
+    #[default]
+    StartByte,
+    /// Byte stuffing with a delimiter after every frame, recovers quickly on noisy links
+    Cobs,
+}
+
+#[derive(Debug)]
+enum FrameDecoder {
+    StartByte(event::decoder::Decoder),
+    Cobs(event::decoder::CobsDecoder),
+}
+
+#[derive(Debug)]
+enum FrameEncoder {
+    StartByte(command::encoder::Encoder),
+    Cobs(command::encoder::CobsEncoder),
+}
 
 #[derive(Debug)]
 pub struct DeviceCoded {
-    decoder: transport::event::decoder::Decoder,
-    encoder: transport::command::encoder::Encoder,
+    decoder: FrameDecoder,
+    encoder: FrameEncoder,
     crc_engine: SoftwareCrcEngine,
+    last_received: Option<Instant>,
+    stats: SharedDecoderStats,
 }
 
 impl Default for DeviceCoded {
     fn default() -> Self {
-        Self::new()
+        Self::new(Framing::default())
     }
 }
 
 impl DeviceCoded {
-    pub fn new() -> Self {
+    pub fn new(framing: Framing) -> Self {
+        let (decoder, encoder) = match framing {
+            Framing::StartByte => (
+                FrameDecoder::StartByte(event::decoder::Decoder::new()),
+                FrameEncoder::StartByte(command::encoder::Encoder::new()),
+            ),
+            Framing::Cobs => (
+                FrameDecoder::Cobs(event::decoder::CobsDecoder::new()),
+                FrameEncoder::Cobs(command::encoder::CobsEncoder::new()),
+            ),
+        };
         Self {
-            decoder: transport::event::decoder::Decoder::new(),
-            encoder: transport::command::encoder::Encoder::new(),
+            decoder,
+            encoder,
             crc_engine: SoftwareCrcEngine::new(),
+            last_received: None,
+            stats: SharedDecoderStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> SharedDecoderStats {
+        self.stats.clone()
+    }
+
+    fn check_timeout(&mut self) {
+        let now = Instant::now();
+        if let Some(last_received) = self.last_received
+            && now - last_received > INTER_BYTE_TIMEOUT
+            && self.decoder.timeout()
+        {
+            tracing::warn!("Dropped a partially received frame after an inter-byte timeout");
+        }
+        self.last_received = Some(now);
+    }
+
+    fn publish_stats(&self) {
+        if let Ok(mut stats) = self.stats.lock() {
+            *stats = self.decoder.stats();
+        }
+    }
+
+    fn map_result(
+        &self,
+        result: Result<
+            Frame<transport::Event>,
+            transport::decoder::DecoderError<transport::event::EventDeserializationError>,
+        >,
+    ) -> Result<Option<Frame<transport::Event>>, DecoderError> {
+        self.publish_stats();
+        match result {
+            Ok(frame) => Ok(Some(frame)),
+            Err(error) => Err(error.into()),
         }
     }
 }
 
 impl Decoder for DeviceCoded {
-    type Item = transport::Event;
+    type Item = Frame<transport::Event>;
     type Error = DecoderError;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if let Some(result) = self.decoder.poll(&mut self.crc_engine) {
+            return self.map_result(result);
+        }
+
+        if !src.is_empty() {
+            self.check_timeout();
+        }
+
         while !src.is_empty() {
             let byte = src[0];
             src.advance(1);
-            match self.decoder.feed(byte, &mut self.crc_engine) {
-                Some(Ok(event)) => {
-                    return Ok(Some(event));
-                }
-                Some(Err(error)) => {
-                    return Err(error.into());
-                }
-                None => {}
+            if let Some(result) = self.decoder.feed(byte, &mut self.crc_engine) {
+                return self.map_result(result);
             }
         }
 
+        self.publish_stats();
         Ok(None)
     }
 }
 
-impl Encoder<transport::Command> for DeviceCoded {
+impl Encoder<Frame<transport::Command>> for DeviceCoded {
     type Error = EncoderError;
 
-    fn encode(&mut self, item: transport::Command, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        let mut buffer = [0; transport::MAX_PACKET_SIZE];
+    fn encode(
+        &mut self,
+        item: Frame<transport::Command>,
+        dst: &mut BytesMut,
+    ) -> Result<(), Self::Error> {
+        // Large enough for frames of either framing
+        let mut buffer = [0; transport::cobs::MAX_FRAME_SIZE];
         let len = self
             .encoder
-            .encode(&item, &mut buffer, &mut self.crc_engine);
+            .encode(item.header, &item.packet, &mut buffer, &mut self.crc_engine);
 
         dst.reserve(len);
         dst.extend_from_slice(&buffer[..len]);
         Ok(())
     }
 }
+
+type DecodeResult = Option<
+    Result<
+        Frame<transport::Event>,
+        transport::decoder::DecoderError<transport::event::EventDeserializationError>,
+    >,
+>;
+
+impl FrameDecoder {
+    fn feed(&mut self, data: u8, crc: &mut SoftwareCrcEngine) -> DecodeResult {
+        match self {
+            FrameDecoder::StartByte(decoder) => decoder.feed(data, crc),
+            FrameDecoder::Cobs(decoder) => decoder.feed(data, crc),
+        }
+    }
+
+    fn poll(&mut self, crc: &mut SoftwareCrcEngine) -> DecodeResult {
+        match self {
+            FrameDecoder::StartByte(decoder) => decoder.poll(crc),
+            FrameDecoder::Cobs(decoder) => decoder.poll(crc),
+        }
+    }
+
+    fn timeout(&mut self) -> bool {
+        match self {
+            FrameDecoder::StartByte(decoder) => decoder.timeout(),
+            FrameDecoder::Cobs(decoder) => decoder.timeout(),
+        }
+    }
+
+    fn stats(&self) -> DecoderStats {
+        match self {
+            FrameDecoder::StartByte(decoder) => decoder.stats(),
+            FrameDecoder::Cobs(decoder) => decoder.stats(),
+        }
+    }
+}
+
+impl FrameEncoder {
+    /// `buffer` has to fit the frames of either framing
+    fn encode(
+        &self,
+        header: Header,
+        packet: &transport::Command,
+        buffer: &mut [u8],
+        crc: &mut SoftwareCrcEngine,
+    ) -> usize {
+        match self {
+            FrameEncoder::StartByte(encoder) => encoder.encode(header, packet, buffer, crc),
+            FrameEncoder::Cobs(encoder) => encoder.encode(header, packet, buffer, crc),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use transport::Event;
+
+    #[test]
+    fn cobs_framed_events_should_be_decoded() {
+        let mut crc = SoftwareCrcEngine::new();
+        let mut buffer = [0; transport::cobs::MAX_FRAME_SIZE];
+        let mut src = BytesMut::new();
+        for sequence in 0..2 {
+            let length = event::encoder::CobsEncoder::new().encode(
+                Header::unsolicited(sequence),
+                &Event::Success,
+                &mut buffer,
+                &mut crc,
+            );
+            src.extend_from_slice(&buffer[..length]);
+        }
+
+        let mut codec = DeviceCoded::new(Framing::Cobs);
+        for sequence in 0..2 {
+            let frame = codec.decode(&mut src).unwrap().unwrap();
+            assert_eq!(frame.header, Header::unsolicited(sequence));
+            assert_eq!(frame.packet, Event::Success);
+        }
+        assert!(codec.decode(&mut src).unwrap().is_none());
+    }
+}
@@ -1,11 +1,18 @@
 use crate::features::interface_kind::InterfaceKind;
-use crate::features::session::codec::DeviceCoded;
+use crate::features::session::codec::{DeviceCoded, Framing, SharedDecoderStats};
 use crate::features::session::error::{DecoderError, EncoderError};
+use crate::features::session::profile::{DeviceProfile, SharedDeviceProfile, supports};
+use crate::features::session::requests::{Overdue, PendingRequests, SharedPendingRequests};
 use futures::stream::{SplitSink, SplitStream};
 use futures::{SinkExt, StreamExt};
+use std::sync::Mutex;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_util::codec::Framed;
 use tonic::codegen::tokio_stream::StreamExt as tokio_stream_ext;
+use transport::capabilities::Capabilities;
+use transport::decoder::DecoderStats;
+use transport::frame::{Frame, FrameKind, Header, SequenceCounter};
+use transport::reliable::RetransmitConfig;
 use transport::{Command, Event};
 
 #[derive(Debug)]
@@ -16,35 +23,186 @@ pub struct DeviceHandle<T: AsyncRead + AsyncWrite> {
 #[derive(Debug)]
 pub struct DeviceReader<T: AsyncRead> {
     pub reader: SplitStream<Framed<T, DeviceCoded>>,
+    pub stats: SharedDecoderStats,
+    pub pending: SharedPendingRequests,
+    pub profile: SharedDeviceProfile,
 }
 
 #[derive(Debug)]
 pub struct DeviceWriter<T: AsyncWrite> {
-    pub writer: SplitSink<Framed<T, DeviceCoded>, Command>,
+    pub writer: SplitSink<Framed<T, DeviceCoded>, Frame<Command>>,
+    pub sequence: SequenceCounter,
+    pub pending: SharedPendingRequests,
+    pub profile: SharedDeviceProfile,
+}
+
+/// An event received from the device
+#[derive(Debug)]
+pub struct IncomingEvent {
+    pub event: Event,
+    /// Id of the request this event answers, `None` for unsolicited events
+    pub request_id: Option<u32>,
 }
 
 impl<T: AsyncRead + AsyncWrite> DeviceHandle<T> {
-    pub fn new(io: T) -> Self {
-        let codec = DeviceCoded::new();
+    pub fn new(io: T, framing: Framing) -> Self {
+        let codec = DeviceCoded::new(framing);
         let framed = Framed::new(io, codec);
         Self { framed }
     }
 
-    pub fn split(self) -> (DeviceReader<T>, DeviceWriter<T>) {
+    /// Requests are retransmitted until answered only if `reliability` is set
+    pub fn split(
+        self,
+        reliability: Option<RetransmitConfig>,
+    ) -> (DeviceReader<T>, DeviceWriter<T>) {
+        let stats = self.framed.codec().stats();
+        let pending = SharedPendingRequests::new(Mutex::new(PendingRequests::new(reliability)));
+        let profile = SharedDeviceProfile::default();
         let (sink, stream) = self.framed.split();
-        (DeviceReader::new(stream), DeviceWriter::new(sink))
+        (
+            DeviceReader::new(stream, stats, pending.clone(), profile.clone()),
+            DeviceWriter::new(sink, pending, profile),
+        )
     }
 }
 
 impl<T: AsyncRead> DeviceReader<T> {
-    pub fn new(stream: SplitStream<Framed<T, DeviceCoded>>) -> Self {
-        Self { reader: stream }
+    pub fn new(
+        stream: SplitStream<Framed<T, DeviceCoded>>,
+        stats: SharedDecoderStats,
+        pending: SharedPendingRequests,
+        profile: SharedDeviceProfile,
+    ) -> Self {
+        Self {
+            reader: stream,
+            stats,
+            pending,
+            profile,
+        }
+    }
+
+    pub fn stats(&self) -> DecoderStats {
+        self.stats.lock().map(|stats| *stats).unwrap_or_default()
+    }
+
+    pub fn profile(&self) -> Option<DeviceProfile> {
+        self.profile.lock().ok().and_then(|profile| *profile)
+    }
+
+    fn update_profile(&self, event: &Event) {
+        let Event::DeviceIntroduction(introduction) = event else {
+            return;
+        };
+        let profile = DeviceProfile::from_introduction(introduction);
+        if profile.is_newer_than_server() {
+            tracing::warn!(
+                "Device speaks protocol version {} but the server only knows version {}, newer features are ignored",
+                profile.protocol_version,
+                transport::PROTOCOL_VERSION
+            );
+        }
+        if let Ok(mut shared) = self.profile.lock() {
+            *shared = Some(profile);
+        }
+    }
+
+    /// Returns `None` for a duplicated response, which is expected after a retransmission
+    fn correlate(&self, frame: Frame<Event>) -> Option<IncomingEvent> {
+        let request_id = match frame.header.kind {
+            FrameKind::Response(sequence) => {
+                let resolved = self
+                    .pending
+                    .lock()
+                    .ok()
+                    .and_then(|mut pending| pending.resolve(sequence));
+                match resolved {
+                    Some((request_id, elapsed)) => {
+                        tracing::debug!("Request {request_id} answered in {elapsed:?}");
+                        Some(request_id)
+                    }
+                    None => {
+                        tracing::debug!("Dropping a duplicated response to request {sequence}");
+                        return None;
+                    }
+                }
+            }
+            FrameKind::Unsolicited => None,
+            FrameKind::Request => {
+                tracing::warn!("Device sent a frame marked as a request");
+                None
+            }
+        };
+        self.update_profile(&frame.packet);
+        Some(IncomingEvent {
+            event: frame.packet,
+            request_id,
+        })
     }
 }
 
 impl<T: AsyncWrite> DeviceWriter<T> {
-    pub fn new(sink: SplitSink<Framed<T, DeviceCoded>, Command>) -> Self {
-        Self { writer: sink }
+    pub fn new(
+        sink: SplitSink<Framed<T, DeviceCoded>, Frame<Command>>,
+        pending: SharedPendingRequests,
+        profile: SharedDeviceProfile,
+    ) -> Self {
+        Self {
+            writer: sink,
+            sequence: SequenceCounter::new(),
+            pending,
+            profile,
+        }
+    }
+
+    pub fn profile(&self) -> Option<DeviceProfile> {
+        self.profile.lock().ok().and_then(|profile| *profile)
+    }
+
+    /// Requests are only marked reliable for devices that answer retransmissions from a cache
+    pub async fn write(&mut self, command: Command, request_id: u32) -> Result<(), EncoderError> {
+        let sequence = self.sequence.advance();
+        let mut header = Header::request(sequence);
+        let reliable = supports(self.profile(), Capabilities::RELIABLE_DELIVERY);
+        if let Ok(mut pending) = self.pending.lock() {
+            if let Some(lost) = pending.insert(sequence, request_id) {
+                tracing::warn!("Request {lost} never got a response");
+            }
+            if reliable && pending.is_reliable() {
+                match pending.retransmit_later(sequence, command.clone()) {
+                    Ok(()) => header = Header::reliable_request(sequence),
+                    Err(_) => tracing::warn!(
+                        "Too many requests in flight, request {request_id} is sent unreliably"
+                    ),
+                }
+            }
+        }
+        self.writer
+            .send(Frame {
+                header,
+                packet: command,
+            })
+            .await
+    }
+
+    /// Resends every reliable request whose response is overdue, returns the ids of the requests
+    /// that were given up and will not be answered anymore
+    pub async fn retransmit(&mut self) -> Result<Vec<u32>, EncoderError> {
+        let mut given_up = Vec::new();
+        loop {
+            let due = match self.pending.lock() {
+                Ok(mut pending) => pending.due_retransmission(),
+                Err(_) => None,
+            };
+            match due {
+                Some(Overdue::Resend(frame)) => {
+                    tracing::warn!("Retransmitting request {}", frame.header.sequence);
+                    self.writer.send(frame).await?;
+                }
+                Some(Overdue::GaveUp(request_id)) => given_up.push(request_id),
+                None => return Ok(given_up),
+            }
+        }
     }
 }
 
@@ -70,10 +228,13 @@ impl DeviceHandleWrapper {
         }
     }
 
-    pub fn split(self) -> (DeviceReaderWrapper, DeviceWriterWrapper) {
+    pub fn split(
+        self,
+        reliability: Option<RetransmitConfig>,
+    ) -> (DeviceReaderWrapper, DeviceWriterWrapper) {
         match self {
             DeviceHandleWrapper::Serial(handle) => {
-                let (reader, writer) = handle.split();
+                let (reader, writer) = handle.split(reliability);
                 (
                     DeviceReaderWrapper::Serial(reader),
                     DeviceWriterWrapper::Serial(writer),
@@ -84,17 +245,92 @@ impl DeviceHandleWrapper {
 }
 
 impl DeviceReaderWrapper {
-    pub async fn read_next(&mut self) -> Option<Result<Event, DecoderError>> {
+    pub async fn read_next(&mut self) -> Option<Result<IncomingEvent, DecoderError>> {
         match self {
-            DeviceReaderWrapper::Serial(reader) => tokio_stream_ext::next(&mut reader.reader).await,
+            DeviceReaderWrapper::Serial(reader) => loop {
+                match tokio_stream_ext::next(&mut reader.reader).await? {
+                    Ok(frame) => {
+                        if let Some(incoming) = reader.correlate(frame) {
+                            return Some(Ok(incoming));
+                        }
+                    }
+                    Err(error) => return Some(Err(error)),
+                }
+            },
+        }
+    }
+
+    /// Link quality seen by the host side decoder
+    pub fn stats(&self) -> DecoderStats {
+        match self {
+            DeviceReaderWrapper::Serial(reader) => reader.stats(),
+        }
+    }
+
+    /// What the device reported in its last introduction
+    pub fn profile(&self) -> Option<DeviceProfile> {
+        match self {
+            DeviceReaderWrapper::Serial(reader) => reader.profile(),
         }
     }
 }
 
 impl DeviceWriterWrapper {
-    pub async fn write(&mut self, command: Command) -> Result<(), EncoderError> {
+    /// Sends a command, the response will carry the same `request_id`
+    pub async fn write(&mut self, command: Command, request_id: u32) -> Result<(), EncoderError> {
+        match self {
+            DeviceWriterWrapper::Serial(writer) => writer.write(command, request_id).await,
+        }
+    }
+
+    /// Returns the ids of the requests that were given up
+    pub async fn retransmit(&mut self) -> Result<Vec<u32>, EncoderError> {
         match self {
-            DeviceWriterWrapper::Serial(writer) => writer.writer.send(command).await,
+            DeviceWriterWrapper::Serial(writer) => writer.retransmit().await,
         }
     }
+
+    /// What the device reported in its last introduction
+    pub fn profile(&self) -> Option<DeviceProfile> {
+        match self {
+            DeviceWriterWrapper::Serial(writer) => writer.profile(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::DuplexStream;
+
+    const RELIABILITY: RetransmitConfig = RetransmitConfig {
+        timeout_ms: 0,
+        max_attempts: 2,
+    };
+
+    fn writer(capabilities: Capabilities) -> (DeviceWriter<DuplexStream>, DuplexStream) {
+        let (host, device) = tokio::io::duplex(1024);
+        let (_, writer) = DeviceHandle::new(host, Framing::Cobs).split(Some(RELIABILITY));
+        *writer.profile.lock().unwrap() = Some(DeviceProfile {
+            protocol_version: transport::PROTOCOL_VERSION,
+            capabilities,
+            max_frame_size: transport::MAX_PACKET_SIZE as u16,
+        });
+        (writer, device)
+    }
+
+    #[tokio::test]
+    async fn unanswered_request_should_be_given_up() {
+        let (mut writer, _device) = writer(Capabilities::RELIABLE_DELIVERY);
+        writer.write(Command::Stop, 42).await.unwrap();
+        assert_eq!(writer.retransmit().await.unwrap(), vec![42]);
+        assert_eq!(writer.retransmit().await.unwrap(), Vec::<u32>::new());
+    }
+
+    #[tokio::test]
+    async fn request_should_not_be_reliable_without_device_support() {
+        let (mut writer, _device) = writer(Capabilities::empty());
+        writer.write(Command::Stop, 42).await.unwrap();
+        assert_eq!(writer.retransmit().await.unwrap(), Vec::<u32>::new());
+    }
 }
@@ -1,5 +1,14 @@
+pub mod blob;
+mod client;
 mod codec;
 pub mod error;
 mod handle;
+mod profile;
+mod requests;
 
-pub use handle::{DeviceHandle, DeviceHandleWrapper, DeviceReaderWrapper, DeviceWriterWrapper};
+pub use handle::{
+    DeviceHandle, DeviceHandleWrapper, DeviceReaderWrapper, DeviceWriterWrapper, IncomingEvent,
+};
+pub use client::{ClientError, DeviceClient, RETRANSMIT_CHECK_PERIOD};
+pub use codec::Framing;
+pub use profile::{DeviceProfile, supports};
@@ -0,0 +1,113 @@
+use std::sync::{Arc, Mutex};
+use transport::capabilities::Capabilities;
+use transport::event::DeviceIntroduction;
+
+/// Firmware whose introduction carried no protocol version
+pub const BASELINE_PROTOCOL_VERSION: u8 = 0;
+/// First protocol version with capabilities, commands outside the baseline need at least this one
+pub const CAPABILITIES_PROTOCOL_VERSION: u8 = 1;
+/// What firmware from before capability reporting implements
+pub const BASELINE_CAPABILITIES: Capabilities =
+    Capabilities::FIRMWARE_UPDATE.union(Capabilities::FAULT_REPORTING);
+
+/// Profile shared between the reader and the writer half of a session, `None` until the device introduced itself
+pub type SharedDeviceProfile = Arc<Mutex<Option<DeviceProfile>>>;
+
+/// What the device reported about itself in its introduction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceProfile {
+    pub protocol_version: u8,
+    pub capabilities: Capabilities,
+    pub max_frame_size: u16,
+}
+
+impl DeviceProfile {
+    /// Assumed until the device introduced itself
+    pub const BASELINE: Self = Self {
+        protocol_version: BASELINE_PROTOCOL_VERSION,
+        capabilities: BASELINE_CAPABILITIES,
+        max_frame_size: transport::MAX_SHORT_PACKET_SIZE as u16,
+    };
+
+    pub fn from_introduction(introduction: &DeviceIntroduction) -> Self {
+        Self {
+            protocol_version: introduction.protocol_version,
+            capabilities: introduction.capabilities,
+            max_frame_size: introduction.max_frame_size,
+        }
+    }
+
+    /// Device speaks a newer protocol than this server, fields and commands it added are not understood
+    pub fn is_newer_than_server(&self) -> bool {
+        self.protocol_version > transport::PROTOCOL_VERSION
+    }
+
+    /// The older of the device's and the server's protocol version
+    pub fn negotiated_version(&self) -> u8 {
+        self.protocol_version.min(transport::PROTOCOL_VERSION)
+    }
+}
+
+/// Only the baseline is used before the device introduced itself, anything beyond it needs a
+/// protocol version with capabilities on both sides
+pub fn supports(profile: Option<DeviceProfile>, capability: Capabilities) -> bool {
+    let profile = profile.unwrap_or(DeviceProfile::BASELINE);
+    let negotiated = BASELINE_CAPABILITIES.contains(capability)
+        || profile.negotiated_version() >= CAPABILITIES_PROTOCOL_VERSION;
+    negotiated && profile.capabilities.contains(capability)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(capabilities: Capabilities) -> DeviceProfile {
+        DeviceProfile {
+            protocol_version: transport::PROTOCOL_VERSION,
+            capabilities,
+            max_frame_size: transport::MAX_PACKET_SIZE as u16,
+        }
+    }
+
+    #[test]
+    fn unknown_device_should_support_only_the_baseline() {
+        assert!(supports(None, Capabilities::FIRMWARE_UPDATE));
+        assert!(supports(None, Capabilities::FAULT_REPORTING));
+        assert!(!supports(None, Capabilities::RELIABLE_DELIVERY));
+        assert!(!supports(None, Capabilities::MOTION_CONTROL));
+    }
+
+    #[test]
+    fn introduced_device_should_support_only_reported_capabilities() {
+        let profile = Some(profile(Capabilities::FAULT_REPORTING));
+        assert!(supports(profile, Capabilities::FAULT_REPORTING));
+        assert!(!supports(profile, Capabilities::FIRMWARE_UPDATE));
+    }
+
+    #[test]
+    fn device_without_protocol_version_should_support_only_the_baseline() {
+        let mut profile = profile(Capabilities::FAULT_REPORTING.union(Capabilities::SCOPE));
+        profile.protocol_version = BASELINE_PROTOCOL_VERSION;
+        assert!(supports(Some(profile), Capabilities::FAULT_REPORTING));
+        assert!(!supports(Some(profile), Capabilities::SCOPE));
+    }
+
+    #[test]
+    fn newer_protocol_should_be_recognized() {
+        let mut profile = profile(Capabilities::empty());
+        assert!(!profile.is_newer_than_server());
+        profile.protocol_version += 1;
+        assert!(profile.is_newer_than_server());
+    }
+
+    #[test]
+    fn negotiated_version_should_be_the_older_one() {
+        let mut profile = profile(Capabilities::empty());
+        profile.protocol_version += 1;
+        assert_eq!(profile.negotiated_version(), transport::PROTOCOL_VERSION);
+        assert_eq!(
+            DeviceProfile::BASELINE.negotiated_version(),
+            BASELINE_PROTOCOL_VERSION
+        );
+    }
+}
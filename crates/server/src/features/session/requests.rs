@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use transport::Command;
+use transport::frame::Frame;
+use transport::reliable::{QueueFull, Retransmission, RetransmitConfig, RetransmitQueue};
+
+/// Requests that may be in flight at once in reliable mode
+const MAX_IN_FLIGHT: usize = 32;
+
+/// Pending requests shared between the reader and the writer half of a session
+pub type SharedPendingRequests = Arc<Mutex<PendingRequests>>;
+
+/// Commands sent to the device that are still waiting for their response, keyed by sequence number
+#[derive(Debug)]
+pub struct PendingRequests {
+    requests: HashMap<u8, PendingRequest>,
+    retransmit: Option<RetransmitQueue<Command, MAX_IN_FLIGHT>>,
+    started: Instant,
+}
+
+/// A reliable request whose response is overdue
+#[derive(Debug, PartialEq)]
+#[allow(clippy::large_enum_variant)]
+pub enum Overdue {
+    Resend(Frame<Command>),
+    /// The request with this id was not answered after all attempts
+    GaveUp(u32),
+}
+
+#[derive(Debug)]
+struct PendingRequest {
+    request_id: u32,
+    sent_at: Instant,
+}
+
+impl PendingRequests {
+    /// Requests are retransmitted until answered only if `reliability` is set
+    pub fn new(reliability: Option<RetransmitConfig>) -> Self {
+        Self {
+            requests: HashMap::new(),
+            retransmit: reliability.map(RetransmitQueue::new),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn is_reliable(&self) -> bool {
+        self.retransmit.is_some()
+    }
+
+    /// Returns the id of an older request that never got a response before its sequence number was reused
+    pub fn insert(&mut self, sequence: u8, request_id: u32) -> Option<u32> {
+        self.requests
+            .insert(
+                sequence,
+                PendingRequest {
+                    request_id,
+                    sent_at: Instant::now(),
+                },
+            )
+            .map(|lost| lost.request_id)
+    }
+
+    /// Keeps a copy of a reliable request until it is answered
+    pub fn retransmit_later(&mut self, sequence: u8, command: Command) -> Result<(), QueueFull> {
+        let now = self.now_ms();
+        match &mut self.retransmit {
+            Some(queue) => queue.push(sequence, command, now),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the id of the answered request and how long the device took to respond
+    pub fn resolve(&mut self, sequence: u8) -> Option<(u32, Duration)> {
+        if let Some(queue) = &mut self.retransmit {
+            queue.acknowledge(sequence);
+        }
+        self.requests
+            .remove(&sequence)
+            .map(|request| (request.request_id, request.sent_at.elapsed()))
+    }
+
+    /// Next reliable request that is due for retransmission, a request that is given up is no longer pending
+    pub fn due_retransmission(&mut self) -> Option<Overdue> {
+        let now = self.now_ms();
+        loop {
+            match self.retransmit.as_mut()?.poll(now)? {
+                Retransmission::Resend(frame) => return Some(Overdue::Resend(frame)),
+                // Its sequence number may have been reused by a newer request already
+                Retransmission::GaveUp(sequence) => {
+                    if let Some(request) = self.requests.remove(&sequence) {
+                        tracing::error!(
+                            "Request {} was not answered by the device, giving up",
+                            request.request_id
+                        );
+                        return Some(Overdue::GaveUp(request.request_id));
+                    }
+                }
+            }
+        }
+    }
+
+    fn now_ms(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn response_should_resolve_to_request_id() {
+        let mut requests = PendingRequests::new(None);
+        assert_eq!(requests.insert(3, 42), None);
+        assert_eq!(requests.resolve(3).map(|(id, _)| id), Some(42));
+        assert_eq!(requests.resolve(3), None);
+    }
+
+    #[test]
+    fn reused_sequence_should_report_unanswered_request() {
+        let mut requests = PendingRequests::new(None);
+        requests.insert(3, 42);
+        assert_eq!(requests.insert(3, 43), Some(42));
+        assert_eq!(requests.resolve(3).map(|(id, _)| id), Some(43));
+    }
+
+    #[test]
+    fn unreliable_requests_should_never_be_retransmitted() {
+        let mut requests = PendingRequests::new(None);
+        requests.insert(3, 42);
+        requests.retransmit_later(3, Command::Stop).unwrap();
+        assert_eq!(requests.due_retransmission(), None);
+    }
+
+    #[test]
+    fn given_up_request_should_no_longer_be_pending() {
+        let mut requests = PendingRequests::new(Some(RetransmitConfig {
+            timeout_ms: 0,
+            max_attempts: 1,
+        }));
+        requests.insert(3, 42);
+        requests.retransmit_later(3, Command::Stop).unwrap();
+        assert_eq!(requests.due_retransmission(), Some(Overdue::GaveUp(42)));
+        assert_eq!(requests.resolve(3), None);
+    }
+}
@@ -16,5 +16,17 @@ pub mod pyrion {
         pub mod session {
             tonic::include_proto!("pyrion.v1.session");
         }
+        pub mod scope {
+            tonic::include_proto!("pyrion.v1.scope");
+        }
+        pub mod black_box {
+            tonic::include_proto!("pyrion.v1.black_box");
+        }
+        pub mod fault_history {
+            tonic::include_proto!("pyrion.v1.fault_history");
+        }
+        pub mod firmware_update {
+            tonic::include_proto!("pyrion.v1.firmware_update");
+        }
     }
 }
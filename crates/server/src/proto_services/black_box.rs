@@ -0,0 +1,81 @@
+use crate::features::black_box::parse_record;
+use crate::features::interface::InterfaceManager;
+use crate::features::session::DeviceClient;
+use crate::proto::pyrion::v1 as pyrion_v1;
+use crate::proto::pyrion::v1::black_box::{DownloadRequest, Frame, PhaseValues, Record, Strategy};
+use crate::proto_services::device::{download_blob, introduce, open_device};
+pub use pyrion_v1::black_box::black_box_server::BlackBoxServer;
+use std::sync::Arc;
+use std::time::Duration;
+use tonic::{Request, Response, Status};
+use transport::black_box::{BLACK_BOX_BLOB_ID, BlackBoxFrame, StrategyKind};
+use transport::capabilities::Capabilities;
+use transport::reliable::RetransmitConfig;
+
+/// How long the device may take to answer a single request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+pub struct BlackBoxService {
+    interfaces: Arc<InterfaceManager>,
+    reliability: Option<RetransmitConfig>,
+}
+
+impl BlackBoxService {
+    pub fn new(interfaces: Arc<InterfaceManager>, reliability: Option<RetransmitConfig>) -> Self {
+        Self {
+            interfaces,
+            reliability,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl pyrion_v1::black_box::black_box_server::BlackBox for BlackBoxService {
+    async fn download(
+        &self,
+        request: Request<DownloadRequest>,
+    ) -> Result<Response<Record>, Status> {
+        let device_handler = open_device(&self.interfaces, &request)?;
+        let mut client = DeviceClient::new(device_handler, self.reliability, REQUEST_TIMEOUT);
+        let introduction = introduce(&mut client, Capabilities::BLACK_BOX).await?;
+
+        let data =
+            download_blob(&mut client, BLACK_BOX_BLOB_ID, introduction.max_frame_size).await?;
+        if data.is_empty() {
+            return Err(Status::not_found(
+                "No fault was recorded since the last reset",
+            ));
+        }
+        let frames =
+            parse_record(&data).map_err(|error| Status::data_loss(format!("{error:?}")))?;
+        Ok(Response::new(Record {
+            frames: frames.iter().map(map_frame).collect(),
+        }))
+    }
+}
+
+fn map_frame(frame: &BlackBoxFrame) -> Frame {
+    let phase_values = |[u, v, w]: [f32; 3]| Some(PhaseValues { u, v, w });
+    Frame {
+        timestamp_us: frame.timestamp,
+        phase_currents: phase_values(frame.phase_currents),
+        v_bus: frame.v_bus,
+        electrical_angle: frame.electrical_angle,
+        duty_cycles: phase_values(frame.duty_cycles),
+        strategy: map_strategy(frame.strategy) as i32,
+        setpoint: frame.setpoint.to_vec(),
+        active_faults: frame.active_faults,
+    }
+}
+
+fn map_strategy(strategy: StrategyKind) -> Strategy {
+    match strategy {
+        StrategyKind::Unknown => Strategy::Unspecified,
+        StrategyKind::Disabled => Strategy::Disabled,
+        StrategyKind::Current => Strategy::Current,
+        StrategyKind::Velocity => Strategy::Velocity,
+        StrategyKind::Position => Strategy::Position,
+        StrategyKind::DutyCycle => Strategy::DutyCycle,
+    }
+}
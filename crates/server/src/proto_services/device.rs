@@ -0,0 +1,159 @@
+use crate::features::connection_string::decode_connection_string;
+use crate::features::interface;
+use crate::features::interface::InterfaceManager;
+use crate::features::session::blob::BlobDownload;
+use crate::features::session::{ClientError, DeviceClient, DeviceHandleWrapper, supports};
+use tonic::{Request, Status};
+use transport::blob::{BlobChunk, BlobId, BlobMode, BlobOpen};
+use transport::capabilities::Capabilities;
+use transport::event::{DeviceIntroduction, ErrorCode};
+use transport::{Command, Event};
+
+/// Connects to the device named by the `connection-string` metadata of the request
+pub fn open_device<T>(
+    interfaces: &InterfaceManager,
+    request: &Request<T>,
+) -> Result<DeviceHandleWrapper, Status> {
+    open_device_at(interfaces, connection_string(request)?)
+}
+
+pub fn connection_string<T>(request: &Request<T>) -> Result<&str, Status> {
+    request
+        .metadata()
+        .get("connection-string")
+        .and_then(|v| v.to_str().ok())
+        .ok_or_else(|| Status::invalid_argument("connection-string"))
+}
+
+pub fn open_device_at(
+    interfaces: &InterfaceManager,
+    connection_string: &str,
+) -> Result<DeviceHandleWrapper, Status> {
+    let (interface, address) = decode_connection_string(connection_string)
+        .ok_or(ConnectionError::InvalidConnectionString)?;
+
+    let handler = interfaces
+        .get_device_handler(interface, &address)
+        .map_err(ConnectionError::InterfaceError)?;
+
+    Ok(handler)
+}
+
+/// Asks the device to introduce itself, fails if it lacks `capability`
+pub async fn introduce(
+    client: &mut DeviceClient,
+    capability: Capabilities,
+) -> Result<DeviceIntroduction, Status> {
+    let Event::DeviceIntroduction(introduction) =
+        expect_response(client.request(Command::IntroduceYourself).await?)?
+    else {
+        return Err(unexpected_response());
+    };
+    if !supports(client.profile(), capability) {
+        return Err(Status::unimplemented(format!(
+            "Device firmware does not support {capability:?}"
+        )));
+    }
+    Ok(introduction)
+}
+
+/// Reads a whole blob, `max_frame_size` is what the device reported in its introduction
+pub async fn download_blob(
+    client: &mut DeviceClient,
+    id: BlobId,
+    max_frame_size: u16,
+) -> Result<Vec<u8>, Status> {
+    let open = Command::OpenBlob(BlobOpen {
+        id,
+        mode: BlobMode::Read,
+        total_size: 0,
+    });
+    let Event::BlobOpened(opened) = expect_response(client.request(open).await?)? else {
+        return Err(unexpected_response());
+    };
+    let chunk_size = BlobChunk::max_data_size(max_frame_size as usize);
+    let mut download = BlobDownload::new(opened, chunk_size);
+    while let Some(read) = download.next_request() {
+        let Event::BlobData(chunk) = expect_response(client.request(read).await?)? else {
+            return Err(unexpected_response());
+        };
+        download
+            .receive(&chunk)
+            .map_err(|error| Status::data_loss(format!("{error:?}")))?;
+    }
+    expect_success(client.request(download.close_request()).await?)?;
+    Ok(download.into_data())
+}
+
+/// A failure of the device becomes the error of the call
+pub fn expect_response(event: Event) -> Result<Event, Status> {
+    match event {
+        Event::Failure(code) => Err(map_failure(code)),
+        event => Ok(event),
+    }
+}
+
+pub fn expect_success(event: Event) -> Result<(), Status> {
+    match expect_response(event)? {
+        Event::Success => Ok(()),
+        _ => Err(unexpected_response()),
+    }
+}
+
+pub fn unexpected_response() -> Status {
+    Status::internal("Device sent an unexpected response")
+}
+
+/// Why the device refused a request of a service
+pub fn map_failure(code: ErrorCode) -> Status {
+    match code {
+        ErrorCode::NotImplemented => Status::unimplemented("Not implemented by the device"),
+        ErrorCode::Busy => Status::unavailable("Device is busy"),
+        ErrorCode::InvalidState | ErrorCode::NotArmed => {
+            Status::failed_precondition(format!("Device refused the request: {code:?}"))
+        }
+        ErrorCode::InvalidArgument => Status::invalid_argument("Device rejected the arguments"),
+        ErrorCode::InvalidImage => Status::invalid_argument("Device rejected the image"),
+        ErrorCode::WrongBoard => {
+            Status::failed_precondition("The image was built for another board revision")
+        }
+        ErrorCode::FlashError => Status::internal("Device failed to access its flash"),
+        ErrorCode::Unknown => Status::internal("Device failed with an unknown error"),
+    }
+}
+
+enum ConnectionError {
+    InvalidConnectionString,
+    InterfaceError(interface::error::ConnectionError),
+}
+
+impl From<ConnectionError> for Status {
+    fn from(err: ConnectionError) -> Self {
+        match err {
+            ConnectionError::InvalidConnectionString => {
+                Status::invalid_argument("connection-string")
+            }
+            ConnectionError::InterfaceError(interface::error::ConnectionError::DeviceNotFound) => {
+                Status::not_found("Device not found")
+            }
+            ConnectionError::InterfaceError(
+                interface::error::ConnectionError::InterfaceNotAvailable,
+            ) => Status::unavailable("Interface not available"),
+        }
+    }
+}
+
+impl From<ClientError> for Status {
+    fn from(err: ClientError) -> Self {
+        match err {
+            ClientError::Timeout => Status::deadline_exceeded("Device did not respond"),
+            ClientError::Unanswered => {
+                Status::unavailable("Device did not answer any retransmission")
+            }
+            ClientError::Closed => Status::unavailable("Device stream closed"),
+            ClientError::Encoder(error) => {
+                Status::internal(format!("Error writing command: {error:?}"))
+            }
+        }
+    }
+}
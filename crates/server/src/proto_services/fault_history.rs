@@ -0,0 +1,85 @@
+use crate::features::interface::InterfaceManager;
+use crate::features::session::DeviceClient;
+use crate::proto::pyrion::v1 as pyrion_v1;
+use crate::proto::pyrion::v1::device_message;
+use crate::proto::pyrion::v1::fault_history::ReadRequest;
+use crate::proto_services::device::{expect_response, introduce, open_device, unexpected_response};
+use crate::proto_services::session::{map_fault_state, map_fault_type};
+use logging::fault_journal::JournalTime;
+use logging::fault_register::FaultType;
+pub use pyrion_v1::fault_history::fault_history_server::FaultHistoryServer;
+use std::sync::Arc;
+use std::time::Duration;
+use tonic::{Request, Response, Status};
+use transport::capabilities::Capabilities;
+use transport::fault_history::FaultHistory;
+use transport::reliable::RetransmitConfig;
+use transport::{Command, Event};
+
+/// How long the device may take to answer a single request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+pub struct FaultHistoryService {
+    interfaces: Arc<InterfaceManager>,
+    reliability: Option<RetransmitConfig>,
+}
+
+impl FaultHistoryService {
+    pub fn new(interfaces: Arc<InterfaceManager>, reliability: Option<RetransmitConfig>) -> Self {
+        Self {
+            interfaces,
+            reliability,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl pyrion_v1::fault_history::fault_history_server::FaultHistory for FaultHistoryService {
+    async fn read(
+        &self,
+        request: Request<ReadRequest>,
+    ) -> Result<Response<device_message::FaultHistory>, Status> {
+        let device_handler = open_device(&self.interfaces, &request)?;
+        let mut client = DeviceClient::new(device_handler, self.reliability, REQUEST_TIMEOUT);
+        introduce(&mut client, Capabilities::FAULT_HISTORY).await?;
+
+        let Event::FaultHistory(history) =
+            expect_response(client.request(Command::ReadFaultHistory).await?)?
+        else {
+            return Err(unexpected_response());
+        };
+        Ok(Response::new(map_fault_history(&history)))
+    }
+}
+
+pub fn map_fault_history(history: &FaultHistory) -> device_message::FaultHistory {
+    device_message::FaultHistory {
+        boot: history.boot as u32,
+        stats: enum_iterator::all::<FaultType>()
+            .zip(history.stats)
+            .map(|(fault, stats)| device_message::FaultStats {
+                r#type: map_fault_type(fault) as i32,
+                occurrences: stats.occurrences,
+                first_seen: Some(map_journal_time(stats.first_seen)),
+                last_seen: Some(map_journal_time(stats.last_seen)),
+            })
+            .collect(),
+        transitions: history
+            .transitions()
+            .iter()
+            .map(|transition| device_message::FaultTransition {
+                r#type: map_fault_type(transition.fault) as i32,
+                state: map_fault_state(transition.state) as i32,
+                time: Some(map_journal_time(transition.time)),
+            })
+            .collect(),
+    }
+}
+
+fn map_journal_time(time: JournalTime) -> device_message::JournalTime {
+    device_message::JournalTime {
+        boot: time.boot as u32,
+        uptime_us: time.uptime,
+    }
+}
@@ -0,0 +1,280 @@
+use crate::features::firmware_update::{
+    BlockResult, FirmwareUpload, UploadError, check_image, load_image,
+};
+use crate::features::interface::InterfaceManager;
+use crate::features::session::DeviceClient;
+use crate::proto::pyrion::v1 as pyrion_v1;
+use crate::proto::pyrion::v1::device_message;
+use crate::proto::pyrion::v1::firmware_update::{
+    GetSlotsRequest, Stage, UpdateProgress, UpdateRequest,
+};
+use crate::proto_services::device::{
+    connection_string, expect_response, expect_success, introduce, map_failure, open_device,
+    open_device_at, unexpected_response,
+};
+use crate::proto_services::session::map_firmware_version;
+pub use pyrion_v1::firmware_update::firmware_update_server::FirmwareUpdateServer;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tonic::codegen::tokio_stream::Stream;
+use tonic::codegen::tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+use transport::capabilities::Capabilities;
+use transport::command::FirmwareBlock;
+use transport::event::DeviceIntroduction;
+use transport::firmware::{BootOutcome, FirmwareSlots, ImageMetadata};
+use transport::reliable::RetransmitConfig;
+use transport::{Command, Event};
+
+/// How long the device may take to answer a single request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long to wait before sending a block again
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+/// The device resets shortly after the update was finalized, it is not looked for before
+const REBOOT_DELAY: Duration = Duration::from_secs(1);
+/// How long the bootloader may take to swap the images and the new firmware to confirm itself,
+/// a failed trial adds another swap
+const REBOOT_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often the device is looked for while it reboots
+const RECONNECT_PERIOD: Duration = Duration::from_millis(500);
+
+type ProgressSender = mpsc::Sender<Result<UpdateProgress, Status>>;
+
+#[derive(Debug)]
+pub struct FirmwareUpdateService {
+    interfaces: Arc<InterfaceManager>,
+    reliability: Option<RetransmitConfig>,
+}
+
+impl FirmwareUpdateService {
+    pub fn new(interfaces: Arc<InterfaceManager>, reliability: Option<RetransmitConfig>) -> Self {
+        Self {
+            interfaces,
+            reliability,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl pyrion_v1::firmware_update::firmware_update_server::FirmwareUpdate for FirmwareUpdateService {
+    type UpdateStream =
+        Pin<Box<dyn Stream<Item = Result<UpdateProgress, Status>> + Send + 'static>>;
+
+    async fn update(
+        &self,
+        request: Request<UpdateRequest>,
+    ) -> Result<Response<Self::UpdateStream>, Status> {
+        let connection_string = connection_string(&request)?.to_string();
+        let device_handler = open_device_at(&self.interfaces, &connection_string)?;
+        let request = request.into_inner();
+        let image = load_image(&request.image)
+            .map_err(|error| Status::invalid_argument(format!("{error:?}")))?;
+        let resume_offset = request.resume_offset as usize;
+        if resume_offset > image.len() {
+            return Err(Status::invalid_argument("resume_offset"));
+        }
+
+        let mut client = DeviceClient::new(device_handler, self.reliability, REQUEST_TIMEOUT);
+        let introduction = introduce(&mut client, Capabilities::FIRMWARE_UPDATE).await?;
+        // Older firmware does not check the header, its images are sent as they are
+        if introduction
+            .capabilities
+            .contains(Capabilities::FIRMWARE_SLOTS)
+        {
+            check_image(&image, introduction.board_revision)
+                .map_err(|error| Status::invalid_argument(format!("{error:?}")))?;
+        }
+        let block_size = FirmwareBlock::max_data_size(introduction.max_frame_size as usize);
+        let mut upload = FirmwareUpload::new(image, block_size, resume_offset);
+
+        let (tx, rx) = mpsc::channel(16);
+        let interfaces = self.interfaces.clone();
+        let reliability = self.reliability;
+        tokio::spawn(async move {
+            let result = async {
+                write_image(&mut client, &mut upload, &tx).await?;
+                // The old firmware is gone along with the connection to it
+                drop(client);
+                let introduction =
+                    wait_for_device(&interfaces, &connection_string, reliability, &tx, &upload)
+                        .await?;
+                let firmware = map_firmware_version(introduction.firmware_version);
+                tracing::info!("Device came back with firmware {}", firmware);
+                send_progress(&tx, Stage::Done, &upload, Some(firmware)).await
+            }
+            .await;
+            if let Err(status) = result {
+                tracing::error!("Firmware update failed: {:?}", status);
+                let _ = tx.send(Err(status)).await;
+            }
+        });
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+
+    async fn get_slots(
+        &self,
+        request: Request<GetSlotsRequest>,
+    ) -> Result<Response<device_message::FirmwareSlots>, Status> {
+        let device_handler = open_device(&self.interfaces, &request)?;
+        let mut client = DeviceClient::new(device_handler, self.reliability, REQUEST_TIMEOUT);
+        introduce(&mut client, Capabilities::FIRMWARE_SLOTS).await?;
+
+        let Event::FirmwareSlots(slots) =
+            expect_response(client.request(Command::ReadFirmwareSlots).await?)?
+        else {
+            return Err(unexpected_response());
+        };
+        Ok(Response::new(map_firmware_slots(&slots)))
+    }
+}
+
+/// Sends the blocks and finalizes the update, the device resets afterwards
+async fn write_image(
+    client: &mut DeviceClient,
+    upload: &mut FirmwareUpload,
+    tx: &ProgressSender,
+) -> Result<(), Status> {
+    send_progress(tx, Stage::Writing, upload, None).await?;
+    while let Some(block) = upload.next_request() {
+        let response = client.request(block).await;
+        let written = upload.written();
+        match upload.receive(response) {
+            Ok(BlockResult::Written) => {
+                send_progress(tx, Stage::Writing, upload, None).await?;
+            }
+            Ok(BlockResult::Retry) => {
+                tracing::warn!("Device did not confirm the block at {}, retrying", written);
+                tokio::time::sleep(RETRY_DELAY).await;
+            }
+            Err(error) => return Err(map_upload_error(error, written)),
+        }
+    }
+
+    send_progress(tx, Stage::Finalizing, upload, None).await?;
+    expect_success(client.request(Command::FinalizeFirmwareUpdate).await?)?;
+    send_progress(tx, Stage::Rebooting, upload, None).await
+}
+
+/// Waits for the device to drop off and come back with the new firmware confirmed
+async fn wait_for_device(
+    interfaces: &InterfaceManager,
+    connection_string: &str,
+    reliability: Option<RetransmitConfig>,
+    tx: &ProgressSender,
+    upload: &FirmwareUpload,
+) -> Result<DeviceIntroduction, Status> {
+    tokio::time::sleep(REBOOT_DELAY).await;
+    let deadline = Instant::now() + REBOOT_TIMEOUT;
+    let mut confirming = false;
+    loop {
+        if let Ok(device_handler) = open_device_at(interfaces, connection_string) {
+            let mut client = DeviceClient::new(device_handler, reliability, REQUEST_TIMEOUT);
+            if let Ok(introduction) = introduce(&mut client, Capabilities::empty()).await {
+                // Older firmware confirms itself as soon as it boots
+                if !introduction
+                    .capabilities
+                    .contains(Capabilities::FIRMWARE_SLOTS)
+                {
+                    return Ok(introduction);
+                }
+                match read_boot_outcome(&mut client).await {
+                    Ok(BootOutcome::Confirmed | BootOutcome::Unknown) => return Ok(introduction),
+                    Ok(BootOutcome::RolledBack) => {
+                        return Err(Status::aborted(
+                            "The new firmware failed its health checks, the previous one is back",
+                        ));
+                    }
+                    Ok(BootOutcome::Trial) if !confirming => {
+                        confirming = true;
+                        send_progress(tx, Stage::Confirming, upload, None).await?;
+                    }
+                    // Still on trial or gone again, it is asked once more
+                    Ok(BootOutcome::Trial) | Err(_) => {}
+                }
+            }
+        }
+        if Instant::now() >= deadline {
+            return Err(Status::deadline_exceeded(
+                "Device did not come back after the update",
+            ));
+        }
+        tokio::time::sleep(RECONNECT_PERIOD).await;
+    }
+}
+
+async fn read_boot_outcome(client: &mut DeviceClient) -> Result<BootOutcome, Status> {
+    match expect_response(client.request(Command::ReadFirmwareSlots).await?)? {
+        Event::FirmwareSlots(slots) => Ok(slots.boot),
+        _ => Err(unexpected_response()),
+    }
+}
+
+async fn send_progress(
+    tx: &ProgressSender,
+    stage: Stage,
+    upload: &FirmwareUpload,
+    firmware: Option<String>,
+) -> Result<(), Status> {
+    let progress = UpdateProgress {
+        stage: stage as i32,
+        written: upload.written() as u32,
+        total: upload.total() as u32,
+        retries: upload.retries(),
+        firmware: firmware.unwrap_or_default(),
+    };
+    // Nobody is left to report the outcome to, the update is not worth finishing
+    tx.send(Ok(progress))
+        .await
+        .map_err(|_| Status::cancelled("Client went away"))
+}
+
+pub fn map_firmware_slots(slots: &FirmwareSlots) -> device_message::FirmwareSlots {
+    device_message::FirmwareSlots {
+        active: map_image_metadata(&slots.active),
+        pending: map_image_metadata(&slots.pending),
+        swap_scheduled: slots.swap_scheduled,
+        boot: map_boot_outcome(slots.boot) as i32,
+    }
+}
+
+fn map_image_metadata(metadata: &ImageMetadata) -> Option<device_message::ImageMetadata> {
+    metadata.present.then(|| device_message::ImageMetadata {
+        version: map_firmware_version(metadata.version),
+        board_revision: metadata.board_revision as u32,
+        git_hash: to_hex(&metadata.git_hash),
+        length: metadata.length,
+        digest: to_hex(&metadata.digest),
+    })
+}
+
+fn map_boot_outcome(outcome: BootOutcome) -> device_message::BootOutcome {
+    match outcome {
+        BootOutcome::Unknown => device_message::BootOutcome::Unspecified,
+        BootOutcome::Confirmed => device_message::BootOutcome::Confirmed,
+        BootOutcome::Trial => device_message::BootOutcome::Trial,
+        BootOutcome::RolledBack => device_message::BootOutcome::RolledBack,
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// `written` is where an upload can be resumed
+fn map_upload_error(error: UploadError, written: usize) -> Status {
+    match error {
+        UploadError::RetriesExhausted => Status::unavailable(format!(
+            "Device stopped confirming blocks, resume at {written}"
+        )),
+        UploadError::OutOfSync => Status::failed_precondition(format!(
+            "Device did not receive the image up to {written}, start over"
+        )),
+        UploadError::Rejected(code) => map_failure(code),
+        UploadError::UnexpectedResponse => unexpected_response(),
+        UploadError::Client(error) => error.into(),
+    }
+}
@@ -1,5 +1,14 @@
+mod black_box;
+mod device;
 mod discovery;
+mod fault_history;
+mod firmware_update;
+mod scope;
 mod session;
 
+pub use black_box::{BlackBoxServer, BlackBoxService};
 pub use discovery::{DeviceDiscoveryServer, DeviceDiscoveryService};
+pub use fault_history::{FaultHistoryServer, FaultHistoryService};
+pub use firmware_update::{FirmwareUpdateServer, FirmwareUpdateService};
+pub use scope::{ScopeServer, ScopeService};
 pub use session::{DeviceSessionServer, DeviceSessionService};
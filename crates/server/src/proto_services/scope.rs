@@ -0,0 +1,185 @@
+use crate::features::interface::InterfaceManager;
+use crate::features::scope::split_capture;
+use crate::features::session::DeviceClient;
+use crate::proto::pyrion::v1 as pyrion_v1;
+use crate::proto::pyrion::v1::device_message;
+use crate::proto::pyrion::v1::scope::{CaptureRequest, Channel, Waveform};
+use crate::proto_services::device::{
+    download_blob, expect_response, expect_success, introduce, open_device, unexpected_response,
+};
+pub use pyrion_v1::scope::scope_server::ScopeServer;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+use tonic::{Request, Response, Status};
+use transport::capabilities::Capabilities;
+use transport::reliable::RetransmitConfig;
+use transport::scope::{
+    MAX_SCOPE_CHANNELS, SCOPE_BLOB_ID, ScopeChannels, ScopeConfig, ScopeSignal, ScopeState,
+    ScopeStatus, ScopeTrigger, TriggerMode,
+};
+use transport::{Command, Event};
+
+/// How long the device may take to answer a single request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long to wait for the trigger if the request does not say
+const DEFAULT_CAPTURE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often the scope is asked whether the capture is complete
+const STATUS_POLL_PERIOD: Duration = Duration::from_millis(20);
+
+#[derive(Debug)]
+pub struct ScopeService {
+    interfaces: Arc<InterfaceManager>,
+    reliability: Option<RetransmitConfig>,
+}
+
+impl ScopeService {
+    pub fn new(interfaces: Arc<InterfaceManager>, reliability: Option<RetransmitConfig>) -> Self {
+        Self {
+            interfaces,
+            reliability,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl pyrion_v1::scope::scope_server::Scope for ScopeService {
+    async fn capture(
+        &self,
+        request: Request<CaptureRequest>,
+    ) -> Result<Response<Waveform>, Status> {
+        let device_handler = open_device(&self.interfaces, &request)?;
+        let request = request.into_inner();
+        let config = map_capture_request(&request)?;
+        let timeout = match request.timeout_ms {
+            0 => DEFAULT_CAPTURE_TIMEOUT,
+            timeout_ms => Duration::from_millis(timeout_ms as u64),
+        };
+
+        let mut client = DeviceClient::new(device_handler, self.reliability, REQUEST_TIMEOUT);
+        let introduction = introduce(&mut client, Capabilities::SCOPE).await?;
+
+        expect_success(client.request(Command::ConfigureScope(config)).await?)?;
+        if config.trigger.mode == TriggerMode::Manual {
+            expect_success(client.request(Command::TriggerScope).await?)?;
+        }
+
+        let deadline = Instant::now() + timeout;
+        let status = loop {
+            let Event::ScopeStatus(status) =
+                expect_response(client.request(Command::ReportScope).await?)?
+            else {
+                return Err(unexpected_response());
+            };
+            if status.state == ScopeState::Done {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                return Err(Status::deadline_exceeded("Scope did not trigger"));
+            }
+            tokio::time::sleep(STATUS_POLL_PERIOD).await;
+        };
+        let data = download_blob(&mut client, SCOPE_BLOB_ID, introduction.max_frame_size).await?;
+
+        let traces = split_capture(&status, &data)
+            .map_err(|error| Status::data_loss(format!("{error:?}")))?;
+        Ok(Response::new(Waveform {
+            sample_rate: status.sample_rate,
+            trigger_index: status.pre_trigger as u32,
+            channels: traces
+                .into_iter()
+                .map(|trace| Channel {
+                    signal: map_scope_signal(trace.signal) as i32,
+                    samples: trace.samples,
+                })
+                .collect(),
+        }))
+    }
+}
+
+pub fn map_scope_status(status: ScopeStatus) -> device_message::ScopeStatus {
+    device_message::ScopeStatus {
+        state: map_scope_state(status.state) as i32,
+        channels: status
+            .channels
+            .iter()
+            .map(|signal| map_scope_signal(signal) as i32)
+            .collect(),
+        length: status.length as u32,
+        pre_trigger: status.pre_trigger as u32,
+        sample_rate: status.sample_rate,
+    }
+}
+
+/// The device checks the config against its buffer, only what does not fit the wire is rejected
+fn map_capture_request(request: &CaptureRequest) -> Result<ScopeConfig, Status> {
+    let invalid = |field: &str| Status::invalid_argument(field.to_string());
+    if request.channels.len() > MAX_SCOPE_CHANNELS {
+        return Err(invalid("channels"));
+    }
+    let mut channels = [None; MAX_SCOPE_CHANNELS];
+    for (channel, signal) in channels.iter_mut().zip(request.channels()) {
+        *channel = Some(map_proto_scope_signal(signal).ok_or_else(|| invalid("channels"))?);
+    }
+    let mode = match request.trigger_mode() {
+        pyrion_v1::scope::TriggerMode::Manual => TriggerMode::Manual,
+        pyrion_v1::scope::TriggerMode::RisingEdge => TriggerMode::RisingEdge,
+        pyrion_v1::scope::TriggerMode::FallingEdge => TriggerMode::FallingEdge,
+        pyrion_v1::scope::TriggerMode::AboveLevel => TriggerMode::AboveLevel,
+        pyrion_v1::scope::TriggerMode::BelowLevel => TriggerMode::BelowLevel,
+        pyrion_v1::scope::TriggerMode::Unspecified => return Err(invalid("trigger_mode")),
+    };
+    Ok(ScopeConfig {
+        channels: ScopeChannels(channels),
+        trigger: ScopeTrigger {
+            mode,
+            channel: u8::try_from(request.trigger_channel)
+                .map_err(|_| invalid("trigger_channel"))?,
+            level: request.trigger_level,
+        },
+        length: u16::try_from(request.length).map_err(|_| invalid("length"))?,
+        pre_trigger: u16::try_from(request.pre_trigger).map_err(|_| invalid("pre_trigger"))?,
+        decimation: u16::try_from(request.decimation).map_err(|_| invalid("decimation"))?,
+    })
+}
+
+fn map_scope_signal(signal: ScopeSignal) -> device_message::ScopeSignal {
+    match signal {
+        ScopeSignal::PhaseCurrentU => device_message::ScopeSignal::PhaseCurrentU,
+        ScopeSignal::PhaseCurrentV => device_message::ScopeSignal::PhaseCurrentV,
+        ScopeSignal::PhaseCurrentW => device_message::ScopeSignal::PhaseCurrentW,
+        ScopeSignal::CurrentD => device_message::ScopeSignal::CurrentD,
+        ScopeSignal::CurrentQ => device_message::ScopeSignal::CurrentQ,
+        ScopeSignal::VoltageD => device_message::ScopeSignal::VoltageD,
+        ScopeSignal::VoltageQ => device_message::ScopeSignal::VoltageQ,
+        ScopeSignal::ElectricalAngle => device_message::ScopeSignal::ElectricalAngle,
+        ScopeSignal::Velocity => device_message::ScopeSignal::Velocity,
+        ScopeSignal::BusVoltage => device_message::ScopeSignal::BusVoltage,
+    }
+}
+
+fn map_proto_scope_signal(signal: device_message::ScopeSignal) -> Option<ScopeSignal> {
+    match signal {
+        device_message::ScopeSignal::PhaseCurrentU => Some(ScopeSignal::PhaseCurrentU),
+        device_message::ScopeSignal::PhaseCurrentV => Some(ScopeSignal::PhaseCurrentV),
+        device_message::ScopeSignal::PhaseCurrentW => Some(ScopeSignal::PhaseCurrentW),
+        device_message::ScopeSignal::CurrentD => Some(ScopeSignal::CurrentD),
+        device_message::ScopeSignal::CurrentQ => Some(ScopeSignal::CurrentQ),
+        device_message::ScopeSignal::VoltageD => Some(ScopeSignal::VoltageD),
+        device_message::ScopeSignal::VoltageQ => Some(ScopeSignal::VoltageQ),
+        device_message::ScopeSignal::ElectricalAngle => Some(ScopeSignal::ElectricalAngle),
+        device_message::ScopeSignal::Velocity => Some(ScopeSignal::Velocity),
+        device_message::ScopeSignal::BusVoltage => Some(ScopeSignal::BusVoltage),
+        device_message::ScopeSignal::Unspecified => None,
+    }
+}
+
+fn map_scope_state(state: ScopeState) -> device_message::ScopeState {
+    match state {
+        ScopeState::Unknown => device_message::ScopeState::Unspecified,
+        ScopeState::Idle => device_message::ScopeState::Idle,
+        ScopeState::Armed => device_message::ScopeState::Armed,
+        ScopeState::Triggered => device_message::ScopeState::Triggered,
+        ScopeState::Done => device_message::ScopeState::Done,
+    }
+}
@@ -1,13 +1,16 @@
-use crate::features::connection_string::decode_connection_string;
-use crate::features::interface;
+use crate::features::device_log::{format_record, trace_record};
 use crate::features::interface::InterfaceManager;
-use crate::features::session::DeviceHandleWrapper;
+use crate::features::session::{DeviceProfile, IncomingEvent, RETRANSMIT_CHECK_PERIOD, supports};
+use crate::proto_services::device::open_device;
+use crate::proto_services::fault_history::map_fault_history;
+use crate::proto_services::firmware_update::map_firmware_slots;
+use crate::proto_services::scope::map_scope_status;
 use crate::proto::pyrion::v1 as pyrion_v1;
 use crate::proto::pyrion::v1::controller_message::ControllerMessage;
 use crate::proto::pyrion::v1::controller_message::controller_message::Payload as ControllerMessagePayload;
 use crate::proto::pyrion::v1::device_message::device_message::Payload as DeviceMessagePayload;
 use crate::proto::pyrion::v1::device_message::{DeviceIntroduction, DeviceMessage, Telemetry};
-use logging::fault_register;
+use logging::fault_register::{self, FaultState, FaultType};
 pub use pyrion_v1::session::device_session_server::DeviceSessionServer;
 use std::pin::Pin;
 use std::sync::Arc;
@@ -16,31 +19,35 @@ use tonic::codegen::tokio_stream::wrappers::ReceiverStream;
 use tonic::codegen::tokio_stream::{Stream, StreamExt};
 use tonic::{Request, Response, Status, Streaming};
 use transport::Command;
-use transport::command::{FIRMWARE_BLOCK_MAX_DATA_SIZE, FirmwareBlock};
-use transport::event::Event;
+use transport::blob::{BlobChunk, BlobClose, BlobId, BlobMode, BlobOpen, BlobRead};
+use transport::capabilities::Capabilities;
+use transport::command::FirmwareBlock;
+use transport::decoder::DecoderStats;
+use transport::event::{ArmingState, ErrorCode, Event, FaultChange};
+use transport::log::{LogLevel, LogRecord};
+use transport::motion::{
+    CurrentSetpoint, DutyCycleSetpoint, PositionSetpoint, TorqueSetpoint, VelocitySetpoint,
+};
+use transport::reliable::RetransmitConfig;
+use transport::telemetry::{
+    TelemetryField, TelemetryFields, TelemetrySample, TelemetrySubscription, TelemetryValue,
+};
 use uuid::Uuid;
 use crate::proto::pyrion::v1::device_message;
+use crate::proto::pyrion::v1::controller_message;
 
 #[derive(Debug)]
 pub struct DeviceSessionService {
     interfaces: Arc<InterfaceManager>,
+    reliability: Option<RetransmitConfig>,
 }
 
 impl DeviceSessionService {
-    pub fn new(interfaces: Arc<InterfaceManager>) -> Self {
-        Self { interfaces }
-    }
-
-    async fn get_device(
-        &self,
-        connection_string: &str,
-    ) -> Result<DeviceHandleWrapper, ConnectionError> {
-        let (interface, address) = decode_connection_string(connection_string)
-            .ok_or(ConnectionError::InvalidConnectionString)?;
-
-        let handler = self.interfaces.get_device_handler(interface, &address)?;
-
-        Ok(handler)
+    pub fn new(interfaces: Arc<InterfaceManager>, reliability: Option<RetransmitConfig>) -> Self {
+        Self {
+            interfaces,
+            reliability,
+        }
     }
 }
 
@@ -52,14 +59,8 @@ impl pyrion_v1::session::device_session_server::DeviceSession for DeviceSessionS
         &self,
         request: Request<Streaming<ControllerMessage>>,
     ) -> Result<Response<Self::OpenStream>, Status> {
-        let connection_string = request
-            .metadata()
-            .get("connection-string")
-            .and_then(|v| v.to_str().ok())
-            .ok_or_else(|| Status::invalid_argument("connection-string"))?;
-
-        let device_handler = self.get_device(connection_string).await?;
-        let (mut reader, mut writer) = device_handler.split();
+        let device_handler = open_device(&self.interfaces, &request)?;
+        let (mut reader, mut writer) = device_handler.split(self.reliability);
 
         let mut in_stream = request.into_inner();
         let (tx, rx) = mpsc::channel(128);
@@ -67,25 +68,28 @@ impl pyrion_v1::session::device_session_server::DeviceSession for DeviceSessionS
         let shutdown = Arc::new(tokio::sync::Notify::new());
         let shutdown_reader = shutdown.clone();
         let shutdown_writer = shutdown.clone();
+        let local_responses = tx.clone();
 
         tokio::spawn(async move {
             loop {
                 tokio::select! {
                     next = reader.read_next() => {
                         match next {
-                            Some(Ok(event)) => {
-                                if !matches!(event, Event::Telemetry(_)){
-                                    tracing::info!("Received event: {:?}", event);
+                            Some(Ok(incoming)) => {
+                                match &incoming.event {
+                                    Event::Log(record) => trace_record(record),
+                                    Event::Telemetry(_) | Event::TelemetrySample(_) => {}
+                                    event => tracing::info!("Received event: {:?}", event),
                                 }
-                                let device_message = map_event_to_proto(event);
+                                let device_message = map_event_to_proto(incoming, reader.stats(), reader.profile());
                                 if let Err(error) = tx.send(Ok(device_message)).await {
                                     tracing::error!("Error sending event: {:?}", error);
                                     break;
                                 }
                             }
                             Some(Err(error)) => {
+                                // A lost response is recovered by the retransmit timer in reliable mode
                                 tracing::error!("Error reading event: {:?}", error);
-                                // TODO ask for retransmission?
                             }
                             None => {
                                 tracing::info!("Device stream closed");
@@ -104,25 +108,52 @@ impl pyrion_v1::session::device_session_server::DeviceSession for DeviceSessionS
 
         // Writer task
         tokio::spawn(async move {
-            loop {
+            let mut retransmit_ticker = tokio::time::interval(RETRANSMIT_CHECK_PERIOD);
+            'session: loop {
                 tokio::select! {
+                    _ = retransmit_ticker.tick() => {
+                        match writer.retransmit().await {
+                            Ok(given_up) => {
+                                // The device will not answer them anymore
+                                for request_id in given_up {
+                                    let failure = failure(request_id, device_message::ErrorCode::Unspecified);
+                                    if local_responses.send(Ok(failure)).await.is_err() {
+                                        break 'session;
+                                    }
+                                }
+                            }
+                            Err(error) => {
+                                tracing::error!("Error retransmitting command: {:?}", error);
+                                break;
+                            }
+                        }
+                    }
                     next = in_stream.next() => {
                         match next {
                             Some(Ok(controller_message)) => {
                                 tracing::info!("Received controller message: {:?}", controller_message);
-                                match map_proto_to_command(controller_message) {
+                                let request_id = controller_message.request_id;
+                                match map_proto_to_command(controller_message, writer.profile()) {
                                     Ok(command) => {
-                                        if let Err(error) = writer.write(command).await {
+                                        if let Err(error) = writer.write(command, request_id).await {
                                             tracing::error!("Error writing command: {:?}", error);
                                             break;
                                         }
                                     },
-                                    Err(CommandMappingError::InvalidPayload) => {
-                                        tracing::warn!("Received command has an invalid payload");
-
-                                    },
-                                    Err(CommandMappingError::NoPayload) => {
-                                        tracing::warn!("Received invalid command, payload was empty");
+                                    Err(error @ (CommandMappingError::InvalidPayload | CommandMappingError::NoPayload)) => {
+                                        tracing::warn!("Received command cannot be sent to the device: {:?}", error);
+                                        let failure = failure(request_id, device_message::ErrorCode::InvalidArgument);
+                                        if local_responses.send(Ok(failure)).await.is_err() {
+                                            break;
+                                        }
+                                    }
+                                    Err(CommandMappingError::Unsupported) => {
+                                        tracing::warn!("Received command is not supported by the device firmware");
+                                        // Answered here, the device would not understand it
+                                        let failure = failure(request_id, device_message::ErrorCode::NotImplemented);
+                                        if local_responses.send(Ok(failure)).await.is_err() {
+                                            break;
+                                        }
                                     }
                                 }
                             }
@@ -150,51 +181,43 @@ impl pyrion_v1::session::device_session_server::DeviceSession for DeviceSessionS
     }
 }
 
-enum ConnectionError {
-    InvalidConnectionString,
-    InterfaceError(interface::error::ConnectionError),
-}
-
-impl From<ConnectionError> for Status {
-    fn from(err: ConnectionError) -> Self {
-        match err {
-            ConnectionError::InvalidConnectionString => {
-                Status::invalid_argument("connection-string")
-            }
-            ConnectionError::InterfaceError(interface::error::ConnectionError::DeviceNotFound) => {
-                Status::not_found("Device not found")
-            }
-            ConnectionError::InterfaceError(
-                interface::error::ConnectionError::InterfaceNotAvailable,
-            ) => Status::unavailable("Interface not available"),
-        }
-    }
-}
-
-impl From<interface::error::ConnectionError> for ConnectionError {
-    fn from(value: interface::error::ConnectionError) -> Self {
-        Self::InterfaceError(value)
+/// Answers a request on behalf of the device
+fn failure(request_id: u32, code: device_message::ErrorCode) -> DeviceMessage {
+    DeviceMessage {
+        request_id: Some(request_id),
+        payload: Some(DeviceMessagePayload::Failure(device_message::Failure {
+            code: code as i32,
+        })),
     }
 }
 
-fn map_event_to_proto(event: Event) -> DeviceMessage {
-    match event {
+fn map_event_to_proto(
+    incoming: IncomingEvent,
+    host_link: DecoderStats,
+    profile: Option<DeviceProfile>,
+) -> DeviceMessage {
+    let request_id = incoming.request_id;
+    // Firmware without link statistics sends zeros in their place
+    let device_links = supports(profile, Capabilities::LINK_QUALITY);
+    match incoming.event {
         Event::DeviceIntroduction(device_introduction) => DeviceMessage {
+            request_id,
             payload: Some(DeviceMessagePayload::DeviceIntroduction(
                 DeviceIntroduction {
-                    firmware: format!(
-                        "{}.{}.{}",
-                        device_introduction.firmware_version[0],
-                        device_introduction.firmware_version[1],
-                        device_introduction.firmware_version[2]
-                    ),
+                    firmware: map_firmware_version(device_introduction.firmware_version),
                     uid: map_uid_to_uuid(&device_introduction.uid)
                         .to_string()
                         .to_uppercase(),
+                    protocol_version: device_introduction.protocol_version as u32,
+                    board_revision: device_introduction.board_revision as u32,
+                    bootloader: map_bootloader_version(device_introduction.bootloader_version),
+                    max_frame_size: device_introduction.max_frame_size as u32,
+                    capabilities: map_capabilities(device_introduction.capabilities),
                 },
             )),
         },
         Event::Telemetry(telemetry) => DeviceMessage {
+            request_id,
             payload: Some(DeviceMessagePayload::Telemetry(Telemetry {
                 cpu_temp: telemetry.cpu_temperature,
                 driver_temp: telemetry.driver_temperature,
@@ -206,28 +229,41 @@ fn map_event_to_proto(event: Event) -> DeviceMessage {
                 uptime: telemetry.uptime,
                 active_faults: telemetry.active_faults,
                 latched_faults: telemetry.latched_faults,
+                usb_link: device_links.then(|| map_link_quality(telemetry.usb_link)),
+                serial_link: device_links.then(|| map_link_quality(telemetry.serial_link)),
+                host_link: Some(map_link_quality(host_link)),
+                arming_state: map_arming_state(telemetry.arming_state) as i32,
             })),
         },
+        Event::TelemetrySample(sample) => DeviceMessage {
+            request_id,
+            payload: Some(DeviceMessagePayload::TelemetrySample(
+                map_telemetry_sample(sample),
+            )),
+        },
         Event::Success => DeviceMessage {
+            request_id,
             payload: Some(DeviceMessagePayload::Success(
                 device_message::Success {},
             )),
         },
-        Event::Failure => DeviceMessage {
+        Event::Failure(code) => DeviceMessage {
+            request_id,
             payload: Some(DeviceMessagePayload::Failure(
-                device_message::Failure {},
+                device_message::Failure {
+                    code: map_error_code(code) as i32,
+                },
             )),
         },
         Event::FaultRegister(error_register) => DeviceMessage {
+            request_id,
             payload: Some(DeviceMessagePayload::FaultRegister(
                 device_message::FaultRegister {
                     faults: enum_iterator::all::<fault_register::FaultType>()
                         .enumerate()
                         .filter_map(|(i, err)| {
                             let value = error_register.cells[i];
-                            let mapped_error = match err {
-                                fault_register::FaultType::Encoder => device_message::FaultType::Encoder,
-                            };
+                            let mapped_error = map_fault_type(err);
 
                             match value {
                                 fault_register::FaultState::Clean => None,
@@ -246,49 +282,401 @@ fn map_event_to_proto(event: Event) -> DeviceMessage {
                         .collect(),
                 },
             )),
+        },
+        Event::BlobOpened(info) => DeviceMessage {
+            request_id,
+            payload: Some(DeviceMessagePayload::BlobOpened(
+                device_message::BlobOpened {
+                    handle: info.handle as u32,
+                    total_size: info.total_size,
+                },
+            )),
+        },
+        Event::BlobData(chunk) => DeviceMessage {
+            request_id,
+            payload: Some(DeviceMessagePayload::BlobData(device_message::BlobData {
+                handle: chunk.handle as u32,
+                offset: chunk.offset,
+                data: chunk.slice().to_vec(),
+            })),
+        },
+        Event::ScopeStatus(status) => DeviceMessage {
+            request_id,
+            payload: Some(DeviceMessagePayload::ScopeStatus(map_scope_status(status))),
+        },
+        Event::Log(record) => DeviceMessage {
+            request_id,
+            payload: Some(DeviceMessagePayload::Log(map_log_record(&record))),
+        },
+        Event::FaultRaised(change) => DeviceMessage {
+            request_id,
+            payload: Some(DeviceMessagePayload::FaultRaised(map_fault_change(change))),
+        },
+        Event::FaultCleared(change) => DeviceMessage {
+            request_id,
+            payload: Some(DeviceMessagePayload::FaultCleared(map_fault_change(change))),
+        },
+        Event::FaultHistory(history) => DeviceMessage {
+            request_id,
+            payload: Some(DeviceMessagePayload::FaultHistory(map_fault_history(&history))),
+        },
+        Event::FirmwareSlots(slots) => DeviceMessage {
+            request_id,
+            payload: Some(DeviceMessagePayload::FirmwareSlots(map_firmware_slots(&slots))),
+        },
+    }
+}
+
+pub fn map_fault_type(fault: FaultType) -> device_message::FaultType {
+    match fault {
+        FaultType::Encoder => device_message::FaultType::Encoder,
+        FaultType::OvercurrentU => device_message::FaultType::OvercurrentU,
+        FaultType::OvercurrentV => device_message::FaultType::OvercurrentV,
+        FaultType::OvercurrentW => device_message::FaultType::OvercurrentW,
+        FaultType::OvercurrentBus => device_message::FaultType::OvercurrentBus,
+        FaultType::BusOvervoltage => device_message::FaultType::BusOvervoltage,
+        FaultType::BusUndervoltage => device_message::FaultType::BusUndervoltage,
+        FaultType::DriverOvertemperature => device_message::FaultType::DriverOvertemperature,
+        FaultType::MotorOvertemperature => device_message::FaultType::MotorOvertemperature,
+        FaultType::McuOvertemperature => device_message::FaultType::McuOvertemperature,
+        FaultType::LoopOverrun => device_message::FaultType::LoopOverrun,
+        FaultType::AdcTimeout => device_message::FaultType::AdcTimeout,
+        FaultType::CommunicationTimeout => device_message::FaultType::CommunicationTimeout,
+        FaultType::ConfigError => device_message::FaultType::ConfigError,
+        FaultType::FlashError => device_message::FaultType::FlashError,
+    }
+}
+
+pub fn map_fault_state(state: FaultState) -> device_message::FaultState {
+    match state {
+        FaultState::Clean => device_message::FaultState::Clean,
+        FaultState::Active => device_message::FaultState::Active,
+        FaultState::Latched => device_message::FaultState::Latched,
+    }
+}
+
+fn map_fault_change(change: FaultChange) -> device_message::FaultChange {
+    device_message::FaultChange {
+        r#type: map_fault_type(change.fault) as i32,
+        state: map_fault_state(change.state) as i32,
+        timestamp_us: change.timestamp,
+    }
+}
+
+fn map_log_record(record: &LogRecord) -> device_message::LogRecord {
+    device_message::LogRecord {
+        level: map_log_level(record.level) as i32,
+        timestamp_us: record.timestamp,
+        message: format_record(record),
+        format: record.message().to_string(),
+        args: record.args().map(str::to_string).collect(),
+    }
+}
+
+fn map_log_level(level: LogLevel) -> device_message::LogLevel {
+    match level {
+        LogLevel::Unknown => device_message::LogLevel::Unspecified,
+        LogLevel::Trace => device_message::LogLevel::Trace,
+        LogLevel::Debug => device_message::LogLevel::Debug,
+        LogLevel::Info => device_message::LogLevel::Info,
+        LogLevel::Warn => device_message::LogLevel::Warn,
+        LogLevel::Error => device_message::LogLevel::Error,
+        LogLevel::Off => device_message::LogLevel::Off,
+    }
+}
+
+fn map_telemetry_sample(sample: TelemetrySample) -> device_message::TelemetrySample {
+    let mut mapped = device_message::TelemetrySample {
+        timestamp_us: sample.timestamp,
+        ..Default::default()
+    };
+    for value in sample.values() {
+        match value {
+            TelemetryValue::PhaseCurrents([u, v, w]) => {
+                mapped.phase_currents = Some(device_message::PhaseCurrents { u, v, w });
+            }
+            TelemetryValue::DqCurrents([d, q]) => {
+                mapped.dq_currents = Some(device_message::DqValues { d, q });
+            }
+            TelemetryValue::DqVoltages([d, q]) => {
+                mapped.dq_voltages = Some(device_message::DqValues { d, q });
+            }
+            TelemetryValue::ElectricalAngle(angle) => mapped.electrical_angle = Some(angle),
+            TelemetryValue::Velocity(velocity) => mapped.velocity = Some(velocity),
+            TelemetryValue::BusVoltage(v_bus) => mapped.v_bus = Some(v_bus),
+            TelemetryValue::Temperatures([cpu, driver, motor]) => {
+                mapped.temperatures = Some(device_message::Temperatures { cpu, driver, motor });
+            }
+            TelemetryValue::LoopTiming([frequency, duration_us]) => {
+                mapped.loop_timing = Some(device_message::LoopTiming {
+                    frequency,
+                    duration_us,
+                });
+            }
+            TelemetryValue::Faults([active, latched]) => {
+                mapped.faults = Some(device_message::FaultBits { active, latched });
+            }
+        }
+    }
+    mapped
+}
+
+fn map_link_quality(stats: DecoderStats) -> device_message::LinkQuality {
+    device_message::LinkQuality {
+        crc_errors: stats.crc_errors,
+        resyncs: stats.resyncs,
+        dropped_bytes: stats.dropped_bytes,
+        lost_frames: stats.lost_frames,
+    }
+}
+
+fn map_error_code(code: ErrorCode) -> device_message::ErrorCode {
+    match code {
+        ErrorCode::Unknown => device_message::ErrorCode::Unspecified,
+        ErrorCode::NotImplemented => device_message::ErrorCode::NotImplemented,
+        ErrorCode::Busy => device_message::ErrorCode::Busy,
+        ErrorCode::InvalidState => device_message::ErrorCode::InvalidState,
+        ErrorCode::InvalidArgument => device_message::ErrorCode::InvalidArgument,
+        ErrorCode::FlashError => device_message::ErrorCode::FlashError,
+        ErrorCode::NotArmed => device_message::ErrorCode::NotArmed,
+        ErrorCode::InvalidImage => device_message::ErrorCode::InvalidImage,
+        ErrorCode::WrongBoard => device_message::ErrorCode::WrongBoard,
+    }
+}
+
+fn map_arming_state(state: ArmingState) -> device_message::ArmingState {
+    match state {
+        ArmingState::Unknown => device_message::ArmingState::Unspecified,
+        ArmingState::Boot => device_message::ArmingState::Boot,
+        ArmingState::Idle => device_message::ArmingState::Idle,
+        ArmingState::Calibrating => device_message::ArmingState::Calibrating,
+        ArmingState::Armed => device_message::ArmingState::Armed,
+        ArmingState::Running => device_message::ArmingState::Running,
+        ArmingState::Fault => device_message::ArmingState::Fault,
+    }
+}
+
+pub fn map_firmware_version(version: [u8; 3]) -> String {
+    format!("{}.{}.{}", version[0], version[1], version[2])
+}
+
+fn map_bootloader_version(version: [u8; 3]) -> Option<String> {
+    (version != [0; 3]).then(|| map_firmware_version(version))
+}
+
+fn map_capabilities(capabilities: Capabilities) -> Vec<i32> {
+    [
+        (Capabilities::RELIABLE_DELIVERY, device_message::Capability::ReliableDelivery),
+        (Capabilities::FAULT_REPORTING, device_message::Capability::FaultReporting),
+        (Capabilities::LINK_QUALITY, device_message::Capability::LinkQuality),
+        (Capabilities::FIRMWARE_UPDATE, device_message::Capability::FirmwareUpdate),
+        (Capabilities::BLOB_TRANSFER, device_message::Capability::BlobTransfer),
+        (Capabilities::MOTION_CONTROL, device_message::Capability::MotionControl),
+        (Capabilities::TELEMETRY_STREAMS, device_message::Capability::TelemetryStreams),
+        (Capabilities::SCOPE, device_message::Capability::Scope),
+        (Capabilities::BLACK_BOX, device_message::Capability::BlackBox),
+        (Capabilities::LOG_FORWARDING, device_message::Capability::LogForwarding),
+        (Capabilities::FAULT_EVENTS, device_message::Capability::FaultEvents),
+        (Capabilities::FAULT_HISTORY, device_message::Capability::FaultHistory),
+        (Capabilities::FIRMWARE_SLOTS, device_message::Capability::FirmwareSlots),
+    ]
+    .into_iter()
+    .filter(|(capability, _)| capabilities.contains(*capability))
+    .map(|(_, mapped)| mapped as i32)
+    .collect()
+}
+
+/// Commands the device did not report support for, or that its protocol version lacks, are not
+/// sent at all
+fn map_proto_to_command(
+    message: ControllerMessage,
+    profile: Option<DeviceProfile>,
+) -> Result<Command, CommandMappingError> {
+    // Until the device introduced itself only short frames are safe
+    let max_frame_size = profile
+        .unwrap_or(DeviceProfile::BASELINE)
+        .max_frame_size as usize;
+    let command = map_payload_to_command(message, max_frame_size)?;
+    let required = match command {
+        Command::WriteFirmwareBlock(_) | Command::FinalizeFirmwareUpdate => {
+            Capabilities::FIRMWARE_UPDATE
+        }
+        Command::ReportFaults | Command::ResetFaults => Capabilities::FAULT_REPORTING,
+        Command::ReadFaultHistory => Capabilities::FAULT_HISTORY,
+        Command::ReadFirmwareSlots => Capabilities::FIRMWARE_SLOTS,
+        Command::OpenBlob(_)
+        | Command::ReadBlob(_)
+        | Command::WriteBlob(_)
+        | Command::CloseBlob(_) => Capabilities::BLOB_TRANSFER,
+        Command::Arm
+        | Command::Disarm
+        | Command::SetCurrent(_)
+        | Command::SetTorque(_)
+        | Command::SetVelocity(_)
+        | Command::SetPosition(_)
+        | Command::SetDutyCycle(_) => Capabilities::MOTION_CONTROL,
+        Command::SubscribeTelemetry(_) => Capabilities::TELEMETRY_STREAMS,
+        Command::ConfigureScope(_) | Command::TriggerScope | Command::ReportScope => {
+            Capabilities::SCOPE
         }
+        Command::SetLogLevel(_) => Capabilities::LOG_FORWARDING,
+        Command::IntroduceYourself | Command::Stop => Capabilities::empty(),
+    };
+    if !supports(profile, required) {
+        return Err(CommandMappingError::Unsupported);
     }
+    Ok(command)
 }
 
-fn map_proto_to_command(message: ControllerMessage) -> Result<Command, CommandMappingError> {
+fn map_payload_to_command(
+    message: ControllerMessage,
+    max_frame_size: usize,
+) -> Result<Command, CommandMappingError> {
     message
         .payload
         .map(|payload| match payload {
             ControllerMessagePayload::IntroduceYourself(_) => Ok(Command::IntroduceYourself),
             ControllerMessagePayload::Stop(_) => Ok(Command::Stop),
+            ControllerMessagePayload::Arm(_) => Ok(Command::Arm),
+            ControllerMessagePayload::Disarm(_) => Ok(Command::Disarm),
             ControllerMessagePayload::WriteFirmwareBlock(write_firmware_block) => {
-                let mut data = [0; FIRMWARE_BLOCK_MAX_DATA_SIZE];
-                let converted_bytes: Vec<u8> = write_firmware_block
-                    .data
-                    .iter()
-                    .flat_map(|&x| x.to_le_bytes())
-                    .collect::<Vec<_>>();
-                if converted_bytes.len() > FIRMWARE_BLOCK_MAX_DATA_SIZE {
+                if write_firmware_block.data.len() > FirmwareBlock::max_data_size(max_frame_size) {
                     return Err(CommandMappingError::InvalidPayload);
                 }
-                data.copy_from_slice(converted_bytes.as_slice());
-                Ok(Command::WriteFirmwareBlock(FirmwareBlock {
-                    offset: write_firmware_block.offset,
-                    length: write_firmware_block.data.len() as u32 * 8,
-                    data,
-                }))
+                Ok(Command::WriteFirmwareBlock(FirmwareBlock::new(
+                    write_firmware_block.offset,
+                    &write_firmware_block.data,
+                )))
             }
             ControllerMessagePayload::FinalizeFirmwareUpdate(_) => {
                 Ok(Command::FinalizeFirmwareUpdate)
             },
             ControllerMessagePayload::ReportFaults(_) => Ok(Command::ReportFaults),
             ControllerMessagePayload::ResetFaults(_) => Ok(Command::ResetFaults),
+            ControllerMessagePayload::ReadFaultHistory(_) => Ok(Command::ReadFaultHistory),
+            ControllerMessagePayload::ReadFirmwareSlots(_) => Ok(Command::ReadFirmwareSlots),
+            ControllerMessagePayload::OpenBlob(open_blob) => {
+                let mode = match open_blob.mode() {
+                    controller_message::BlobMode::Read => BlobMode::Read,
+                    controller_message::BlobMode::Write => BlobMode::Write,
+                    controller_message::BlobMode::Unspecified => {
+                        return Err(CommandMappingError::InvalidPayload);
+                    }
+                };
+                Ok(Command::OpenBlob(BlobOpen {
+                    id: BlobId(narrow(open_blob.blob_id)?),
+                    mode,
+                    total_size: open_blob.total_size,
+                }))
+            }
+            ControllerMessagePayload::ReadBlob(read_blob) => Ok(Command::ReadBlob(BlobRead {
+                handle: narrow(read_blob.handle)?,
+                offset: read_blob.offset,
+                // The device answers with at most what fits into one of its frames
+                length: read_blob
+                    .length
+                    .min(BlobChunk::max_data_size(max_frame_size) as u32)
+                    as u16,
+            })),
+            ControllerMessagePayload::WriteBlob(write_blob) => {
+                if write_blob.data.len() > BlobChunk::max_data_size(max_frame_size) {
+                    return Err(CommandMappingError::InvalidPayload);
+                }
+                Ok(Command::WriteBlob(BlobChunk::new(
+                    narrow(write_blob.handle)?,
+                    write_blob.offset,
+                    &write_blob.data,
+                )))
+            }
+            ControllerMessagePayload::CloseBlob(close_blob) => Ok(Command::CloseBlob(BlobClose {
+                handle: narrow(close_blob.handle)?,
+                checksum: close_blob.checksum,
+            })),
+            ControllerMessagePayload::SetCurrent(set_current) => {
+                Ok(Command::SetCurrent(CurrentSetpoint {
+                    d: set_current.d,
+                    q: set_current.q,
+                }))
+            }
+            ControllerMessagePayload::SetTorque(set_torque) => {
+                Ok(Command::SetTorque(TorqueSetpoint {
+                    torque: set_torque.torque,
+                }))
+            }
+            ControllerMessagePayload::SetVelocity(set_velocity) => {
+                Ok(Command::SetVelocity(VelocitySetpoint {
+                    velocity: set_velocity.velocity,
+                }))
+            }
+            ControllerMessagePayload::SetPosition(set_position) => {
+                Ok(Command::SetPosition(PositionSetpoint {
+                    position: set_position.position,
+                }))
+            }
+            ControllerMessagePayload::SetDutyCycle(set_duty_cycle) => {
+                Ok(Command::SetDutyCycle(DutyCycleSetpoint {
+                    duty_cycle: set_duty_cycle.duty_cycle,
+                }))
+            }
+            ControllerMessagePayload::SubscribeTelemetry(subscribe_telemetry) => {
+                let fields = subscribe_telemetry
+                    .fields()
+                    .map(map_telemetry_field)
+                    .collect::<Result<TelemetryFields, _>>()?;
+                Ok(Command::SubscribeTelemetry(TelemetrySubscription {
+                    fields,
+                    rate_hz: u16::try_from(subscribe_telemetry.rate_hz)
+                        .map_err(|_| CommandMappingError::InvalidPayload)?,
+                }))
+            }
+            ControllerMessagePayload::SetLogLevel(set_log_level) => {
+                let level = match set_log_level.min_level() {
+                    device_message::LogLevel::Trace => LogLevel::Trace,
+                    device_message::LogLevel::Debug => LogLevel::Debug,
+                    device_message::LogLevel::Info => LogLevel::Info,
+                    device_message::LogLevel::Warn => LogLevel::Warn,
+                    device_message::LogLevel::Error => LogLevel::Error,
+                    device_message::LogLevel::Off => LogLevel::Off,
+                    device_message::LogLevel::Unspecified => {
+                        return Err(CommandMappingError::InvalidPayload);
+                    }
+                };
+                Ok(Command::SetLogLevel(level))
+            }
         })
         .ok_or(CommandMappingError::NoPayload)?
 }
 
+fn map_telemetry_field(
+    field: controller_message::TelemetryField,
+) -> Result<TelemetryField, CommandMappingError> {
+    match field {
+        controller_message::TelemetryField::PhaseCurrents => Ok(TelemetryField::PhaseCurrents),
+        controller_message::TelemetryField::DqCurrents => Ok(TelemetryField::DqCurrents),
+        controller_message::TelemetryField::DqVoltages => Ok(TelemetryField::DqVoltages),
+        controller_message::TelemetryField::ElectricalAngle => Ok(TelemetryField::ElectricalAngle),
+        controller_message::TelemetryField::Velocity => Ok(TelemetryField::Velocity),
+        controller_message::TelemetryField::BusVoltage => Ok(TelemetryField::BusVoltage),
+        controller_message::TelemetryField::Temperatures => Ok(TelemetryField::Temperatures),
+        controller_message::TelemetryField::LoopTiming => Ok(TelemetryField::LoopTiming),
+        controller_message::TelemetryField::Faults => Ok(TelemetryField::Faults),
+        controller_message::TelemetryField::Unspecified => Err(CommandMappingError::InvalidPayload),
+    }
+}
+
+fn narrow(value: u32) -> Result<u8, CommandMappingError> {
+    u8::try_from(value).map_err(|_| CommandMappingError::InvalidPayload)
+}
+
 fn map_uid_to_uuid(uid: &[u8]) -> Uuid {
     let mut bytes = [0u8; 16];
     bytes[..12].copy_from_slice(uid);
     Uuid::from_bytes(bytes)
 }
 
+#[derive(Debug)]
 enum CommandMappingError {
     NoPayload,
     InvalidPayload,
+    Unsupported,
 }
@@ -1,10 +1,13 @@
 use crate::configuration::Configuration;
 use crate::features::interface::SerialInterface;
 use crate::proto_services::{
-    DeviceDiscoveryServer, DeviceDiscoveryService, DeviceSessionServer, DeviceSessionService,
+    BlackBoxServer, BlackBoxService, DeviceDiscoveryServer, DeviceDiscoveryService,
+    DeviceSessionServer, DeviceSessionService, FaultHistoryServer, FaultHistoryService,
+    FirmwareUpdateServer, FirmwareUpdateService, ScopeServer, ScopeService,
 };
 use tonic::transport::Server;
 use tonic::transport::server::Router;
+use transport::reliable::RetransmitConfig;
 
 pub struct Application {
     router: Router,
@@ -25,12 +28,32 @@ impl Application {
         let discovery = DeviceDiscoveryService::new(interfaces.clone());
         let discovery = DeviceDiscoveryServer::new(discovery);
 
-        let session = DeviceSessionService::new(interfaces.clone());
+        let reliability = config.session.reliable.then_some(RetransmitConfig {
+            timeout_ms: config.session.retransmit_timeout_ms,
+            max_attempts: config.session.max_attempts,
+        });
+        let session = DeviceSessionService::new(interfaces.clone(), reliability);
         let session = DeviceSessionServer::new(session);
 
+        let scope = ScopeService::new(interfaces.clone(), reliability);
+        let scope = ScopeServer::new(scope);
+
+        let black_box = BlackBoxService::new(interfaces.clone(), reliability);
+        let black_box = BlackBoxServer::new(black_box);
+
+        let fault_history = FaultHistoryService::new(interfaces.clone(), reliability);
+        let fault_history = FaultHistoryServer::new(fault_history);
+
+        let firmware_update = FirmwareUpdateService::new(interfaces.clone(), reliability);
+        let firmware_update = FirmwareUpdateServer::new(firmware_update);
+
         let router = Server::builder()
             .add_service(discovery)
-            .add_service(session);
+            .add_service(session)
+            .add_service(scope)
+            .add_service(black_box)
+            .add_service(fault_history)
+            .add_service(firmware_update);
 
         Ok(Self { router, address })
     }
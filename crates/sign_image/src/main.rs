@@ -0,0 +1,108 @@
+use anyhow::{Context, bail};
+use ed25519_compact::{KeyPair, Seed};
+use firmware_image::{HEADER_OFFSET, ImageHeader, SignatureTrailer};
+use std::fs;
+use std::path::Path;
+
+const USAGE: &str = "Usage:
+    sign-image keygen <key file>
+    sign-image sign <key file> <image.bin> <signed image.bin>
+    sign-image info <image.bin>";
+
+fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["keygen", key] => keygen(key),
+        ["sign", key, input, output] => sign(key, input, output),
+        ["info", image] => info(image),
+        _ => bail!(USAGE),
+    }
+}
+
+/// Writes a new private key and its public key next to it, with a `.pub` extension
+fn keygen(key_path: &str) -> anyhow::Result<()> {
+    let mut seed = [0; 32];
+    getrandom::getrandom(&mut seed).context("Failed to generate a key")?;
+    let key = KeyPair::from_seed(Seed::new(seed));
+    let public_key_path = format!("{key_path}.pub");
+    if let Some(directory) = Path::new(key_path).parent() {
+        fs::create_dir_all(directory).context("Failed to create the key directory")?;
+    }
+    fs::write(key_path, to_hex(&seed) + "\n").context("Failed to write the key")?;
+    fs::write(&public_key_path, to_hex(key.pk.as_slice()) + "\n")
+        .context("Failed to write the public key")?;
+    println!("Wrote {key_path} and {public_key_path}");
+    Ok(())
+}
+
+fn sign(key_path: &str, input: &str, output: &str) -> anyhow::Result<()> {
+    let key = fs::read_to_string(key_path).context("Failed to read the key")?;
+    let seed = from_hex(key.trim())
+        .and_then(|seed| seed.try_into().ok())
+        .context("The key is not 32 bytes of hex")?;
+    let key = KeyPair::from_seed(Seed::new(seed));
+
+    let mut image = fs::read(input).context("Failed to read the image")?;
+    let trailer = firmware_image::sign(&mut image, &key.sk)
+        .map_err(|error| anyhow::anyhow!("Failed to sign the image: {error:?}"))?;
+    let mut buffer = [0; SignatureTrailer::SIZE];
+    trailer.write(&mut buffer);
+    image.extend_from_slice(&buffer);
+    fs::write(output, &image).context("Failed to write the signed image")?;
+    println!("Signed {input} ({} bytes) into {output}", image.len());
+    Ok(())
+}
+
+fn info(path: &str) -> anyhow::Result<()> {
+    let image = fs::read(path).context("Failed to read the image")?;
+    let header = image
+        .get(HEADER_OFFSET..HEADER_OFFSET + ImageHeader::SIZE)
+        .and_then(|header| ImageHeader::read(header.try_into().unwrap()))
+        .context("The image has no metadata header")?;
+    let [major, minor, patch] = header.version;
+    println!("Version:        {major}.{minor}.{patch}");
+    println!("Board revision: {}", header.board_revision);
+    println!("Git hash:       {}", to_hex(&header.git_hash));
+    println!("Length:         {}", header.length);
+    println!("Digest:         {}", to_hex(&header.digest));
+    let signed = image
+        .get(header.length as usize..)
+        .and_then(|trailer| trailer.try_into().ok())
+        .is_some_and(|trailer| SignatureTrailer::read(trailer).is_ok());
+    println!("Signed:         {}", if signed { "yes" } else { "no" });
+    Ok(())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.is_ascii() || !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_should_round_trip() {
+        assert_eq!(
+            from_hex(&to_hex(&[0, 0x9f, 0xff])),
+            Some(vec![0, 0x9f, 0xff])
+        );
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("zz"), None);
+    }
+}
@@ -0,0 +1,44 @@
+#![no_main]
+
+use crc_engine::CrcEngine;
+use crc_engine::software::SoftwareCrcEngine;
+use libfuzzer_sys::fuzz_target;
+use transport::MAX_PACKET_SIZE;
+use transport::event::decoder::{CobsDecoder, Decoder};
+use transport::frame::HEADER_SIZE;
+
+fuzz_target!(|data: &[u8]| {
+    let mut crc = SoftwareCrcEngine::new();
+
+    // Raw byte stream, mostly exercises framing and CRC rejection
+    let mut decoder = Decoder::new();
+    for &byte in data {
+        let mut result = decoder.feed(byte, &mut crc);
+        while result.is_some() {
+            result = decoder.poll(&mut crc);
+        }
+    }
+
+    // The same stream under byte stuffed framing
+    let mut decoder = CobsDecoder::new();
+    for &byte in data {
+        let _ = decoder.feed(byte, &mut crc);
+    }
+
+    // The same bytes wrapped in a valid frame, so they reach Header and Event deserialization
+    if data.len() < HEADER_SIZE {
+        return;
+    }
+    let body = &data[..data.len().min(u8::MAX as usize + HEADER_SIZE)];
+    let mut frame = [0u8; MAX_PACKET_SIZE];
+    frame[0] = 0xCC;
+    frame[1] = (body.len() - HEADER_SIZE) as u8;
+    frame[2..body.len() + 2].copy_from_slice(body);
+    let crc_bytes = crc.calculate(&frame[..body.len() + 2]).to_le_bytes();
+    frame[body.len() + 2..body.len() + 4].copy_from_slice(&crc_bytes);
+
+    let mut decoder = Decoder::new();
+    for &byte in &frame[..body.len() + 4] {
+        let _ = decoder.feed(byte, &mut crc);
+    }
+});
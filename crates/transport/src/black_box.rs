@@ -0,0 +1,109 @@
+use crate::blob::BlobId;
+use crate::packet::{Field, InvalidField, Payload};
+
+/// Serves the control steps recorded before the last fault: a [`BlackBoxHeader`] followed by its
+/// frames, empty while nothing was recorded
+pub const BLACK_BOX_BLOB_ID: BlobId = BlobId(0x02);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Payload)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BlackBoxHeader {
+    pub frame_count: u16,
+    /// Newer firmware may append fields to a frame, a reader skips what it does not know
+    pub frame_size: u16,
+}
+
+/// What [`BlackBoxFrame::setpoint`] holds depends on the strategy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StrategyKind {
+    /// Sent by a newer firmware, this build does not know the strategy
+    Unknown,
+    /// Both setpoints are zero
+    Disabled,
+    /// d and q axis currents in amperes
+    Current,
+    /// Target speed in radians per second and the q axis current requested for it
+    Velocity,
+    /// Target angle in radians and the speed requested for it
+    Position,
+    /// Duty cycle on the q axis, the second setpoint is zero
+    DutyCycle,
+}
+
+/// State of the control loop after one step
+#[derive(Debug, Clone, Copy, PartialEq, Payload)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BlackBoxFrame {
+    /// Microseconds since boot
+    pub timestamp: u64,
+    /// u, v and w in amperes
+    pub phase_currents: [f32; 3],
+    pub v_bus: f32,
+    pub electrical_angle: f32,
+    /// u, v and w, zero while the outputs are off
+    pub duty_cycles: [f32; 3],
+    pub strategy: StrategyKind,
+    pub setpoint: [f32; 2],
+    /// Bit `n` is set while the fault type `n` is active
+    pub active_faults: u32,
+}
+
+impl StrategyKind {
+    pub fn to_u8(self) -> u8 {
+        match self {
+            StrategyKind::Unknown => 0x00,
+            StrategyKind::Disabled => 0x01,
+            StrategyKind::Current => 0x02,
+            StrategyKind::Velocity => 0x03,
+            StrategyKind::Position => 0x04,
+            StrategyKind::DutyCycle => 0x05,
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            0x01 => StrategyKind::Disabled,
+            0x02 => StrategyKind::Current,
+            0x03 => StrategyKind::Velocity,
+            0x04 => StrategyKind::Position,
+            0x05 => StrategyKind::DutyCycle,
+            _ => StrategyKind::Unknown,
+        }
+    }
+}
+
+impl Field for StrategyKind {
+    const SIZE: usize = 1;
+
+    fn write(&self, buffer: &mut [u8]) {
+        buffer[0] = self.to_u8();
+    }
+
+    fn read(data: &[u8]) -> Result<Self, InvalidField> {
+        data.first().copied().map(Self::from_u8).ok_or(InvalidField)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn longer_frame_should_be_read() {
+        let frame = BlackBoxFrame {
+            timestamp: 1_000,
+            phase_currents: [1.0, -0.5, -0.5],
+            v_bus: 24.0,
+            electrical_angle: 1.5,
+            duty_cycles: [0.5, 0.25, 0.75],
+            strategy: StrategyKind::Velocity,
+            setpoint: [100.0, 2.0],
+            active_faults: 0b1,
+        };
+        let mut buffer = [0xAA; BlackBoxFrame::SIZE + 4];
+        frame.write(&mut buffer);
+        assert_eq!(BlackBoxFrame::read(&buffer), Ok(frame));
+        assert_eq!(StrategyKind::from_u8(0x7F), StrategyKind::Unknown);
+    }
+}
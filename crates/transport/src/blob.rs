@@ -0,0 +1,219 @@
+use crate::frame::max_payload_size;
+use crate::packet::{Field, InvalidField, Payload};
+
+pub const BLOB_CHUNK_MAX_DATA_SIZE: usize = 1024;
+
+/// Selects what a transfer reads or writes, the firmware decides which ids it serves
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Payload)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BlobId(pub u8);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BlobMode {
+    Read,
+    Write,
+}
+
+/// Starts a transfer, the device answers with [`BlobInfo`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Payload)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BlobOpen {
+    pub id: BlobId,
+    pub mode: BlobMode,
+    /// Size of the data that is going to be written, ignored for reads
+    pub total_size: u32,
+}
+
+/// Fields added by newer firmware are appended, so a longer payload is fine
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Payload)]
+#[payload(extensible)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BlobInfo {
+    /// Changes with every transfer, so a late request cannot touch a newer transfer
+    pub handle: u8,
+    pub total_size: u32,
+}
+
+/// Asks for a chunk, the device answers with a shorter one at the end of the blob
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Payload)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BlobRead {
+    pub handle: u8,
+    pub offset: u32,
+    pub length: u16,
+}
+
+/// Carries data in both directions, as a write request and as the answer to a read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BlobChunk {
+    pub handle: u8,
+    pub offset: u32,
+    pub length: u16,
+    pub data: [u8; BLOB_CHUNK_MAX_DATA_SIZE],
+}
+
+/// Ends a transfer, the device compares `checksum` with its own view of the data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Payload)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BlobClose {
+    pub handle: u8,
+    pub checksum: u32,
+}
+
+impl Field for BlobMode {
+    const SIZE: usize = 1;
+
+    fn write(&self, buffer: &mut [u8]) {
+        buffer[0] = match self {
+            BlobMode::Read => 0,
+            BlobMode::Write => 1,
+        };
+    }
+
+    fn read(data: &[u8]) -> Result<Self, InvalidField> {
+        match data.first() {
+            Some(0) => Ok(BlobMode::Read),
+            Some(1) => Ok(BlobMode::Write),
+            _ => Err(InvalidField),
+        }
+    }
+}
+
+impl BlobChunk {
+    const HEADER_SIZE: usize = 7;
+
+    pub fn new(handle: u8, offset: u32, data: &[u8]) -> Self {
+        let length = data.len().min(BLOB_CHUNK_MAX_DATA_SIZE);
+        let mut buffer = [0; BLOB_CHUNK_MAX_DATA_SIZE];
+        buffer[..length].copy_from_slice(&data[..length]);
+        Self {
+            handle,
+            offset,
+            length: length as u16,
+            data: buffer,
+        }
+    }
+
+    /// Largest chunk a peer that accepts frames of up to `max_frame_size` bytes can take
+    pub fn max_data_size(max_frame_size: usize) -> usize {
+        max_payload_size(max_frame_size)
+            .saturating_sub(1 + Self::HEADER_SIZE)
+            .min(BLOB_CHUNK_MAX_DATA_SIZE)
+    }
+
+    /// Returns the valid part of the chunk, a `length` larger than the buffer is clamped
+    pub fn slice(&self) -> &[u8] {
+        let length = (self.length as usize).min(BLOB_CHUNK_MAX_DATA_SIZE);
+        &self.data[..length]
+    }
+}
+
+impl Payload for BlobChunk {
+    type Error = InvalidField;
+
+    fn serialize(&self, buffer: &mut [u8]) -> usize {
+        let data = self.slice();
+        self.handle.write(buffer);
+        self.offset.write(&mut buffer[1..]);
+        (data.len() as u16).write(&mut buffer[5..]);
+        buffer[7..7 + data.len()].copy_from_slice(data);
+        Self::HEADER_SIZE + data.len()
+    }
+
+    fn deserialize(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() < Self::HEADER_SIZE {
+            return Err(InvalidField);
+        }
+        let length = u16::read(&data[5..])?;
+        let content = &data[Self::HEADER_SIZE..];
+        if length as usize > BLOB_CHUNK_MAX_DATA_SIZE || content.len() != length as usize {
+            return Err(InvalidField);
+        }
+        Ok(Self::new(data[0], u32::read(&data[1..])?, content))
+    }
+}
+
+/// CRC-32/ISO-HDLC over a whole blob, computed chunk by chunk on both ends of a transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checksum {
+    state: u32,
+}
+
+impl Default for Checksum {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Checksum {
+    pub const fn new() -> Self {
+        Self { state: 0xFFFF_FFFF }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.state ^= byte as u32;
+            for _ in 0..8 {
+                let mask = (self.state & 1).wrapping_neg();
+                self.state = (self.state >> 1) ^ (0xEDB8_8320 & mask);
+            }
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum_should_match_check_value() {
+        let mut checksum = Checksum::new();
+        checksum.update(b"1234");
+        checksum.update(b"56789");
+        assert_eq!(checksum.finish(), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn chunk_should_serialize_and_deserialize() {
+        let chunk = BlobChunk::new(3, 2048, &[1, 2, 3, 4, 5]);
+        let mut buffer = [0; 64];
+        let length = chunk.serialize(&mut buffer);
+        assert_eq!(length, 12);
+        assert_eq!(BlobChunk::deserialize(&buffer[..length]), Ok(chunk));
+        assert_eq!(chunk.slice(), &[1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn chunk_with_inconsistent_length_should_return_error() {
+        let mut buffer = [0; 16];
+        let length = BlobChunk::new(3, 0, &[1, 2, 3]).serialize(&mut buffer);
+        assert_eq!(
+            BlobChunk::deserialize(&buffer[..length + 1]),
+            Err(InvalidField)
+        );
+        assert_eq!(
+            BlobChunk::deserialize(&buffer[..length - 1]),
+            Err(InvalidField)
+        );
+    }
+
+    #[test]
+    fn open_with_unknown_mode_should_return_error() {
+        assert_eq!(BlobOpen::deserialize(&[1, 2, 0, 0, 0, 0]), Err(InvalidField));
+    }
+
+    #[test]
+    fn max_chunk_size_should_follow_frame_size() {
+        assert_eq!(
+            BlobChunk::max_data_size(crate::MAX_PACKET_SIZE),
+            BLOB_CHUNK_MAX_DATA_SIZE
+        );
+        assert_eq!(BlobChunk::max_data_size(crate::MAX_SHORT_PACKET_SIZE), 247);
+    }
+}
@@ -0,0 +1,61 @@
+use crate::packet::Payload;
+
+/// Optional features a firmware supports, reported in its introduction
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Payload)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Capabilities(pub u32);
+
+impl Capabilities {
+    /// Answers retransmitted reliable requests from its response cache
+    pub const RELIABLE_DELIVERY: Self = Self(1 << 0);
+    /// Implements ReportFaults and ResetFaults
+    pub const FAULT_REPORTING: Self = Self(1 << 1);
+    /// Reports decoder statistics of its links in telemetry
+    pub const LINK_QUALITY: Self = Self(1 << 2);
+    /// Implements WriteFirmwareBlock and FinalizeFirmwareUpdate
+    pub const FIRMWARE_UPDATE: Self = Self(1 << 3);
+    /// Implements OpenBlob, ReadBlob, WriteBlob and CloseBlob
+    pub const BLOB_TRANSFER: Self = Self(1 << 4);
+    /// Implements Arm, Disarm, SetCurrent, SetTorque, SetVelocity, SetPosition and SetDutyCycle
+    pub const MOTION_CONTROL: Self = Self(1 << 5);
+    /// Implements SubscribeTelemetry and streams telemetry samples
+    pub const TELEMETRY_STREAMS: Self = Self(1 << 6);
+    /// Implements ConfigureScope, TriggerScope and ReportScope and serves captures as a blob
+    pub const SCOPE: Self = Self(1 << 7);
+    /// Records the control steps before a fault and serves them as a blob
+    pub const BLACK_BOX: Self = Self(1 << 8);
+    /// Implements SetLogLevel and forwards its log messages
+    pub const LOG_FORWARDING: Self = Self(1 << 9);
+    /// Sends FaultRaised and FaultCleared whenever the fault register changes
+    pub const FAULT_EVENTS: Self = Self(1 << 10);
+    /// Implements ReadFaultHistory
+    pub const FAULT_HISTORY: Self = Self(1 << 11);
+    /// Implements ReadFirmwareSlots and checks the metadata header of updates
+    pub const FIRMWARE_SLOTS: Self = Self(1 << 12);
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn union_should_contain_both_capabilities() {
+        let capabilities = Capabilities::RELIABLE_DELIVERY.union(Capabilities::LINK_QUALITY);
+        assert!(capabilities.contains(Capabilities::RELIABLE_DELIVERY));
+        assert!(capabilities.contains(Capabilities::LINK_QUALITY));
+        assert!(!capabilities.contains(Capabilities::FIRMWARE_UPDATE));
+        assert!(capabilities.contains(Capabilities::empty()));
+    }
+}
@@ -0,0 +1,232 @@
+use crate::MAX_PAYLOAD_SIZE;
+use crate::cobs::{DELIMITER, MAX_FRAME_SIZE, decode_in_place};
+use crate::decoder::{
+    DecoderError, DecoderStats, MIN_PAYLOAD_LENGTH, SequenceTracker, decode_body,
+};
+use crate::frame::{Frame, HEADER_SIZE, MAX_SHORT_PAYLOAD_SIZE};
+use crate::packet::Packet;
+use crc_engine::CrcEngine;
+
+/// Accepts the frames of [`crate::cobs::encoder::Encoder`].
+///
+/// Every [`DELIMITER`] ends a frame, so after corruption decoding picks up again with the next
+/// frame without searching the received bytes for a plausible start.
+#[derive(Debug)]
+pub struct Decoder<T: Packet> {
+    position: usize,
+    buffer: [u8; MAX_FRAME_SIZE],
+    /// Set after a frame outgrew the buffer, bytes are dropped until the next delimiter
+    overflow: bool,
+    stats: DecoderStats,
+    sequence: SequenceTracker,
+    _phantom: core::marker::PhantomData<T>,
+}
+
+impl<T: Packet> Default for Decoder<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Packet> Decoder<T> {
+    pub fn new() -> Self {
+        Self {
+            position: 0,
+            buffer: [0; MAX_FRAME_SIZE],
+            overflow: false,
+            stats: DecoderStats::default(),
+            sequence: SequenceTracker::default(),
+            _phantom: core::marker::PhantomData,
+        }
+    }
+
+    /// Feeds a single byte into the decoder, a frame is decoded once its delimiter arrives
+    pub fn feed(
+        &mut self,
+        data: u8,
+        crc: &mut impl CrcEngine,
+    ) -> Option<Result<Frame<T>, DecoderError<T::Error>>> {
+        if data != DELIMITER {
+            if self.overflow {
+                self.drop_bytes(1);
+            } else if self.position == self.buffer.len() {
+                self.stats.resyncs = self.stats.resyncs.wrapping_add(1);
+                self.drop_bytes(self.position + 1);
+                self.position = 0;
+                self.overflow = true;
+            } else {
+                self.buffer[self.position] = data;
+                self.position += 1;
+            }
+            return None;
+        }
+
+        let length = self.position;
+        self.position = 0;
+        if core::mem::take(&mut self.overflow) || length == 0 {
+            // Also skips the empty frames a sender may use to flush noise off the line
+            return None;
+        }
+        Some(self.decode_frame(length, crc))
+    }
+
+    /// Kept for parity with [`crate::decoder::Decoder::poll`], a frame is never left buffered
+    pub fn poll(
+        &mut self,
+        _crc: &mut impl CrcEngine,
+    ) -> Option<Result<Frame<T>, DecoderError<T::Error>>> {
+        None
+    }
+
+    /// Inter-byte timeout hook, drops a partially received frame.
+    ///
+    /// Returns `true` if there was anything to drop.
+    pub fn timeout(&mut self) -> bool {
+        if self.is_idle() {
+            return false;
+        }
+        self.drop_bytes(self.position);
+        self.position = 0;
+        self.overflow = false;
+        true
+    }
+
+    /// Returns `true` if the decoder is not in the middle of a frame
+    pub fn is_idle(&self) -> bool {
+        self.position == 0 && !self.overflow
+    }
+
+    pub fn stats(&self) -> DecoderStats {
+        self.stats
+    }
+
+    fn decode_frame(
+        &mut self,
+        length: usize,
+        crc: &mut impl CrcEngine,
+    ) -> Result<Frame<T>, DecoderError<T::Error>> {
+        let unstuffed = decode_in_place(&mut self.buffer[..length]);
+        let Some((length, crc_size)) =
+            unstuffed.and_then(|length| crc_size(length).map(|crc_size| (length, crc_size)))
+        else {
+            self.stats.resyncs = self.stats.resyncs.wrapping_add(1);
+            self.drop_bytes(length);
+            return Err(DecoderError::InvalidFrame);
+        };
+
+        let frame = &self.buffer[..length];
+        let valid = if crc_size == 2 {
+            crc.check(frame)
+        } else {
+            crc.check_32(frame)
+        };
+        if !valid {
+            self.stats.crc_errors = self.stats.crc_errors.wrapping_add(1);
+            self.drop_bytes(length);
+            return Err(DecoderError::InvalidCrc);
+        }
+
+        decode_body(
+            &self.buffer[..length - crc_size],
+            &mut self.sequence,
+            &mut self.stats,
+        )
+    }
+
+    fn drop_bytes(&mut self, count: usize) {
+        self.stats.dropped_bytes = self.stats.dropped_bytes.wrapping_add(count as u32);
+    }
+}
+
+/// Size of the CRC of a frame with the given unstuffed size, `None` if no payload fits it
+fn crc_size(frame_length: usize) -> Option<usize> {
+    let short_payload = frame_length.checked_sub(HEADER_SIZE + 2)?;
+    if (MIN_PAYLOAD_LENGTH..=MAX_SHORT_PAYLOAD_SIZE).contains(&short_payload) {
+        return Some(2);
+    }
+    let long_payload = frame_length.checked_sub(HEADER_SIZE + 4)?;
+    (MAX_SHORT_PAYLOAD_SIZE < long_payload && long_payload <= MAX_PAYLOAD_SIZE).then_some(4)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Command;
+    use crate::command::decoder::CobsDecoder;
+    use crate::command::encoder::CobsEncoder;
+    use crate::decoder::DecoderError;
+    use crate::frame::{Frame, Header};
+    use crc_engine::software::SoftwareCrcEngine;
+
+    fn frame(sequence: u8) -> ([u8; 16], usize) {
+        let mut buffer = [0; 16];
+        let length = CobsEncoder::new().encode(
+            Header::request(sequence),
+            &Command::IntroduceYourself,
+            &mut buffer,
+            &mut SoftwareCrcEngine::new(),
+        );
+        (buffer, length)
+    }
+
+    fn feed_all(
+        decoder: &mut CobsDecoder,
+        data: &[u8],
+    ) -> Option<Result<Frame<Command>, DecoderError<crate::command::Error>>> {
+        let mut crc = SoftwareCrcEngine::new();
+        let mut last = None;
+        for &byte in data {
+            if let Some(result) = decoder.feed(byte, &mut crc) {
+                last = Some(result);
+            }
+        }
+        last
+    }
+
+    #[test]
+    fn frame_after_a_corrupted_one_should_be_decoded() {
+        let mut decoder = CobsDecoder::new();
+        let (mut corrupted, length) = frame(1);
+        corrupted[length - 2] ^= 0x40;
+        assert_eq!(
+            feed_all(&mut decoder, &corrupted[..length]),
+            Some(Err(DecoderError::InvalidCrc))
+        );
+
+        let (valid, length) = frame(2);
+        assert_eq!(
+            feed_all(&mut decoder, &valid[..length]),
+            Some(Ok(Frame {
+                header: Header::request(2),
+                packet: Command::IntroduceYourself,
+            }))
+        );
+        assert_eq!(decoder.stats().crc_errors, 1);
+    }
+
+    #[test]
+    fn frame_after_overlong_noise_should_be_decoded() {
+        let mut decoder = CobsDecoder::new();
+        let noise = [0x55; super::MAX_FRAME_SIZE + 10];
+        assert_eq!(feed_all(&mut decoder, &noise), None);
+        assert!(!decoder.is_idle());
+
+        let (valid, length) = frame(7);
+        let mut data = [0; 17];
+        data[1..length + 1].copy_from_slice(&valid[..length]);
+        assert!(matches!(
+            feed_all(&mut decoder, &data[..length + 1]),
+            Some(Ok(_))
+        ));
+        assert_eq!(decoder.stats().resyncs, 1);
+        assert!(decoder.is_idle());
+    }
+
+    #[test]
+    fn malformed_stuffing_should_return_error() {
+        let mut decoder = CobsDecoder::new();
+        assert_eq!(
+            feed_all(&mut decoder, &[0x09, 0x01, 0x02, 0x00]),
+            Some(Err(DecoderError::InvalidFrame))
+        );
+    }
+}
@@ -0,0 +1,59 @@
+use crate::cobs::{DELIMITER, MAX_UNENCODED_SIZE, encode_in_place, stuffing_overhead};
+use crate::frame::{HEADER_SIZE, Header, MAX_SHORT_PAYLOAD_SIZE};
+use crate::packet::Packet;
+use crc_engine::CrcEngine;
+
+/// Room left in front of the frame for the stuffing to grow into
+const STUFFING_SPACE: usize = stuffing_overhead(MAX_UNENCODED_SIZE);
+
+/// Produces byte stuffed `[header, payload, crc]` frames followed by [`DELIMITER`].
+///
+/// The CRC width follows the payload size like in start byte frames: a CRC-16 up to
+/// [`MAX_SHORT_PAYLOAD_SIZE`] bytes and a CRC-32 above.
+#[derive(Debug)]
+pub struct Encoder<T: Packet> {
+    _phantom: core::marker::PhantomData<T>,
+}
+
+impl<T: Packet> Default for Encoder<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Packet> Encoder<T> {
+    pub fn new() -> Self {
+        Self {
+            _phantom: core::marker::PhantomData,
+        }
+    }
+
+    /// `buffer` has to hold [`crate::cobs::MAX_FRAME_SIZE`] bytes for the largest packets
+    pub fn encode(
+        &self,
+        header: Header,
+        packet: &T,
+        buffer: &mut [u8],
+        crc: &mut impl CrcEngine,
+    ) -> usize {
+        let payload_start = STUFFING_SPACE + HEADER_SIZE;
+        header.serialize(&mut buffer[STUFFING_SPACE..payload_start]);
+        let payload_len = packet.serialize(&mut buffer[payload_start..]);
+        let crc_start = payload_start + payload_len;
+        let frame = STUFFING_SPACE..crc_start;
+
+        let crc_len = if payload_len <= MAX_SHORT_PAYLOAD_SIZE {
+            let crc_val = crc.calculate(&buffer[frame]);
+            buffer[crc_start..crc_start + 2].copy_from_slice(&crc_val.to_le_bytes());
+            2
+        } else {
+            let crc_val = crc.calculate_32(&buffer[frame]);
+            buffer[crc_start..crc_start + 4].copy_from_slice(&crc_val.to_le_bytes());
+            4
+        };
+
+        let length = encode_in_place(buffer, STUFFING_SPACE, HEADER_SIZE + payload_len + crc_len);
+        buffer[length] = DELIMITER;
+        length + 1
+    }
+}
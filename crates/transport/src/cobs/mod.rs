@@ -0,0 +1,110 @@
+use crate::MAX_PAYLOAD_SIZE;
+use crate::frame::HEADER_SIZE;
+
+pub mod decoder;
+pub mod encoder;
+
+/// Ends every frame, the byte stuffing guarantees it does not appear anywhere else
+pub const DELIMITER: u8 = 0x00;
+
+/// Largest frame of [`encoder::Encoder`], including the delimiter
+pub const MAX_FRAME_SIZE: usize = encoded_size(MAX_UNENCODED_SIZE) + 1;
+
+/// Header, the largest payload and a CRC-32
+const MAX_UNENCODED_SIZE: usize = HEADER_SIZE + MAX_PAYLOAD_SIZE + 4;
+
+/// Worst case size of `length` bytes after byte stuffing, without the delimiter
+pub const fn encoded_size(length: usize) -> usize {
+    length + stuffing_overhead(length)
+}
+
+const fn stuffing_overhead(length: usize) -> usize {
+    1 + length / 254
+}
+
+/// Stuffs `buffer[start..start + length]` and writes the result to the beginning of `buffer`.
+///
+/// The output never overtakes the input as long as `start` is at least the stuffing overhead,
+/// so no second buffer is needed. Returns the size of the stuffed data.
+fn encode_in_place(buffer: &mut [u8], start: usize, length: usize) -> usize {
+    debug_assert!(start >= stuffing_overhead(length));
+    let mut code_index = 0;
+    let mut output = 1;
+    let mut code = 1u8;
+    for input in start..start + length {
+        let byte = buffer[input];
+        if byte != 0 {
+            buffer[output] = byte;
+            output += 1;
+            code += 1;
+        }
+        if byte == 0 || code == 0xFF {
+            buffer[code_index] = code;
+            code_index = output;
+            output += 1;
+            code = 1;
+        }
+    }
+    buffer[code_index] = code;
+    output
+}
+
+/// Reverses the byte stuffing in place, returns the size of the data or `None` if it is malformed
+fn decode_in_place(buffer: &mut [u8]) -> Option<usize> {
+    let mut input = 0;
+    let mut output = 0;
+    while input < buffer.len() {
+        let code = buffer[input] as usize;
+        if code == 0 {
+            return None;
+        }
+        let block_end = input + code;
+        if block_end > buffer.len() {
+            return None;
+        }
+        buffer.copy_within(input + 1..block_end, output);
+        output += code - 1;
+        input = block_end;
+        if code != 0xFF && input < buffer.len() {
+            buffer[output] = 0;
+            output += 1;
+        }
+    }
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(data: &[u8], buffer: &mut [u8]) -> usize {
+        let start = stuffing_overhead(data.len());
+        buffer[start..start + data.len()].copy_from_slice(data);
+        encode_in_place(buffer, start, data.len())
+    }
+
+    #[test]
+    fn zeros_should_be_replaced() {
+        let mut buffer = [0xEE; 16];
+        let length = encode(&[0x11, 0x00, 0x00, 0x22], &mut buffer);
+        assert_eq!(&buffer[..length], &[0x02, 0x11, 0x01, 0x02, 0x22]);
+        assert_eq!(decode_in_place(&mut buffer[..length]), Some(4));
+        assert_eq!(&buffer[..4], &[0x11, 0x00, 0x00, 0x22]);
+    }
+
+    #[test]
+    fn long_runs_without_zeros_should_be_split() {
+        let data: [u8; 600] = core::array::from_fn(|i| (i % 255) as u8 + 1);
+        let mut buffer = [0; encoded_size(600)];
+        let length = encode(&data, &mut buffer);
+        assert!(length <= encoded_size(600));
+        assert!(!buffer[..length].contains(&DELIMITER));
+        let decoded = decode_in_place(&mut buffer[..length]).unwrap();
+        assert_eq!(&buffer[..decoded], &data);
+    }
+
+    #[test]
+    fn block_running_past_the_end_should_be_rejected() {
+        assert_eq!(decode_in_place(&mut [0x05, 0x11, 0x22]), None);
+    }
+}
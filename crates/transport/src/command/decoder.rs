@@ -1,20 +1,23 @@
 use crate::command::Command;
-use crate::decoder;
+use crate::{cobs, decoder};
 
-pub type Decoder = decoder::Decoder<Command, 0xAA>;
+pub type Decoder = decoder::Decoder<Command, 0xAA, 0xAB>;
+pub type CobsDecoder = cobs::decoder::Decoder<Command>;
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::MAX_PACKET_SIZE;
+    use crate::command::{FIRMWARE_BLOCK_MAX_DATA_SIZE, FirmwareBlock};
     use crate::decoder::DecoderError;
+    use crate::frame::Header;
     use crc_engine::CrcEngine;
     use crc_engine::software::SoftwareCrcEngine;
 
     #[test]
     fn introduce_yourself_command_should_be_recognized() {
         let buffer = {
-            let mut buffer = [0xAA, 0x01, 0x01, 0x00, 0x00];
+            let mut buffer = [0xAA, 0x01, 0x05, 0x00, 0x00, 0x01, 0x00, 0x00];
             calculate_and_attach_crc(&mut buffer);
             buffer
         };
@@ -28,15 +31,16 @@ mod tests {
                 assert!(result.is_some());
                 let some = result.unwrap();
                 assert!(some.is_ok());
-                let command = some.unwrap();
-                assert_eq!(command, Command::IntroduceYourself);
+                let frame = some.unwrap();
+                assert_eq!(frame.header, Header::request(5));
+                assert_eq!(frame.packet, Command::IntroduceYourself);
             }
         }
     }
 
     #[test]
     fn invalid_crc_should_be_recognized() {
-        let buffer = [0xAA, 0x01, 0x01, 0x00, 0x00];
+        let buffer = [0xAA, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00];
         let mut parser = Decoder::new();
         let mut crc = SoftwareCrcEngine::new();
         for i in 0..buffer.len() {
@@ -49,23 +53,217 @@ mod tests {
     }
 
     #[test]
-    fn max_length_message_should_be_parsed() {
+    fn max_length_short_message_should_be_parsed() {
+        let data_size = u8::MAX as usize - 9;
         let buffer = {
-            let mut buffer = [0; MAX_PACKET_SIZE];
+            let mut buffer = [0; crate::MAX_SHORT_PACKET_SIZE];
             buffer[0] = 0xAA;
             buffer[1] = u8::MAX;
-            buffer[2] = 0x01; // IntroduceYourself command
+            buffer[5] = 0x10; // WriteFirmwareBlock command
+            buffer[10..14].copy_from_slice(&(data_size as u32).to_le_bytes());
             calculate_and_attach_crc(&mut buffer);
             buffer
         };
         let mut crc = SoftwareCrcEngine::new();
         let mut parser = Decoder::new();
-        for i in 0..buffer.len() - 1 {
-            let _ = parser.feed(buffer[i], &mut crc);
+        let result = decode_single(&mut parser, &buffer, &mut crc);
+        assert_eq!(
+            result,
+            Ok(Command::WriteFirmwareBlock(FirmwareBlock {
+                offset: 0,
+                length: data_size as u32,
+                data: [0; FIRMWARE_BLOCK_MAX_DATA_SIZE],
+            }))
+        );
+    }
+
+    #[test]
+    fn largest_firmware_block_should_be_parsed_from_long_frame() {
+        let payload_size = 1 + 8 + FIRMWARE_BLOCK_MAX_DATA_SIZE;
+        let frame_size = crate::frame::frame_size(payload_size);
+        let mut buffer = [0; MAX_PACKET_SIZE];
+        buffer[0] = 0xAB;
+        buffer[1..3].copy_from_slice(&(payload_size as u16).to_le_bytes());
+        buffer[6] = 0x10; // WriteFirmwareBlock command
+        buffer[11..15].copy_from_slice(&(FIRMWARE_BLOCK_MAX_DATA_SIZE as u32).to_le_bytes());
+        buffer[15..15 + FIRMWARE_BLOCK_MAX_DATA_SIZE].fill(0x5A);
+        calculate_and_attach_crc_32(&mut buffer[..frame_size]);
+
+        let mut crc = SoftwareCrcEngine::new();
+        let mut parser = Decoder::new();
+        let result = decode_single(&mut parser, &buffer[..frame_size], &mut crc);
+        assert_eq!(
+            result,
+            Ok(Command::WriteFirmwareBlock(FirmwareBlock {
+                offset: 0,
+                length: FIRMWARE_BLOCK_MAX_DATA_SIZE as u32,
+                data: [0x5A; FIRMWARE_BLOCK_MAX_DATA_SIZE],
+            }))
+        );
+    }
+
+    #[test]
+    fn long_frame_with_invalid_crc_should_be_recognized() {
+        let mut buffer = [0xAB, 0x01, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00];
+        calculate_and_attach_crc_32(&mut buffer);
+        buffer[6] = 0x01;
+        let mut crc = SoftwareCrcEngine::new();
+        let mut parser = Decoder::new();
+        let result = decode_single(&mut parser, &buffer, &mut crc);
+        assert_eq!(result, Err(DecoderError::InvalidCrc));
+    }
+
+    #[test]
+    fn oversized_long_frame_should_resync() {
+        let buffer = {
+            let mut buffer = [0xAB, 0xFF, 0xFF, 0xAA, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00];
+            calculate_and_attach_crc(&mut buffer[3..]);
+            buffer
+        };
+        let mut crc = SoftwareCrcEngine::new();
+        let mut parser = Decoder::new();
+        let result = decode_single(&mut parser, &buffer, &mut crc);
+        assert_eq!(result, Ok(Command::Stop));
+        assert_eq!(parser.stats().resyncs, 1);
+        assert_eq!(parser.stats().dropped_bytes, 3);
+    }
+
+    #[test]
+    fn garbage_before_start_byte_should_be_counted_as_dropped() {
+        let buffer = {
+            let mut buffer = [0x00, 0x13, 0xAA, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00];
+            calculate_and_attach_crc(&mut buffer[2..]);
+            buffer
+        };
+        let mut crc = SoftwareCrcEngine::new();
+        let mut parser = Decoder::new();
+        let result = decode_single(&mut parser, &buffer, &mut crc);
+        assert_eq!(result, Ok(Command::IntroduceYourself));
+        assert_eq!(parser.stats().dropped_bytes, 2);
+    }
+
+    #[test]
+    fn start_byte_in_noise_should_resync_to_the_next_frame() {
+        let frame = {
+            let mut frame = [0xAA, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00];
+            calculate_and_attach_crc(&mut frame);
+            frame
+        };
+        // A noise START_BYTE announces a 10-byte payload that swallows the real frame
+        let mut buffer = [0u8; 17];
+        buffer[0] = 0xAA;
+        buffer[1] = 10;
+        buffer[2..10].copy_from_slice(&frame);
+        let mut crc = SoftwareCrcEngine::new();
+        let mut parser = Decoder::new();
+        for &byte in &buffer[..buffer.len() - 1] {
+            assert!(parser.feed(byte, &mut crc).is_none());
         }
+
         let result = parser.feed(buffer[buffer.len() - 1], &mut crc);
-        let command = result.unwrap().unwrap();
-        assert_eq!(command, Command::IntroduceYourself);
+        assert_eq!(result.unwrap().unwrap_err(), DecoderError::InvalidCrc);
+        let result = parser.poll(&mut crc);
+        assert_eq!(result.unwrap().unwrap().packet, Command::Stop);
+        assert!(parser.poll(&mut crc).is_none());
+        assert!(parser.is_idle());
+
+        let stats = parser.stats();
+        assert_eq!(stats.crc_errors, 1);
+        assert_eq!(stats.resyncs, 1);
+        assert_eq!(stats.dropped_bytes, 9);
+    }
+
+    #[test]
+    fn zero_length_should_resync() {
+        let buffer = {
+            let mut buffer = [0xAA, 0x00, 0xAA, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00];
+            calculate_and_attach_crc(&mut buffer[2..]);
+            buffer
+        };
+        let mut crc = SoftwareCrcEngine::new();
+        let mut parser = Decoder::new();
+        let result = decode_single(&mut parser, &buffer, &mut crc);
+        assert_eq!(result, Ok(Command::IntroduceYourself));
+        assert_eq!(parser.stats().resyncs, 1);
+        assert_eq!(parser.stats().dropped_bytes, 2);
+    }
+
+    #[test]
+    fn timeout_should_drop_partial_frame() {
+        let buffer = {
+            let mut buffer = [0xAA, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00];
+            calculate_and_attach_crc(&mut buffer);
+            buffer
+        };
+        let mut crc = SoftwareCrcEngine::new();
+        let mut parser = Decoder::new();
+        for &byte in &buffer[..3] {
+            assert!(parser.feed(byte, &mut crc).is_none());
+        }
+        assert!(!parser.is_idle());
+
+        assert!(parser.timeout());
+        assert!(parser.is_idle());
+        assert!(!parser.timeout());
+        assert_eq!(parser.stats().dropped_bytes, 3);
+
+        let result = decode_single(&mut parser, &buffer, &mut crc);
+        assert_eq!(result, Ok(Command::IntroduceYourself));
+    }
+
+    fn decode_single(
+        parser: &mut Decoder,
+        buffer: &[u8],
+        crc: &mut SoftwareCrcEngine,
+    ) -> Result<Command, DecoderError<crate::command::Error>> {
+        let mut results = buffer.iter().filter_map(|&byte| parser.feed(byte, crc));
+        let result = results.next().expect("No packet decoded");
+        assert!(results.next().is_none(), "More than one packet decoded");
+        result.map(|frame| frame.packet)
+    }
+
+    #[test]
+    fn invalid_header_should_be_recognized() {
+        let buffer = {
+            let mut buffer = [0xAA, 0x01, 0x00, 0x07, 0x00, 0x01, 0x00, 0x00];
+            calculate_and_attach_crc(&mut buffer);
+            buffer
+        };
+        let mut crc = SoftwareCrcEngine::new();
+        let mut parser = Decoder::new();
+        let result = decode_single(&mut parser, &buffer, &mut crc);
+        assert_eq!(result, Err(DecoderError::InvalidHeader));
+        assert!(parser.is_idle());
+    }
+
+    #[test]
+    fn sequence_gap_should_be_counted_as_lost_frames() {
+        let mut crc = SoftwareCrcEngine::new();
+        let mut parser = Decoder::new();
+        for sequence in [0xFE, 0xFF, 0x02] {
+            let mut buffer = [0xAA, 0x01, sequence, 0x00, 0x00, 0x02, 0x00, 0x00];
+            calculate_and_attach_crc(&mut buffer);
+            assert_eq!(
+                decode_single(&mut parser, &buffer, &mut crc),
+                Ok(Command::Stop)
+            );
+        }
+        assert_eq!(parser.stats().lost_frames, 2);
+    }
+
+    #[test]
+    fn retransmitted_frame_should_not_count_as_lost() {
+        let mut crc = SoftwareCrcEngine::new();
+        let mut parser = Decoder::new();
+        for sequence in [0x01, 0x02, 0x02, 0x03] {
+            let mut buffer = [0xAA, 0x01, sequence, 0x80, 0x00, 0x02, 0x00, 0x00];
+            calculate_and_attach_crc(&mut buffer);
+            assert_eq!(
+                decode_single(&mut parser, &buffer, &mut crc),
+                Ok(Command::Stop)
+            );
+        }
+        assert_eq!(parser.stats().lost_frames, 0);
     }
 
     fn calculate_and_attach_crc(buffer: &mut [u8]) {
@@ -75,4 +273,11 @@ mod tests {
         buffer[buffer.len() - 2] = crc_bytes[0];
         buffer[buffer.len() - 1] = crc_bytes[1];
     }
+
+    fn calculate_and_attach_crc_32(buffer: &mut [u8]) {
+        let mut crc = SoftwareCrcEngine::new();
+        let length = buffer.len() - 4;
+        let crc = crc.calculate_32(&buffer[..length]);
+        buffer[length..].copy_from_slice(&crc.to_le_bytes());
+    }
 }
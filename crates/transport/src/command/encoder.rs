@@ -1,3 +1,4 @@
-use crate::{Command, encoder};
+use crate::{Command, cobs, encoder};
 
-pub type Encoder = encoder::Encoder<Command, 0xAA>;
+pub type Encoder = encoder::Encoder<Command, 0xAA, 0xAB>;
+pub type CobsEncoder = cobs::encoder::Encoder<Command>;
@@ -1,22 +1,72 @@
-use crate::helpers::decode_u32;
-use crate::packet::Packet;
-use core::array::TryFromSliceError;
+use crate::blob::{BlobChunk, BlobClose, BlobOpen, BlobRead};
+use crate::frame::max_payload_size;
+use crate::log::LogLevel;
+use crate::motion::{
+    CurrentSetpoint, DutyCycleSetpoint, PositionSetpoint, TorqueSetpoint, VelocitySetpoint,
+};
+use crate::packet::{Field, InvalidField, Packet, Payload};
+use crate::scope::ScopeConfig;
+use crate::telemetry::TelemetrySubscription;
 
 pub mod decoder;
 pub mod encoder;
 
-pub const FIRMWARE_BLOCK_MAX_DATA_SIZE: usize = u8::MAX as usize - (size_of::<u32>() * 2) - 1;
+/// Blocks this large need a long frame, see [`FirmwareBlock::max_data_size`] for older peers
+pub const FIRMWARE_BLOCK_MAX_DATA_SIZE: usize = 1024;
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Packet)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[packet(error = Error, unknown = Error::CommandNotFound)]
 #[allow(clippy::large_enum_variant)]
 pub enum Command {
-    IntroduceYourself,                 // 0x01
-    Stop,                              // 0x02
-    WriteFirmwareBlock(FirmwareBlock), // 0x10
-    FinalizeFirmwareUpdate,            // 0x11
-    ReportFaults,                      // 0x71
-    ResetFaults,                       // 0x72
+    #[packet(opcode = 0x01)]
+    IntroduceYourself,
+    #[packet(opcode = 0x02)]
+    Stop,
+    #[packet(opcode = 0x03)]
+    Arm,
+    #[packet(opcode = 0x04)]
+    Disarm,
+    #[packet(opcode = 0x10)]
+    WriteFirmwareBlock(FirmwareBlock),
+    #[packet(opcode = 0x11)]
+    FinalizeFirmwareUpdate,
+    #[packet(opcode = 0x12)]
+    ReadFirmwareSlots,
+    #[packet(opcode = 0x20)]
+    OpenBlob(BlobOpen),
+    #[packet(opcode = 0x21)]
+    ReadBlob(BlobRead),
+    #[packet(opcode = 0x22)]
+    WriteBlob(BlobChunk),
+    #[packet(opcode = 0x23)]
+    CloseBlob(BlobClose),
+    #[packet(opcode = 0x30)]
+    SetCurrent(CurrentSetpoint),
+    #[packet(opcode = 0x31)]
+    SetTorque(TorqueSetpoint),
+    #[packet(opcode = 0x32)]
+    SetVelocity(VelocitySetpoint),
+    #[packet(opcode = 0x33)]
+    SetPosition(PositionSetpoint),
+    #[packet(opcode = 0x34)]
+    SetDutyCycle(DutyCycleSetpoint),
+    #[packet(opcode = 0x40)]
+    SubscribeTelemetry(TelemetrySubscription),
+    #[packet(opcode = 0x50)]
+    ConfigureScope(ScopeConfig),
+    #[packet(opcode = 0x51)]
+    TriggerScope,
+    #[packet(opcode = 0x52)]
+    ReportScope,
+    #[packet(opcode = 0x60)]
+    SetLogLevel(LogLevel),
+    #[packet(opcode = 0x71)]
+    ReportFaults,
+    #[packet(opcode = 0x72)]
+    ResetFaults,
+    #[packet(opcode = 0x75)]
+    ReadFaultHistory,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -27,78 +77,65 @@ pub struct FirmwareBlock {
     pub data: [u8; FIRMWARE_BLOCK_MAX_DATA_SIZE],
 }
 
-impl Packet for Command {
-    type Error = Error;
+impl FirmwareBlock {
+    const HEADER_SIZE: usize = size_of::<u32>() * 2;
 
-    fn deserialize(data: &[u8]) -> Result<Self, Self::Error> {
-        let cmd_byte = data[0];
-        match cmd_byte {
-            0x01 => Ok(Command::IntroduceYourself),
-            0x02 => Ok(Command::Stop),
-            0x10 => {
-                let packet = FirmwareBlock::deserialize(&data[1..])?;
-                Ok(Command::WriteFirmwareBlock(packet))
-            }
-            0x11 => Ok(Command::FinalizeFirmwareUpdate),
-            0x71 => Ok(Command::ReportFaults),
-            0x72 => Ok(Command::ResetFaults),
-            _ => Err(Error::CommandNotFound),
+    pub fn new(offset: u32, data: &[u8]) -> Self {
+        let length = data.len().min(FIRMWARE_BLOCK_MAX_DATA_SIZE);
+        let mut buffer = [0; FIRMWARE_BLOCK_MAX_DATA_SIZE];
+        buffer[..length].copy_from_slice(&data[..length]);
+        Self {
+            offset,
+            length: length as u32,
+            data: buffer,
         }
     }
 
-    fn serialize(&self, buffer: &mut [u8]) -> usize {
-        match self {
-            Command::IntroduceYourself => {
-                buffer[0] = 0x01;
-                1
-            }
-            Command::Stop => {
-                buffer[0] = 0x02;
-                1
-            }
-            Command::WriteFirmwareBlock(packet) => {
-                buffer[0] = 0x10;
-                packet.serialize(&mut buffer[1..]) + 1
-            }
-            Command::FinalizeFirmwareUpdate => {
-                buffer[0] = 0x11;
-                1
-            }
-            Command::ReportFaults => {
-                buffer[0] = 0x71;
-                1
-            }
-            Command::ResetFaults => {
-                buffer[0] = 0x72;
-                1
-            }
-        }
+    /// Largest block a peer that accepts frames of up to `max_frame_size` bytes can take
+    pub fn max_data_size(max_frame_size: usize) -> usize {
+        max_payload_size(max_frame_size)
+            .saturating_sub(1 + Self::HEADER_SIZE)
+            .min(FIRMWARE_BLOCK_MAX_DATA_SIZE)
+    }
+
+    /// Returns the valid part of the block, a `length` larger than the buffer is clamped
+    pub fn slice(&self) -> &[u8] {
+        let length = (self.length as usize).min(FIRMWARE_BLOCK_MAX_DATA_SIZE);
+        &self.data[..length]
     }
 }
 
-impl FirmwareBlock {
-    pub fn serialize(&self, buffer: &mut [u8]) -> usize {
-        buffer[..4].copy_from_slice(&self.offset.to_le_bytes());
-        buffer[4..8].copy_from_slice(&self.length.to_le_bytes());
-        buffer[8..self.length as usize + 8].copy_from_slice(&self.data[..self.length as usize]);
-        FIRMWARE_BLOCK_MAX_DATA_SIZE + 8
+impl Payload for FirmwareBlock {
+    type Error = InvalidField;
+
+    fn serialize(&self, buffer: &mut [u8]) -> usize {
+        let data = self.slice();
+        self.offset.write(buffer);
+        (data.len() as u32).write(&mut buffer[4..]);
+        buffer[8..data.len() + 8].copy_from_slice(data);
+        data.len() + Self::HEADER_SIZE
     }
 
-    pub fn deserialize(data: &[u8]) -> Result<Self, Error> {
-        let offset = decode_u32(&data[..4])?;
-        let length = decode_u32(&data[4..8])?;
+    fn deserialize(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() < Self::HEADER_SIZE {
+            return Err(InvalidField);
+        }
+
+        let offset = u32::read(data)?;
+        let length = u32::read(&data[4..])?;
+        let content = &data[Self::HEADER_SIZE..];
+        if length as usize > FIRMWARE_BLOCK_MAX_DATA_SIZE || content.len() != length as usize {
+            return Err(InvalidField);
+        }
+
         let mut buffer: [u8; FIRMWARE_BLOCK_MAX_DATA_SIZE] = [0; FIRMWARE_BLOCK_MAX_DATA_SIZE];
-        buffer[..length as usize].copy_from_slice(&data[8..length as usize + 8]);
+        buffer[..content.len()].copy_from_slice(content);
         Ok(Self {
             offset,
             length,
             data: buffer,
         })
     }
-
-    pub fn slice(&self) -> &[u8] {
-        &self.data[..self.length as usize]
-    }
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -108,8 +145,8 @@ pub enum Error {
     InvalidContent,
 }
 
-impl From<TryFromSliceError> for Error {
-    fn from(_: TryFromSliceError) -> Self {
+impl From<InvalidField> for Error {
+    fn from(_: InvalidField) -> Self {
         Error::InvalidContent
     }
 }
@@ -166,6 +203,59 @@ mod tests {
         assert_eq!(result.unwrap(), command);
     }
 
+    #[test]
+    fn max_block_size_should_follow_frame_size() {
+        assert_eq!(
+            FirmwareBlock::max_data_size(crate::MAX_SHORT_PACKET_SIZE),
+            246
+        );
+        assert_eq!(
+            FirmwareBlock::max_data_size(MAX_PACKET_SIZE),
+            FIRMWARE_BLOCK_MAX_DATA_SIZE
+        );
+        assert_eq!(
+            FirmwareBlock::max_data_size(crate::MAX_SHORT_PACKET_SIZE + 1),
+            246
+        );
+        assert_eq!(FirmwareBlock::max_data_size(0), 0);
+    }
+
+    #[test]
+    fn empty_buffer_should_return_error() {
+        let result = Command::deserialize(&[]);
+        assert_eq!(result.err().unwrap(), Error::InvalidContent);
+    }
+
+    #[test]
+    fn command_without_payload_should_reject_trailing_bytes() {
+        let result = Command::deserialize(&[0x02, 0x00]);
+        assert_eq!(result.err().unwrap(), Error::InvalidContent);
+    }
+
+    #[test]
+    fn write_firmware_block_with_short_header_should_return_error() {
+        let result = Command::deserialize(&[0x10, 0x01, 0x02, 0x03]);
+        assert_eq!(result.err().unwrap(), Error::InvalidContent);
+    }
+
+    #[test]
+    fn write_firmware_block_with_too_large_length_should_return_error() {
+        let mut buffer = [0; MAX_PACKET_SIZE];
+        buffer[0] = 0x10;
+        buffer[5..9].copy_from_slice(&(FIRMWARE_BLOCK_MAX_DATA_SIZE as u32 + 1).to_le_bytes());
+        let result = Command::deserialize(&buffer);
+        assert_eq!(result.err().unwrap(), Error::InvalidContent);
+    }
+
+    #[test]
+    fn write_firmware_block_with_inconsistent_length_should_return_error() {
+        let mut buffer = [0; 20];
+        buffer[0] = 0x10;
+        buffer[5..9].copy_from_slice(&4u32.to_le_bytes());
+        let result = Command::deserialize(&buffer);
+        assert_eq!(result.err().unwrap(), Error::InvalidContent);
+    }
+
     #[test]
     fn finalize_firmware_update_command() {
         let mut buffer = [0; MAX_PACKET_SIZE];
@@ -186,6 +276,23 @@ mod tests {
         assert_eq!(result.unwrap(), Command::ReportFaults);
     }
 
+    #[test]
+    fn set_current_command() {
+        let mut buffer = [0; MAX_PACKET_SIZE];
+        let command = Command::SetCurrent(CurrentSetpoint { d: 0.0, q: -1.5 });
+        let len = command.serialize(&mut buffer);
+        assert_eq!(len, 9);
+        assert_eq!(buffer[0], 0x30);
+        assert_eq!(&buffer[5..9], &(-1.5f32).to_le_bytes());
+        assert_eq!(Command::deserialize(&buffer[..len]), Ok(command));
+    }
+
+    #[test]
+    fn set_velocity_with_short_payload_should_return_error() {
+        let result = Command::deserialize(&[0x32, 0x00, 0x00, 0x80]);
+        assert_eq!(result.err().unwrap(), Error::InvalidContent);
+    }
+
     #[test]
     fn reset_faults_command() {
         let mut buffer = [0; MAX_PACKET_SIZE];
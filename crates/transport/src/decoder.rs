@@ -1,66 +1,257 @@
-use crate::MAX_PACKET_SIZE;
-use crate::packet::Packet;
+use crate::frame::{Frame, HEADER_SIZE, Header};
+use crate::packet::{Packet, Payload};
+use crate::{MAX_PACKET_SIZE, MAX_PAYLOAD_SIZE};
 use core::fmt::Debug;
 use crc_engine::CrcEngine;
 
+/// Every packet carries at least its type byte, a shorter frame can only come from noise
+pub(crate) const MIN_PAYLOAD_LENGTH: usize = 1;
+
+/// Sequence numbers up to this far behind the expected one are treated as retransmissions
+const REPLAY_WINDOW: u8 = 128;
+
+/// Accepts both the short and the long frames of [`crate::encoder::Encoder`]
 #[derive(Debug)]
-pub struct Decoder<T: Packet, const START_BYTE: u8> {
+pub struct Decoder<T: Packet, const START_BYTE: u8, const LONG_START_BYTE: u8> {
     position: usize,
     buffer: [u8; MAX_PACKET_SIZE],
+    stats: DecoderStats,
+    sequence: SequenceTracker,
     _phantom: core::marker::PhantomData<T>,
 }
 
-impl<T: Packet, const START_BYTE: u8> Default for Decoder<T, START_BYTE> {
+/// Link quality counters, they wrap around on overflow
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Payload)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DecoderStats {
+    pub crc_errors: u32,
+    pub resyncs: u32,
+    pub dropped_bytes: u32,
+    /// Frames missing between two consecutive sequence numbers, a restarted peer shows up here too
+    pub lost_frames: u32,
+}
+
+impl<T: Packet, const START_BYTE: u8, const LONG_START_BYTE: u8> Default
+    for Decoder<T, START_BYTE, LONG_START_BYTE>
+{
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl<T: Packet, const START_BYTE: u8> Decoder<T, START_BYTE> {
+impl<T: Packet, const START_BYTE: u8, const LONG_START_BYTE: u8>
+    Decoder<T, START_BYTE, LONG_START_BYTE>
+{
     pub fn new() -> Self {
         Self {
             position: 0,
             buffer: [0; MAX_PACKET_SIZE],
+            stats: DecoderStats::default(),
+            sequence: SequenceTracker::default(),
             _phantom: core::marker::PhantomData,
         }
     }
 
+    /// Feeds a single byte into the decoder.
+    ///
+    /// After a resynchronisation the buffer may already hold further frames, so once this
+    /// returns `Some` the caller should drain them with [`Decoder::poll`].
     pub fn feed(
         &mut self,
         data: u8,
         crc: &mut impl CrcEngine,
-    ) -> Option<Result<T, DecoderError<T::Error>>> {
-        if self.position == 0 && data != START_BYTE {
+    ) -> Option<Result<Frame<T>, DecoderError<T::Error>>> {
+        if self.position == 0 && !Self::is_start(data) {
+            self.drop_bytes(1);
             return None;
         }
 
         self.buffer[self.position] = data;
         self.position += 1;
 
-        if self.position > 2 {
-            let length = self.length();
-            if self.position == length + 4 {
-                let result = self.parse_command(crc);
-                self.position = 0;
-                return Some(result);
+        self.poll(crc)
+    }
+
+    /// Decodes the next frame that is already fully buffered, without consuming new input
+    pub fn poll(
+        &mut self,
+        crc: &mut impl CrcEngine,
+    ) -> Option<Result<Frame<T>, DecoderError<T::Error>>> {
+        loop {
+            if self.position == 0 {
+                return None;
+            }
+            let framing = self.framing();
+            if self.position < framing.header_start() {
+                return None;
+            }
+
+            let length = self.length(framing);
+            if !(MIN_PAYLOAD_LENGTH..=MAX_PAYLOAD_SIZE).contains(&length) {
+                self.resync();
+                continue;
+            }
+
+            let frame_length = framing.header_start() + HEADER_SIZE + length + framing.crc_size();
+            if self.position < frame_length {
+                return None;
             }
+
+            let valid = match framing {
+                Framing::Short => crc.check(&self.buffer[..frame_length]),
+                Framing::Long => crc.check_32(&self.buffer[..frame_length]),
+            };
+            if !valid {
+                self.stats.crc_errors = self.stats.crc_errors.wrapping_add(1);
+                self.resync();
+                return Some(Err(DecoderError::InvalidCrc));
+            }
+
+            let result = self.decode_frame(framing, frame_length);
+            self.consume(frame_length);
+            return Some(result);
+        }
+    }
+
+    /// Inter-byte timeout hook, drops a partially received frame.
+    ///
+    /// Returns `true` if there was anything to drop.
+    pub fn timeout(&mut self) -> bool {
+        if self.position == 0 {
+            return false;
+        }
+        self.drop_bytes(self.position);
+        self.position = 0;
+        true
+    }
+
+    /// Returns `true` if the decoder is not in the middle of a frame
+    pub fn is_idle(&self) -> bool {
+        self.position == 0
+    }
+
+    pub fn stats(&self) -> DecoderStats {
+        self.stats
+    }
+
+    fn decode_frame(
+        &mut self,
+        framing: Framing,
+        frame_length: usize,
+    ) -> Result<Frame<T>, DecoderError<T::Error>> {
+        decode_body(
+            &self.buffer[framing.header_start()..frame_length - framing.crc_size()],
+            &mut self.sequence,
+            &mut self.stats,
+        )
+    }
+
+    /// Drops the current frame candidate and restarts from the next start byte in the buffer
+    fn resync(&mut self) {
+        self.stats.resyncs = self.stats.resyncs.wrapping_add(1);
+        let next_start = self.buffer[1..self.position]
+            .iter()
+            .position(|&byte| Self::is_start(byte))
+            .map(|index| index + 1)
+            .unwrap_or(self.position);
+        self.drop_bytes(next_start);
+        self.shift(next_start);
+    }
+
+    /// Removes a decoded frame, anything behind it is kept only from the next start byte onwards
+    fn consume(&mut self, frame_length: usize) {
+        let next_start = self.buffer[frame_length..self.position]
+            .iter()
+            .position(|&byte| Self::is_start(byte))
+            .map(|index| index + frame_length)
+            .unwrap_or(self.position);
+        self.drop_bytes(next_start - frame_length);
+        self.shift(next_start);
+    }
+
+    fn shift(&mut self, count: usize) {
+        self.buffer.copy_within(count..self.position, 0);
+        self.position -= count;
+    }
+
+    fn drop_bytes(&mut self, count: usize) {
+        self.stats.dropped_bytes = self.stats.dropped_bytes.wrapping_add(count as u32);
+    }
+
+    fn is_start(byte: u8) -> bool {
+        byte == START_BYTE || byte == LONG_START_BYTE
+    }
+
+    fn framing(&self) -> Framing {
+        if self.buffer[0] == LONG_START_BYTE {
+            Framing::Long
+        } else {
+            Framing::Short
         }
+    }
 
-        None
+    fn length(&self, framing: Framing) -> usize {
+        match framing {
+            Framing::Short => self.buffer[1] as usize,
+            Framing::Long => u16::from_le_bytes([self.buffer[1], self.buffer[2]]) as usize,
+        }
     }
+}
+
+/// Follows the sequence numbers of the peer to count lost frames
+#[derive(Debug, Default)]
+pub(crate) struct SequenceTracker {
+    expected: Option<u8>,
+}
 
-    fn parse_command(&self, crc: &mut impl CrcEngine) -> Result<T, DecoderError<T::Error>> {
-        if !crc.check(&self.buffer[..self.length() + 4]) {
-            return Err(DecoderError::InvalidCrc);
+impl SequenceTracker {
+    fn track(&mut self, sequence: u8, stats: &mut DecoderStats) {
+        if let Some(expected) = self.expected {
+            let lost = sequence.wrapping_sub(expected);
+            if lost >= REPLAY_WINDOW {
+                // A sequence number from the recent past is a retransmission, not a gap
+                return;
+            }
+            stats.lost_frames = stats.lost_frames.wrapping_add(lost as u32);
         }
+        self.expected = Some(sequence.wrapping_add(1));
+    }
+}
 
-        let packet = T::deserialize(&self.buffer[2..self.length() + 2])
-            .map_err(DecoderError::DeserializeError)?;
-        Ok(packet)
+/// Decodes `[header, payload]` of a frame whose CRC was already checked
+pub(crate) fn decode_body<T: Packet>(
+    body: &[u8],
+    sequence: &mut SequenceTracker,
+    stats: &mut DecoderStats,
+) -> Result<Frame<T>, DecoderError<T::Error>> {
+    let header =
+        Header::deserialize(&body[..HEADER_SIZE]).map_err(|_| DecoderError::InvalidHeader)?;
+    sequence.track(header.sequence, stats);
+    let packet = T::deserialize(&body[HEADER_SIZE..]).map_err(DecoderError::DeserializeError)?;
+    Ok(Frame { header, packet })
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Framing {
+    /// One length byte and a CRC-16
+    Short,
+    /// Two length bytes and a CRC-32
+    Long,
+}
+
+impl Framing {
+    fn header_start(self) -> usize {
+        match self {
+            Framing::Short => 2,
+            Framing::Long => 3,
+        }
     }
 
-    fn length(&self) -> usize {
-        self.buffer[1] as usize
+    fn crc_size(self) -> usize {
+        match self {
+            Framing::Short => 2,
+            Framing::Long => 4,
+        }
     }
 }
 
@@ -69,5 +260,8 @@ impl<T: Packet, const START_BYTE: u8> Decoder<T, START_BYTE> {
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum DecoderError<E> {
     InvalidCrc,
+    /// The frame boundaries were found but the content between them is malformed
+    InvalidFrame,
+    InvalidHeader,
     DeserializeError(E),
 }
@@ -1,34 +1,606 @@
+use crate::frame::{HEADER_SIZE, Header, MAX_SHORT_PAYLOAD_SIZE};
 use crate::packet::Packet;
 use crc_engine::CrcEngine;
 
+/// Produces short frames `[START_BYTE, length, header, payload, crc16]` and, for payloads that do
+/// not fit, long frames `[LONG_START_BYTE, length_lo, length_hi, header, payload, crc32]`
 #[derive(Debug)]
-pub struct Encoder<T: Packet, const START_BYTE: u8> {
+pub struct Encoder<T: Packet, const START_BYTE: u8, const LONG_START_BYTE: u8> {
     _phantom: core::marker::PhantomData<T>,
 }
 
-impl<T: Packet, const START_BYTE: u8> Default for Encoder<T, START_BYTE> {
+impl<T: Packet, const START_BYTE: u8, const LONG_START_BYTE: u8> Default
+    for Encoder<T, START_BYTE, LONG_START_BYTE>
+{
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl<T: Packet, const START_BYTE: u8> Encoder<T, START_BYTE> {
+impl<T: Packet, const START_BYTE: u8, const LONG_START_BYTE: u8>
+    Encoder<T, START_BYTE, LONG_START_BYTE>
+{
     pub fn new() -> Self {
         Self {
             _phantom: core::marker::PhantomData,
         }
     }
 
-    pub fn encode(&self, packet: &T, buffer: &mut [u8], crc: &mut impl CrcEngine) -> usize {
-        buffer[0] = START_BYTE;
-        let payload_len = packet.serialize(&mut buffer[2..]);
-        buffer[1] = payload_len as u8;
-        let crc_val = crc.calculate(&buffer[..2 + payload_len]);
-        let crc_bytes = crc_val.to_le_bytes();
-        buffer[2 + payload_len] = crc_bytes[0];
-        buffer[2 + payload_len + 1] = crc_bytes[1];
-        2 + payload_len + 2
+    pub fn encode(
+        &self,
+        header: Header,
+        packet: &T,
+        buffer: &mut [u8],
+        crc: &mut impl CrcEngine,
+    ) -> usize {
+        // Laid out as a long frame first, the length is only known after serialization
+        let payload_start = 3 + HEADER_SIZE;
+        header.serialize(&mut buffer[3..payload_start]);
+        let payload_len = packet.serialize(&mut buffer[payload_start..]);
+
+        if payload_len <= MAX_SHORT_PAYLOAD_SIZE {
+            buffer.copy_within(3..payload_start + payload_len, 2);
+            buffer[0] = START_BYTE;
+            buffer[1] = payload_len as u8;
+            let frame_len = 2 + HEADER_SIZE + payload_len;
+            let crc_val = crc.calculate(&buffer[..frame_len]);
+            buffer[frame_len..frame_len + 2].copy_from_slice(&crc_val.to_le_bytes());
+            frame_len + 2
+        } else {
+            buffer[0] = LONG_START_BYTE;
+            buffer[1..3].copy_from_slice(&(payload_len as u16).to_le_bytes());
+            let frame_len = payload_start + payload_len;
+            let crc_val = crc.calculate_32(&buffer[..frame_len]);
+            buffer[frame_len..frame_len + 4].copy_from_slice(&crc_val.to_le_bytes());
+            frame_len + 4
+        }
     }
 }
 
-// TODO: Add tests, especially for the encode -> decode cycle
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use crate::blob::{
+        BLOB_CHUNK_MAX_DATA_SIZE, BlobChunk, BlobClose, BlobId, BlobInfo, BlobMode, BlobOpen,
+        BlobRead,
+    };
+    use crate::capabilities::Capabilities;
+    use crate::command::{FIRMWARE_BLOCK_MAX_DATA_SIZE, FirmwareBlock};
+    use crate::decoder::DecoderStats;
+    use crate::event::{
+        ArmingState, DeviceIntroduction, ErrorCode, FaultChange, FaultRegister, Telemetry,
+    };
+    use crate::fault_history::FaultHistory;
+    use crate::firmware::{BootOutcome, FirmwareSlots, ImageMetadata};
+    use crate::frame::{Frame, Header};
+    use crate::log::{LogLevel, LogRecord};
+    use crate::motion::{
+        CurrentSetpoint, DutyCycleSetpoint, PositionSetpoint, TorqueSetpoint, VelocitySetpoint,
+    };
+    use crate::scope::{
+        ScopeChannels, ScopeConfig, ScopeSignal, ScopeState, ScopeStatus, ScopeTrigger, TriggerMode,
+    };
+    use crate::telemetry::{
+        TelemetryFields, TelemetrySample, TelemetrySubscription, TelemetryValue,
+    };
+    use crate::{Command, Event, MAX_PACKET_SIZE, cobs, command, event};
+    use crc_engine::software::SoftwareCrcEngine;
+    use enum_iterator::Sequence;
+    use logging::fault_journal::{FaultStats, FaultTransition, JOURNAL_TRANSITIONS, JournalTime};
+    use logging::fault_register::{FaultState, FaultType};
+    use proptest::prelude::*;
+
+    fn firmware_block() -> impl Strategy<Value = FirmwareBlock> {
+        (
+            any::<u32>(),
+            proptest::collection::vec(any::<u8>(), 0..=FIRMWARE_BLOCK_MAX_DATA_SIZE),
+        )
+            .prop_map(|(offset, content)| {
+                let mut data = [0; FIRMWARE_BLOCK_MAX_DATA_SIZE];
+                data[..content.len()].copy_from_slice(&content);
+                FirmwareBlock {
+                    offset,
+                    length: content.len() as u32,
+                    data,
+                }
+            })
+    }
+
+    fn blob_chunk() -> impl Strategy<Value = BlobChunk> {
+        (
+            any::<(u8, u32)>(),
+            proptest::collection::vec(any::<u8>(), 0..=BLOB_CHUNK_MAX_DATA_SIZE),
+        )
+            .prop_map(|((handle, offset), content)| BlobChunk::new(handle, offset, &content))
+    }
+
+    fn blob_command() -> impl Strategy<Value = Command> {
+        prop_oneof![
+            (any::<(u8, bool, u32)>()).prop_map(|(id, write, total_size)| {
+                Command::OpenBlob(BlobOpen {
+                    id: BlobId(id),
+                    mode: if write {
+                        BlobMode::Write
+                    } else {
+                        BlobMode::Read
+                    },
+                    total_size,
+                })
+            }),
+            any::<(u8, u32, u16)>().prop_map(|(handle, offset, length)| {
+                Command::ReadBlob(BlobRead {
+                    handle,
+                    offset,
+                    length,
+                })
+            }),
+            blob_chunk().prop_map(Command::WriteBlob),
+            any::<(u8, u32)>().prop_map(|(handle, checksum)| {
+                Command::CloseBlob(BlobClose { handle, checksum })
+            }),
+        ]
+    }
+
+    fn command() -> impl Strategy<Value = Command> {
+        prop_oneof![
+            Just(Command::IntroduceYourself),
+            control_command(),
+            firmware_command(),
+            fault_command(),
+            blob_command(),
+            any::<(u32, u16)>().prop_map(|(fields, rate_hz)| {
+                Command::SubscribeTelemetry(TelemetrySubscription {
+                    fields: TelemetryFields(fields),
+                    rate_hz,
+                })
+            }),
+            scope_command(),
+            any::<u8>().prop_map(|level| Command::SetLogLevel(LogLevel::from_u8(level))),
+            motion_command(),
+        ]
+    }
+
+    fn motion_command() -> impl Strategy<Value = Command> {
+        prop_oneof![
+            (finite_f32(), finite_f32())
+                .prop_map(|(d, q)| Command::SetCurrent(CurrentSetpoint { d, q })),
+            finite_f32().prop_map(|torque| Command::SetTorque(TorqueSetpoint { torque })),
+            finite_f32().prop_map(|velocity| Command::SetVelocity(VelocitySetpoint { velocity })),
+            finite_f32().prop_map(|position| Command::SetPosition(PositionSetpoint { position })),
+            finite_f32()
+                .prop_map(|duty_cycle| Command::SetDutyCycle(DutyCycleSetpoint { duty_cycle })),
+        ]
+    }
+
+    fn control_command() -> impl Strategy<Value = Command> {
+        prop_oneof![
+            Just(Command::Stop),
+            Just(Command::Arm),
+            Just(Command::Disarm),
+        ]
+    }
+
+    fn firmware_command() -> impl Strategy<Value = Command> {
+        prop_oneof![
+            firmware_block().prop_map(Command::WriteFirmwareBlock),
+            Just(Command::FinalizeFirmwareUpdate),
+            Just(Command::ReadFirmwareSlots),
+        ]
+    }
+
+    fn fault_command() -> impl Strategy<Value = Command> {
+        prop_oneof![
+            Just(Command::ReportFaults),
+            Just(Command::ResetFaults),
+            Just(Command::ReadFaultHistory),
+        ]
+    }
+
+    fn scope_command() -> impl Strategy<Value = Command> {
+        prop_oneof![
+            scope_config().prop_map(Command::ConfigureScope),
+            Just(Command::TriggerScope),
+            Just(Command::ReportScope),
+        ]
+    }
+
+    fn scope_channels() -> impl Strategy<Value = ScopeChannels> {
+        let signal = proptest::option::of(
+            (0x01u8..=0x0A).prop_map(|code| ScopeSignal::from_u8(code).unwrap()),
+        );
+        [signal.clone(), signal.clone(), signal.clone(), signal].prop_map(ScopeChannels)
+    }
+
+    fn scope_config() -> impl Strategy<Value = ScopeConfig> {
+        let mode = prop_oneof![
+            Just(TriggerMode::Manual),
+            Just(TriggerMode::RisingEdge),
+            Just(TriggerMode::FallingEdge),
+            Just(TriggerMode::AboveLevel),
+            Just(TriggerMode::BelowLevel),
+        ];
+        (
+            scope_channels(),
+            mode,
+            any::<u8>(),
+            finite_f32(),
+            any::<[u16; 3]>(),
+        )
+            .prop_map(
+                |(channels, mode, channel, level, [length, pre_trigger, decimation])| ScopeConfig {
+                    channels,
+                    trigger: ScopeTrigger {
+                        mode,
+                        channel,
+                        level,
+                    },
+                    length,
+                    pre_trigger,
+                    decimation,
+                },
+            )
+    }
+
+    fn scope_status() -> impl Strategy<Value = ScopeStatus> {
+        (
+            any::<u8>(),
+            scope_channels(),
+            any::<[u16; 2]>(),
+            finite_f32(),
+        )
+            .prop_map(|(state, channels, [length, pre_trigger], sample_rate)| {
+                ScopeStatus {
+                    state: ScopeState::from_u8(state),
+                    channels,
+                    length,
+                    pre_trigger,
+                    sample_rate,
+                }
+            })
+    }
+
+    fn finite_f32() -> impl Strategy<Value = f32> {
+        -1.0e6f32..1.0e6f32
+    }
+
+    fn decoder_stats() -> impl Strategy<Value = DecoderStats> {
+        any::<[u32; 4]>().prop_map(|[crc_errors, resyncs, dropped_bytes, lost_frames]| {
+            DecoderStats {
+                crc_errors,
+                resyncs,
+                dropped_bytes,
+                lost_frames,
+            }
+        })
+    }
+
+    fn telemetry() -> impl Strategy<Value = Telemetry> {
+        (
+            [finite_f32(), finite_f32(), finite_f32(), finite_f32()],
+            [finite_f32(), finite_f32(), finite_f32()],
+            any::<u64>(),
+            any::<[u32; 2]>(),
+            [decoder_stats(), decoder_stats()],
+            any::<u8>().prop_map(ArmingState::from_u8),
+        )
+            .prop_map(
+                |(temperatures, power, uptime, faults, [usb_link, serial_link], arming_state)| {
+                    Telemetry {
+                        cpu_temperature: temperatures[0],
+                        driver_temperature: temperatures[1],
+                        motor_temperature: temperatures[2],
+                        v_bus: temperatures[3],
+                        power_consumption: power[0],
+                        current_consumption: power[1],
+                        duty_cycle: power[2],
+                        uptime,
+                        active_faults: faults[0],
+                        latched_faults: faults[1],
+                        usb_link,
+                        serial_link,
+                        arming_state,
+                    }
+                },
+            )
+    }
+
+    fn telemetry_value() -> impl Strategy<Value = TelemetryValue> {
+        prop_oneof![
+            [finite_f32(), finite_f32(), finite_f32()].prop_map(TelemetryValue::PhaseCurrents),
+            [finite_f32(), finite_f32()].prop_map(TelemetryValue::DqCurrents),
+            [finite_f32(), finite_f32()].prop_map(TelemetryValue::DqVoltages),
+            finite_f32().prop_map(TelemetryValue::ElectricalAngle),
+            finite_f32().prop_map(TelemetryValue::Velocity),
+            finite_f32().prop_map(TelemetryValue::BusVoltage),
+            [finite_f32(), finite_f32(), finite_f32()].prop_map(TelemetryValue::Temperatures),
+            any::<[u32; 2]>().prop_map(TelemetryValue::LoopTiming),
+            any::<[u32; 2]>().prop_map(TelemetryValue::Faults),
+        ]
+    }
+
+    fn telemetry_sample() -> impl Strategy<Value = TelemetrySample> {
+        (
+            any::<u64>(),
+            proptest::collection::vec(telemetry_value(), 0..10),
+        )
+            .prop_map(|(timestamp, values)| {
+                let mut sample = TelemetrySample::new(timestamp);
+                for value in values {
+                    sample.insert(value);
+                }
+                sample
+            })
+    }
+
+    fn log_record() -> impl Strategy<Value = LogRecord> {
+        (
+            any::<(u8, u64)>(),
+            ".{0,60}",
+            proptest::collection::vec(".{0,40}", 0..6),
+        )
+            .prop_map(|((level, timestamp), message, args)| {
+                let mut record = LogRecord::new(LogLevel::from_u8(level), timestamp, &message);
+                for arg in args {
+                    record.push_arg(&arg);
+                }
+                record
+            })
+    }
+
+    fn header() -> impl Strategy<Value = Header> {
+        prop_oneof![
+            any::<u8>().prop_map(Header::request),
+            any::<u8>().prop_map(Header::reliable_request),
+            any::<(u8, u8)>().prop_map(|(sequence, request)| Header::response(sequence, request)),
+            any::<u8>().prop_map(Header::unsolicited),
+        ]
+    }
+
+    fn fault_state() -> impl Strategy<Value = FaultState> {
+        prop_oneof![
+            Just(FaultState::Clean),
+            Just(FaultState::Active),
+            Just(FaultState::Latched),
+        ]
+    }
+
+    fn fault_register() -> impl Strategy<Value = FaultRegister> {
+        proptest::collection::vec(fault_state(), FaultType::CARDINALITY).prop_map(|cells| {
+            FaultRegister {
+                cells: cells.try_into().unwrap(),
+            }
+        })
+    }
+
+    fn fault_change() -> impl Strategy<Value = FaultChange> {
+        (0..FaultType::CARDINALITY, fault_state(), any::<u64>()).prop_map(
+            |(fault, state, timestamp)| FaultChange {
+                fault: enum_iterator::all::<FaultType>().nth(fault).unwrap(),
+                state,
+                timestamp,
+            },
+        )
+    }
+
+    fn journal_time() -> impl Strategy<Value = JournalTime> {
+        any::<(u16, u64)>().prop_map(|(boot, uptime)| JournalTime { boot, uptime })
+    }
+
+    fn fault_history() -> impl Strategy<Value = FaultHistory> {
+        let stats = (any::<u32>(), journal_time(), journal_time()).prop_map(
+            |(occurrences, first_seen, last_seen)| FaultStats {
+                occurrences,
+                first_seen,
+                last_seen,
+            },
+        );
+        let transition =
+            (fault_change(), any::<u16>()).prop_map(|(change, boot)| FaultTransition {
+                fault: change.fault,
+                state: change.state,
+                time: JournalTime {
+                    boot,
+                    uptime: change.timestamp,
+                },
+            });
+        (
+            any::<u16>(),
+            proptest::collection::vec(stats, FaultType::CARDINALITY),
+            proptest::collection::vec(transition, 0..=JOURNAL_TRANSITIONS),
+        )
+            .prop_map(|(boot, stats, transitions)| {
+                let mut history = FaultHistory::new(boot, stats.try_into().unwrap());
+                for transition in transitions {
+                    history.push(transition);
+                }
+                history
+            })
+    }
+
+    fn image_metadata() -> impl Strategy<Value = ImageMetadata> {
+        (
+            any::<(bool, [u8; 3], u8, u32)>(),
+            any::<[u8; 20]>(),
+            any::<[u8; 32]>(),
+        )
+            .prop_map(
+                |((present, version, board_revision, length), git_hash, digest)| ImageMetadata {
+                    present,
+                    version,
+                    board_revision,
+                    git_hash,
+                    length,
+                    digest,
+                },
+            )
+    }
+
+    fn firmware_slots() -> impl Strategy<Value = FirmwareSlots> {
+        (
+            image_metadata(),
+            image_metadata(),
+            any::<bool>(),
+            any::<u8>().prop_map(BootOutcome::from_u8),
+        )
+            .prop_map(|(active, pending, swap_scheduled, boot)| FirmwareSlots {
+                active,
+                pending,
+                swap_scheduled,
+                boot,
+            })
+    }
+
+    fn blob_event() -> impl Strategy<Value = Event> {
+        prop_oneof![
+            any::<(u8, u32)>().prop_map(|(handle, total_size)| {
+                Event::BlobOpened(BlobInfo { handle, total_size })
+            }),
+            blob_chunk().prop_map(Event::BlobData),
+        ]
+    }
+
+    fn fault_event() -> impl Strategy<Value = Event> {
+        prop_oneof![
+            fault_register().prop_map(Event::FaultRegister),
+            fault_change().prop_map(Event::FaultRaised),
+            fault_change().prop_map(Event::FaultCleared),
+            fault_history().prop_map(Event::FaultHistory),
+        ]
+    }
+
+    fn event() -> impl Strategy<Value = Event> {
+        prop_oneof![
+            (
+                any::<[u8; 12]>(),
+                any::<[[u8; 3]; 2]>(),
+                any::<[u8; 2]>(),
+                any::<u16>(),
+                any::<u32>(),
+            )
+                .prop_map(
+                    |(
+                        uid,
+                        [firmware_version, bootloader_version],
+                        [protocol_version, board_revision],
+                        max_frame_size,
+                        capabilities,
+                    )| {
+                        Event::DeviceIntroduction(DeviceIntroduction {
+                            uid,
+                            firmware_version,
+                            protocol_version,
+                            board_revision,
+                            bootloader_version,
+                            max_frame_size,
+                            capabilities: Capabilities(capabilities),
+                        })
+                    }
+                ),
+            telemetry().prop_map(Event::Telemetry),
+            Just(Event::Success),
+            any::<u8>().prop_map(|code| Event::Failure(ErrorCode::from_u8(code))),
+            blob_event(),
+            fault_event(),
+            telemetry_sample().prop_map(Event::TelemetrySample),
+            scope_status().prop_map(Event::ScopeStatus),
+            log_record().prop_map(Event::Log),
+            firmware_slots().prop_map(Event::FirmwareSlots),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn encoded_command_should_decode_to_the_same_command(header in header(), command in command()) {
+            let mut crc = SoftwareCrcEngine::new();
+            let mut buffer = [0; MAX_PACKET_SIZE];
+            let length = command::encoder::Encoder::new().encode(header, &command, &mut buffer, &mut crc);
+
+            let mut decoder = command::decoder::Decoder::new();
+            let mut decoded = None;
+            for (i, &byte) in buffer[..length].iter().enumerate() {
+                let result = decoder.feed(byte, &mut crc);
+                if i < length - 1 {
+                    prop_assert!(result.is_none());
+                } else {
+                    decoded = result;
+                }
+            }
+            prop_assert_eq!(decoded, Some(Ok(Frame { header, packet: command })));
+        }
+
+        #[test]
+        fn encoded_event_should_decode_to_the_same_event(header in header(), event in event()) {
+            let mut crc = SoftwareCrcEngine::new();
+            let mut buffer = [0; MAX_PACKET_SIZE];
+            let length = event::encoder::Encoder::new().encode(header, &event, &mut buffer, &mut crc);
+
+            let mut decoder = event::decoder::Decoder::new();
+            let mut decoded = None;
+            for (i, &byte) in buffer[..length].iter().enumerate() {
+                let result = decoder.feed(byte, &mut crc);
+                if i < length - 1 {
+                    prop_assert!(result.is_none());
+                } else {
+                    decoded = result;
+                }
+            }
+            prop_assert_eq!(decoded, Some(Ok(Frame { header, packet: event })));
+        }
+
+        #[test]
+        fn cobs_encoded_event_should_decode_to_the_same_event(header in header(), event in event()) {
+            let mut crc = SoftwareCrcEngine::new();
+            let mut buffer = [0; cobs::MAX_FRAME_SIZE];
+            let length = event::encoder::CobsEncoder::new().encode(header, &event, &mut buffer, &mut crc);
+            prop_assert!(!buffer[..length - 1].contains(&cobs::DELIMITER));
+
+            let mut decoder = event::decoder::CobsDecoder::new();
+            let mut decoded = None;
+            for (i, &byte) in buffer[..length].iter().enumerate() {
+                let result = decoder.feed(byte, &mut crc);
+                if i < length - 1 {
+                    prop_assert!(result.is_none());
+                } else {
+                    decoded = result;
+                }
+            }
+            prop_assert_eq!(decoded, Some(Ok(Frame { header, packet: event })));
+        }
+
+        #[test]
+        fn cobs_encoded_command_should_decode_to_the_same_command(header in header(), command in command()) {
+            let mut crc = SoftwareCrcEngine::new();
+            let mut buffer = [0; cobs::MAX_FRAME_SIZE];
+            let length = command::encoder::CobsEncoder::new().encode(header, &command, &mut buffer, &mut crc);
+
+            let mut decoder = command::decoder::CobsDecoder::new();
+            let decoded = buffer[..length].iter().find_map(|&byte| decoder.feed(byte, &mut crc));
+            prop_assert_eq!(decoded, Some(Ok(Frame { header, packet: command })));
+        }
+
+        #[test]
+        fn decoders_should_not_panic_on_random_input(data in proptest::collection::vec(any::<u8>(), 0..1024)) {
+            let mut crc = SoftwareCrcEngine::new();
+            let mut command_decoder = command::decoder::Decoder::new();
+            let mut event_decoder = event::decoder::Decoder::new();
+            let mut cobs_decoder = command::decoder::CobsDecoder::new();
+            for &byte in &data {
+                let _ = cobs_decoder.feed(byte, &mut crc);
+                let mut result = command_decoder.feed(byte, &mut crc);
+                while result.is_some() {
+                    result = command_decoder.poll(&mut crc);
+                }
+                let mut result = event_decoder.feed(byte, &mut crc);
+                while result.is_some() {
+                    result = event_decoder.poll(&mut crc);
+                }
+            }
+        }
+
+        #[test]
+        fn packets_should_not_panic_on_random_payload(data in proptest::collection::vec(any::<u8>(), 0..=u8::MAX as usize)) {
+            use crate::packet::Packet;
+            let _ = Command::deserialize(&data);
+            let _ = Event::deserialize(&data);
+        }
+    }
+}
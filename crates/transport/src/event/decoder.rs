@@ -1,4 +1,5 @@
-use crate::decoder;
 use crate::event::Event;
+use crate::{cobs, decoder};
 
-pub type Decoder = decoder::Decoder<Event, 0xCC>;
+pub type Decoder = decoder::Decoder<Event, 0xCC, 0xCD>;
+pub type CobsDecoder = cobs::decoder::Decoder<Event>;
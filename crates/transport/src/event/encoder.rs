@@ -1,3 +1,4 @@
-use crate::{Event, encoder};
+use crate::{Event, cobs, encoder};
 
-pub type Encoder = encoder::Encoder<Event, 0xCC>;
+pub type Encoder = encoder::Encoder<Event, 0xCC, 0xCD>;
+pub type CobsEncoder = cobs::encoder::Encoder<Event>;
@@ -1,75 +1,158 @@
-use crate::helpers::{decode_f32, decode_u32, decode_u64};
-use crate::packet::Packet;
-use core::array::TryFromSliceError;
+use crate::blob::{BlobChunk, BlobInfo};
+use crate::capabilities::Capabilities;
+use crate::decoder::DecoderStats;
+use crate::fault_history::FaultHistory;
+use crate::firmware::FirmwareSlots;
+use crate::log::LogRecord;
+use crate::packet::{Field, InvalidField, Packet, Payload};
+use crate::scope::ScopeStatus;
+use crate::telemetry::TelemetrySample;
 use enum_iterator::Sequence;
 use logging::fault_register;
 
 pub mod decoder;
 pub mod encoder;
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Clone, Copy, Packet)]
+#[packet(
+    error = EventDeserializationError,
+    unknown = EventDeserializationError::EventNotFound
+)]
+#[allow(clippy::large_enum_variant)]
 pub enum Event {
-    DeviceIntroduction(DeviceIntroduction), // 0x01
-    Telemetry(Telemetry),                   // 0x02
-    Success,                                // 0x03
-    Failure,                                // 0x04
-    FaultRegister(FaultRegister),           // 0x71
+    #[packet(opcode = 0x01)]
+    DeviceIntroduction(DeviceIntroduction),
+    #[packet(opcode = 0x02)]
+    Telemetry(Telemetry),
+    #[packet(opcode = 0x03)]
+    Success,
+    #[packet(opcode = 0x04)]
+    Failure(ErrorCode),
+    #[packet(opcode = 0x12)]
+    FirmwareSlots(FirmwareSlots),
+    #[packet(opcode = 0x20)]
+    BlobOpened(BlobInfo),
+    #[packet(opcode = 0x21)]
+    BlobData(BlobChunk),
+    #[packet(opcode = 0x40)]
+    TelemetrySample(TelemetrySample),
+    #[packet(opcode = 0x52)]
+    ScopeStatus(ScopeStatus),
+    #[packet(opcode = 0x60)]
+    Log(LogRecord),
+    #[packet(opcode = 0x71)]
+    FaultRegister(FaultRegister),
+    #[packet(opcode = 0x73)]
+    FaultRaised(FaultChange),
+    #[packet(opcode = 0x74)]
+    FaultCleared(FaultChange),
+    #[packet(opcode = 0x75)]
+    FaultHistory(FaultHistory),
 }
 
-impl Packet for Event {
-    type Error = EventDeserializationError;
+/// Why a command was not executed
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Sequence)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ErrorCode {
+    /// Sent by a newer firmware, this build does not know the code
+    Unknown,
+    /// The firmware recognises the command but does not implement it
+    NotImplemented,
+    /// The command could not be queued, retrying later may succeed
+    Busy,
+    /// The command is not allowed in the current state of the device
+    InvalidState,
+    /// The command carries a value outside of the accepted range
+    InvalidArgument,
+    /// Reading, erasing or writing the flash failed
+    FlashError,
+    /// The command needs the motor to be armed first
+    NotArmed,
+    /// The firmware image is damaged or lacks its metadata header
+    InvalidImage,
+    /// The firmware image was built for another board revision
+    WrongBoard,
+}
 
-    fn deserialize(data: &[u8]) -> Result<Self, Self::Error> {
-        let event_type = data[0];
-        match event_type {
-            0x01 => {
-                let device_introduction = DeviceIntroduction::deserialize(&data[1..])?;
-                Ok(Event::DeviceIntroduction(device_introduction))
-            }
-            0x02 => {
-                let telemetry = Telemetry::deserialize(&data[1..])?;
-                Ok(Event::Telemetry(telemetry))
-            }
-            0x03 => Ok(Event::Success),
-            0x04 => Ok(Event::Failure),
-            0x71 => {
-                let error_register = FaultRegister::deserialize(&data[1..])?;
-                Ok(Event::FaultRegister(error_register))
-            }
-            _ => Err(EventDeserializationError::EventNotFound),
+impl ErrorCode {
+    pub fn to_u8(self) -> u8 {
+        match self {
+            ErrorCode::Unknown => 0x00,
+            ErrorCode::NotImplemented => 0x01,
+            ErrorCode::Busy => 0x02,
+            ErrorCode::InvalidState => 0x03,
+            ErrorCode::InvalidArgument => 0x04,
+            ErrorCode::FlashError => 0x05,
+            ErrorCode::NotArmed => 0x06,
+            ErrorCode::InvalidImage => 0x07,
+            ErrorCode::WrongBoard => 0x08,
         }
     }
 
-    fn serialize(&self, buffer: &mut [u8]) -> usize {
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            0x01 => ErrorCode::NotImplemented,
+            0x02 => ErrorCode::Busy,
+            0x03 => ErrorCode::InvalidState,
+            0x04 => ErrorCode::InvalidArgument,
+            0x05 => ErrorCode::FlashError,
+            0x06 => ErrorCode::NotArmed,
+            0x07 => ErrorCode::InvalidImage,
+            0x08 => ErrorCode::WrongBoard,
+            _ => ErrorCode::Unknown,
+        }
+    }
+}
+
+/// Where the motor controller is in its arming sequence
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Sequence)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ArmingState {
+    /// Sent by a newer firmware, this build does not know the state
+    Unknown,
+    /// Waiting for the first measurements
+    Boot,
+    /// Outputs are off, setpoints are rejected
+    Idle,
+    /// Measuring the current sensor offsets after an arm request
+    Calibrating,
+    /// Setpoints are accepted, the motor is not driven yet
+    Armed,
+    /// Following a setpoint
+    Running,
+    /// Disarmed by an active fault, returns to idle once it clears
+    Fault,
+}
+
+impl ArmingState {
+    pub fn to_u8(self) -> u8 {
         match self {
-            Event::DeviceIntroduction(device_introduction) => {
-                buffer[0] = 0x01;
-                let content_len = device_introduction.serialize(&mut buffer[1..]);
-                1 + content_len
-            }
-            Event::Telemetry(telemetry) => {
-                buffer[0] = 0x02;
-                let content_len = telemetry.serialize(&mut buffer[1..]);
-                1 + content_len
-            }
-            Event::Success => {
-                buffer[0] = 0x03;
-                1
-            }
-            Event::Failure => {
-                buffer[0] = 0x04;
-                1
-            }
-            Event::FaultRegister(fault_register) => {
-                buffer[0] = 0x71;
-                let content_len = fault_register.serialize(&mut buffer[1..]);
-                1 + content_len
-            }
+            ArmingState::Unknown => 0x00,
+            ArmingState::Boot => 0x01,
+            ArmingState::Idle => 0x02,
+            ArmingState::Calibrating => 0x03,
+            ArmingState::Armed => 0x04,
+            ArmingState::Running => 0x05,
+            ArmingState::Fault => 0x06,
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            0x01 => ArmingState::Boot,
+            0x02 => ArmingState::Idle,
+            0x03 => ArmingState::Calibrating,
+            0x04 => ArmingState::Armed,
+            0x05 => ArmingState::Running,
+            0x06 => ArmingState::Fault,
+            _ => ArmingState::Unknown,
         }
     }
 }
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+/// Fields added by newer firmware are appended, so a longer payload is fine
+#[derive(Debug, PartialEq, Clone, Copy, Payload)]
+#[payload(extensible)]
 pub struct Telemetry {
     pub cpu_temperature: f32,     // in kelvins
     pub driver_temperature: f32,  // in kelvins
@@ -81,105 +164,101 @@ pub struct Telemetry {
     pub uptime: u64,              // milliseconds
     pub active_faults: u32,
     pub latched_faults: u32,
+    pub usb_link: DecoderStats,
+    pub serial_link: DecoderStats,
+    pub arming_state: ArmingState,
 }
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+/// Fields added by newer firmware are appended, so a longer payload is fine
+#[derive(Debug, PartialEq, Clone, Copy, Payload)]
+#[payload(extensible)]
 pub struct DeviceIntroduction {
     pub uid: [u8; 12],
     pub firmware_version: [u8; 3],
+    pub protocol_version: u8,
+    pub board_revision: u8,
+    /// All zeros if the bootloader does not report its version
+    pub bootloader_version: [u8; 3],
+    /// Largest frame the device accepts, including framing overhead
+    pub max_frame_size: u16,
+    pub capabilities: Capabilities,
 }
 
+/// One cell per fault type in catalogue order, the payload is as long as the catalogue of the
+/// sender. Cells missing from older firmware are clean, cells of unknown faults are skipped
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub struct FaultRegister {
     pub cells: [fault_register::FaultState; fault_register::FaultType::CARDINALITY],
 }
 
-impl Telemetry {
-    pub fn serialize(&self, buffer: &mut [u8]) -> usize {
-        buffer[..4].copy_from_slice(&self.cpu_temperature.to_le_bytes());
-        buffer[4..8].copy_from_slice(&self.driver_temperature.to_le_bytes());
-        buffer[8..12].copy_from_slice(&self.motor_temperature.to_le_bytes());
-        buffer[12..16].copy_from_slice(&self.v_bus.to_le_bytes());
-        buffer[16..20].copy_from_slice(&self.power_consumption.to_le_bytes());
-        buffer[20..24].copy_from_slice(&self.current_consumption.to_le_bytes());
-        buffer[24..28].copy_from_slice(&self.duty_cycle.to_le_bytes());
-        buffer[28..36].copy_from_slice(&self.uptime.to_le_bytes());
-        buffer[36..40].copy_from_slice(&self.active_faults.to_le_bytes());
-        buffer[40..44].copy_from_slice(&self.latched_faults.to_le_bytes());
-        44
+/// A cell of the fault register changed, sent unsolicited. A cleared fault is latched until it
+/// is reset, the reset reports it as clean
+#[derive(Debug, PartialEq, Clone, Copy, Payload)]
+pub struct FaultChange {
+    pub fault: fault_register::FaultType,
+    pub state: fault_register::FaultState,
+    /// Microseconds since boot
+    pub timestamp: u64,
+}
+
+impl Field for ErrorCode {
+    const SIZE: usize = 1;
+
+    fn write(&self, buffer: &mut [u8]) {
+        buffer[0] = self.to_u8();
     }
 
-    pub fn deserialize(data: &[u8]) -> Result<Self, EventDeserializationError> {
-        let cpu_temperature = decode_f32(&data[0..4])?;
-        let driver_temperature = decode_f32(&data[4..8])?;
-        let motor_temperature = decode_f32(&data[8..12])?;
-        let v_bus = decode_f32(&data[12..16])?;
-        let power_consumption = decode_f32(&data[16..20])?;
-        let current_consumption = decode_f32(&data[20..24])?;
-        let duty_cycle = decode_f32(&data[24..28])?;
-        let uptime = decode_u64(&data[28..36])?;
-        let ongoing_errors = decode_u32(&data[36..40])?;
-        let resolved_errors = decode_u32(&data[40..44])?;
-        Ok(Self {
-            cpu_temperature,
-            driver_temperature,
-            motor_temperature,
-            v_bus,
-            power_consumption,
-            current_consumption,
-            duty_cycle,
-            uptime,
-            active_faults: ongoing_errors,
-            latched_faults: resolved_errors,
-        })
+    fn read(data: &[u8]) -> Result<Self, InvalidField> {
+        data.first().copied().map(Self::from_u8).ok_or(InvalidField)
     }
 }
 
-impl DeviceIntroduction {
-    pub fn serialize(&self, buffer: &mut [u8]) -> usize {
-        buffer[..12].copy_from_slice(&self.uid);
-        buffer[12..15].copy_from_slice(&self.firmware_version);
-        15
+impl Payload for ErrorCode {
+    type Error = InvalidField;
+
+    fn serialize(&self, buffer: &mut [u8]) -> usize {
+        self.write(buffer);
+        Self::SIZE
     }
 
-    pub fn deserialize(data: &[u8]) -> Result<Self, EventDeserializationError> {
-        Ok(Self {
-            uid: data[0..12]
-                .try_into()
-                .map_err(|_| EventDeserializationError::InvalidContent)?,
-            firmware_version: data[12..15]
-                .try_into()
-                .map_err(|_| EventDeserializationError::InvalidContent)?,
-        })
+    /// Devices from before the error codes send failures without one
+    fn deserialize(data: &[u8]) -> Result<Self, Self::Error> {
+        match data.len() {
+            0 => Ok(ErrorCode::Unknown),
+            Self::SIZE => Self::read(data),
+            _ => Err(InvalidField),
+        }
     }
 }
 
-impl FaultRegister {
-    pub fn serialize(&self, buffer: &mut [u8]) -> usize {
-        for (i, cell) in self.cells.iter().enumerate() {
-            buffer[i] = *cell as u8;
-        }
+impl Payload for FaultRegister {
+    type Error = InvalidField;
 
+    fn serialize(&self, buffer: &mut [u8]) -> usize {
+        for (cell, byte) in self.cells.iter().zip(buffer.iter_mut()) {
+            cell.write(core::slice::from_mut(byte));
+        }
         self.cells.len()
     }
 
-    pub fn deserialize(data: &[u8]) -> Result<Self, EventDeserializationError> {
-        if data.len() != fault_register::FaultType::CARDINALITY {
-            return Err(EventDeserializationError::InvalidContent);
+    fn deserialize(data: &[u8]) -> Result<Self, Self::Error> {
+        let mut cells = [fault_register::FaultState::Clean; fault_register::FaultType::CARDINALITY];
+        for (cell, byte) in cells.iter_mut().zip(data) {
+            *cell = fault_register::FaultState::read(core::slice::from_ref(byte))?;
         }
+        Ok(Self { cells })
+    }
+}
 
-        let mut cells = [fault_register::FaultState::Clean; fault_register::FaultType::CARDINALITY];
+impl Field for ArmingState {
+    const SIZE: usize = 1;
 
-        for (i, &v) in data.iter().enumerate() {
-            cells[i] = match v {
-                0 => fault_register::FaultState::Clean,
-                1 => fault_register::FaultState::Active,
-                2 => fault_register::FaultState::Latched,
-                _ => return Err(EventDeserializationError::InvalidContent),
-            };
-        }
+    fn write(&self, buffer: &mut [u8]) {
+        buffer[0] = self.to_u8();
+    }
 
-        Ok(Self { cells })
+    fn read(data: &[u8]) -> Result<Self, InvalidField> {
+        data.first().copied().map(Self::from_u8).ok_or(InvalidField)
     }
 }
 
@@ -190,8 +269,8 @@ pub enum EventDeserializationError {
     InvalidContent,
 }
 
-impl From<TryFromSliceError> for EventDeserializationError {
-    fn from(_: TryFromSliceError) -> Self {
+impl From<InvalidField> for EventDeserializationError {
+    fn from(_: InvalidField) -> Self {
         EventDeserializationError::InvalidContent
     }
 }
@@ -213,10 +292,23 @@ mod tests {
             uptime: u64::MAX,
             active_faults: 2,
             latched_faults: 4,
+            usb_link: DecoderStats {
+                crc_errors: 1,
+                resyncs: 2,
+                dropped_bytes: 3,
+                lost_frames: 0,
+            },
+            serial_link: DecoderStats {
+                crc_errors: 4,
+                resyncs: 5,
+                dropped_bytes: u32::MAX,
+                lost_frames: 6,
+            },
+            arming_state: ArmingState::Running,
         };
         let mut buffer = [0u8; 256];
         let length = telemetry.serialize(&mut buffer);
-        assert_eq!(length, 44);
+        assert_eq!(length, 77);
         let deserialized = Telemetry::deserialize(&buffer[..length]).unwrap();
         assert_eq!(deserialized, telemetry);
     }
@@ -226,14 +318,36 @@ mod tests {
         let device_introduction = DeviceIntroduction {
             uid: [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12],
             firmware_version: [13, 14, 15],
+            protocol_version: 16,
+            board_revision: 17,
+            bootloader_version: [18, 19, 20],
+            max_frame_size: 0x1516,
+            capabilities: Capabilities(0x1718_191A),
         };
         let mut buffer = [0u8; 256];
         let length = device_introduction.serialize(&mut buffer);
-        assert_eq!(length, 15);
+        assert_eq!(length, 26);
         let deserialized = DeviceIntroduction::deserialize(&buffer[..length]).unwrap();
         assert_eq!(deserialized, device_introduction);
     }
 
+    #[test]
+    pub fn device_introduction_from_newer_firmware_should_skip_unknown_fields() {
+        let device_introduction = DeviceIntroduction {
+            uid: [1; 12],
+            firmware_version: [2; 3],
+            protocol_version: 3,
+            board_revision: 4,
+            bootloader_version: [5; 3],
+            max_frame_size: 6,
+            capabilities: Capabilities(7),
+        };
+        let mut buffer = [0xFFu8; 256];
+        let length = device_introduction.serialize(&mut buffer);
+        let deserialized = DeviceIntroduction::deserialize(&buffer[..length + 4]).unwrap();
+        assert_eq!(deserialized, device_introduction);
+    }
+
     #[test]
     pub fn success_event() {
         let mut buffer = [0; 100];
@@ -244,11 +358,75 @@ mod tests {
 
     #[test]
     pub fn failure_event() {
+        for code in enum_iterator::all::<ErrorCode>() {
+            let mut buffer = [0; 100];
+            let len = Event::Failure(code).serialize(&mut buffer);
+            let result = Event::deserialize(&buffer[..len]);
+            assert_eq!(result.unwrap(), Event::Failure(code));
+        }
+    }
+
+    #[test]
+    pub fn failure_from_newer_firmware_should_have_unknown_code() {
+        let result = Event::deserialize(&[0x04, 0xEE]);
+        assert_eq!(result.unwrap(), Event::Failure(ErrorCode::Unknown));
+    }
+
+    #[test]
+    pub fn failure_without_code_should_be_unknown() {
+        let result = Event::deserialize(&[0x04]);
+        assert_eq!(result.unwrap(), Event::Failure(ErrorCode::Unknown));
+    }
+
+    #[test]
+    pub fn failure_with_trailing_bytes_should_return_error() {
+        let result = Event::deserialize(&[0x04, 0x01, 0x00]);
+        assert_eq!(result.err().unwrap(), EventDeserializationError::InvalidContent);
+    }
+
+    #[test]
+    pub fn empty_buffer_should_return_error() {
+        let result = Event::deserialize(&[]);
+        assert_eq!(result.err().unwrap(), EventDeserializationError::InvalidContent);
+    }
+
+    #[test]
+    pub fn event_without_payload_should_reject_trailing_bytes() {
+        let result = Event::deserialize(&[0x03, 0x00]);
+        assert_eq!(result.err().unwrap(), EventDeserializationError::InvalidContent);
+    }
+
+    #[test]
+    pub fn truncated_telemetry_should_return_error() {
         let mut buffer = [0; 100];
-        let len = Event::Failure.serialize(&mut buffer);
-        let result = Event::deserialize(&buffer[..len]);
+        let len = Event::Telemetry(Telemetry {
+            cpu_temperature: 0.0,
+            driver_temperature: 0.0,
+            motor_temperature: 0.0,
+            v_bus: 0.0,
+            power_consumption: 0.0,
+            current_consumption: 0.0,
+            duty_cycle: 0.0,
+            uptime: 0,
+            active_faults: 0,
+            latched_faults: 0,
+            usb_link: DecoderStats::default(),
+            serial_link: DecoderStats::default(),
+            arming_state: ArmingState::Idle,
+        })
+        .serialize(&mut buffer);
+        for short_len in 0..len {
+            let result = Event::deserialize(&buffer[..short_len]);
+            assert_eq!(result.err().unwrap(), EventDeserializationError::InvalidContent);
+        }
+        let result = Event::deserialize(&buffer[..len + 1]);
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), Event::Failure);
+    }
+
+    #[test]
+    pub fn truncated_device_introduction_should_return_error() {
+        let result = Event::deserialize(&[0x01, 1, 2, 3, 4]);
+        assert_eq!(result.err().unwrap(), EventDeserializationError::InvalidContent);
     }
 
     #[test]
@@ -262,4 +440,32 @@ mod tests {
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), Event::FaultRegister(error_register));
     }
+
+    #[test]
+    pub fn fault_register_from_older_firmware_should_fill_missing_cells() {
+        // Firmware before the fault catalogue only knew the encoder fault
+        let result = Event::deserialize(&[0x71, 1]);
+        let mut cells = [fault_register::FaultState::Clean; fault_register::FaultType::CARDINALITY];
+        cells[fault_register::FaultType::Encoder as usize] = fault_register::FaultState::Active;
+        assert_eq!(result.unwrap(), Event::FaultRegister(FaultRegister { cells }));
+    }
+
+    #[test]
+    pub fn fault_register_from_newer_firmware_should_skip_unknown_cells() {
+        let mut buffer = [0; fault_register::FaultType::CARDINALITY + 3];
+        buffer[0] = 0x71;
+        buffer[1..].fill(2);
+        let result = Event::deserialize(&buffer).unwrap();
+        let cells = [fault_register::FaultState::Latched; fault_register::FaultType::CARDINALITY];
+        assert_eq!(result, Event::FaultRegister(FaultRegister { cells }));
+    }
+
+    #[test]
+    pub fn fault_change_with_unknown_fault_should_return_error() {
+        let result = Event::deserialize(&[0x73, 0xFF, 1, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            result.err().unwrap(),
+            EventDeserializationError::InvalidContent
+        );
+    }
 }
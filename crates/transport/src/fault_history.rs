@@ -0,0 +1,176 @@
+use crate::MAX_PAYLOAD_SIZE;
+use crate::packet::{Field, InvalidField, Payload};
+use enum_iterator::{Sequence, all};
+use logging::fault_journal::{
+    FaultJournal, FaultStats, FaultTransition, JOURNAL_TRANSITIONS, JournalTime,
+};
+use logging::fault_register::{FaultState, FaultType};
+
+/// The fault journal of the device, times are given as the boot and the microseconds since it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FaultHistory {
+    /// Boot the device is in, see [`FaultJournal::boot`]
+    pub boot: u16,
+    /// Indexed by the fault type
+    pub stats: [FaultStats; FaultType::CARDINALITY],
+    transitions: [FaultTransition; JOURNAL_TRANSITIONS],
+    count: u8,
+}
+
+// The whole history goes in a single long frame
+const _: () = assert!(FaultHistory::MAX_SIZE <= MAX_PAYLOAD_SIZE);
+
+impl FaultHistory {
+    const HEADER_SIZE: usize = 2 + <[FaultStats; FaultType::CARDINALITY]>::SIZE + 1;
+    pub const MAX_SIZE: usize = Self::HEADER_SIZE + JOURNAL_TRANSITIONS * FaultTransition::SIZE;
+    const EMPTY_TRANSITION: FaultTransition = FaultTransition {
+        fault: FaultType::Encoder,
+        state: FaultState::Clean,
+        time: JournalTime { boot: 0, uptime: 0 },
+    };
+
+    pub fn new(boot: u16, stats: [FaultStats; FaultType::CARDINALITY]) -> Self {
+        Self {
+            boot,
+            stats,
+            transitions: [Self::EMPTY_TRANSITION; JOURNAL_TRANSITIONS],
+            count: 0,
+        }
+    }
+
+    /// Appends a transition, it is dropped once the history is full
+    pub fn push(&mut self, transition: FaultTransition) {
+        if (self.count as usize) < JOURNAL_TRANSITIONS {
+            self.transitions[self.count as usize] = transition;
+            self.count += 1;
+        }
+    }
+
+    /// Oldest first
+    pub fn transitions(&self) -> &[FaultTransition] {
+        &self.transitions[..self.count as usize]
+    }
+}
+
+impl From<&FaultJournal> for FaultHistory {
+    fn from(journal: &FaultJournal) -> Self {
+        let stats = core::array::from_fn(|i| {
+            let fault = all::<FaultType>().nth(i).unwrap();
+            *journal.stats(fault)
+        });
+        let mut history = Self::new(journal.boot(), stats);
+        for transition in journal.transitions() {
+            history.push(*transition);
+        }
+        history
+    }
+}
+
+impl Field for JournalTime {
+    const SIZE: usize = 10;
+
+    fn write(&self, buffer: &mut [u8]) {
+        self.boot.write(buffer);
+        self.uptime.write(&mut buffer[2..]);
+    }
+
+    fn read(data: &[u8]) -> Result<Self, InvalidField> {
+        Ok(Self {
+            boot: u16::read(data)?,
+            uptime: u64::read(data.get(2..).ok_or(InvalidField)?)?,
+        })
+    }
+}
+
+impl Field for FaultStats {
+    const SIZE: usize = 4 + 2 * JournalTime::SIZE;
+
+    fn write(&self, buffer: &mut [u8]) {
+        self.occurrences.write(buffer);
+        self.first_seen.write(&mut buffer[4..]);
+        self.last_seen.write(&mut buffer[4 + JournalTime::SIZE..]);
+    }
+
+    fn read(data: &[u8]) -> Result<Self, InvalidField> {
+        let field = |offset: usize| data.get(offset..).ok_or(InvalidField);
+        Ok(Self {
+            occurrences: u32::read(data)?,
+            first_seen: JournalTime::read(field(4)?)?,
+            last_seen: JournalTime::read(field(4 + JournalTime::SIZE)?)?,
+        })
+    }
+}
+
+impl Field for FaultTransition {
+    const SIZE: usize = 2 + JournalTime::SIZE;
+
+    fn write(&self, buffer: &mut [u8]) {
+        self.fault.write(buffer);
+        self.state.write(&mut buffer[1..]);
+        self.time.write(&mut buffer[2..]);
+    }
+
+    fn read(data: &[u8]) -> Result<Self, InvalidField> {
+        let field = |offset: usize| data.get(offset..).ok_or(InvalidField);
+        Ok(Self {
+            fault: FaultType::read(data)?,
+            state: FaultState::read(field(1)?)?,
+            time: JournalTime::read(field(2)?)?,
+        })
+    }
+}
+
+impl Payload for FaultHistory {
+    type Error = InvalidField;
+
+    fn serialize(&self, buffer: &mut [u8]) -> usize {
+        self.boot.write(buffer);
+        self.stats.write(&mut buffer[2..]);
+        self.count.write(&mut buffer[Self::HEADER_SIZE - 1..]);
+        let mut offset = Self::HEADER_SIZE;
+        for transition in self.transitions() {
+            transition.write(&mut buffer[offset..]);
+            offset += FaultTransition::SIZE;
+        }
+        offset
+    }
+
+    fn deserialize(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() < Self::HEADER_SIZE {
+            return Err(InvalidField);
+        }
+        let count = data[Self::HEADER_SIZE - 1] as usize;
+        if count > JOURNAL_TRANSITIONS
+            || data.len() != Self::HEADER_SIZE + count * FaultTransition::SIZE
+        {
+            return Err(InvalidField);
+        }
+
+        let mut history = Self::new(u16::read(data)?, Field::read(&data[2..])?);
+        for i in 0..count {
+            let offset = Self::HEADER_SIZE + i * FaultTransition::SIZE;
+            history.push(FaultTransition::read(&data[offset..])?);
+        }
+        Ok(history)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_with_wrong_transition_count_should_return_error() {
+        let mut history = FaultHistory::new(3, [FaultStats::default(); FaultType::CARDINALITY]);
+        history.push(FaultHistory::EMPTY_TRANSITION);
+        let mut buffer = [0; FaultHistory::MAX_SIZE];
+        let length = history.serialize(&mut buffer);
+        assert_eq!(FaultHistory::deserialize(&buffer[..length]), Ok(history));
+
+        buffer[FaultHistory::HEADER_SIZE - 1] = 2;
+        assert_eq!(
+            FaultHistory::deserialize(&buffer[..length]),
+            Err(InvalidField)
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use crate::packet::{Field, InvalidField, Payload};
+
+/// Metadata header of the image in a flash slot
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Payload)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ImageMetadata {
+    /// False if the slot holds no image with a header, the other fields are zero then
+    pub present: bool,
+    pub version: [u8; 3],
+    pub board_revision: u8,
+    /// All zeros if the image was not built from a git checkout
+    pub git_hash: [u8; 20],
+    /// Zero if the image was not stamped by the signing tool
+    pub length: u32,
+    /// SHA-256 of the image, see the firmware-image crate
+    pub digest: [u8; 32],
+}
+
+/// How the running image came to run
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BootOutcome {
+    /// Sent by a newer firmware, this build does not know the outcome
+    #[default]
+    Unknown,
+    /// The image is confirmed, the bootloader keeps it
+    Confirmed,
+    /// A new image runs on trial, the bootloader brings the previous one back unless it is
+    /// confirmed in time
+    Trial,
+    /// The trial of an update failed, the bootloader brought this image back
+    RolledBack,
+}
+
+impl BootOutcome {
+    pub fn to_u8(self) -> u8 {
+        match self {
+            BootOutcome::Unknown => 0x00,
+            BootOutcome::Confirmed => 0x01,
+            BootOutcome::Trial => 0x02,
+            BootOutcome::RolledBack => 0x03,
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            0x01 => BootOutcome::Confirmed,
+            0x02 => BootOutcome::Trial,
+            0x03 => BootOutcome::RolledBack,
+            _ => BootOutcome::Unknown,
+        }
+    }
+}
+
+impl Field for BootOutcome {
+    const SIZE: usize = 1;
+
+    fn write(&self, buffer: &mut [u8]) {
+        buffer[0] = self.to_u8();
+    }
+
+    fn read(data: &[u8]) -> Result<Self, InvalidField> {
+        data.first().copied().map(Self::from_u8).ok_or(InvalidField)
+    }
+}
+
+/// Fields added by newer firmware are appended, so a longer payload is fine
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Payload)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[payload(extensible)]
+pub struct FirmwareSlots {
+    /// The running image
+    pub active: ImageMetadata,
+    /// The image in the DFU partition, the previous firmware after an update was swapped in
+    pub pending: ImageMetadata,
+    /// The bootloader swaps the pending image in on the next reset
+    pub swap_scheduled: bool,
+    pub boot: BootOutcome,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn firmware_slots_should_serialize_and_deserialize() {
+        let slots = FirmwareSlots {
+            active: ImageMetadata {
+                present: true,
+                version: [1, 2, 3],
+                board_revision: 4,
+                git_hash: [5; 20],
+                length: 0x0607_0809,
+                digest: [10; 32],
+            },
+            pending: ImageMetadata::default(),
+            swap_scheduled: true,
+            boot: BootOutcome::RolledBack,
+        };
+        let mut buffer = [0; 256];
+        let length = slots.serialize(&mut buffer);
+        assert_eq!(length, 124);
+        assert_eq!(FirmwareSlots::deserialize(&buffer[..length]), Ok(slots));
+        assert!(FirmwareSlots::deserialize(&buffer[..length + 2]).is_ok());
+    }
+}
@@ -0,0 +1,224 @@
+pub const HEADER_SIZE: usize = 3;
+/// START_BYTE, one length byte and a CRC-16
+pub const SHORT_FRAME_OVERHEAD: usize = 4;
+/// LONG_START_BYTE, two length bytes and a CRC-32
+pub const LONG_FRAME_OVERHEAD: usize = 7;
+
+/// Payloads up to this size are sent in short frames, which every peer understands
+pub const MAX_SHORT_PAYLOAD_SIZE: usize = u8::MAX as usize;
+
+const KIND_REQUEST: u8 = 0x00;
+const KIND_RESPONSE: u8 = 0x01;
+const KIND_UNSOLICITED: u8 = 0x02;
+const KIND_MASK: u8 = 0x7F;
+const FLAG_RELIABLE: u8 = 0x80;
+
+/// Size of the frame the encoder produces for a payload of the given size
+pub const fn frame_size(payload_size: usize) -> usize {
+    if payload_size <= MAX_SHORT_PAYLOAD_SIZE {
+        payload_size + HEADER_SIZE + SHORT_FRAME_OVERHEAD
+    } else {
+        payload_size + HEADER_SIZE + LONG_FRAME_OVERHEAD
+    }
+}
+
+/// Largest payload that fits in a frame of at most `max_frame_size` bytes
+pub fn max_payload_size(max_frame_size: usize) -> usize {
+    let payload = max_frame_size.saturating_sub(HEADER_SIZE + SHORT_FRAME_OVERHEAD);
+    if payload <= MAX_SHORT_PAYLOAD_SIZE {
+        return payload;
+    }
+    // Does not fit in a short frame, a long frame has more overhead
+    (max_frame_size - HEADER_SIZE - LONG_FRAME_OVERHEAD).max(MAX_SHORT_PAYLOAD_SIZE)
+}
+
+/// A decoded packet together with its frame header
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Frame<T> {
+    pub header: Header,
+    pub packet: T,
+}
+
+/// Sits between the length byte and the payload: `[sequence, kind | flags, reference]`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Header {
+    /// Per-link counter of the sender, a gap means frames were lost on the way
+    pub sequence: u8,
+    pub kind: FrameKind,
+    /// Only valid for requests, the sender retransmits the request until it gets a response
+    pub reliable: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FrameKind {
+    /// Sent by the host, answered with exactly one response
+    Request,
+    /// Answer to the request with the given sequence number
+    Response(u8),
+    /// Sent by the device on its own, e.g. telemetry
+    Unsolicited,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct InvalidHeader;
+
+impl Header {
+    pub fn request(sequence: u8) -> Self {
+        Self {
+            sequence,
+            kind: FrameKind::Request,
+            reliable: false,
+        }
+    }
+
+    pub fn reliable_request(sequence: u8) -> Self {
+        Self {
+            sequence,
+            kind: FrameKind::Request,
+            reliable: true,
+        }
+    }
+
+    pub fn response(sequence: u8, request: u8) -> Self {
+        Self {
+            sequence,
+            kind: FrameKind::Response(request),
+            reliable: false,
+        }
+    }
+
+    pub fn unsolicited(sequence: u8) -> Self {
+        Self {
+            sequence,
+            kind: FrameKind::Unsolicited,
+            reliable: false,
+        }
+    }
+
+    pub fn serialize(&self, buffer: &mut [u8]) -> usize {
+        let (kind, reference) = match self.kind {
+            FrameKind::Request => (KIND_REQUEST, 0),
+            FrameKind::Response(request) => (KIND_RESPONSE, request),
+            FrameKind::Unsolicited => (KIND_UNSOLICITED, 0),
+        };
+        let flags = if self.reliable { FLAG_RELIABLE } else { 0 };
+        buffer[0] = self.sequence;
+        buffer[1] = kind | flags;
+        buffer[2] = reference;
+        HEADER_SIZE
+    }
+
+    pub fn deserialize(data: &[u8]) -> Result<Self, InvalidHeader> {
+        let &[sequence, kind, reference] = data else {
+            return Err(InvalidHeader);
+        };
+        let reliable = kind & FLAG_RELIABLE != 0;
+        let kind = match (kind & KIND_MASK, reference) {
+            (KIND_REQUEST, 0) => FrameKind::Request,
+            (KIND_RESPONSE, request) => FrameKind::Response(request),
+            (KIND_UNSOLICITED, 0) => FrameKind::Unsolicited,
+            _ => return Err(InvalidHeader),
+        };
+        if reliable && kind != FrameKind::Request {
+            return Err(InvalidHeader);
+        }
+        Ok(Self {
+            sequence,
+            kind,
+            reliable,
+        })
+    }
+}
+
+/// Hands out sequence numbers for outgoing frames, wraps around after 255
+#[derive(Debug, Default)]
+pub struct SequenceCounter {
+    next: u8,
+}
+
+impl SequenceCounter {
+    pub fn new() -> Self {
+        Self { next: 0 }
+    }
+
+    pub fn advance(&mut self) -> u8 {
+        let sequence = self.next;
+        self.next = self.next.wrapping_add(1);
+        sequence
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_should_survive_round_trip() {
+        let headers = [
+            Header::request(7),
+            Header::reliable_request(8),
+            Header::response(0xFF, 7),
+            Header::unsolicited(0),
+        ];
+        for header in headers {
+            let mut buffer = [0; HEADER_SIZE];
+            assert_eq!(header.serialize(&mut buffer), HEADER_SIZE);
+            assert_eq!(Header::deserialize(&buffer), Ok(header));
+        }
+    }
+
+    #[test]
+    fn unknown_kind_should_return_error() {
+        assert_eq!(Header::deserialize(&[0, 0x03, 0]), Err(InvalidHeader));
+    }
+
+    #[test]
+    fn reference_outside_of_response_should_return_error() {
+        assert_eq!(
+            Header::deserialize(&[0, KIND_REQUEST, 1]),
+            Err(InvalidHeader)
+        );
+        assert_eq!(
+            Header::deserialize(&[0, KIND_UNSOLICITED, 1]),
+            Err(InvalidHeader)
+        );
+    }
+
+    #[test]
+    fn reliable_flag_outside_of_request_should_return_error() {
+        assert_eq!(
+            Header::deserialize(&[0, KIND_RESPONSE | FLAG_RELIABLE, 1]),
+            Err(InvalidHeader)
+        );
+    }
+
+    #[test]
+    fn truncated_header_should_return_error() {
+        assert_eq!(Header::deserialize(&[0, KIND_REQUEST]), Err(InvalidHeader));
+    }
+
+    #[test]
+    fn max_payload_size_should_match_frame_size() {
+        for payload_size in [1, MAX_SHORT_PAYLOAD_SIZE, MAX_SHORT_PAYLOAD_SIZE + 1, 1000] {
+            assert_eq!(max_payload_size(frame_size(payload_size)), payload_size);
+        }
+        assert_eq!(
+            max_payload_size(frame_size(MAX_SHORT_PAYLOAD_SIZE) + 1),
+            MAX_SHORT_PAYLOAD_SIZE
+        );
+        assert_eq!(max_payload_size(0), 0);
+    }
+
+    #[test]
+    fn sequence_counter_should_wrap_around() {
+        let mut counter = SequenceCounter::new();
+        for expected in 0..=u8::MAX {
+            assert_eq!(counter.advance(), expected);
+        }
+        assert_eq!(counter.advance(), 0);
+    }
+}
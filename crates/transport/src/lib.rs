@@ -1,12 +1,34 @@
 #![no_std]
-pub const MAX_PACKET_SIZE: usize = (u8::MAX as usize) + 4;
+// Lets the derive macros refer to `::transport` from inside this crate as well
+extern crate self as transport;
 
+/// Largest payload of a long frame
+pub const MAX_PAYLOAD_SIZE: usize = 1040;
+pub const MAX_PACKET_SIZE: usize = frame::frame_size(MAX_PAYLOAD_SIZE);
+/// Largest frame peers without long frame support accept
+pub const MAX_SHORT_PACKET_SIZE: usize = frame::frame_size(frame::MAX_SHORT_PAYLOAD_SIZE);
+
+/// Bumped on every change that older peers cannot ignore.
+/// Packets may grow new trailing fields without a bump, decoders skip bytes they do not know.
+pub const PROTOCOL_VERSION: u8 = 2;
+
+pub mod black_box;
+pub mod blob;
+pub mod capabilities;
+pub mod cobs;
 pub mod command;
 pub mod decoder;
 pub mod encoder;
 pub mod event;
-pub(crate) mod helpers;
-mod packet;
+pub mod fault_history;
+pub mod firmware;
+pub mod frame;
+pub mod log;
+pub mod motion;
+pub mod packet;
+pub mod reliable;
+pub mod scope;
+pub mod telemetry;
 
 pub use command::Command;
 pub use event::Event;
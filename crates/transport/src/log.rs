@@ -0,0 +1,202 @@
+use crate::packet::{Field, InvalidField, Payload};
+
+/// Text of a record that still fits into a short frame
+pub const LOG_TEXT_MAX_SIZE: usize = 240;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LogLevel {
+    /// Sent by a newer firmware, this build does not know the level
+    Unknown,
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+    /// Only valid as the minimum level, stops the forwarding
+    Off,
+}
+
+/// A log message of the firmware, the format string is sent as written and the arguments are
+/// rendered with their `Debug` implementation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LogRecord {
+    pub level: LogLevel,
+    /// Microseconds since boot
+    pub timestamp: u64,
+    length: u16,
+    /// The message and the arguments, separated by a zero byte
+    text: [u8; LOG_TEXT_MAX_SIZE],
+}
+
+impl LogLevel {
+    pub fn to_u8(self) -> u8 {
+        match self {
+            LogLevel::Unknown => 0x00,
+            LogLevel::Trace => 0x01,
+            LogLevel::Debug => 0x02,
+            LogLevel::Info => 0x03,
+            LogLevel::Warn => 0x04,
+            LogLevel::Error => 0x05,
+            LogLevel::Off => 0x06,
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            0x01 => LogLevel::Trace,
+            0x02 => LogLevel::Debug,
+            0x03 => LogLevel::Info,
+            0x04 => LogLevel::Warn,
+            0x05 => LogLevel::Error,
+            0x06 => LogLevel::Off,
+            _ => LogLevel::Unknown,
+        }
+    }
+}
+
+impl Field for LogLevel {
+    const SIZE: usize = 1;
+
+    fn write(&self, buffer: &mut [u8]) {
+        buffer[0] = self.to_u8();
+    }
+
+    fn read(data: &[u8]) -> Result<Self, InvalidField> {
+        data.first().copied().map(Self::from_u8).ok_or(InvalidField)
+    }
+}
+
+impl Payload for LogLevel {
+    type Error = InvalidField;
+
+    fn serialize(&self, buffer: &mut [u8]) -> usize {
+        self.write(buffer);
+        Self::SIZE
+    }
+
+    // `Self::Error` would be the variant
+    fn deserialize(data: &[u8]) -> Result<Self, InvalidField> {
+        if data.len() != Self::SIZE {
+            return Err(InvalidField);
+        }
+        Self::read(data)
+    }
+}
+
+impl LogRecord {
+    const HEADER_SIZE: usize = 11;
+
+    /// A message longer than [`LOG_TEXT_MAX_SIZE`] is cut
+    pub fn new(level: LogLevel, timestamp: u64, message: &str) -> Self {
+        let mut record = Self {
+            level,
+            timestamp,
+            length: 0,
+            text: [0; LOG_TEXT_MAX_SIZE],
+        };
+        record.push_str(message);
+        record
+    }
+
+    /// Appends an argument, it is cut or dropped once the record is full
+    pub fn push_arg(&mut self, arg: &str) {
+        if (self.length as usize) < LOG_TEXT_MAX_SIZE {
+            self.text[self.length as usize] = 0;
+            self.length += 1;
+            self.push_str(arg);
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        self.fields().next().unwrap_or_default()
+    }
+
+    pub fn args(&self) -> impl Iterator<Item = &str> {
+        self.fields().skip(1)
+    }
+
+    fn fields(&self) -> impl Iterator<Item = &str> {
+        // Only whole characters are stored, so the text is always valid
+        let text = core::str::from_utf8(&self.text[..self.length as usize]).unwrap_or_default();
+        text.split('\0')
+    }
+
+    fn push_str(&mut self, value: &str) {
+        let start = self.length as usize;
+        let mut end = value.len().min(LOG_TEXT_MAX_SIZE - start);
+        while !value.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.text[start..start + end].copy_from_slice(&value.as_bytes()[..end]);
+        self.length += end as u16;
+    }
+}
+
+impl Payload for LogRecord {
+    type Error = InvalidField;
+
+    fn serialize(&self, buffer: &mut [u8]) -> usize {
+        let text = &self.text[..self.length as usize];
+        self.level.write(buffer);
+        self.timestamp.write(&mut buffer[1..]);
+        self.length.write(&mut buffer[9..]);
+        buffer[Self::HEADER_SIZE..Self::HEADER_SIZE + text.len()].copy_from_slice(text);
+        Self::HEADER_SIZE + text.len()
+    }
+
+    fn deserialize(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() < Self::HEADER_SIZE {
+            return Err(InvalidField);
+        }
+        let length = u16::read(&data[9..])?;
+        let content = &data[Self::HEADER_SIZE..];
+        if length as usize > LOG_TEXT_MAX_SIZE || content.len() != length as usize {
+            return Err(InvalidField);
+        }
+        core::str::from_utf8(content).map_err(|_| InvalidField)?;
+
+        let mut text = [0; LOG_TEXT_MAX_SIZE];
+        text[..content.len()].copy_from_slice(content);
+        Ok(Self {
+            level: LogLevel::read(data)?,
+            timestamp: u64::read(&data[1..])?,
+            length,
+            text,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arguments_should_be_kept_apart_from_the_message() {
+        let mut record = LogRecord::new(LogLevel::Warn, 10, "Request {} took {} ms");
+        record.push_arg("7");
+        record.push_arg("");
+        assert_eq!(record.message(), "Request {} took {} ms");
+        assert!(record.args().eq(["7", ""]));
+        let record = LogRecord::new(LogLevel::Info, 0, "Booting");
+        assert_eq!(record.args().count(), 0);
+    }
+
+    #[test]
+    fn long_argument_should_be_cut_at_a_char_boundary() {
+        let message = [b'a'; LOG_TEXT_MAX_SIZE - 3];
+        let mut record = LogRecord::new(LogLevel::Info, 0, core::str::from_utf8(&message).unwrap());
+        record.push_arg("ąę");
+        record.push_arg("dropped");
+        assert!(record.args().eq(["ą"]));
+    }
+
+    #[test]
+    fn text_that_is_not_utf8_should_return_error() {
+        let mut data = [0; LogRecord::HEADER_SIZE + 1];
+        data[9] = 1;
+        data[LogRecord::HEADER_SIZE] = 0xFF;
+        assert_eq!(LogRecord::deserialize(&data), Err(InvalidField));
+    }
+}
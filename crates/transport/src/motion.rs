@@ -0,0 +1,37 @@
+use crate::packet::Payload;
+
+/// Currents in amperes, the device runs its current loop on these
+#[derive(Debug, Clone, Copy, PartialEq, Payload)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CurrentSetpoint {
+    pub d: f32,
+    pub q: f32,
+}
+
+/// Shaft torque in newton metres, turned into a q axis current by the device
+#[derive(Debug, Clone, Copy, PartialEq, Payload)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TorqueSetpoint {
+    pub torque: f32,
+}
+
+/// Shaft speed in radians per second
+#[derive(Debug, Clone, Copy, PartialEq, Payload)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct VelocitySetpoint {
+    pub velocity: f32,
+}
+
+/// Shaft angle in radians
+#[derive(Debug, Clone, Copy, PartialEq, Payload)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PositionSetpoint {
+    pub position: f32,
+}
+
+/// Open loop q axis voltage, from -1 to 1 of the largest one the bus allows
+#[derive(Debug, Clone, Copy, PartialEq, Payload)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DutyCycleSetpoint {
+    pub duty_cycle: f32,
+}
@@ -1,7 +1,161 @@
 use core::fmt::Debug;
+use enum_iterator::all;
+use logging::fault_register::{FaultState, FaultType};
+
+pub use transport_derive::{Packet, Payload};
 
 pub trait Packet: Sized + Debug {
     type Error;
     fn deserialize(data: &[u8]) -> Result<Self, Self::Error>;
     fn serialize(&self, buffer: &mut [u8]) -> usize;
 }
+
+/// Content of a packet variant, usually implemented with `#[derive(Payload)]`
+pub trait Payload: Sized {
+    type Error;
+    fn serialize(&self, buffer: &mut [u8]) -> usize;
+    fn deserialize(data: &[u8]) -> Result<Self, Self::Error>;
+}
+
+/// Value with a fixed size little-endian layout
+pub trait Field: Sized {
+    const SIZE: usize;
+    /// `buffer` has to hold at least `SIZE` bytes
+    fn write(&self, buffer: &mut [u8]);
+    /// Reads the first `SIZE` bytes of `data`
+    fn read(data: &[u8]) -> Result<Self, InvalidField>;
+}
+
+#[derive(Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct InvalidField;
+
+macro_rules! impl_field_for_number {
+    ($($number:ty),*) => {
+        $(
+            impl Field for $number {
+                const SIZE: usize = size_of::<$number>();
+
+                fn write(&self, buffer: &mut [u8]) {
+                    buffer[..Self::SIZE].copy_from_slice(&self.to_le_bytes());
+                }
+
+                fn read(data: &[u8]) -> Result<Self, InvalidField> {
+                    data.get(..Self::SIZE)
+                        .and_then(|bytes| bytes.try_into().ok())
+                        .map(<$number>::from_le_bytes)
+                        .ok_or(InvalidField)
+                }
+            }
+        )*
+    };
+}
+
+impl_field_for_number!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+impl<T: Field, const N: usize> Field for [T; N] {
+    const SIZE: usize = T::SIZE * N;
+
+    fn write(&self, buffer: &mut [u8]) {
+        for (i, item) in self.iter().enumerate() {
+            item.write(&mut buffer[i * T::SIZE..]);
+        }
+    }
+
+    fn read(data: &[u8]) -> Result<Self, InvalidField> {
+        if data.len() < Self::SIZE {
+            return Err(InvalidField);
+        }
+        let items: [Result<T, InvalidField>; N] =
+            core::array::from_fn(|i| T::read(&data[i * T::SIZE..]));
+        if items.iter().any(Result::is_err) {
+            return Err(InvalidField);
+        }
+        Ok(items.map(|item| item.unwrap_or_else(|_| unreachable!())))
+    }
+}
+
+impl Field for bool {
+    const SIZE: usize = 1;
+
+    fn write(&self, buffer: &mut [u8]) {
+        buffer[0] = *self as u8;
+    }
+
+    fn read(data: &[u8]) -> Result<Self, InvalidField> {
+        match data.first() {
+            Some(0) => Ok(false),
+            Some(1) => Ok(true),
+            _ => Err(InvalidField),
+        }
+    }
+}
+
+impl Field for FaultState {
+    const SIZE: usize = 1;
+
+    fn write(&self, buffer: &mut [u8]) {
+        buffer[0] = *self as u8;
+    }
+
+    fn read(data: &[u8]) -> Result<Self, InvalidField> {
+        match data.first() {
+            Some(0) => Ok(FaultState::Clean),
+            Some(1) => Ok(FaultState::Active),
+            Some(2) => Ok(FaultState::Latched),
+            _ => Err(InvalidField),
+        }
+    }
+}
+
+impl Field for FaultType {
+    const SIZE: usize = 1;
+
+    fn write(&self, buffer: &mut [u8]) {
+        buffer[0] = *self as u8;
+    }
+
+    fn read(data: &[u8]) -> Result<Self, InvalidField> {
+        let index = *data.first().ok_or(InvalidField)?;
+        all::<FaultType>().nth(index as usize).ok_or(InvalidField)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Payload)]
+    struct Fixed {
+        byte: u8,
+        word: u32,
+        array: [u16; 2],
+    }
+
+    #[derive(Debug, PartialEq, Payload)]
+    #[payload(extensible)]
+    struct Extensible(Fixed, i8);
+
+    #[test]
+    fn fields_should_be_laid_out_in_order() {
+        let fixed = Fixed {
+            byte: 0x01,
+            word: 0x0504_0302,
+            array: [0x0706, 0x0908],
+        };
+        let mut buffer = [0; 16];
+        assert_eq!(fixed.serialize(&mut buffer), 9);
+        assert_eq!(&buffer[..9], &[1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        assert_eq!(Fixed::deserialize(&buffer[..9]), Ok(fixed));
+    }
+
+    #[test]
+    fn length_should_be_checked() {
+        let buffer = [0; 11];
+        assert_eq!(Fixed::deserialize(&buffer[..8]), Err(InvalidField));
+        assert_eq!(Fixed::deserialize(&buffer[..10]), Err(InvalidField));
+        assert_eq!(Extensible::deserialize(&buffer[..9]), Err(InvalidField));
+        assert!(Extensible::deserialize(&buffer[..10]).is_ok());
+        assert!(Extensible::deserialize(&buffer[..11]).is_ok());
+    }
+}
@@ -0,0 +1,274 @@
+use crate::frame::{Frame, Header};
+use crate::{MAX_PACKET_SIZE, cobs};
+
+/// Largest encoded response of either framing
+const MAX_RESPONSE_SIZE: usize = if cobs::MAX_FRAME_SIZE > MAX_PACKET_SIZE {
+    cobs::MAX_FRAME_SIZE
+} else {
+    MAX_PACKET_SIZE
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RetransmitConfig {
+    pub timeout_ms: u64,
+    /// Transmissions including the first one before the request is given up
+    pub max_attempts: u8,
+}
+
+impl Default for RetransmitConfig {
+    fn default() -> Self {
+        Self {
+            timeout_ms: 200,
+            max_attempts: 5,
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct QueueFull;
+
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Retransmission<T> {
+    /// The request was not answered in time and has to be sent again
+    Resend(Frame<T>),
+    /// The request with this sequence number was not answered after all attempts
+    GaveUp(u8),
+}
+
+#[derive(Debug)]
+struct Outstanding<T> {
+    sequence: u8,
+    packet: T,
+    deadline: u64,
+    attempts: u8,
+}
+
+/// Reliable requests that are not answered yet, the response doubles as the acknowledgement.
+///
+/// Time is passed in as milliseconds of any monotonic clock, so it works with embassy and tokio.
+#[derive(Debug)]
+pub struct RetransmitQueue<T, const N: usize> {
+    config: RetransmitConfig,
+    entries: [Option<Outstanding<T>>; N],
+}
+
+impl<T: Clone, const N: usize> RetransmitQueue<T, N> {
+    pub fn new(config: RetransmitConfig) -> Self {
+        Self {
+            config,
+            entries: [const { None }; N],
+        }
+    }
+
+    /// Tracks a request that has just been sent for the first time
+    pub fn push(&mut self, sequence: u8, packet: T, now_ms: u64) -> Result<(), QueueFull> {
+        let slot = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.is_none())
+            .ok_or(QueueFull)?;
+        *slot = Some(Outstanding {
+            sequence,
+            packet,
+            deadline: now_ms + self.config.timeout_ms,
+            attempts: 1,
+        });
+        Ok(())
+    }
+
+    /// Stops retransmitting the request, returns `false` if it was not outstanding
+    pub fn acknowledge(&mut self, sequence: u8) -> bool {
+        match self.entries.iter_mut().find(|entry| {
+            entry
+                .as_ref()
+                .is_some_and(|outstanding| outstanding.sequence == sequence)
+        }) {
+            Some(entry) => {
+                *entry = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the next request that is due, call repeatedly until it returns `None`
+    pub fn poll(&mut self, now_ms: u64) -> Option<Retransmission<T>> {
+        let entry = self.entries.iter_mut().find(|entry| {
+            entry
+                .as_ref()
+                .is_some_and(|outstanding| outstanding.deadline <= now_ms)
+        })?;
+        let outstanding = entry.as_mut()?;
+        if outstanding.attempts >= self.config.max_attempts {
+            let sequence = outstanding.sequence;
+            *entry = None;
+            return Some(Retransmission::GaveUp(sequence));
+        }
+
+        outstanding.attempts += 1;
+        outstanding.deadline = now_ms + self.config.timeout_ms;
+        Some(Retransmission::Resend(Frame {
+            header: Header::reliable_request(outstanding.sequence),
+            packet: outstanding.packet.clone(),
+        }))
+    }
+
+    /// The earliest moment at which [`RetransmitQueue::poll`] has something to do
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.entries
+            .iter()
+            .flatten()
+            .map(|outstanding| outstanding.deadline)
+            .min()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.iter().all(Option::is_none)
+    }
+}
+
+/// The last answered reliable request of a link together with its encoded response,
+/// so a retransmitted request is answered again without being executed twice
+#[derive(Debug)]
+pub struct ResponseCache<T> {
+    request: Option<Frame<T>>,
+    response: [u8; MAX_RESPONSE_SIZE],
+    length: usize,
+}
+
+impl<T> Default for ResponseCache<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> ResponseCache<T> {
+    pub fn new() -> Self {
+        Self {
+            request: None,
+            response: [0; MAX_RESPONSE_SIZE],
+            length: 0,
+        }
+    }
+
+    pub fn store(&mut self, request: Frame<T>, response: &[u8]) {
+        self.response[..response.len()].copy_from_slice(response);
+        self.length = response.len();
+        self.request = Some(request);
+    }
+}
+
+impl<T: PartialEq> ResponseCache<T> {
+    /// Returns the encoded response if the request is a retransmission of the cached one
+    pub fn replay(&self, request: &Frame<T>) -> Option<&[u8]> {
+        self.request
+            .as_ref()
+            .filter(|cached| {
+                cached.header.sequence == request.header.sequence && cached.packet == request.packet
+            })
+            .map(|_| &self.response[..self.length])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Command;
+
+    const CONFIG: RetransmitConfig = RetransmitConfig {
+        timeout_ms: 100,
+        max_attempts: 3,
+    };
+
+    #[test]
+    fn unanswered_request_should_be_resent_after_timeout() {
+        let mut queue = RetransmitQueue::<Command, 4>::new(CONFIG);
+        queue.push(7, Command::Stop, 0).unwrap();
+
+        assert_eq!(queue.poll(99), None);
+        assert_eq!(
+            queue.poll(100),
+            Some(Retransmission::Resend(Frame {
+                header: Header::reliable_request(7),
+                packet: Command::Stop,
+            }))
+        );
+        assert_eq!(queue.poll(150), None);
+        assert_eq!(queue.next_deadline(), Some(200));
+    }
+
+    #[test]
+    fn request_should_be_given_up_after_max_attempts() {
+        let mut queue = RetransmitQueue::<Command, 4>::new(CONFIG);
+        queue.push(7, Command::Stop, 0).unwrap();
+
+        assert!(matches!(queue.poll(100), Some(Retransmission::Resend(_))));
+        assert!(matches!(queue.poll(200), Some(Retransmission::Resend(_))));
+        assert_eq!(queue.poll(300), Some(Retransmission::GaveUp(7)));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn acknowledged_request_should_not_be_resent() {
+        let mut queue = RetransmitQueue::<Command, 4>::new(CONFIG);
+        queue.push(7, Command::Stop, 0).unwrap();
+
+        assert!(queue.acknowledge(7));
+        assert!(!queue.acknowledge(7));
+        assert_eq!(queue.poll(1000), None);
+        assert_eq!(queue.next_deadline(), None);
+    }
+
+    #[test]
+    fn full_queue_should_reject_request() {
+        let mut queue = RetransmitQueue::<Command, 1>::new(CONFIG);
+        queue.push(1, Command::Stop, 0).unwrap();
+        assert_eq!(queue.push(2, Command::Stop, 0), Err(QueueFull));
+    }
+
+    #[test]
+    fn cache_should_hold_the_largest_cobs_frame() {
+        let mut cache = ResponseCache::new();
+        let request = Frame {
+            header: Header::reliable_request(7),
+            packet: Command::Stop,
+        };
+        let response = [0x55; cobs::MAX_FRAME_SIZE];
+        cache.store(request.clone(), &response);
+        assert_eq!(cache.replay(&request), Some(response.as_slice()));
+    }
+
+    #[test]
+    fn cache_should_replay_only_identical_request() {
+        let mut cache = ResponseCache::new();
+        let request = Frame {
+            header: Header::reliable_request(7),
+            packet: Command::Stop,
+        };
+        assert_eq!(cache.replay(&request), None);
+
+        cache.store(
+            Frame {
+                header: Header::reliable_request(7),
+                packet: Command::Stop,
+            },
+            &[1, 2, 3],
+        );
+        assert_eq!(cache.replay(&request), Some([1, 2, 3].as_slice()));
+
+        let next_request = Frame {
+            header: Header::reliable_request(8),
+            packet: Command::Stop,
+        };
+        assert_eq!(cache.replay(&next_request), None);
+
+        let other_command = Frame {
+            header: Header::reliable_request(7),
+            packet: Command::ResetFaults,
+        };
+        assert_eq!(cache.replay(&other_command), None);
+    }
+}
@@ -0,0 +1,238 @@
+use crate::blob::BlobId;
+use crate::packet::{Field, InvalidField, Payload};
+
+/// Serves the last complete capture as `f32` samples, oldest first and with the channels of a
+/// control step next to each other
+pub const SCOPE_BLOB_ID: BlobId = BlobId(0x01);
+pub const MAX_SCOPE_CHANNELS: usize = 4;
+
+/// Values the control loop can record, in SI units
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ScopeSignal {
+    PhaseCurrentU,
+    PhaseCurrentV,
+    PhaseCurrentW,
+    CurrentD,
+    CurrentQ,
+    VoltageD,
+    VoltageQ,
+    ElectricalAngle,
+    Velocity,
+    BusVoltage,
+}
+
+/// Signals of the recorded channels, used channels come first
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ScopeChannels(pub [Option<ScopeSignal>; MAX_SCOPE_CHANNELS]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TriggerMode {
+    /// Only TriggerScope starts the capture
+    Manual,
+    RisingEdge,
+    FallingEdge,
+    /// Triggers as soon as the channel is at or above the level
+    AboveLevel,
+    BelowLevel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Payload)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ScopeTrigger {
+    pub mode: TriggerMode,
+    /// Index into the channels of the capture
+    pub channel: u8,
+    pub level: f32,
+}
+
+/// Arms the scope, a capture that was not read yet is dropped
+#[derive(Debug, Clone, Copy, PartialEq, Payload)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ScopeConfig {
+    pub channels: ScopeChannels,
+    pub trigger: ScopeTrigger,
+    /// Samples per channel
+    pub length: u16,
+    /// Samples per channel recorded before the trigger
+    pub pre_trigger: u16,
+    /// Records every n-th control step
+    pub decimation: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ScopeState {
+    /// Sent by a newer firmware, this build does not know the state
+    Unknown,
+    Idle,
+    /// Recording and waiting for the trigger
+    Armed,
+    /// Recording the samples after the trigger
+    Triggered,
+    /// The capture is complete and can be read from [`SCOPE_BLOB_ID`]
+    Done,
+}
+
+/// Fields added by newer firmware are appended, so a longer payload is fine
+#[derive(Debug, Clone, Copy, PartialEq, Payload)]
+#[payload(extensible)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ScopeStatus {
+    pub state: ScopeState,
+    pub channels: ScopeChannels,
+    pub length: u16,
+    pub pre_trigger: u16,
+    /// Samples per second and channel
+    pub sample_rate: f32,
+}
+
+impl ScopeSignal {
+    pub fn to_u8(self) -> u8 {
+        match self {
+            ScopeSignal::PhaseCurrentU => 0x01,
+            ScopeSignal::PhaseCurrentV => 0x02,
+            ScopeSignal::PhaseCurrentW => 0x03,
+            ScopeSignal::CurrentD => 0x04,
+            ScopeSignal::CurrentQ => 0x05,
+            ScopeSignal::VoltageD => 0x06,
+            ScopeSignal::VoltageQ => 0x07,
+            ScopeSignal::ElectricalAngle => 0x08,
+            ScopeSignal::Velocity => 0x09,
+            ScopeSignal::BusVoltage => 0x0A,
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0x01 => Some(ScopeSignal::PhaseCurrentU),
+            0x02 => Some(ScopeSignal::PhaseCurrentV),
+            0x03 => Some(ScopeSignal::PhaseCurrentW),
+            0x04 => Some(ScopeSignal::CurrentD),
+            0x05 => Some(ScopeSignal::CurrentQ),
+            0x06 => Some(ScopeSignal::VoltageD),
+            0x07 => Some(ScopeSignal::VoltageQ),
+            0x08 => Some(ScopeSignal::ElectricalAngle),
+            0x09 => Some(ScopeSignal::Velocity),
+            0x0A => Some(ScopeSignal::BusVoltage),
+            _ => None,
+        }
+    }
+}
+
+impl ScopeChannels {
+    pub fn iter(&self) -> impl Iterator<Item = ScopeSignal> + '_ {
+        self.0.iter().map_while(|signal| *signal)
+    }
+}
+
+impl ScopeState {
+    pub fn to_u8(self) -> u8 {
+        match self {
+            ScopeState::Unknown => 0x00,
+            ScopeState::Idle => 0x01,
+            ScopeState::Armed => 0x02,
+            ScopeState::Triggered => 0x03,
+            ScopeState::Done => 0x04,
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            0x01 => ScopeState::Idle,
+            0x02 => ScopeState::Armed,
+            0x03 => ScopeState::Triggered,
+            0x04 => ScopeState::Done,
+            _ => ScopeState::Unknown,
+        }
+    }
+}
+
+/// A byte per channel, 0 for an unused one
+impl Field for ScopeChannels {
+    const SIZE: usize = MAX_SCOPE_CHANNELS;
+
+    fn write(&self, buffer: &mut [u8]) {
+        for (byte, signal) in buffer.iter_mut().zip(self.0) {
+            *byte = signal.map_or(0, ScopeSignal::to_u8);
+        }
+    }
+
+    fn read(data: &[u8]) -> Result<Self, InvalidField> {
+        let codes = <[u8; MAX_SCOPE_CHANNELS]>::read(data)?;
+        let mut channels = [None; MAX_SCOPE_CHANNELS];
+        for (channel, code) in channels.iter_mut().zip(codes) {
+            if code != 0 {
+                *channel = Some(ScopeSignal::from_u8(code).ok_or(InvalidField)?);
+            }
+        }
+        Ok(Self(channels))
+    }
+}
+
+impl Field for TriggerMode {
+    const SIZE: usize = 1;
+
+    fn write(&self, buffer: &mut [u8]) {
+        buffer[0] = match self {
+            TriggerMode::Manual => 0,
+            TriggerMode::RisingEdge => 1,
+            TriggerMode::FallingEdge => 2,
+            TriggerMode::AboveLevel => 3,
+            TriggerMode::BelowLevel => 4,
+        };
+    }
+
+    fn read(data: &[u8]) -> Result<Self, InvalidField> {
+        match data.first() {
+            Some(0) => Ok(TriggerMode::Manual),
+            Some(1) => Ok(TriggerMode::RisingEdge),
+            Some(2) => Ok(TriggerMode::FallingEdge),
+            Some(3) => Ok(TriggerMode::AboveLevel),
+            Some(4) => Ok(TriggerMode::BelowLevel),
+            _ => Err(InvalidField),
+        }
+    }
+}
+
+impl Field for ScopeState {
+    const SIZE: usize = 1;
+
+    fn write(&self, buffer: &mut [u8]) {
+        buffer[0] = self.to_u8();
+    }
+
+    fn read(data: &[u8]) -> Result<Self, InvalidField> {
+        data.first().copied().map(Self::from_u8).ok_or(InvalidField)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channels_should_skip_unused_slots() {
+        let channels = ScopeChannels([
+            Some(ScopeSignal::CurrentQ),
+            Some(ScopeSignal::BusVoltage),
+            None,
+            None,
+        ]);
+        let mut buffer = [0xFF; 4];
+        channels.write(&mut buffer);
+        assert_eq!(buffer, [0x05, 0x0A, 0x00, 0x00]);
+        assert_eq!(ScopeChannels::read(&buffer), Ok(channels));
+        let mut signals = channels.iter();
+        assert_eq!(signals.next(), Some(ScopeSignal::CurrentQ));
+        assert_eq!(signals.next(), Some(ScopeSignal::BusVoltage));
+        assert_eq!(signals.next(), None);
+    }
+
+    #[test]
+    fn unknown_signal_should_return_error() {
+        assert_eq!(ScopeChannels::read(&[0x05, 0x7F, 0, 0]), Err(InvalidField));
+    }
+}
@@ -0,0 +1,254 @@
+use crate::packet::{Field, InvalidField, Payload};
+use enum_iterator::{Sequence, all};
+
+/// Largest rate a link can stream telemetry samples at
+pub const MAX_TELEMETRY_RATE_HZ: u16 = 1000;
+
+/// Tag and length in front of every value of a [`TelemetrySample`]
+const VALUE_HEADER_SIZE: usize = 2;
+
+macro_rules! telemetry_registry {
+    ($($(#[$doc:meta])* $name:ident = $tag:literal: $ty:ty,)*) => {
+        /// Values a [`TelemetrySample`] can carry, the tag identifies them on the wire
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Sequence)]
+        #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+        pub enum TelemetryField {
+            $($(#[$doc])* $name,)*
+        }
+
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        pub enum TelemetryValue {
+            $($(#[$doc])* $name($ty),)*
+        }
+
+        impl TelemetryField {
+            pub fn tag(self) -> u8 {
+                match self {
+                    $(TelemetryField::$name => $tag,)*
+                }
+            }
+
+            pub fn from_tag(tag: u8) -> Option<Self> {
+                match tag {
+                    $($tag => Some(TelemetryField::$name),)*
+                    _ => None,
+                }
+            }
+
+            fn size(self) -> usize {
+                match self {
+                    $(TelemetryField::$name => <$ty as Field>::SIZE,)*
+                }
+            }
+        }
+
+        impl TelemetryValue {
+            pub fn field(&self) -> TelemetryField {
+                match self {
+                    $(TelemetryValue::$name(_) => TelemetryField::$name,)*
+                }
+            }
+
+            fn write(&self, buffer: &mut [u8]) {
+                match self {
+                    $(TelemetryValue::$name(value) => value.write(buffer),)*
+                }
+            }
+
+            fn read(field: TelemetryField, data: &[u8]) -> Result<Self, InvalidField> {
+                match field {
+                    $(TelemetryField::$name => <$ty>::read(data).map(TelemetryValue::$name),)*
+                }
+            }
+        }
+    };
+}
+
+telemetry_registry! {
+    /// u, v and w in amperes
+    PhaseCurrents = 0x01: [f32; 3],
+    /// d and q axis currents in amperes
+    DqCurrents = 0x02: [f32; 2],
+    /// d and q axis voltages put on the motor, in volts
+    DqVoltages = 0x03: [f32; 2],
+    /// Electrical rotor angle in radians
+    ElectricalAngle = 0x04: f32,
+    /// Shaft speed in radians per second
+    Velocity = 0x05: f32,
+    /// DC bus voltage in volts
+    BusVoltage = 0x06: f32,
+    /// CPU, driver and motor temperatures in kelvins
+    Temperatures = 0x07: [f32; 3],
+    /// Control loop frequency in hertz and the duration of its last step in microseconds
+    LoopTiming = 0x08: [u32; 2],
+    /// A bit per fault type for active and for latched faults
+    Faults = 0x09: [u32; 2],
+}
+
+/// Set of [`TelemetryField`]s, bit `n` selects the field with tag `n`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Payload)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TelemetryFields(pub u32);
+
+impl TelemetryFields {
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub fn with(self, field: TelemetryField) -> Self {
+        Self(self.0 | 1 << field.tag())
+    }
+
+    pub fn contains(self, field: TelemetryField) -> bool {
+        self.0 & 1 << field.tag() != 0
+    }
+
+    /// Selected fields this build knows, bits of newer fields are skipped
+    pub fn iter(self) -> impl Iterator<Item = TelemetryField> {
+        all::<TelemetryField>().filter(move |field| self.contains(*field))
+    }
+}
+
+impl FromIterator<TelemetryField> for TelemetryFields {
+    fn from_iter<T: IntoIterator<Item = TelemetryField>>(iter: T) -> Self {
+        iter.into_iter().fold(Self::empty(), Self::with)
+    }
+}
+
+/// Replaces the telemetry of the link the command arrives on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Payload)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TelemetrySubscription {
+    pub fields: TelemetryFields,
+    /// Samples per second, 0 stops the telemetry of the link
+    pub rate_hz: u16,
+}
+
+/// Values are encoded as a tag, a length and the value, so any combination can be decoded and
+/// values unknown to the receiver are skipped
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TelemetrySample {
+    /// Microseconds since boot
+    pub timestamp: u64,
+    values: [Option<TelemetryValue>; TelemetryField::CARDINALITY],
+}
+
+impl TelemetrySample {
+    pub fn new(timestamp: u64) -> Self {
+        Self {
+            timestamp,
+            values: [None; TelemetryField::CARDINALITY],
+        }
+    }
+
+    /// Replaces the value of the same field
+    pub fn insert(&mut self, value: TelemetryValue) {
+        self.values[value.field() as usize] = Some(value);
+    }
+
+    pub fn get(&self, field: TelemetryField) -> Option<TelemetryValue> {
+        self.values[field as usize]
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = TelemetryValue> + '_ {
+        self.values.iter().flatten().copied()
+    }
+}
+
+impl Payload for TelemetrySample {
+    type Error = InvalidField;
+
+    fn serialize(&self, buffer: &mut [u8]) -> usize {
+        self.timestamp.write(buffer);
+        let mut length = u64::SIZE;
+        for value in self.values() {
+            let field = value.field();
+            buffer[length] = field.tag();
+            buffer[length + 1] = field.size() as u8;
+            value.write(&mut buffer[length + VALUE_HEADER_SIZE..]);
+            length += VALUE_HEADER_SIZE + field.size();
+        }
+        length
+    }
+
+    fn deserialize(data: &[u8]) -> Result<Self, Self::Error> {
+        let mut sample = Self::new(u64::read(data)?);
+        let mut rest = &data[u64::SIZE..];
+        while !rest.is_empty() {
+            let [tag, size, ..] = *rest else {
+                return Err(InvalidField);
+            };
+            let end = VALUE_HEADER_SIZE + size as usize;
+            let value = rest.get(VALUE_HEADER_SIZE..end).ok_or(InvalidField)?;
+            // Newer firmware may append to a value, like to any other payload
+            if let Some(field) = TelemetryField::from_tag(tag) {
+                sample.insert(TelemetryValue::read(field, value)?);
+            }
+            rest = &rest[end..];
+        }
+        Ok(sample)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> TelemetrySample {
+        let mut sample = TelemetrySample::new(1_000_000);
+        sample.insert(TelemetryValue::DqCurrents([0.5, -1.5]));
+        sample.insert(TelemetryValue::BusVoltage(24.0));
+        sample.insert(TelemetryValue::Faults([0b01, 0b10]));
+        sample
+    }
+
+    #[test]
+    fn sample_should_serialize_and_deserialize() {
+        let sample = sample();
+        let mut buffer = [0; 128];
+        let length = sample.serialize(&mut buffer);
+        assert_eq!(length, 8 + (2 + 8) + (2 + 4) + (2 + 8));
+        assert_eq!(&buffer[8..10], &[0x02, 8]);
+        assert_eq!(TelemetrySample::deserialize(&buffer[..length]), Ok(sample));
+    }
+
+    #[test]
+    fn unknown_values_should_be_skipped() {
+        let mut buffer = [0; 128];
+        let length = sample().serialize(&mut buffer);
+        buffer[length..length + 4].copy_from_slice(&[0x7F, 2, 0xAA, 0xBB]);
+        assert_eq!(
+            TelemetrySample::deserialize(&buffer[..length + 4]),
+            Ok(sample())
+        );
+    }
+
+    #[test]
+    fn truncated_value_should_return_error() {
+        let mut buffer = [0; 128];
+        let length = sample().serialize(&mut buffer);
+        assert_eq!(
+            TelemetrySample::deserialize(&buffer[..length - 1]),
+            Err(InvalidField)
+        );
+        buffer[length - 9] = 4;
+        assert_eq!(
+            TelemetrySample::deserialize(&buffer[..length - 4]),
+            Err(InvalidField)
+        );
+    }
+
+    #[test]
+    fn fields_should_only_iterate_known_selected_fields() {
+        let known = TelemetryFields::empty()
+            .with(TelemetryField::Velocity)
+            .with(TelemetryField::PhaseCurrents);
+        let fields = TelemetryFields(known.0 | 1 << 31);
+        assert!(fields.contains(TelemetryField::Velocity));
+        assert!(!fields.contains(TelemetryField::Faults));
+        let mut iter = fields.iter();
+        assert_eq!(iter.next(), Some(TelemetryField::PhaseCurrents));
+        assert_eq!(iter.next(), Some(TelemetryField::Velocity));
+        assert_eq!(iter.next(), None);
+    }
+}
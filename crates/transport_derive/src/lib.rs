@@ -0,0 +1,231 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{Data, DeriveInput, Expr, Fields, LitInt, Member, Type, parse_macro_input};
+
+/// Lays out the fields of a struct one after another in little-endian order.
+///
+/// Implements `transport::packet::Field`, so the struct can be nested in other payloads, and
+/// `transport::packet::Payload`. A payload has to be exactly as long as its fields unless the
+/// struct is marked `#[payload(extensible)]`, then trailing bytes from newer peers are skipped.
+#[proc_macro_derive(Payload, attributes(payload))]
+pub fn derive_payload(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    payload(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Implements `transport::packet::Packet` for an enum whose variants carry one opcode each.
+///
+/// The enum needs `#[packet(error = Type, unknown = expression)]`, the error must convert from
+/// `InvalidField` and from the errors of the variant payloads. Every variant needs
+/// `#[packet(opcode = 0x..)]` and is either a unit variant or carries a single `Payload`.
+#[proc_macro_derive(Packet, attributes(packet))]
+pub fn derive_packet(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    packet(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn payload(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new(
+            input.span(),
+            "Payload can only be derived for structs",
+        ));
+    };
+
+    let mut extensible = false;
+    for attribute in input.attrs.iter().filter(|a| a.path().is_ident("payload")) {
+        attribute.parse_nested_meta(|meta| {
+            if meta.path.is_ident("extensible") {
+                extensible = true;
+                Ok(())
+            } else {
+                Err(meta.error("expected `extensible`"))
+            }
+        })?;
+    }
+
+    let members: Vec<Member> = data.fields.members().collect();
+    let types: Vec<&Type> = data.fields.iter().map(|field| &field.ty).collect();
+    let offsets: Vec<TokenStream2> = (0..types.len())
+        .map(|i| {
+            let previous = &types[..i];
+            quote! { 0 #(+ <#previous as ::transport::packet::Field>::SIZE)* }
+        })
+        .collect();
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let length_check = if extensible {
+        quote! { data.len() < <Self as ::transport::packet::Field>::SIZE }
+    } else {
+        quote! { data.len() != <Self as ::transport::packet::Field>::SIZE }
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::transport::packet::Field for #name #ty_generics #where_clause {
+            const SIZE: usize = 0 #(+ <#types as ::transport::packet::Field>::SIZE)*;
+
+            fn write(&self, buffer: &mut [u8]) {
+                #(::transport::packet::Field::write(&self.#members, &mut buffer[#offsets..]);)*
+            }
+
+            fn read(data: &[u8]) -> Result<Self, ::transport::packet::InvalidField> {
+                if data.len() < <Self as ::transport::packet::Field>::SIZE {
+                    return Err(::transport::packet::InvalidField);
+                }
+                Ok(Self {
+                    #(#members: <#types as ::transport::packet::Field>::read(&data[#offsets..])?,)*
+                })
+            }
+        }
+
+        impl #impl_generics ::transport::packet::Payload for #name #ty_generics #where_clause {
+            type Error = ::transport::packet::InvalidField;
+
+            fn serialize(&self, buffer: &mut [u8]) -> usize {
+                ::transport::packet::Field::write(self, buffer);
+                <Self as ::transport::packet::Field>::SIZE
+            }
+
+            fn deserialize(data: &[u8]) -> Result<Self, Self::Error> {
+                if #length_check {
+                    return Err(::transport::packet::InvalidField);
+                }
+                ::transport::packet::Field::read(data)
+            }
+        }
+    })
+}
+
+fn packet(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Enum(data) = &input.data else {
+        return Err(syn::Error::new(
+            input.span(),
+            "Packet can only be derived for enums",
+        ));
+    };
+
+    let mut error: Option<Type> = None;
+    let mut unknown: Option<Expr> = None;
+    for attribute in input.attrs.iter().filter(|a| a.path().is_ident("packet")) {
+        attribute.parse_nested_meta(|meta| {
+            if meta.path.is_ident("error") {
+                error = Some(meta.value()?.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("unknown") {
+                unknown = Some(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `error` or `unknown`"))
+            }
+        })?;
+    }
+    let missing = |argument| {
+        syn::Error::new(
+            input.ident.span(),
+            format!("missing `#[packet({argument} = ...)]`"),
+        )
+    };
+    let error = error.ok_or_else(|| missing("error"))?;
+    let unknown = unknown.ok_or_else(|| missing("unknown"))?;
+
+    let mut opcodes: Vec<u8> = Vec::new();
+    let mut deserialize_arms = Vec::new();
+    let mut serialize_arms = Vec::new();
+    for variant in &data.variants {
+        let opcode = variant_opcode(variant)?;
+        if opcodes.contains(&opcode) {
+            return Err(syn::Error::new(
+                variant.span(),
+                format!("opcode {opcode:#04x} is used twice"),
+            ));
+        }
+        opcodes.push(opcode);
+
+        let ident = &variant.ident;
+        match &variant.fields {
+            Fields::Unit => {
+                deserialize_arms.push(quote! {
+                    #opcode => {
+                        if !payload.is_empty() {
+                            return Err(::core::convert::From::from(::transport::packet::InvalidField));
+                        }
+                        Ok(Self::#ident)
+                    }
+                });
+                serialize_arms.push(quote! {
+                    Self::#ident => {
+                        buffer[0] = #opcode;
+                        1
+                    }
+                });
+            }
+            Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+                let ty = &fields.unnamed[0].ty;
+                deserialize_arms.push(quote! {
+                    #opcode => Ok(Self::#ident(
+                        <#ty as ::transport::packet::Payload>::deserialize(payload)?,
+                    )),
+                });
+                serialize_arms.push(quote! {
+                    Self::#ident(payload) => {
+                        buffer[0] = #opcode;
+                        1 + ::transport::packet::Payload::serialize(payload, &mut buffer[1..])
+                    }
+                });
+            }
+            _ => {
+                return Err(syn::Error::new(
+                    variant.span(),
+                    "variants must be units or carry a single payload",
+                ));
+            }
+        }
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::transport::packet::Packet for #name #ty_generics #where_clause {
+            type Error = #error;
+
+            fn deserialize(data: &[u8]) -> Result<Self, Self::Error> {
+                let Some((&opcode, payload)) = data.split_first() else {
+                    return Err(::core::convert::From::from(::transport::packet::InvalidField));
+                };
+                match opcode {
+                    #(#deserialize_arms)*
+                    _ => Err(#unknown),
+                }
+            }
+
+            fn serialize(&self, buffer: &mut [u8]) -> usize {
+                match self {
+                    #(#serialize_arms)*
+                }
+            }
+        }
+    })
+}
+
+fn variant_opcode(variant: &syn::Variant) -> syn::Result<u8> {
+    let mut opcode = None;
+    for attribute in variant.attrs.iter().filter(|a| a.path().is_ident("packet")) {
+        attribute.parse_nested_meta(|meta| {
+            if meta.path.is_ident("opcode") {
+                let value: LitInt = meta.value()?.parse()?;
+                opcode = Some(value.base10_parse::<u8>()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `opcode`"))
+            }
+        })?;
+    }
+    opcode.ok_or_else(|| syn::Error::new(variant.span(), "missing `#[packet(opcode = ...)]`"))
+}
@@ -1,4 +1,5 @@
 #![no_std]
+use controller_shared::policy::FaultPolicies;
 use embassy_stm32::time::{Hertz, khz, mhz};
 
 #[derive(Copy, Clone, Debug)]
@@ -11,6 +12,10 @@ pub struct UserConfig {
     pub can_bitrate: u32,
     pub fd_can_bitrate: u32,
     pub shaft_position_detector: ShaftPositionDetector,
+    /// Keeps the fault journal in flash across reboots
+    pub persist_fault_journal: bool,
+    /// How the controller reacts to each fault type
+    pub fault_policies: FaultPolicies,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -32,6 +37,8 @@ impl Default for UserConfig {
             can_bitrate: 250_000,
             fd_can_bitrate: 250_000,
             shaft_position_detector: ShaftPositionDetector::AS5600,
+            persist_fault_journal: true,
+            fault_policies: FaultPolicies::default(),
         }
     }
 }
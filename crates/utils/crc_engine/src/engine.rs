@@ -1,10 +1,25 @@
 pub trait CrcEngine {
     fn calculate(&mut self, data: &[u8]) -> u16;
 
+    /// CRC-32/ISO-HDLC, the one used by Ethernet and zlib
+    fn calculate_32(&mut self, data: &[u8]) -> u32;
+
     fn check(&mut self, data: &[u8]) -> bool {
         assert!(data.len() > 2, "Data too short");
         let calculated_crc = self.calculate(&data[..data.len() - 2]);
         let received_crc = u16::from_le_bytes([data[data.len() - 2], data[data.len() - 1]]);
         calculated_crc == received_crc
     }
+
+    fn check_32(&mut self, data: &[u8]) -> bool {
+        assert!(data.len() > 4, "Data too short");
+        let (data, received_crc) = data.split_at(data.len() - 4);
+        let received_crc = u32::from_le_bytes([
+            received_crc[0],
+            received_crc[1],
+            received_crc[2],
+            received_crc[3],
+        ]);
+        self.calculate_32(data) == received_crc
+    }
 }
@@ -3,31 +3,52 @@ use crate::engine::CrcEngine;
 use embassy_stm32::crc::{InputReverseConfig, PolySize};
 use embassy_stm32::{Peri, crc, peripherals};
 
+/// CRC-32/ISO-HDLC, the peripheral has no output XOR so it is applied in software
+const CRC_32_POLY: u32 = 0x04C1_1DB7;
+
+/// The driver cannot be reconfigured, so one is set up for every calculation
 pub struct HardwareCrcEngine<'a> {
-    crc: crc::Crc<'a>,
+    peri: Peri<'a, peripherals::CRC>,
 }
 
 impl<'a> HardwareCrcEngine<'a> {
     pub fn new(peri: Peri<'a, peripherals::CRC>) -> Self {
-        let config = crc::Config::new(
-            InputReverseConfig::None,
-            false,
-            PolySize::Width16,
-            0xFFFF,
-            CRC_POLY as u32,
-        )
-        .expect("Invalid CRC config");
-        Self {
-            crc: crc::Crc::new(peri, config),
-        }
+        Self { peri }
     }
 }
 
 impl<'a> CrcEngine for HardwareCrcEngine<'a> {
     fn calculate(&mut self, data: &[u8]) -> u16 {
-        self.crc.reset();
-        self.crc.feed_bytes(data);
-        let value = self.crc.read();
-        (value & 0xFFFF) as u16
+        let mut crc = crc::Crc::new(self.peri.reborrow(), config_16());
+        crc.feed_bytes(data);
+        (crc.read() & 0xFFFF) as u16
+    }
+
+    fn calculate_32(&mut self, data: &[u8]) -> u32 {
+        let mut crc = crc::Crc::new(self.peri.reborrow(), config_32());
+        crc.feed_bytes(data);
+        !crc.read()
     }
 }
+
+fn config_16() -> crc::Config {
+    crc::Config::new(
+        InputReverseConfig::None,
+        false,
+        PolySize::Width16,
+        0xFFFF,
+        CRC_POLY as u32,
+    )
+    .expect("Invalid CRC config")
+}
+
+fn config_32() -> crc::Config {
+    crc::Config::new(
+        InputReverseConfig::Byte,
+        true,
+        PolySize::Width32,
+        0xFFFF_FFFF,
+        CRC_32_POLY,
+    )
+    .expect("Invalid CRC-32 config")
+}
@@ -5,6 +5,7 @@ use crc::{Algorithm, Crc};
 
 pub struct SoftwareCrcEngine {
     crc: Crc<u16>,
+    crc_32: Crc<u32>,
 }
 
 impl Debug for SoftwareCrcEngine {
@@ -32,7 +33,8 @@ impl SoftwareCrcEngine {
             residue: 0,
         };
         let crc = crc::Crc::<u16>::new(&ALGORITHM);
-        Self { crc }
+        let crc_32 = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+        Self { crc, crc_32 }
     }
 }
 
@@ -40,4 +42,30 @@ impl CrcEngine for SoftwareCrcEngine {
     fn calculate(&mut self, data: &[u8]) -> u16 {
         self.crc.checksum(data)
     }
+
+    fn calculate_32(&mut self, data: &[u8]) -> u32 {
+        self.crc_32.checksum(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc_32_should_match_check_value() {
+        let mut engine = SoftwareCrcEngine::new();
+        assert_eq!(engine.calculate_32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn appended_crc_32_should_pass_check() {
+        let mut engine = SoftwareCrcEngine::new();
+        let mut data = [1, 2, 3, 4, 5, 0, 0, 0, 0];
+        let crc = engine.calculate_32(&data[..5]);
+        data[5..].copy_from_slice(&crc.to_le_bytes());
+        assert!(engine.check_32(&data));
+        data[0] ^= 1;
+        assert!(!engine.check_32(&data));
+    }
 }
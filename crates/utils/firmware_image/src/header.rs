@@ -0,0 +1,133 @@
+use crate::{DIGEST_SIZE, ImageError, image_digest};
+use core::ops::Range;
+use embedded_storage::nor_flash::ReadNorFlash;
+
+/// The header sits behind the vector table, the firmware's memory.x keeps this offset free
+pub const HEADER_OFFSET: usize = 0x200;
+const HEADER_MAGIC: u32 = 0x4849_5950;
+const GIT_HASH_SIZE: usize = 20;
+/// Where the digest sits in the image, it is hashed as zeros
+const DIGEST_RANGE: Range<usize> = HEADER_OFFSET + 32..HEADER_OFFSET + 32 + DIGEST_SIZE;
+
+/// Describes the image it is embedded in. The build fills in the version, board and commit,
+/// the signing tool the length and the digest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct ImageHeader {
+    magic: u32,
+    pub version: [u8; 3],
+    pub board_revision: u8,
+    /// All zeros if the image was not built from a git checkout
+    pub git_hash: [u8; GIT_HASH_SIZE],
+    /// Zero until the image is stamped
+    pub length: u32,
+    /// SHA-256 of the image with this field zeroed, zero until the image is stamped
+    pub digest: [u8; DIGEST_SIZE],
+}
+
+// The firmware embeds the header as a static, its layout has to match the serialized one
+const _: () = assert!(size_of::<ImageHeader>() == ImageHeader::SIZE);
+
+impl ImageHeader {
+    pub const SIZE: usize = 64;
+
+    pub const fn new(version: [u8; 3], board_revision: u8, git_hash: [u8; GIT_HASH_SIZE]) -> Self {
+        Self {
+            magic: HEADER_MAGIC,
+            version,
+            board_revision,
+            git_hash,
+            length: 0,
+            digest: [0; DIGEST_SIZE],
+        }
+    }
+
+    pub fn write(&self, buffer: &mut [u8; Self::SIZE]) {
+        buffer[0..4].copy_from_slice(&self.magic.to_le_bytes());
+        buffer[4..7].copy_from_slice(&self.version);
+        buffer[7] = self.board_revision;
+        buffer[8..28].copy_from_slice(&self.git_hash);
+        buffer[28..32].copy_from_slice(&self.length.to_le_bytes());
+        buffer[32..64].copy_from_slice(&self.digest);
+    }
+
+    /// Returns `None` if the buffer holds no header
+    pub fn read(buffer: &[u8; Self::SIZE]) -> Option<Self> {
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(buffer[offset..offset + 4].try_into().unwrap());
+        (u32_at(0) == HEADER_MAGIC).then(|| Self {
+            magic: HEADER_MAGIC,
+            version: buffer[4..7].try_into().unwrap(),
+            board_revision: buffer[7],
+            git_hash: buffer[8..28].try_into().unwrap(),
+            length: u32_at(28),
+            digest: buffer[32..64].try_into().unwrap(),
+        })
+    }
+
+    /// Reads the header of the image at the start of `flash`
+    pub fn read_from<F: ReadNorFlash>(flash: &mut F) -> Result<Self, ImageError> {
+        let mut buffer = [0; Self::SIZE];
+        flash
+            .read(HEADER_OFFSET as u32, &mut buffer)
+            .map_err(|_| ImageError::Flash)?;
+        Self::read(&buffer).ok_or(ImageError::MissingHeader)
+    }
+}
+
+/// Fills in the length and the digest of the header in `image`
+pub(crate) fn stamp_header(image: &mut [u8]) -> Result<(), ImageError> {
+    let length = image.len() as u32;
+    let header = image
+        .get_mut(HEADER_OFFSET..HEADER_OFFSET + ImageHeader::SIZE)
+        .ok_or(ImageError::MissingHeader)?;
+    let header: &mut [u8; ImageHeader::SIZE] = header.try_into().unwrap();
+    let mut fields = ImageHeader::read(header).ok_or(ImageError::MissingHeader)?;
+    fields.length = length;
+    fields.digest = [0; DIGEST_SIZE];
+    fields.write(header);
+
+    let digest = image_digest(&mut SliceFlash(image), length, DIGEST_RANGE)?;
+    image[DIGEST_RANGE].copy_from_slice(&digest);
+    Ok(())
+}
+
+/// Checks that the image at the start of `flash` is meant for `board_revision` and matches its
+/// digest
+pub fn check_header<F: ReadNorFlash>(
+    flash: &mut F,
+    board_revision: u8,
+) -> Result<ImageHeader, ImageError> {
+    let header = ImageHeader::read_from(flash)?;
+    if header.board_revision != board_revision {
+        return Err(ImageError::WrongBoard);
+    }
+    if (header.length as usize) < DIGEST_RANGE.end || header.length as usize > flash.capacity() {
+        return Err(ImageError::InvalidLength);
+    }
+    if image_digest(flash, header.length, DIGEST_RANGE)? != header.digest {
+        return Err(ImageError::DigestMismatch);
+    }
+    Ok(header)
+}
+
+/// Lets the host side hash an image in memory like the device hashes its flash
+struct SliceFlash<'a>(&'a [u8]);
+
+impl embedded_storage::nor_flash::ErrorType for SliceFlash<'_> {
+    type Error = embedded_storage::nor_flash::NorFlashErrorKind;
+}
+
+impl ReadNorFlash for SliceFlash<'_> {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let data = self.0.get(offset as usize..offset as usize + bytes.len());
+        bytes.copy_from_slice(data.ok_or(Self::Error::OutOfBounds)?);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.0.len()
+    }
+}
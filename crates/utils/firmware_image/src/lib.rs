@@ -0,0 +1,303 @@
+#![no_std]
+
+use core::ops::Range;
+use ed25519_compact::{PublicKey, SecretKey, Signature};
+use embedded_storage::nor_flash::ReadNorFlash;
+use sha2::{Digest, Sha256};
+
+mod header;
+
+pub use header::{HEADER_OFFSET, ImageHeader, check_header};
+
+/// The image length goes in a reserved entry of the Cortex-M vector table, the linker leaves it
+/// zeroed
+pub const LENGTH_OFFSET: usize = 0x20;
+const TRAILER_MAGIC: [u8; 4] = *b"PYSG";
+const DIGEST_SIZE: usize = 32;
+const SIGNATURE_SIZE: usize = 64;
+/// The image is hashed in chunks this large
+const READ_CHUNK_SIZE: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ImageError {
+    Flash,
+    /// No signature trailer follows the image
+    Unsigned,
+    /// The length entry holds something else or the image does not fit
+    InvalidLength,
+    /// The image changed after it was digested
+    DigestMismatch,
+    /// The image was signed with another key
+    BadSignature,
+    /// The image carries no metadata header
+    MissingHeader,
+    /// The image was built for another board revision
+    WrongBoard,
+}
+
+/// Appended right behind the image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignatureTrailer {
+    /// SHA-256 of the image, length entry included
+    pub digest: [u8; DIGEST_SIZE],
+    /// Ed25519 signature of the digest
+    pub signature: [u8; SIGNATURE_SIZE],
+}
+
+impl SignatureTrailer {
+    pub const SIZE: usize = TRAILER_MAGIC.len() + DIGEST_SIZE + SIGNATURE_SIZE;
+
+    pub fn write(&self, buffer: &mut [u8; Self::SIZE]) {
+        let (magic, rest) = buffer.split_at_mut(TRAILER_MAGIC.len());
+        let (digest, signature) = rest.split_at_mut(DIGEST_SIZE);
+        magic.copy_from_slice(&TRAILER_MAGIC);
+        digest.copy_from_slice(&self.digest);
+        signature.copy_from_slice(&self.signature);
+    }
+
+    pub fn read(buffer: &[u8; Self::SIZE]) -> Result<Self, ImageError> {
+        let (magic, rest) = buffer.split_at(TRAILER_MAGIC.len());
+        if magic != TRAILER_MAGIC {
+            return Err(ImageError::Unsigned);
+        }
+        let (digest, signature) = rest.split_at(DIGEST_SIZE);
+        Ok(Self {
+            digest: digest.try_into().unwrap(),
+            signature: signature.try_into().unwrap(),
+        })
+    }
+}
+
+/// Writes the length into the vector table and the header of `image`, along with the digest of
+/// the header
+pub fn stamp(image: &mut [u8]) -> Result<(), ImageError> {
+    let length = u32::try_from(image.len())
+        .map_err(|_| ImageError::InvalidLength)?
+        .to_le_bytes();
+    let entry = image
+        .get_mut(LENGTH_OFFSET..LENGTH_OFFSET + length.len())
+        .ok_or(ImageError::InvalidLength)?;
+    // Anything but zero or the length of an image signed before is not a vector table
+    if entry != [0; 4] && entry != length {
+        return Err(ImageError::InvalidLength);
+    }
+    entry.copy_from_slice(&length);
+    header::stamp_header(image)
+}
+
+/// Stamps and signs the image, the trailer has to be appended to the image
+pub fn sign(image: &mut [u8], key: &SecretKey) -> Result<SignatureTrailer, ImageError> {
+    stamp(image)?;
+    let digest: [u8; DIGEST_SIZE] = Sha256::digest(&*image).into();
+    Ok(SignatureTrailer {
+        digest,
+        signature: *key.sign(digest, None),
+    })
+}
+
+/// Checks the image at the start of `flash` against `public_key` and returns its length
+pub fn verify<F: ReadNorFlash>(flash: &mut F, public_key: &[u8; 32]) -> Result<u32, ImageError> {
+    let mut length = [0; 4];
+    flash
+        .read(LENGTH_OFFSET as u32, &mut length)
+        .map_err(|_| ImageError::Flash)?;
+    let length = u32::from_le_bytes(length);
+    // Zero in an image that was never signed, all ones in erased flash
+    if length == 0 || length == u32::MAX {
+        return Err(ImageError::Unsigned);
+    }
+    if (length as usize) < LENGTH_OFFSET + 4
+        || length as usize + SignatureTrailer::SIZE > flash.capacity()
+    {
+        return Err(ImageError::InvalidLength);
+    }
+
+    let mut trailer = [0; SignatureTrailer::SIZE];
+    flash
+        .read(length, &mut trailer)
+        .map_err(|_| ImageError::Flash)?;
+    let trailer = SignatureTrailer::read(&trailer)?;
+
+    let digest = image_digest(flash, length, 0..0)?;
+    if digest != trailer.digest {
+        return Err(ImageError::DigestMismatch);
+    }
+
+    PublicKey::new(*public_key)
+        .verify(digest, &Signature::new(trailer.signature))
+        .map_err(|_| ImageError::BadSignature)?;
+    Ok(length)
+}
+
+/// SHA-256 of the first `length` bytes of `flash`, the bytes in `zeroed` are hashed as zeros
+fn image_digest<F: ReadNorFlash>(
+    flash: &mut F,
+    length: u32,
+    zeroed: Range<usize>,
+) -> Result<[u8; DIGEST_SIZE], ImageError> {
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; READ_CHUNK_SIZE];
+    let mut offset = 0;
+    while offset < length as usize {
+        let chunk = (length as usize - offset).min(READ_CHUNK_SIZE);
+        flash
+            .read(offset as u32, &mut buffer[..chunk])
+            .map_err(|_| ImageError::Flash)?;
+        let start = zeroed.start.clamp(offset, offset + chunk);
+        let end = zeroed.end.clamp(offset, offset + chunk);
+        buffer[start - offset..end - offset].fill(0);
+        hasher.update(&buffer[..chunk]);
+        offset += chunk;
+    }
+    Ok(hasher.finalize().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_compact::{KeyPair, Seed};
+    use embedded_storage::nor_flash::{ErrorType, NorFlashError, NorFlashErrorKind};
+
+    const KEY: [u8; 32] = [7; 32];
+    const BOARD_REVISION: u8 = 3;
+    const LENGTH: usize = 700;
+
+    struct MemoryFlash([u8; 1024]);
+
+    #[derive(Debug)]
+    struct OutOfBounds;
+
+    impl NorFlashError for OutOfBounds {
+        fn kind(&self) -> NorFlashErrorKind {
+            NorFlashErrorKind::OutOfBounds
+        }
+    }
+
+    impl ErrorType for MemoryFlash {
+        type Error = OutOfBounds;
+    }
+
+    impl ReadNorFlash for MemoryFlash {
+        const READ_SIZE: usize = 1;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            let data = self.0.get(offset as usize..offset as usize + bytes.len());
+            bytes.copy_from_slice(data.ok_or(OutOfBounds)?);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.0.len()
+        }
+    }
+
+    /// An image like the linker lays it out, with a header that was not stamped yet
+    fn image(length: usize) -> [u8; 1024] {
+        let mut image = [0xFF; 1024];
+        image[..length]
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, byte)| *byte = i as u8);
+        image[LENGTH_OFFSET..LENGTH_OFFSET + 4].fill(0);
+        let header = ImageHeader::new([1, 2, 3], BOARD_REVISION, [9; 20]);
+        let header_bytes = &mut image[HEADER_OFFSET..HEADER_OFFSET + ImageHeader::SIZE];
+        header.write(header_bytes.try_into().unwrap());
+        image
+    }
+
+    /// A signed image written to erased flash
+    fn signed_flash() -> MemoryFlash {
+        let mut flash = MemoryFlash(image(LENGTH));
+        let trailer = sign(&mut flash.0[..LENGTH], &key_pair(KEY).sk).unwrap();
+        let mut buffer = [0; SignatureTrailer::SIZE];
+        trailer.write(&mut buffer);
+        flash.0[LENGTH..LENGTH + SignatureTrailer::SIZE].copy_from_slice(&buffer);
+        flash
+    }
+
+    fn key_pair(seed: [u8; 32]) -> KeyPair {
+        KeyPair::from_seed(Seed::new(seed))
+    }
+
+    fn public_key() -> [u8; 32] {
+        *key_pair(KEY).pk
+    }
+
+    #[test]
+    fn signed_image_should_be_accepted() {
+        let mut flash = signed_flash();
+        assert_eq!(verify(&mut flash, &public_key()), Ok(LENGTH as u32));
+        assert_eq!(
+            flash.0[LENGTH_OFFSET..LENGTH_OFFSET + 4],
+            (LENGTH as u32).to_le_bytes()
+        );
+
+        let header = check_header(&mut flash, BOARD_REVISION).unwrap();
+        assert_eq!(header.version, [1, 2, 3]);
+        assert_eq!(header.git_hash, [9; 20]);
+        assert_eq!(header.length, LENGTH as u32);
+    }
+
+    #[test]
+    fn tampered_image_should_be_rejected() {
+        let mut flash = signed_flash();
+        flash.0[100] ^= 1;
+        assert_eq!(
+            verify(&mut flash, &public_key()),
+            Err(ImageError::DigestMismatch)
+        );
+        assert_eq!(
+            check_header(&mut flash, BOARD_REVISION),
+            Err(ImageError::DigestMismatch)
+        );
+
+        let other_key = *key_pair([8; 32]).pk;
+        assert_eq!(
+            verify(&mut signed_flash(), &other_key),
+            Err(ImageError::BadSignature)
+        );
+    }
+
+    #[test]
+    fn unsigned_image_should_be_rejected() {
+        let mut flash = MemoryFlash([0; 1024]);
+        assert_eq!(verify(&mut flash, &public_key()), Err(ImageError::Unsigned));
+
+        let mut flash = signed_flash();
+        flash.0[LENGTH] = 0xFF;
+        assert_eq!(verify(&mut flash, &public_key()), Err(ImageError::Unsigned));
+
+        flash.0[LENGTH_OFFSET..LENGTH_OFFSET + 4].copy_from_slice(&1000u32.to_le_bytes());
+        assert_eq!(
+            verify(&mut flash, &public_key()),
+            Err(ImageError::InvalidLength)
+        );
+    }
+
+    #[test]
+    fn image_for_another_board_should_be_rejected() {
+        let mut flash = signed_flash();
+        assert_eq!(
+            check_header(&mut flash, BOARD_REVISION + 1),
+            Err(ImageError::WrongBoard)
+        );
+
+        flash.0[HEADER_OFFSET] ^= 1;
+        assert_eq!(
+            check_header(&mut flash, BOARD_REVISION),
+            Err(ImageError::MissingHeader)
+        );
+    }
+
+    #[test]
+    fn image_without_vector_table_or_header_should_not_be_signed() {
+        let key = key_pair(KEY).sk;
+        assert_eq!(sign(&mut [0; 16], &key), Err(ImageError::InvalidLength));
+        assert_eq!(sign(&mut [1; 64], &key), Err(ImageError::InvalidLength));
+        assert_eq!(
+            sign(&mut [0; HEADER_OFFSET + ImageHeader::SIZE], &key),
+            Err(ImageError::MissingHeader)
+        );
+    }
+}
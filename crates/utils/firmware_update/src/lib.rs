@@ -0,0 +1,476 @@
+#![no_std]
+
+use core::sync::atomic::{AtomicU8, Ordering};
+use embassy_boot::{BlockingFirmwareState, FirmwareUpdaterConfig, State};
+use embedded_storage::nor_flash::NorFlash;
+use firmware_image::{ImageError, ImageHeader};
+use logging::{error, info};
+use transport::blob::Checksum;
+use transport::command::FirmwareBlock;
+use transport::event::ErrorCode;
+use transport::firmware::{BootOutcome, FirmwareSlots, ImageMetadata};
+
+/// Largest write size of the flashes this works with
+const MAX_WRITE_SIZE: usize = 32;
+/// The image is read back in chunks this large
+const VERIFY_CHUNK_SIZE: usize = 256;
+/// Value of erased flash, the last write of an image is padded with it
+const ERASED: u8 = 0xFF;
+
+/// Unknown until the boot state was read, no firmware block is taken before
+static OUTCOME: AtomicU8 = AtomicU8::new(0);
+
+/// How the running image came to run, set once the boot state was read
+pub fn outcome() -> BootOutcome {
+    BootOutcome::from_u8(OUTCOME.load(Ordering::Relaxed))
+}
+
+pub fn set_outcome(outcome: BootOutcome) {
+    OUTCOME.store(outcome.to_u8(), Ordering::Relaxed);
+}
+
+/// The DFU partition may hold the image a failed trial goes back to, it must not be touched before
+/// the running image is known to be confirmed
+fn accepts_blocks(outcome: BootOutcome) -> bool {
+    matches!(outcome, BootOutcome::Confirmed | BootOutcome::RolledBack)
+}
+
+/// Takes the blocks of WriteFirmwareBlock and FinalizeFirmwareUpdate
+pub trait FirmwareTarget {
+    fn write_block(&mut self, block: &FirmwareBlock) -> Result<(), ErrorCode>;
+
+    /// Verifies the received image and has the bootloader swap it in on the next reset
+    fn finalize(&mut self) -> Result<(), ErrorCode>;
+
+    fn read_slots(&mut self) -> Result<FirmwareSlots, ErrorCode>;
+}
+
+/// Writes a new image into the DFU partition. Blocks have to arrive in order, a block at offset
+/// zero starts the upload over.
+pub struct FirmwareUpdate<'d, DFU: NorFlash, STATE: NorFlash> {
+    dfu: DFU,
+    state: BlockingFirmwareState<'d, STATE>,
+    /// Header of the running image, updates have to be built for the same board
+    active: ImageHeader,
+    /// Bytes received, including the pending ones
+    received: u32,
+    /// Bytes written to the DFU partition
+    written: u32,
+    /// Everything below was erased during this upload
+    erased_until: u32,
+    /// Received bytes that do not fill a whole write yet
+    pending: [u8; MAX_WRITE_SIZE],
+    pending_length: usize,
+    checksum: Checksum,
+}
+
+impl<'d, DFU: NorFlash, STATE: NorFlash> FirmwareUpdate<'d, DFU, STATE> {
+    /// `aligned` is the buffer [`BlockingFirmwareState`] needs, as large as a state write
+    pub fn new(
+        config: FirmwareUpdaterConfig<DFU, STATE>,
+        aligned: &'d mut [u8],
+        active: ImageHeader,
+    ) -> Self {
+        Self::with_state(
+            config.dfu,
+            BlockingFirmwareState::new(config.state, aligned),
+            active,
+        )
+    }
+
+    fn with_state(dfu: DFU, state: BlockingFirmwareState<'d, STATE>, active: ImageHeader) -> Self {
+        assert!(DFU::WRITE_SIZE <= MAX_WRITE_SIZE);
+        Self {
+            dfu,
+            state,
+            active,
+            received: 0,
+            written: 0,
+            erased_until: 0,
+            pending: [0; MAX_WRITE_SIZE],
+            pending_length: 0,
+            checksum: Checksum::new(),
+        }
+    }
+
+    fn restart(&mut self) {
+        self.received = 0;
+        self.written = 0;
+        self.erased_until = 0;
+        self.pending_length = 0;
+        self.checksum = Checksum::new();
+    }
+
+    fn append(&mut self, mut data: &[u8]) -> Result<(), ErrorCode> {
+        if self.pending_length > 0 {
+            let taken = (DFU::WRITE_SIZE - self.pending_length).min(data.len());
+            self.pending[self.pending_length..self.pending_length + taken]
+                .copy_from_slice(&data[..taken]);
+            self.pending_length += taken;
+            data = &data[taken..];
+            if self.pending_length < DFU::WRITE_SIZE {
+                return Ok(());
+            }
+            let pending = self.pending;
+            self.program(&pending[..DFU::WRITE_SIZE])?;
+            self.pending_length = 0;
+        }
+
+        let aligned = data.len() - data.len() % DFU::WRITE_SIZE;
+        self.program(&data[..aligned])?;
+        let rest = &data[aligned..];
+        self.pending[..rest.len()].copy_from_slice(rest);
+        self.pending_length = rest.len();
+        Ok(())
+    }
+
+    /// Writes behind the written data, the sectors it reaches are erased first
+    fn program(&mut self, data: &[u8]) -> Result<(), ErrorCode> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let end = self.written + data.len() as u32;
+        if end > self.erased_until {
+            let erase_end = end
+                .next_multiple_of(DFU::ERASE_SIZE as u32)
+                .min(self.dfu.capacity() as u32);
+            self.dfu.erase(self.erased_until, erase_end).map_err(|_| {
+                error!("Failed to erase the DFU partition");
+                ErrorCode::FlashError
+            })?;
+            self.erased_until = erase_end;
+        }
+        self.dfu.write(self.written, data).map_err(|_| {
+            error!("Failed to write the DFU partition");
+            ErrorCode::FlashError
+        })?;
+        self.written = end;
+        Ok(())
+    }
+
+    /// Reads the image back, it has to match what was received
+    fn verify(&mut self) -> Result<(), ErrorCode> {
+        let mut checksum = Checksum::new();
+        let mut buffer = [0; VERIFY_CHUNK_SIZE];
+        let mut offset = 0;
+        while offset < self.received {
+            let length = ((self.received - offset) as usize).min(VERIFY_CHUNK_SIZE);
+            self.dfu
+                .read(offset, &mut buffer[..length])
+                .map_err(|_| ErrorCode::FlashError)?;
+            checksum.update(&buffer[..length]);
+            offset += length as u32;
+        }
+        if checksum != self.checksum {
+            error!("The image in the DFU partition does not match the received one");
+            return Err(ErrorCode::FlashError);
+        }
+        Ok(())
+    }
+}
+
+impl<DFU: NorFlash, STATE: NorFlash> FirmwareTarget for FirmwareUpdate<'_, DFU, STATE> {
+    fn write_block(&mut self, block: &FirmwareBlock) -> Result<(), ErrorCode> {
+        let data = block.slice();
+        if !accepts_blocks(outcome()) {
+            error!("The running image is not confirmed yet");
+            return Err(ErrorCode::InvalidState);
+        }
+        if block.offset == 0 {
+            self.restart();
+        }
+        if block.offset != self.received {
+            return Err(ErrorCode::InvalidArgument);
+        }
+        let end = self.received as usize + data.len();
+        if end > self.dfu.capacity() {
+            return Err(ErrorCode::InvalidArgument);
+        }
+
+        self.checksum.update(data);
+        self.received = end as u32;
+        // Whatever made it to the flash is unknown now, only a new upload can fix it
+        self.append(data).inspect_err(|_| self.restart())
+    }
+
+    fn finalize(&mut self) -> Result<(), ErrorCode> {
+        if self.received == 0 {
+            return Err(ErrorCode::InvalidState);
+        }
+        let result = self.finish();
+        self.restart();
+        result
+    }
+
+    fn read_slots(&mut self) -> Result<FirmwareSlots, ErrorCode> {
+        let pending = match ImageHeader::read_from(&mut self.dfu) {
+            Ok(header) => map_metadata(&header),
+            Err(ImageError::MissingHeader) => ImageMetadata::default(),
+            Err(_) => return Err(ErrorCode::FlashError),
+        };
+        let state = self.state.get_state().map_err(|_| ErrorCode::FlashError)?;
+        let boot = outcome();
+        Ok(FirmwareSlots {
+            active: map_metadata(&self.active),
+            pending,
+            // The swap state stays until a trial image is confirmed
+            swap_scheduled: state == State::Swap && boot != BootOutcome::Trial,
+            boot,
+        })
+    }
+}
+
+impl<DFU: NorFlash, STATE: NorFlash> FirmwareUpdate<'_, DFU, STATE> {
+    fn finish(&mut self) -> Result<(), ErrorCode> {
+        if self.pending_length > 0 {
+            let mut last = self.pending;
+            last[self.pending_length..].fill(ERASED);
+            self.program(&last[..DFU::WRITE_SIZE])?;
+        }
+        self.verify()?;
+        firmware_image::check_header(&mut self.dfu, self.active.board_revision).map_err(
+            |image_error| {
+                error!("Rejecting the image: {:?}", image_error);
+                map_image_error(image_error)
+            },
+        )?;
+        self.state
+            .mark_updated()
+            .map_err(|_| ErrorCode::FlashError)?;
+        info!(
+            "Received a {} byte image, it is swapped in on the next reset",
+            self.received
+        );
+        Ok(())
+    }
+}
+
+fn map_image_error(error: ImageError) -> ErrorCode {
+    match error {
+        ImageError::Flash => ErrorCode::FlashError,
+        ImageError::WrongBoard => ErrorCode::WrongBoard,
+        ImageError::Unsigned
+        | ImageError::InvalidLength
+        | ImageError::DigestMismatch
+        | ImageError::BadSignature
+        | ImageError::MissingHeader => ErrorCode::InvalidImage,
+    }
+}
+
+fn map_metadata(header: &ImageHeader) -> ImageMetadata {
+    ImageMetadata {
+        present: true,
+        version: header.version,
+        board_revision: header.board_revision,
+        git_hash: header.git_hash,
+        length: header.length,
+        digest: header.digest,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_storage::nor_flash::{ErrorType, NorFlashError, NorFlashErrorKind, ReadNorFlash};
+    use firmware_image::HEADER_OFFSET;
+    use transport::command::FIRMWARE_BLOCK_MAX_DATA_SIZE;
+
+    const SECTOR_SIZE: usize = 64;
+
+    struct MemoryFlash<const SIZE: usize> {
+        data: [u8; SIZE],
+        erases: usize,
+    }
+
+    #[derive(Debug)]
+    struct OutOfBounds;
+
+    impl NorFlashError for OutOfBounds {
+        fn kind(&self) -> NorFlashErrorKind {
+            NorFlashErrorKind::OutOfBounds
+        }
+    }
+
+    impl<const SIZE: usize> MemoryFlash<SIZE> {
+        fn new() -> Self {
+            Self {
+                data: [0; SIZE],
+                erases: 0,
+            }
+        }
+    }
+
+    impl<const SIZE: usize> ErrorType for MemoryFlash<SIZE> {
+        type Error = OutOfBounds;
+    }
+
+    impl<const SIZE: usize> ReadNorFlash for MemoryFlash<SIZE> {
+        const READ_SIZE: usize = 1;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            let data = self
+                .data
+                .get(offset as usize..offset as usize + bytes.len());
+            bytes.copy_from_slice(data.ok_or(OutOfBounds)?);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            SIZE
+        }
+    }
+
+    impl<const SIZE: usize> NorFlash for MemoryFlash<SIZE> {
+        const WRITE_SIZE: usize = 8;
+        const ERASE_SIZE: usize = SECTOR_SIZE;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+            assert_eq!(from as usize % SECTOR_SIZE, 0);
+            let sectors = self.data.get_mut(from as usize..to as usize);
+            sectors.ok_or(OutOfBounds)?.fill(ERASED);
+            self.erases += 1;
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            assert_eq!(offset as usize % 8, 0);
+            assert_eq!(bytes.len() % 8, 0);
+            let target = self
+                .data
+                .get_mut(offset as usize..offset as usize + bytes.len());
+            let target = target.ok_or(OutOfBounds)?;
+            // Flash bits only go from one to zero without an erase
+            assert!(target.iter().all(|&byte| byte == ERASED));
+            target.copy_from_slice(bytes);
+            Ok(())
+        }
+    }
+
+    const BOARD_REVISION: u8 = 2;
+    const IMAGE_SIZE: usize = 700;
+
+    fn update<'d>(
+        aligned: &'d mut [u8; 8],
+    ) -> FirmwareUpdate<'d, MemoryFlash<1024>, MemoryFlash<{ 2 * SECTOR_SIZE }>> {
+        set_outcome(BootOutcome::Confirmed);
+        let state = BlockingFirmwareState::new(MemoryFlash::new(), aligned);
+        let active = ImageHeader::new([1, 0, 0], BOARD_REVISION, [0; 20]);
+        FirmwareUpdate::with_state(MemoryFlash::new(), state, active)
+    }
+
+    /// A stamped image with a metadata header for `board_revision`
+    fn image(board_revision: u8) -> [u8; IMAGE_SIZE] {
+        let mut image: [u8; IMAGE_SIZE] = core::array::from_fn(|i| i as u8);
+        image[0x20..0x24].fill(0);
+        let header = ImageHeader::new([1, 2, 3], board_revision, [4; 20]);
+        let header_bytes = &mut image[HEADER_OFFSET..HEADER_OFFSET + ImageHeader::SIZE];
+        header.write(header_bytes.try_into().unwrap());
+        firmware_image::stamp(&mut image).unwrap();
+        image
+    }
+
+    fn write_image(update: &mut impl FirmwareTarget, image: &[u8]) {
+        for (i, chunk) in image.chunks(100).enumerate() {
+            assert_eq!(update.write_block(&block(i as u32 * 100, chunk)), Ok(()));
+        }
+    }
+
+    fn block(offset: u32, data: &[u8]) -> FirmwareBlock {
+        let mut block = FirmwareBlock {
+            offset,
+            length: data.len() as u32,
+            data: [0; FIRMWARE_BLOCK_MAX_DATA_SIZE],
+        };
+        block.data[..data.len()].copy_from_slice(data);
+        block
+    }
+
+    #[test]
+    fn unaligned_blocks_should_end_up_in_order() {
+        let mut aligned = [0; 8];
+        let mut update = update(&mut aligned);
+        let image = image(BOARD_REVISION);
+        for (i, chunk) in image.chunks(13).enumerate() {
+            assert_eq!(update.write_block(&block(i as u32 * 13, chunk)), Ok(()));
+        }
+        assert_eq!(update.finalize(), Ok(()));
+
+        assert_eq!(&update.dfu.data[..IMAGE_SIZE], &image);
+        assert!(
+            update.dfu.data[IMAGE_SIZE..704]
+                .iter()
+                .all(|&byte| byte == ERASED)
+        );
+        assert_eq!(update.dfu.erases, 11);
+    }
+
+    #[test]
+    fn blocks_should_wait_for_a_confirmed_image() {
+        assert!(!accepts_blocks(BootOutcome::Unknown));
+        assert!(!accepts_blocks(BootOutcome::Trial));
+        assert!(accepts_blocks(BootOutcome::Confirmed));
+        assert!(accepts_blocks(BootOutcome::RolledBack));
+    }
+
+    #[test]
+    fn block_out_of_order_should_be_rejected() {
+        let mut aligned = [0; 8];
+        let mut update = update(&mut aligned);
+        assert_eq!(update.write_block(&block(0, &[1; 16])), Ok(()));
+        assert_eq!(
+            update.write_block(&block(32, &[2; 16])),
+            Err(ErrorCode::InvalidArgument)
+        );
+        assert_eq!(update.write_block(&block(16, &[2; 16])), Ok(()));
+        assert_eq!(
+            update.write_block(&block(32, &[3; 1000])),
+            Err(ErrorCode::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn finalize_without_image_should_fail() {
+        let mut aligned = [0; 8];
+        let mut update = update(&mut aligned);
+        assert_eq!(update.finalize(), Err(ErrorCode::InvalidState));
+
+        write_image(&mut update, &image(BOARD_REVISION));
+        update.finalize().unwrap();
+        assert_eq!(update.finalize(), Err(ErrorCode::InvalidState));
+    }
+
+    #[test]
+    fn image_for_another_board_or_damaged_should_be_refused() {
+        let mut aligned = [0; 8];
+        let mut update = update(&mut aligned);
+        write_image(&mut update, &image(BOARD_REVISION + 1));
+        assert_eq!(update.finalize(), Err(ErrorCode::WrongBoard));
+
+        let mut damaged = image(BOARD_REVISION);
+        damaged[IMAGE_SIZE - 1] ^= 1;
+        write_image(&mut update, &damaged);
+        assert_eq!(update.finalize(), Err(ErrorCode::InvalidImage));
+
+        write_image(&mut update, &[1; 64]);
+        assert_eq!(update.finalize(), Err(ErrorCode::InvalidImage));
+        assert!(!update.read_slots().unwrap().swap_scheduled);
+    }
+
+    #[test]
+    fn slots_should_report_both_images() {
+        let mut aligned = [0; 8];
+        let mut update = update(&mut aligned);
+        let slots = update.read_slots().unwrap();
+        assert_eq!(slots.active.version, [1, 0, 0]);
+        assert_eq!(slots.active.board_revision, BOARD_REVISION);
+        assert!(!slots.pending.present);
+
+        write_image(&mut update, &image(BOARD_REVISION));
+        update.finalize().unwrap();
+        let slots = update.read_slots().unwrap();
+        assert!(slots.pending.present);
+        assert_eq!(slots.pending.version, [1, 2, 3]);
+        assert_eq!(slots.pending.length, IMAGE_SIZE as u32);
+        assert!(slots.swap_scheduled);
+    }
+}
@@ -0,0 +1,318 @@
+use crate::fault_register::{FaultState, FaultType};
+#[cfg(feature = "fault-journal")]
+use core::cell::RefCell;
+#[cfg(feature = "fault-journal")]
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use enum_iterator::{Sequence, all};
+
+/// Transitions kept in the journal, older ones are dropped
+pub const JOURNAL_TRANSITIONS: usize = 32;
+/// Room [`FaultJournal::to_bytes`] needs, a multiple of the flash write size
+pub const JOURNAL_SIZE: usize = FaultJournal::STORED_SIZE.div_ceil(8) * 8;
+
+const MAGIC: u32 = 0x464A_4E31;
+
+#[cfg(feature = "fault-journal")]
+pub type SharedFaultJournal = Mutex<CriticalSectionRawMutex, RefCell<FaultJournal>>;
+
+/// Uptime in one of the boots counted since the journal was erased
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct JournalTime {
+    pub boot: u16,
+    /// Microseconds since that boot
+    pub uptime: u64,
+}
+
+/// How often a fault turned active, the times are zero while it never did
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FaultStats {
+    pub occurrences: u32,
+    pub first_seen: JournalTime,
+    pub last_seen: JournalTime,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FaultTransition {
+    pub fault: FaultType,
+    /// State the fault changed to
+    pub state: FaultState,
+    pub time: JournalTime,
+}
+
+/// History of the fault register, kept across [`crate::fault_register::FaultRegister::reset`]
+/// and, if it is stored, across reboots
+pub struct FaultJournal {
+    boot: u16,
+    stats: [FaultStats; FaultType::CARDINALITY],
+    transitions: [FaultTransition; JOURNAL_TRANSITIONS],
+    next_transition: usize,
+    recorded: usize,
+    /// Changed since it was last stored
+    dirty: bool,
+}
+
+impl JournalTime {
+    const STORED_SIZE: usize = 10;
+
+    fn write(&self, buffer: &mut [u8]) {
+        buffer[..2].copy_from_slice(&self.boot.to_le_bytes());
+        buffer[2..Self::STORED_SIZE].copy_from_slice(&self.uptime.to_le_bytes());
+    }
+
+    fn read(data: &[u8]) -> Self {
+        Self {
+            boot: u16::from_le_bytes([data[0], data[1]]),
+            uptime: u64::from_le_bytes(data[2..Self::STORED_SIZE].try_into().unwrap()),
+        }
+    }
+}
+
+impl FaultStats {
+    const STORED_SIZE: usize = 4 + 2 * JournalTime::STORED_SIZE;
+}
+
+impl FaultTransition {
+    const STORED_SIZE: usize = 2 + JournalTime::STORED_SIZE;
+    const EMPTY: Self = Self {
+        fault: FaultType::Encoder,
+        state: FaultState::Clean,
+        time: JournalTime { boot: 0, uptime: 0 },
+    };
+}
+
+impl Default for FaultJournal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FaultJournal {
+    /// Magic, boot, fault type count, stats, transition count, transitions and checksum
+    const STORED_SIZE: usize = 4
+        + 2
+        + 1
+        + FaultType::CARDINALITY * FaultStats::STORED_SIZE
+        + 1
+        + JOURNAL_TRANSITIONS * FaultTransition::STORED_SIZE
+        + 4;
+
+    pub const fn new() -> Self {
+        Self {
+            boot: 0,
+            stats: [FaultStats {
+                occurrences: 0,
+                first_seen: JournalTime { boot: 0, uptime: 0 },
+                last_seen: JournalTime { boot: 0, uptime: 0 },
+            }; FaultType::CARDINALITY],
+            transitions: [FaultTransition::EMPTY; JOURNAL_TRANSITIONS],
+            next_transition: 0,
+            recorded: 0,
+            dirty: false,
+        }
+    }
+
+    /// Counts the boots since the journal was erased. Only boots that changed the journal are
+    /// stored, so each boot in it has its own number.
+    pub fn boot(&self) -> u16 {
+        self.boot
+    }
+
+    pub fn stats(&self, fault: FaultType) -> &FaultStats {
+        &self.stats[fault as usize]
+    }
+
+    /// Oldest first
+    pub fn transitions(&self) -> impl Iterator<Item = &FaultTransition> {
+        let start = self.next_transition + JOURNAL_TRANSITIONS - self.recorded;
+        (start..start + self.recorded).map(|i| &self.transitions[i % JOURNAL_TRANSITIONS])
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn record(&mut self, fault: FaultType, state: FaultState, uptime: u64) {
+        let time = JournalTime {
+            boot: self.boot,
+            uptime,
+        };
+        if state == FaultState::Active {
+            let stats = &mut self.stats[fault as usize];
+            if stats.occurrences == 0 {
+                stats.first_seen = time;
+            }
+            stats.occurrences = stats.occurrences.saturating_add(1);
+            stats.last_seen = time;
+        }
+        self.push(FaultTransition { fault, state, time });
+        self.dirty = true;
+    }
+
+    fn push(&mut self, transition: FaultTransition) {
+        self.transitions[self.next_transition] = transition;
+        self.next_transition = (self.next_transition + 1) % JOURNAL_TRANSITIONS;
+        self.recorded = (self.recorded + 1).min(JOURNAL_TRANSITIONS);
+    }
+
+    /// Stores the journal so [`Self::from_bytes`] can restore it, it is not dirty afterwards
+    pub fn to_bytes(&mut self, buffer: &mut [u8; JOURNAL_SIZE]) {
+        buffer.fill(0);
+        buffer[..4].copy_from_slice(&MAGIC.to_le_bytes());
+        buffer[4..6].copy_from_slice(&self.boot.to_le_bytes());
+        buffer[6] = FaultType::CARDINALITY as u8;
+        let mut offset = 7;
+        for stats in &self.stats {
+            buffer[offset..offset + 4].copy_from_slice(&stats.occurrences.to_le_bytes());
+            stats.first_seen.write(&mut buffer[offset + 4..]);
+            stats
+                .last_seen
+                .write(&mut buffer[offset + 4 + JournalTime::STORED_SIZE..]);
+            offset += FaultStats::STORED_SIZE;
+        }
+        buffer[offset] = self.recorded as u8;
+        offset += 1;
+        for transition in self.transitions() {
+            buffer[offset] = transition.fault as u8;
+            buffer[offset + 1] = transition.state as u8;
+            transition.time.write(&mut buffer[offset + 2..]);
+            offset += FaultTransition::STORED_SIZE;
+        }
+        let checksum = checksum(&buffer[..offset]);
+        buffer[offset..offset + 4].copy_from_slice(&checksum.to_le_bytes());
+        self.dirty = false;
+    }
+
+    /// Restores a stored journal as the next boot. Returns `None` for an erased or damaged
+    /// journal, fault types of a newer firmware are dropped.
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        let read_u32 = |offset: usize| {
+            data.get(offset..offset + 4)
+                .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        };
+        if read_u32(0)? != MAGIC {
+            return None;
+        }
+        let fault_types = *data.get(6)? as usize;
+        let transitions_offset = 7 + fault_types * FaultStats::STORED_SIZE;
+        let count = *data.get(transitions_offset)? as usize;
+        let end = transitions_offset + 1 + count * FaultTransition::STORED_SIZE;
+        if count > JOURNAL_TRANSITIONS || read_u32(end)? != checksum(&data[..end]) {
+            return None;
+        }
+
+        let mut journal = Self::new();
+        journal.boot = u16::from_le_bytes([data[4], data[5]]).wrapping_add(1);
+        for (i, stats) in journal.stats.iter_mut().enumerate().take(fault_types) {
+            let offset = 7 + i * FaultStats::STORED_SIZE;
+            *stats = FaultStats {
+                occurrences: read_u32(offset)?,
+                first_seen: JournalTime::read(&data[offset + 4..]),
+                last_seen: JournalTime::read(&data[offset + 4 + JournalTime::STORED_SIZE..]),
+            };
+        }
+        for i in 0..count {
+            let offset = transitions_offset + 1 + i * FaultTransition::STORED_SIZE;
+            let state = match data[offset + 1] {
+                0 => FaultState::Clean,
+                1 => FaultState::Active,
+                2 => FaultState::Latched,
+                _ => return None,
+            };
+            let Some(fault) = all::<FaultType>().nth(data[offset] as usize) else {
+                continue;
+            };
+            journal.push(FaultTransition {
+                fault,
+                state,
+                time: JournalTime::read(&data[offset + 2..]),
+            });
+        }
+        Some(journal)
+    }
+}
+
+/// Catches journals cut short by a reset while they were written
+fn checksum(data: &[u8]) -> u32 {
+    data.iter()
+        .fold(0u32, |sum, byte| sum.rotate_left(5) ^ *byte as u32)
+}
+
+#[cfg(feature = "fault-journal")]
+pub fn journal() -> &'static SharedFaultJournal {
+    static JOURNAL: SharedFaultJournal = Mutex::new(RefCell::new(FaultJournal::new()));
+    &JOURNAL
+}
+
+#[cfg(feature = "fault-journal")]
+pub(crate) fn record(fault: FaultType, state: FaultState, uptime: u64) {
+    journal().lock(|journal| journal.borrow_mut().record(fault, state, uptime));
+}
+
+/// Continues a stored journal, changes recorded since the boot are moved into it. Returns false
+/// if nothing valid was stored.
+#[cfg(feature = "fault-journal")]
+pub fn restore(data: &[u8]) -> bool {
+    let Some(mut restored) = FaultJournal::from_bytes(data) else {
+        return false;
+    };
+    journal().lock(|journal| {
+        let mut journal = journal.borrow_mut();
+        for transition in journal.transitions() {
+            restored.record(transition.fault, transition.state, transition.time.uptime);
+        }
+        *journal = restored;
+    });
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_turning_active_should_count_as_occurrence() {
+        let mut journal = FaultJournal::new();
+        journal.record(FaultType::AdcTimeout, FaultState::Active, 10);
+        journal.record(FaultType::AdcTimeout, FaultState::Latched, 20);
+        journal.record(FaultType::AdcTimeout, FaultState::Active, 30);
+
+        let stats = journal.stats(FaultType::AdcTimeout);
+        assert_eq!(stats.occurrences, 2);
+        assert_eq!(stats.first_seen.uptime, 10);
+        assert_eq!(stats.last_seen.uptime, 30);
+        assert_eq!(journal.transitions().count(), 3);
+    }
+
+    #[test]
+    fn oldest_transitions_should_be_dropped() {
+        let mut journal = FaultJournal::new();
+        for uptime in 0..JOURNAL_TRANSITIONS as u64 + 5 {
+            journal.record(FaultType::Encoder, FaultState::Active, uptime);
+        }
+        let mut transitions = journal.transitions();
+        assert_eq!(transitions.next().map(|t| t.time.uptime), Some(5));
+        assert_eq!(transitions.count(), JOURNAL_TRANSITIONS - 1);
+    }
+
+    #[test]
+    fn stored_journal_should_continue_in_the_next_boot() {
+        let mut journal = FaultJournal::new();
+        journal.record(FaultType::BusUndervoltage, FaultState::Active, 10);
+        journal.record(FaultType::BusUndervoltage, FaultState::Latched, 20);
+        let mut buffer = [0; JOURNAL_SIZE];
+        journal.to_bytes(&mut buffer);
+        assert!(!journal.is_dirty());
+
+        let restored = FaultJournal::from_bytes(&buffer).unwrap();
+        assert_eq!(restored.boot(), 1);
+        assert_eq!(
+            restored.stats(FaultType::BusUndervoltage),
+            journal.stats(FaultType::BusUndervoltage)
+        );
+        assert!(restored.transitions().eq(journal.transitions()));
+
+        buffer[20] ^= 1;
+        assert!(FaultJournal::from_bytes(&buffer).is_none());
+        assert!(FaultJournal::from_bytes(&[0xFF; JOURNAL_SIZE]).is_none());
+    }
+}
@@ -1,7 +1,16 @@
 use core::sync::atomic::Ordering;
+#[cfg(feature = "fault-events")]
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
 use enum_iterator::{Sequence, all};
 use portable_atomic::{AtomicU8, AtomicUsize};
 
+/// Changes waiting to be sent, newer changes are dropped while it is full
+#[cfg(feature = "fault-events")]
+const CHANGE_QUEUE_SIZE: usize = 8;
+
+#[cfg(feature = "fault-events")]
+static CHANGES: Channel<CriticalSectionRawMutex, FaultChange, CHANGE_QUEUE_SIZE> = Channel::new();
+
 #[repr(u8)]
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum FaultState {
@@ -10,16 +19,52 @@ pub enum FaultState {
     Latched = 2,
 }
 
+/// The order is part of the protocol, new types go at the end
 #[derive(Sequence, Clone, Copy, Debug, PartialEq)]
 pub enum FaultType {
     Encoder,
-    // add more later
+    OvercurrentU,
+    OvercurrentV,
+    OvercurrentW,
+    /// Estimated from the power drawn by the motor
+    OvercurrentBus,
+    BusOvervoltage,
+    BusUndervoltage,
+    DriverOvertemperature,
+    MotorOvertemperature,
+    McuOvertemperature,
+    /// A control step took longer than allowed
+    LoopOverrun,
+    /// The ADC did not deliver a measurement in time
+    AdcTimeout,
+    /// No control command arrived in time while the motor was running
+    CommunicationTimeout,
+    /// The stored configuration is missing or invalid
+    ConfigError,
+    /// Reading, erasing or writing the flash failed
+    FlashError,
 }
 
 pub struct FaultRegister {
     cells: [AtomicU8; FaultType::CARDINALITY],
     active_count: AtomicUsize,
     resolved_count: AtomicUsize,
+    raised_count: AtomicUsize,
+    /// Only the shared register reports its changes
+    #[cfg_attr(
+        not(any(feature = "fault-events", feature = "fault-journal")),
+        allow(dead_code)
+    )]
+    notify: bool,
+}
+
+/// A cell of the shared register that changed, see [`next_change`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FaultChange {
+    pub fault: FaultType,
+    pub state: FaultState,
+    /// Microseconds since boot
+    pub timestamp: u64,
 }
 
 const fn idx(err: FaultType) -> usize {
@@ -46,21 +91,41 @@ impl Default for FaultRegister {
 impl FaultRegister {
     const fn new() -> Self {
         Self {
-            cells: [AtomicU8::new(FaultState::Clean as u8); FaultType::CARDINALITY],
+            cells: [const { AtomicU8::new(FaultState::Clean as u8) }; FaultType::CARDINALITY],
             active_count: AtomicUsize::new(0),
             resolved_count: AtomicUsize::new(0),
+            raised_count: AtomicUsize::new(0),
+            notify: false,
         }
     }
 
     pub fn shared() -> &'static Self {
-        static ERROR_REGISTER: FaultRegister = FaultRegister::new();
+        static ERROR_REGISTER: FaultRegister = FaultRegister {
+            notify: true,
+            ..FaultRegister::new()
+        };
         &ERROR_REGISTER
     }
 
-    fn store(&self, e: FaultType, v: FaultState) {
-        self.cells[idx(e)].store(v as u8, Ordering::SeqCst);
+    #[cfg(any(feature = "fault-events", feature = "fault-journal"))]
+    fn notify(&self, fault: FaultType, state: FaultState) {
+        if self.notify {
+            let timestamp = embassy_time::Instant::now().as_micros();
+            #[cfg(feature = "fault-journal")]
+            crate::fault_journal::record(fault, state, timestamp);
+            // The host can still read the whole register with ReportFaults
+            #[cfg(feature = "fault-events")]
+            let _ = CHANGES.try_send(FaultChange {
+                fault,
+                state,
+                timestamp,
+            });
+        }
     }
 
+    #[cfg(not(any(feature = "fault-events", feature = "fault-journal")))]
+    fn notify(&self, _fault: FaultType, _state: FaultState) {}
+
     pub fn load(&self, e: FaultType) -> FaultState {
         self.cells[idx(e)].load(Ordering::SeqCst).into()
     }
@@ -72,10 +137,14 @@ impl FaultRegister {
         match prev.into() {
             FaultState::Clean => {
                 self.active_count.fetch_add(1, Ordering::SeqCst);
+                self.raised_count.fetch_add(1, Ordering::SeqCst);
+                self.notify(e, FaultState::Active);
             }
             FaultState::Latched => {
                 self.resolved_count.fetch_sub(1, Ordering::SeqCst);
                 self.active_count.fetch_add(1, Ordering::SeqCst);
+                self.raised_count.fetch_add(1, Ordering::SeqCst);
+                self.notify(e, FaultState::Active);
             }
             FaultState::Active => {}
         }
@@ -93,12 +162,27 @@ impl FaultRegister {
         {
             self.active_count.fetch_sub(1, Ordering::SeqCst);
             self.resolved_count.fetch_add(1, Ordering::SeqCst);
+            self.notify(e, FaultState::Latched);
         }
     }
 
+    /// Sets a single fault back to clean, whatever its state
+    pub fn clear(&self, e: FaultType) {
+        let prev = self.cells[idx(e)].swap(FaultState::Clean as u8, Ordering::SeqCst);
+        match prev.into() {
+            FaultState::Clean => return,
+            FaultState::Active => self.active_count.fetch_sub(1, Ordering::SeqCst),
+            FaultState::Latched => self.resolved_count.fetch_sub(1, Ordering::SeqCst),
+        };
+        self.notify(e, FaultState::Clean);
+    }
+
     pub fn reset(&self) {
         for e in all::<FaultType>() {
-            self.store(e, FaultState::Clean);
+            let prev = self.cells[idx(e)].swap(FaultState::Clean as u8, Ordering::SeqCst);
+            if prev != FaultState::Clean as u8 {
+                self.notify(e, FaultState::Clean);
+            }
         }
         self.active_count.store(0, Ordering::SeqCst);
         self.resolved_count.store(0, Ordering::SeqCst);
@@ -112,6 +196,12 @@ impl FaultRegister {
         self.resolved_count.load(Ordering::SeqCst)
     }
 
+    /// Counts every time a fault turned active, it is not reset, so a poller can tell a fault
+    /// was raised even if it cleared in the meantime
+    pub fn raised_count(&self) -> usize {
+        self.raised_count.load(Ordering::SeqCst)
+    }
+
     pub fn any_active(&self) -> bool {
         self.active_count() != 0
     }
@@ -129,6 +219,12 @@ impl FaultRegister {
     }
 }
 
+/// Waits for the next change of the shared register
+#[cfg(feature = "fault-events")]
+pub async fn next_change() -> FaultChange {
+    CHANGES.receive().await
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -199,6 +295,23 @@ mod tests {
         assert!(!reg.any_latched());
     }
 
+    #[test]
+    fn clear_should_only_clear_the_given_fault() {
+        let reg = fresh_register();
+
+        reg.set(FaultType::Encoder);
+        reg.set(FaultType::AdcTimeout);
+        reg.resolve_if_set(FaultType::AdcTimeout);
+
+        reg.clear(FaultType::AdcTimeout);
+        reg.clear(FaultType::FlashError);
+
+        assert_eq!(reg.load(FaultType::AdcTimeout), FaultState::Clean);
+        assert_eq!(reg.load(FaultType::Encoder), FaultState::Active);
+        assert_eq!(reg.active_count(), 1);
+        assert_eq!(reg.latched_count(), 0);
+    }
+
     #[test]
     fn active_count_should_return_number_of_active_faults() {
         let reg = fresh_register();
@@ -268,10 +381,9 @@ mod tests {
 
         reg.set(FaultType::Encoder);
 
-        assert_eq!(
-            reg.snapshot(),
-            [FaultState::Active]
-        );
+        let mut expected = [FaultState::Clean; FaultType::CARDINALITY];
+        expected[idx(FaultType::Encoder)] = FaultState::Active;
+        assert_eq!(reg.snapshot(), expected);
     }
 
     #[test]
@@ -281,10 +393,9 @@ mod tests {
         reg.set(FaultType::Encoder);
         reg.resolve_if_set(FaultType::Encoder);
 
-        assert_eq!(
-            reg.snapshot(),
-            [FaultState::Latched]
-        );
+        let mut expected = [FaultState::Clean; FaultType::CARDINALITY];
+        expected[idx(FaultType::Encoder)] = FaultState::Latched;
+        assert_eq!(reg.snapshot(), expected);
     }
 
     #[test]
@@ -296,7 +407,7 @@ mod tests {
 
         assert_eq!(
             reg.snapshot(),
-            [FaultState::Clean]
+            [FaultState::Clean; FaultType::CARDINALITY]
         );
     }
 
@@ -343,6 +454,19 @@ mod tests {
         assert_eq!(reg.latched_count(), 0);
     }
 
+    #[test]
+    fn raised_count_should_only_count_faults_turning_active() {
+        let reg = fresh_register();
+
+        reg.set(FaultType::Encoder);
+        reg.set(FaultType::Encoder);
+        reg.resolve_if_set(FaultType::Encoder);
+        reg.set(FaultType::Encoder);
+        reg.reset();
+
+        assert_eq!(reg.raised_count(), 2);
+    }
+
     #[test]
     fn reset_should_clear_counters() {
         let reg = fresh_register();
@@ -1,8 +1,12 @@
 #![no_std]
 #![macro_use]
 
+#[cfg(feature = "errors")]
+pub mod fault_journal;
 #[cfg(feature = "errors")]
 pub mod fault_register;
 mod freq_meter;
 mod macro_logs;
+#[cfg(feature = "sink")]
+pub mod sink;
 pub use freq_meter::FreqMeter;
@@ -2,6 +2,32 @@
 #[cfg(all(feature = "defmt", feature = "log"))]
 compile_error!("You may not enable both `defmt` and `log` features.");
 
+/// Hands the record to the sink as well, the arguments are evaluated a second time but only while
+/// the level is forwarded
+#[cfg(feature = "sink")]
+#[doc(hidden)]
+#[macro_export]
+#[collapse_debuginfo(yes)]
+macro_rules! forward {
+    ($level:ident, $s:literal $(, $x:expr)*) => {
+        if $crate::sink::enabled($crate::sink::Level::$level) {
+            $crate::sink::push(
+                $crate::sink::Level::$level,
+                $s,
+                &[$(&$x as &dyn ::core::fmt::Debug),*],
+            );
+        }
+    };
+}
+
+#[cfg(not(feature = "sink"))]
+#[doc(hidden)]
+#[macro_export]
+#[collapse_debuginfo(yes)]
+macro_rules! forward {
+    ($level:ident, $s:literal $(, $x:expr)*) => {};
+}
+
 #[cfg(feature = "log")]
 #[macro_export]
 #[collapse_debuginfo(yes)]
@@ -9,6 +35,7 @@ macro_rules! trace {
     ($s:literal $(, $x:expr)* $(,)?) => {
         {
             ::log::trace!($s $(, $x)*);
+            $crate::forward!(Trace, $s $(, $x)*);
         }
     };
 }
@@ -20,6 +47,7 @@ macro_rules! trace {
     ($s:literal $(, $x:expr)* $(,)?) => {
         {
             ::defmt::trace!($s $(, $x)*);
+            $crate::forward!(Trace, $s $(, $x)*);
         }
     };
 }
@@ -30,6 +58,7 @@ macro_rules! trace {
 macro_rules! trace {
     ($s:literal $(, $x:expr)* $(,)?) => {{
         let _ = ($(& $x),*);
+        $crate::forward!(Trace, $s $(, $x)*);
     }};
 }
 
@@ -40,6 +69,7 @@ macro_rules! debug {
     ($s:literal $(, $x:expr)* $(,)?) => {
         {
             ::log::debug!($s $(, $x)*);
+            $crate::forward!(Debug, $s $(, $x)*);
         }
     };
 }
@@ -51,6 +81,7 @@ macro_rules! debug {
     ($s:literal $(, $x:expr)* $(,)?) => {
         {
             ::defmt::debug!($s $(, $x)*);
+            $crate::forward!(Debug, $s $(, $x)*);
         }
     };
 }
@@ -61,6 +92,7 @@ macro_rules! debug {
 macro_rules! debug {
     ($s:literal $(, $x:expr)* $(,)?) => {{
         let _ = ($(& $x),*);
+        $crate::forward!(Debug, $s $(, $x)*);
     }};
 }
 
@@ -71,6 +103,7 @@ macro_rules! info {
     ($s:literal $(, $x:expr)* $(,)?) => {
         {
             ::log::info!($s $(, $x)*);
+            $crate::forward!(Info, $s $(, $x)*);
         }
     };
 }
@@ -82,6 +115,7 @@ macro_rules! info {
     ($s:literal $(, $x:expr)* $(,)?) => {
         {
             ::defmt::info!($s $(, $x)*);
+            $crate::forward!(Info, $s $(, $x)*);
         }
     };
 }
@@ -92,6 +126,7 @@ macro_rules! info {
 macro_rules! info {
     ($s:literal $(, $x:expr)* $(,)?) => {{
         let _ = ($(& $x),*);
+        $crate::forward!(Info, $s $(, $x)*);
     }};
 }
 
@@ -102,6 +137,7 @@ macro_rules! warn {
     ($s:literal $(, $x:expr)* $(,)?) => {
         {
             ::log::warn!($s $(, $x)*);
+            $crate::forward!(Warn, $s $(, $x)*);
         }
     };
 }
@@ -113,6 +149,7 @@ macro_rules! warn {
     ($s:literal $(, $x:expr)* $(,)?) => {
         {
             ::defmt::warn!($s $(, $x)*);
+            $crate::forward!(Warn, $s $(, $x)*);
         }
     };
 }
@@ -123,6 +160,7 @@ macro_rules! warn {
 macro_rules! warn {
     ($s:literal $(, $x:expr)* $(,)?) => {{
         let _ = ($(& $x),*);
+        $crate::forward!(Warn, $s $(, $x)*);
     }};
 }
 
@@ -133,6 +171,7 @@ macro_rules! error {
     ($s:literal $(, $x:expr)* $(,)?) => {
         {
             ::log::error!($s $(, $x)*);
+            $crate::forward!(Error, $s $(, $x)*);
         }
     };
 }
@@ -144,6 +183,7 @@ macro_rules! error {
     ($s:literal $(, $x:expr)* $(,)?) => {
         {
             ::defmt::error!($s $(, $x)*);
+            $crate::forward!(Error, $s $(, $x)*);
         }
     };
 }
@@ -154,5 +194,6 @@ macro_rules! error {
 macro_rules! error {
     ($s:literal $(, $x:expr)* $(,)?) => {{
         let _ = ($(& $x),*);
+        $crate::forward!(Error, $s $(, $x)*);
     }};
 }
\ No newline at end of file
@@ -0,0 +1,131 @@
+use core::fmt::{Debug, Write};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::Instant;
+use portable_atomic::{AtomicU8, Ordering};
+
+/// Room for the rendered arguments of a record, longer arguments are cut
+pub const LOG_ARGS_SIZE: usize = 96;
+/// Records waiting to be sent, newer records are dropped while it is full
+const QUEUE_SIZE: usize = 16;
+const OFF: u8 = u8::MAX;
+
+static QUEUE: Channel<CriticalSectionRawMutex, LogRecord, QUEUE_SIZE> = Channel::new();
+/// Forwarding is off until the host asks for it
+static MIN_LEVEL: AtomicU8 = AtomicU8::new(OFF);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+/// A log message as written in the code, with its arguments rendered by `Debug`
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    pub level: Level,
+    /// Microseconds since boot
+    pub timestamp: u64,
+    pub message: &'static str,
+    args: [u8; LOG_ARGS_SIZE],
+    args_length: usize,
+}
+
+impl LogRecord {
+    pub fn new(level: Level, timestamp: u64, message: &'static str, args: &[&dyn Debug]) -> Self {
+        let mut writer = ArgsWriter {
+            buffer: [0; LOG_ARGS_SIZE],
+            length: 0,
+        };
+        for arg in args {
+            if writer.length == LOG_ARGS_SIZE {
+                break;
+            }
+            // The writer cuts what does not fit instead of failing
+            let _ = write!(writer, "{arg:?}");
+            writer.buffer[writer.length] = 0;
+            writer.length += 1;
+        }
+        Self {
+            level,
+            timestamp,
+            message,
+            args: writer.buffer,
+            args_length: writer.length,
+        }
+    }
+
+    pub fn args(&self) -> impl Iterator<Item = &str> {
+        // Only whole characters are stored, so the arguments are always valid
+        core::str::from_utf8(&self.args[..self.args_length])
+            .unwrap_or_default()
+            .split_terminator('\0')
+    }
+}
+
+/// Keeps the last byte free for the terminator of the argument
+struct ArgsWriter {
+    buffer: [u8; LOG_ARGS_SIZE],
+    length: usize,
+}
+
+impl Write for ArgsWriter {
+    fn write_str(&mut self, value: &str) -> core::fmt::Result {
+        let mut end = value.len().min(LOG_ARGS_SIZE - 1 - self.length);
+        while !value.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.buffer[self.length..self.length + end].copy_from_slice(&value.as_bytes()[..end]);
+        self.length += end;
+        Ok(())
+    }
+}
+
+/// `None` stops the forwarding
+pub fn set_min_level(level: Option<Level>) {
+    MIN_LEVEL.store(level.map_or(OFF, |level| level as u8), Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 >= MIN_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Queues a record if its level is forwarded, safe to call from interrupts
+pub fn push(level: Level, message: &'static str, args: &[&dyn Debug]) {
+    if enabled(level) {
+        let record = LogRecord::new(level, Instant::now().as_micros(), message, args);
+        let _ = QUEUE.try_send(record);
+    }
+}
+
+/// Waits for the next record to forward
+pub async fn receive() -> LogRecord {
+    QUEUE.receive().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arguments_should_be_rendered_separately() {
+        let record = LogRecord::new(Level::Warn, 0, "{} of {:?}", &[&3, &"blob"]);
+        assert!(record.args().eq(["3", "\"blob\""]));
+        let record = LogRecord::new(Level::Info, 0, "Booting", &[]);
+        assert_eq!(record.args().count(), 0);
+    }
+
+    #[test]
+    fn long_argument_should_be_cut() {
+        let long = [b'a'; LOG_ARGS_SIZE];
+        let long = core::str::from_utf8(&long).unwrap();
+        let record = LogRecord::new(Level::Error, 0, "{} {}", &[&long, &1]);
+        let mut args = record.args();
+        assert_eq!(args.next().map(str::len), Some(LOG_ARGS_SIZE - 1));
+        assert_eq!(args.next(), None);
+    }
+}
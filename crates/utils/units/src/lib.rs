@@ -8,12 +8,15 @@ use portable_atomic::AtomicF32;
 pub use uom::fmt::DisplayStyle;
 use uom::num::Float;
 pub use uom::si;
+use uom::si::angle::radian;
+use uom::si::angular_velocity::radian_per_second;
 use uom::si::electric_current::ampere;
 use uom::si::electric_potential::volt;
 pub use uom::si::f32::AngularVelocity;
 pub use uom::si::f32::Ratio;
 pub use uom::si::f32::*;
 use uom::si::thermodynamic_temperature::kelvin;
+use uom::si::torque::newton_meter;
 
 pub type DutyCycle = Ratio;
 
@@ -39,6 +42,9 @@ macro_rules! impl_atomic_unit_type {
 impl_atomic_unit_type!(ElectricPotential, volt);
 impl_atomic_unit_type!(ElectricCurrent, ampere);
 impl_atomic_unit_type!(ThermodynamicTemperature, kelvin);
+impl_atomic_unit_type!(Angle, radian);
+impl_atomic_unit_type!(AngularVelocity, radian_per_second);
+impl_atomic_unit_type!(Torque, newton_meter);
 
 pub struct AtomicUnit<T: F32UnitType> {
     value: AtomicF32,
@@ -23,6 +23,9 @@ error()   { echo -e "${RED}[ERROR]${NC} $1"; exit 1; }
 SCRIPT_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")" && pwd)"
 cd "$SCRIPT_DIR"
 
+# Made on first use and kept out of git, release devices get their own key
+DEVELOPMENT_KEY="$SCRIPT_DIR/crates/bootloader/keys/development.key"
+
 if [[ ! -d "crates" ]]; then
     error "Could not find the 'crates' directory. Please place this script in the repository root."
 fi
@@ -67,9 +70,21 @@ Commands:
 EOF
 }
 
+ensure_development_key() {
+    if [[ ! -f "$DEVELOPMENT_KEY" ]]; then
+        info "Generating a development key ($DEVELOPMENT_KEY)..."
+        (cd "$SCRIPT_DIR" && cargo run --release -p sign-image -- keygen "$DEVELOPMENT_KEY")
+    fi
+}
+
 do_flash() {
     local target=$1
     if [[ "$target" == "bootloader" ]]; then
+        if [[ -z "${PYRION_PUBLIC_KEY:-}" ]]; then
+            ensure_development_key
+            warn "PYRION_PUBLIC_KEY is not set, the bootloader accepts images signed with the development key."
+            export PYRION_PUBLIC_KEY="$DEVELOPMENT_KEY.pub"
+        fi
         info "Flashing bootloader via probe-rs..."
         pushd "crates/bootloader" > /dev/null
           cargo run --release
@@ -95,6 +110,17 @@ do_dfu() {
       info "Generating raw binary ($bin_out)..."
       cargo objcopy --release -- -O binary "$bin_out"
 
+      local signed_out="firmware.signed.bin"
+      local signing_key="${PYRION_SIGNING_KEY:-$DEVELOPMENT_KEY}"
+      if [[ -z "${PYRION_SIGNING_KEY:-}" ]]; then
+          ensure_development_key
+      fi
+      info "Signing the image ($signed_out)..."
+      # Built for the host, outside of the firmware's target configuration
+      (cd "$SCRIPT_DIR" && cargo run --release -p sign-image -- sign "$signing_key" \
+          "crates/firmware/$bin_out" "crates/firmware/$signed_out")
+      bin_out="$signed_out"
+
       info "Flashing 'firmware' via DFU..."
       local dfu_output
       local dfu_status